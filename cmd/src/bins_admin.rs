@@ -0,0 +1,111 @@
+use clap::{Parser, Subcommand};
+use front::client_fs::keeper::{
+    change_config, replication_jobs, replication_status, ConfigChange,
+};
+use log::LevelFilter;
+use tribbler::config::{Config, DEFAULT_CONFIG_LOCATION};
+use tribbler::error::TritonFileResult;
+
+/// changes the backends of a running cluster through its leader keeper
+#[derive(Parser, Debug)]
+#[clap(name = "bins-admin")]
+struct Args {
+    /// log level to use
+    #[clap(short, long, default_value = "INFO")]
+    log_level: LevelFilter,
+    /// bin configuration file, used to find the keepers
+    #[clap(short, long, default_value = DEFAULT_CONFIG_LOCATION)]
+    config: String,
+
+    #[clap(subcommand)]
+    command: AdminCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum AdminCommand {
+    /// adds a running backend to the cluster and moves bins onto it
+    Add { addr: String },
+    /// moves the bins of a backend elsewhere and retires it; backends of
+    /// the config file can't be retired
+    Remove { addr: String },
+    /// stops placing bins on a backend and copies its bins elsewhere, so
+    /// that it can be removed safely afterwards
+    Drain { addr: String },
+    /// prints the current backends of the cluster
+    Show,
+    /// prints the bins that have fewer than two copies, and how many inodes
+    /// anti-entropy found to differ between replicas
+    Status,
+    /// prints the replication jobs of the leader keeper and how far they got
+    Jobs,
+}
+
+#[tokio::main]
+async fn main() -> TritonFileResult<()> {
+    let args = Args::parse();
+    env_logger::builder().filter_level(args.log_level).init();
+    let cfg = Config::read(Some(&args.config))?;
+    let change = match args.command {
+        AdminCommand::Add { addr } => ConfigChange::AddBackend(addr),
+        AdminCommand::Remove { addr } => ConfigChange::RemoveBackend(addr),
+        AdminCommand::Drain { addr } => ConfigChange::DrainBackend(addr),
+        AdminCommand::Show => ConfigChange::Show,
+        AdminCommand::Status => {
+            let status = replication_status(&cfg.keepers).await?;
+            println!("{} live backends", status.live);
+            if status.under_replicated.is_empty() {
+                println!("every bin has two copies");
+            }
+            for bin in status.under_replicated.iter() {
+                println!("  {} (under-replicated)", bin);
+            }
+            println!(
+                "anti-entropy: {} passes, {} inodes compared, {} divergent, {} repaired",
+                status.verify_rounds.unwrap_or(0),
+                status.inodes_verified.unwrap_or(0),
+                status.divergent.unwrap_or(0),
+                status.repaired.unwrap_or(0)
+            );
+            return Ok(());
+        }
+        AdminCommand::Jobs => {
+            let jobs = replication_jobs(&cfg.keepers).await?;
+            if jobs.is_empty() {
+                println!("no replication jobs");
+            }
+            for job in jobs.iter() {
+                println!(
+                    "  job {}: {} of {} from {} to {}: {}, {} tries, {} copied",
+                    job.id,
+                    if job.files { "files" } else { "keys" },
+                    job.for_addr,
+                    job.from,
+                    job.to,
+                    job.state,
+                    job.attempts,
+                    job.copied
+                );
+                if let Some(checkpoint) = &job.checkpoint {
+                    let unit = if job.files { "inodes" } else { "bins" };
+                    println!("    copied {} up to {}", unit, checkpoint);
+                }
+                if let Some(error) = &job.error {
+                    println!("    last error: {}", error);
+                }
+            }
+            return Ok(());
+        }
+    };
+    let config = change_config(&cfg.keepers, change).await?;
+    println!("cluster config {}:", config.version);
+    for back in config.backs.iter() {
+        if config.drained.contains(back) {
+            println!("  {} (drained, safe to remove)", back);
+        } else if config.draining.contains(back) {
+            println!("  {} (draining)", back);
+        } else {
+            println!("  {}", back);
+        }
+    }
+    Ok(())
+}
@@ -1,48 +1,18 @@
-use std::fs::File;
-use std::str::FromStr;
-use std::io::{BufRead, BufReader, ErrorKind};
-use actix_files::Files;
-use actix_web::{web, App, HttpServer};
-use clap::Parser;
-use front::client_fs;
-use front::client_fs::lab::{new_bin_client, serve_back};
-use front::client_fs::{client::new_client, front::Front};
-use log::{info, warn, LevelFilter, error};
-
-use tribbler::config::Config;
-use tribbler::config::DEFAULT_CONFIG_LOCATION;
-use tribbler::error::{TritonFileResult, TritonFileError};
-// use tribbler::ref_impl::RefServer;
-use tribbler::disfuser_server::DisfuserServer;
-use fuser::{
-    Filesystem, KernelConfig, MountOption, ReplyAttr, ReplyCreate, ReplyData, ReplyDirectory,
-    ReplyEmpty, ReplyEntry, ReplyOpen, ReplyStatfs, ReplyWrite, ReplyXattr, Request, TimeOrNow,
-    FUSE_ROOT_ID,
-};
-use client_fs::front::USERNAME;
-
-#[derive(Debug, Clone)]
-enum ServerType {
-    Ref,
-    Lab,
-}
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::Command;
 
-impl FromStr for ServerType {
-    type Err = TritonFileError;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s.to_lowercase().as_str() {
-            "ref" => Ok(ServerType::Ref),
-            "lab" => Ok(ServerType::Lab),
-            _ => Err(TritonFileError::Unknown(format!(
-                "{} not a valid ServerType",
-                s
-            ))),
-        }
-    }
-}
+use clap::{Args, Parser, Subcommand};
+use front::client_fs::binstore::BinStore;
+use front::client_fs::front::{Front, USERNAME};
+use fuser::MountOption;
+use log::{error, info, warn, LevelFilter};
+use tokio::signal::unix::{signal, SignalKind};
 
-/// A program which runs the tribbler front-end service.
+use tribbler::config::{Config, NamespaceKind, DEFAULT_CONFIG_LOCATION};
+use tribbler::error::{TritonFileError, TritonFileResult};
+
+/// A program which mounts the files of a namespace, or unmounts them.
 #[derive(Parser, Debug)]
 #[clap(name = "trib-front")]
 struct Cfg {
@@ -50,20 +20,63 @@ struct Cfg {
     #[clap(short, long, default_value = "INFO")]
     log_level: LevelFilter,
 
-    /// server type to run the front-end against
-    #[clap(short, long, default_value = "ref")]
-    server_type: ServerType,
+    #[clap(subcommand)]
+    command: FrontCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum FrontCommand {
+    /// mounts a namespace and serves it until it is unmounted, or the front
+    /// gets SIGTERM or SIGINT
+    Mount(MountArgs),
+    /// unmounts the namespace mounted at a mountpoint; the front serving it
+    /// sends on the writes it held back, then exits
+    Unmount { mountpoint: PathBuf },
+}
 
+#[derive(Args, Debug)]
+struct MountArgs {
+    /// the directory to mount at
+    mountpoint: PathBuf,
+
+    /// bin configuration file
     #[clap(short, long, default_value = DEFAULT_CONFIG_LOCATION)]
     config: String,
 
-    /// the host address to bind to. e.g. 127.0.0.1 or 0.0.0.0
-    #[clap(long, default_value = "0.0.0.0")]
-    host: String,
+    /// the namespace to mount, one of those in the config
+    #[clap(long, default_value = USERNAME)]
+    namespace: String,
+
+    /// mounts read-only
+    #[clap(long)]
+    read_only: bool,
+
+    /// lets users other than the one mounting reach the files, as shared
+    /// namespaces always do; needs `user_allow_other` in /etc/fuse.conf
+    #[clap(long)]
+    allow_other: bool,
+
+    /// has reads and writes bypass the kernel's page cache
+    #[clap(long)]
+    direct_io: bool,
+
+    /// how long attributes are cached, in milliseconds, instead of what the
+    /// config says
+    #[clap(long)]
+    attr_ttl_ms: Option<u64>,
+
+    /// how long names found in a directory are cached, in milliseconds
+    #[clap(long)]
+    entry_ttl_ms: Option<u64>,
+
+    /// how long names found missing are cached, in milliseconds
+    #[clap(long)]
+    negative_ttl_ms: Option<u64>,
 
-    /// the host port to bind
-    #[clap(long, default_value = "8080")]
-    port: u16,
+    /// detaches from the terminal once the config is read; logs still go to
+    /// stderr
+    #[clap(long)]
+    daemon: bool,
 }
 
 fn main() -> TritonFileResult<()> {
@@ -74,84 +87,255 @@ fn main() -> TritonFileResult<()> {
         .filter_level(args.log_level)
         .init();
 
-    let cfg = Config::read(Some(&args.config))?;
-    let runtime = tokio::runtime::Builder::new_current_thread()
-    .enable_io()
-    .build()
-    .unwrap();    
-    let bin_client_pre = new_bin_client(cfg.backs);
-    let bin_client = runtime.block_on(bin_client_pre).unwrap();
-
-    let mut options = vec![MountOption::FSName(format!("fuser"))];
-    #[cfg(feature = "abi-7-26")]
-    {
-        options.push(MountOption::AutoUnmount);
+    match args.command {
+        FrontCommand::Mount(mount_args) => mount(mount_args),
+        FrontCommand::Unmount { mountpoint } => unmount(&mountpoint),
     }
-    #[cfg(not(feature = "abi-7-26"))]
-    {
-        options.push(MountOption::AutoUnmount);
+}
+
+fn mount(args: MountArgs) -> TritonFileResult<()> {
+    let mut cfg = Config::read(Some(&args.config))?;
+    let namespace = cfg.namespace(&args.namespace)?;
+    if !args.mountpoint.is_dir() {
+        return Err(Box::new(TritonFileError::FileDoesNotExist(
+            args.mountpoint.display().to_string(),
+        )));
+    }
+    if let Some(ttl) = args.attr_ttl_ms {
+        cfg.cache.attr_ttl_ms = ttl;
+    }
+    if let Some(ttl) = args.entry_ttl_ms {
+        cfg.cache.entry_ttl_ms = ttl;
+    }
+    if let Some(ttl) = args.negative_ttl_ms {
+        cfg.cache.negative_ttl_ms = ttl;
+    }
+
+    let mut options = vec![MountOption::FSName("fuser".to_string()), MountOption::NoDev];
+    options.push(match args.read_only {
+        true => MountOption::RO,
+        false => MountOption::RW,
+    });
+    if args.allow_other || namespace.kind == NamespaceKind::Shared {
         options.push(MountOption::AllowOther);
-        options.push(MountOption::NoDev);
-        options.push(MountOption::Sync);
-        options.push(MountOption::NoExec);
-        options.push(MountOption::RW);
-        options.push(MountOption::Suid)
-        // options.push(MountOption::AllowRoot);
-    }
-    // let handle = Handle::current();
-    let front =  Front::new(
-        bin_client,
+        // Has the kernel unmount if the front dies; fusermount only allows
+        // it along with allow_other.
+        options.push(MountOption::AutoUnmount);
+    }
+    if namespace.kind == NamespaceKind::Shared {
+        // Everyone works on the files of a shared namespace as themselves,
+        // so the kernel checks their permissions on each access.
+        options.push(MountOption::DefaultPermissions);
+    }
+
+    if args.daemon {
+        // Before the runtime starts its threads, which the child would not
+        // have.
+        if unsafe { libc::daemon(1, 1) } != 0 {
+            return Err(Box::new(std::io::Error::last_os_error()));
+        }
+    }
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(cfg.concurrency.worker_threads.max(1))
+        .enable_all()
+        .build()
+        .unwrap();
+    let handle = runtime.handle().clone();
+    // Built inside the runtime, which keeps its membership fresh.
+    let binstore =
+        runtime.block_on(async { BinStore::with_keepers(cfg.backs, cfg.keepers, cfg.read_policy) });
+    let membership = binstore.membership();
+
+    let mut front = Front::with_namespace(
+        Box::new(binstore),
         runtime,
-        USERNAME.to_string(),
+        namespace,
+        cfg.cache,
+        cfg.write_back,
+        cfg.read_cache,
+        cfg.concurrency.max_requests,
     );
+    front.set_offline(cfg.offline);
+    front.set_direct_io(args.direct_io);
+    let invalidator = front.invalidator();
 
-    info!("The file system is ready to be mounted");
-    let result = fuser::mount2(
-        front, 
-        "/Users/lynnz/Desktop/tmp",
-        &options,
-    );
-    dbg!(&result);
-    if let Err(e) = result {
-                // Return a special error code for permission denied, which usually indicates that
-                // "user_allow_other" is missing from /etc/fuse.conf
-        if e.kind() == ErrorKind::PermissionDenied {
-            error!("{}", e.to_string());
-            std::process::exit(2);
+    let mut session = match fuser::Session::new(front, &args.mountpoint, &options) {
+        Ok(session) => session,
+        Err(e) => {
+            // Return a special error code for permission denied, which usually indicates that
+            // "user_allow_other" is missing from /etc/fuse.conf
+            if e.kind() == ErrorKind::PermissionDenied {
+                error!("{}", e.to_string());
+                std::process::exit(2);
+            }
+            return Err(Box::new(e));
         }
+    };
+    // Invalidations pushed by the backends are passed on to the kernel
+    // while the session runs.
+    invalidator
+        .with_notifier(session.notifier())
+        .spawn(membership);
+    let mut unmounter = session.unmount_callable();
+    handle.spawn(unmount_when_terminated(
+        args.mountpoint.clone(),
+        move || unmounter.unmount(),
+    ));
+
+    info!(
+        "namespace {} mounted at {}",
+        args.namespace,
+        args.mountpoint.display()
+    );
+    let result = session.run();
+    // The front sends on what it held back as the session goes.
+    drop(session);
+    result?;
+    info!("{} unmounted", args.mountpoint.display());
+    Ok(())
+}
+
+// Unmounts `mountpoint` with `unmount` once the front gets SIGTERM or
+// SIGINT, which ends the session as if it had been unmounted from outside.
+async fn unmount_when_terminated<F>(mountpoint: PathBuf, unmount: F)
+where
+    F: FnOnce() -> std::io::Result<()>,
+{
+    if let Err(e) = terminated().await {
+        warn!("not unmounting on SIGTERM: {}", e);
+        return;
+    }
+    info!("unmounting {}", mountpoint.display());
+    if let Err(e) = unmount() {
+        warn!("unmounting {}: {}", mountpoint.display(), e);
+    }
+}
+
+// Waits for SIGTERM or SIGINT.
+async fn terminated() -> std::io::Result<()> {
+    let mut term = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    tokio::select! {
+        _ = term.recv() => (),
+        _ = interrupt.recv() => (),
     }
     Ok(())
+}
+
+// Unmounts `mountpoint` the way its unprivileged owner may.
+fn unmount(mountpoint: &Path) -> TritonFileResult<()> {
+    #[cfg(target_os = "linux")]
+    let commands: &[(&str, &[&str])] = &[("fusermount3", &["-u"]), ("fusermount", &["-u"])];
+    #[cfg(not(target_os = "linux"))]
+    let commands: &[(&str, &[&str])] = &[("umount", &[])];
+    for (program, args) in commands {
+        match Command::new(program).args(*args).arg(mountpoint).status() {
+            Ok(status) if status.success() => return Ok(()),
+            Ok(status) => {
+                return Err(Box::new(TritonFileError::Unknown(format!(
+                    "{} {}: {}",
+                    program,
+                    mountpoint.display(),
+                    status
+                ))))
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => return Err(Box::new(e)),
+        }
+    }
+    Err(Box::new(TritonFileError::Unknown(
+        "no command to unmount with".to_string(),
+    )))
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use clap::Parser;
+    use front::client_fs::front::USERNAME;
+    use tribbler::config::DEFAULT_CONFIG_LOCATION;
 
-    // let server: web::Data<Srv> = web::Data::new(srv_impl);
-    // match populate(&server).await {
-    //     Ok(_) => info!("Pre-populated test-server successfully"),
-    //     Err(e) => warn!("Failed to pre-populate test server: {}", e),
-    // }
-    // let srv = HttpServer::new(move || {
-    //     App::new()
-    //         .app_data(server.clone())
-    //         .service(
-    //             web::scope("/api")
-    //                 .service(api::add_user)
-    //                 .service(api::list_users)
-    //                 .service(api::list_tribs)
-    //                 .service(api::list_home)
-    //                 .service(api::is_following)
-    //                 .service(api::follow)
-    //                 .service(api::unfollow)
-    //                 .service(api::following)
-    //                 .service(api::post),
-    //         )
-    //         .service(Files::new("/", "./www").index_file("index.html"))
-    // })
-    // .bind((args.host.as_str(), args.port))?
-    // .run();
-    // info!("============================================");
-    // info!(
-    //     "TRIBBLER SERVING AT ::: http://{}:{}",
-    //     &args.host, &args.port
-    // );
-    // info!("============================================");
-    // srv.await?;
-    // Ok(())
+    use super::{unmount, unmount_when_terminated, Cfg, FrontCommand};
+
+    #[test]
+    fn test_parse_mount() {
+        let cfg = Cfg::try_parse_from([
+            "trib-front",
+            "mount",
+            "/mnt/team",
+            "--namespace",
+            "team",
+            "--read-only",
+            "--attr-ttl-ms",
+            "250",
+        ])
+        .unwrap();
+        let args = match cfg.command {
+            FrontCommand::Mount(args) => args,
+            command => panic!("parsed {:?}", command),
+        };
+        assert_eq!(PathBuf::from("/mnt/team"), args.mountpoint);
+        assert_eq!("team", args.namespace);
+        assert!(args.read_only);
+        assert!(!args.allow_other && !args.direct_io && !args.daemon);
+        assert_eq!(Some(250), args.attr_ttl_ms);
+        assert_eq!(None, args.entry_ttl_ms);
+        assert_eq!(DEFAULT_CONFIG_LOCATION, args.config);
+
+        let cfg = Cfg::try_parse_from(["trib-front", "mount", "/mnt/me"]).unwrap();
+        match cfg.command {
+            FrontCommand::Mount(args) => assert_eq!(USERNAME, args.namespace),
+            command => panic!("parsed {:?}", command),
+        }
+    }
+
+    #[test]
+    fn test_parse_unmount() {
+        let cfg = Cfg::try_parse_from(["trib-front", "unmount", "/mnt/team"]).unwrap();
+        match cfg.command {
+            FrontCommand::Unmount { mountpoint } => {
+                assert_eq!(PathBuf::from("/mnt/team"), mountpoint)
+            }
+            command => panic!("parsed {:?}", command),
+        }
+        assert!(Cfg::try_parse_from(["trib-front", "mount"]).is_err());
+        assert!(
+            Cfg::try_parse_from(["trib-front", "mount", "/mnt/team", "--attr-ttl-ms", "soon"])
+                .is_err()
+        );
+        assert!(Cfg::try_parse_from(["trib-front", "remount", "/mnt/team"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_sigterm_unmounts() {
+        let unmounted = Arc::new(AtomicBool::new(false));
+        let flag = unmounted.clone();
+        let task = tokio::spawn(unmount_when_terminated(
+            PathBuf::from("/mnt/team"),
+            move || {
+                flag.store(true, Ordering::SeqCst);
+                Ok(())
+            },
+        ));
+        // Let the task install its handlers before the signal is raised.
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!unmounted.load(Ordering::SeqCst));
+        assert_eq!(0, unsafe { libc::kill(libc::getpid(), libc::SIGTERM) });
+        tokio::time::timeout(Duration::from_secs(5), task)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(unmounted.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_unmount_not_mounted() {
+        let dir = std::env::temp_dir();
+        assert!(unmount(&dir).is_err());
+    }
 }
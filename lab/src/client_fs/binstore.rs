@@ -1,7 +1,8 @@
-use super::ops::{union, ListOp, LogOp, OpKind};
+use super::ops::{union, ListOp, LogOp, OpKind, RepairRecord};
 use async_trait::async_trait;
 use fuser::FileAttr;
 use fuser::TimeOrNow;
+use fuser::FUSE_ROOT_ID;
 use libc::c_int;
 use log::info;
 use serde::de::DeserializeOwned;
@@ -12,28 +13,55 @@ use std::cmp;
 use std::collections::hash_map::DefaultHasher;
 use std::ffi::OsStr;
 use std::fmt;
+use std::future::Future;
 use fuser::FileType;
 use std::hash::{Hash, Hasher};
 use std::sync::atomic;
-use std::time::SystemTime;
+use std::sync::Arc;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
 use tribbler::colon;
-use tribbler::error::{TritonFileError, TritonFileResult};
+use tribbler::config::{ReadPolicy, DEFAULT_NAMESPACE};
+use tribbler::error::{is_epoch_mismatch, TritonFileError, TritonFileResult, SUCCESS};
 use tribbler::storage;
 use tribbler::storage::{FileRequest, KeyValue, ServerFileSystem, Storage};
 use crate::client_fs::binstore::storage::DataList;
 
-use super::client;
+use super::client::ClientPool;
+use super::latency::Latencies;
+use super::membership::Membership;
+use super::retry::{with_retry, RetryPolicy};
 
 pub struct BinStore {
     addrs: Vec<String>,
+    read_policy: ReadPolicy,
+    pool: Arc<ClientPool>,
+    membership: Arc<Membership>,
+    latencies: Arc<Latencies>,
 }
 
 pub struct ReliableStore {
-    addrs: Vec<String>,
+    // Backends of the bin in the order they are tried, if pinned; otherwise
+    // the ring follows the backends of the current cluster config.
+    pinned: Option<Vec<String>>,
     prefix: String,
+    // Namespace the files of the bin are kept in on the backends, if not
+    // the backends' own file tree.
+    namespace: Option<String>,
     clock: atomic::AtomicU64,
     index: usize,
     simple: bool,
+    policy: RetryPolicy,
+    read_policy: ReadPolicy,
+    pool: Arc<ClientPool>,
+    membership: Arc<Membership>,
+    latencies: Arc<Latencies>,
+    // Newest version seen per inode, with when it was last seen, and
+    // inodes whose backup recently missed a mutation; both keep non-primary
+    // reads from going back in time. Each holds at most TRACKED_INODES.
+    versions: Mutex<HashMap<u64, (u64, Instant)>>,
+    lagging: Mutex<HashMap<u64, Instant>>,
 }
 
 #[derive(Debug)]
@@ -48,6 +76,26 @@ impl fmt::Display for KeyKind {
     }
 }
 
+// Raw KV list on each backend holding RepairRecords for replicas that
+// missed a mutation.
+pub const KEY_REPAIR_LOG: &str = "REPAIR_LOG";
+// How long reads of an inode stay on the primary after its backup missed
+// a mutation.
+const LAGGING_READ_WINDOW: Duration = Duration::from_secs(30);
+// Most inodes a store keeps versions of, or keeps lagging backups of.
+const TRACKED_INODES: usize = 4096;
+// Hedge delay used until the primary has latency samples.
+const DEFAULT_HEDGE_DELAY: Duration = Duration::from_millis(20);
+// getattr does not check permissions, so version probes run as root.
+const VERSION_PROBE_REQUEST: FileRequest = FileRequest {
+    uid: 0,
+    gid: 0,
+    pid: 0,
+};
+// How many times a mutation is re-applied to a lagging backup before it is
+// recorded for repair instead.
+const REDRIVE_ATTEMPTS: u32 = 2;
+
 // Sort operations by their clock.
 fn sort_ops(ops: &mut Vec<LogOp>) {
     ops.sort_by(|a, b| {
@@ -71,6 +119,27 @@ pub fn compose_key(prefix: &str, kind: KeyKind, key: &str) -> String {
     )
 }
 
+// Once `map` is full, forgets the half of its inodes `touched` longest
+// ago. Inodes past LAGGING_READ_WINDOW go first, and a version forgotten
+// is learned again from the primary on the next mutation or read.
+fn make_room<V>(map: &mut HashMap<u64, V>, touched: impl Fn(&V) -> Instant) {
+    if map.len() < TRACKED_INODES {
+        return;
+    }
+    let mut times: Vec<Instant> = map.values().map(&touched).collect();
+    let (_, median, _) = times.select_nth_unstable(TRACKED_INODES / 2);
+    let median = *median;
+    map.retain(|_, v| touched(v) > median);
+}
+
+// Whether `err` says no live backend was left to pick.
+fn is_no_live_store(err: &(dyn std::error::Error + Send + Sync + 'static)) -> bool {
+    matches!(
+        err.downcast_ref::<TritonFileError>(),
+        Some(TritonFileError::NoLiveStore)
+    )
+}
+
 // Decode a list of string into a list of ops.
 fn decode_ops<T: DeserializeOwned>(ops: Vec<String>) -> TritonFileResult<Vec<T>> {
     let mut result = vec![];
@@ -89,61 +158,60 @@ fn encode_ops<T: Serialize>(ops: Vec<T>) -> TritonFileResult<Vec<String>> {
     Ok(result)
 }
 
+// The inode a successful create or make_node made.
+fn made_file(res: &(Option<(FileAttr, u64)>, c_int)) -> Option<(u64, FileType)> {
+    match res {
+        (Some((attr, _)), SUCCESS) => Some((attr.ino, attr.kind)),
+        _ => None,
+    }
+}
+
+// The directory a successful mkdir made.
+fn made_dir(res: &(Option<FileAttr>, c_int)) -> Option<(u64, FileType)> {
+    match res {
+        (Some(attr), SUCCESS) => Some((attr.ino, attr.kind)),
+        _ => None,
+    }
+}
+
 fn box_err<T>(err: TritonFileError) -> TritonFileResult<T> {
     Err(Box::new(err))
 }
 
 // This is our backend interface.
 impl ReliableStore {
-    async fn get_store(&self, count: i32) -> TritonFileResult<Box<dyn Storage>> {
-        // start from the index and find the first alive one
-        let mut idx = 0;
+    // A client of addr for the files of this bin.
+    fn client(&self, addr: &str) -> TritonFileResult<Box<dyn Storage>> {
+        self.pool.client_in(addr, self.namespace.as_deref())
+    }
+
+    // Returns the count-th alive backend of this bin and its address.
+    async fn pick_store(&self, count: i32) -> TritonFileResult<(String, Box<dyn Storage>)> {
+        // The view is normally kept fresh in the background; only refresh
+        // inline if nobody has done so recently or an RPC just failed.
+        if self.membership.is_stale() {
+            self.membership.refresh(&self.pool).await;
+        }
+        let replicas = match &self.pinned {
+            Some(pinned) => pinned.clone(),
+            None => ring(&self.prefix, &self.membership.backs()),
+        };
+        // start from the index and pick the count-th alive one
         let mut count = count;
-        // info!("{} addrs len: {}", count, self.addrs.len());
-        let mut limit = self.addrs.len() * 2;
-        loop {
-            limit -= 1;
-            if limit == 0 {
-                break box_err(TritonFileError::Unknown(
-                    "can't find any live store".to_string(),
-                ));
+        for addr in replicas.iter() {
+            if !self.membership.is_alive(addr) {
+                continue;
             }
-            if idx == self.addrs.len() {
-                idx = 0;
+            count -= 1;
+            if count == 0 {
+                return Ok((addr.clone(), self.client(addr)?));
             }
-            let addr = &self.addrs[idx];
-            let result = client::new_client(addr).await;
-            match result {
-                Ok(client) => {
-                    let res = client.clock(0).await;
-                    match res {
-                        Ok(_) => {
-                            count -= 1;
-                            if count == 0 {
-                                break Ok(client);
-                            } else {
-                                idx += 1;
-                                continue;
-                            }
-                        }
-                        Err(err) => {
-                            info!(
-                                "Can't clock with store @ {}: {}",
-                                self.addrs[idx],
-                                err.to_string()
-                            );
-                            idx += 1;
-                            continue;
-                        }
-                    }
-                }
-                Err(err) => {
-                    info!("Can't create client: {}", err.to_string());
-                    idx += 1;
-                    continue;
-                }
-            };
         }
+        box_err(TritonFileError::NoLiveStore)
+    }
+
+    async fn get_store(&self, count: i32) -> TritonFileResult<Box<dyn Storage>> {
+        Ok(self.pick_store(count).await?.1)
     }
 
     // Gets the primary backend for key.
@@ -162,57 +230,527 @@ impl ReliableStore {
     //     }
     // }
 
-    // Get sorted ops for key, key should be already composed.
-    async fn get_sorted_ops(&self, key: &str) -> TritonFileResult<Vec<LogOp>> {
-        loop {
-            let primary = self.primary_store().await?;
-            let mut primary_log = if self.simple {
-                match primary.get(key).await {
-                    Err(err) => {
-                        // info!("Error getting primary {:#?}", err);
-                        continue;
-                    }
-                    Ok(Some(val)) => vec![val],
-                    Ok(None) => vec![],
-                }
-            } else {
-                match primary.list_get(key).await {
-                    Err(err) => {
-                        // info!("Error getting primary {:#?}", err);
-                        continue;
+    // Runs f under the store's retry policy, invalidating the liveness view
+    // whenever an attempt fails so the next one picks fresh replicas.
+    async fn with_retry<T, F, Fut>(&self, op: &str, f: F) -> TritonFileResult<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = TritonFileResult<T>>,
+    {
+        with_retry(&self.policy, op, || self.membership.invalidate(), f).await
+    }
+
+    // Applies a mutation to the primary and the backup at the same time. The
+    // primary's result is the result of the operation: if the primary fails
+    // the mutation is retried, but not on a backup that already applied it;
+    // if only the backup fails the backup is re-driven and, failing that,
+    // recorded for repair (see redrive). A backup rejecting our epoch fails
+    // the mutation too. With a single live backend the mutation goes to it
+    // alone; the keeper notices the bin is under-replicated and copies it
+    // once a second backend is back.
+    async fn fan_out<T, F, Fut>(&self, op: &str, inode: u64, f: F) -> TritonFileResult<T>
+    where
+        F: Fn(Box<dyn Storage>) -> Fut,
+        Fut: Future<Output = TritonFileResult<T>>,
+    {
+        let f = &f;
+        // The backup that applied the mutation in an earlier attempt.
+        let applied: &Mutex<Option<String>> = &Mutex::new(None);
+        self.with_retry(op, || async move {
+            let (primary_addr, primary) = self.pick_store(1).await?;
+            let (backup_addr, backup) = match self.pick_store(2).await {
+                Ok(backup) => backup,
+                Err(err) => match err.downcast_ref::<TritonFileError>() {
+                    Some(TritonFileError::NoLiveStore) => {
+                        info!("{} on {} without a backup", op, primary_addr);
+                        let res = f(primary).await?;
+                        self.note_version(&primary_addr, inode).await;
+                        return Ok(res);
                     }
-                    Ok(ret) => ret.0,
+                    _ => return Err(err),
+                },
+            };
+            if applied.lock().unwrap().as_deref() == Some(backup_addr.as_str()) {
+                let res = f(primary).await?;
+                self.note_version(&primary_addr, inode).await;
+                return Ok(res);
+            }
+            let (primary_res, backup_res) = tokio::join!(f(primary), f(backup));
+            if backup_res.is_ok() {
+                *applied.lock().unwrap() = Some(backup_addr.clone());
+            }
+            let res = primary_res?;
+            self.note_version(&primary_addr, inode).await;
+            if let Err(err) = backup_res {
+                info!("{} on backup {} failed: {}", op, backup_addr, err);
+                if is_epoch_mismatch(err.as_ref()) {
+                    // Our view is stale, so the primary we wrote to may have
+                    // been failed over. Retry under the current epoch rather
+                    // than treating the backup as lagging.
+                    return Err(err);
                 }
+                self.mark_lagging(inode);
+                self.redrive(op, inode, &primary_addr, &backup_addr, f)
+                    .await;
+            }
+            Ok(res)
+        })
+        .await
+    }
+
+    // Makes a new inode on the primary alone, then copies it to the backup
+    // under the primary's number and links it there as `name` of `parent`,
+    // if it has a name yet. Were the replicas to number new inodes each on
+    // their own, two creates racing on them could give one number to
+    // different files. `made` picks the new inode out of the primary's
+    // result. Only the primary's side is retried.
+    async fn fan_out_new<T, F, Fut>(
+        &self,
+        op: &str,
+        req: &FileRequest,
+        parent: u64,
+        name: Option<&OsStr>,
+        f: F,
+        made: fn(&T) -> Option<(u64, FileType)>,
+    ) -> TritonFileResult<T>
+    where
+        F: Fn(Box<dyn Storage>) -> Fut,
+        Fut: Future<Output = TritonFileResult<T>>,
+    {
+        let f = &f;
+        let (res, primary_addr, backup_addr) = self
+            .with_retry(op, || async move {
+                let (primary_addr, primary) = self.pick_store(1).await?;
+                let backup_addr = match self.pick_store(2).await {
+                    Ok((backup_addr, _)) => Some(backup_addr),
+                    Err(err) if is_no_live_store(err.as_ref()) => None,
+                    Err(err) => return Err(err),
+                };
+                Ok((f(primary).await?, primary_addr, backup_addr))
+            })
+            .await?;
+        self.note_version(&primary_addr, parent).await;
+        let (inode, kind) = match made(&res) {
+            Some(new) => new,
+            None => return Ok(res),
+        };
+        let backup_addr = match backup_addr {
+            Some(backup_addr) => backup_addr,
+            None => {
+                info!("{} on {} without a backup", op, primary_addr);
+                return Ok(res);
+            }
+        };
+
+        for attempt in 0..=REDRIVE_ATTEMPTS {
+            if attempt > 0 {
+                tokio::time::sleep(self.policy.backoff(attempt)).await;
+            }
+            let copied = tokio::time::timeout(
+                self.policy.attempt_timeout,
+                self.copy_new(req, &primary_addr, &backup_addr, parent, name, inode, kind),
+            )
+            .await;
+            match copied {
+                Ok(Ok(())) => return Ok(res),
+                Ok(Err(err)) => info!("{} on backup {} failed: {}", op, backup_addr, err),
+                Err(_) => info!("{} on backup {} timed out", op, backup_addr),
+            }
+        }
+        self.mark_lagging(parent);
+        self.mark_lagging(inode);
+        self.record_repair(op, inode, &primary_addr, &backup_addr)
+            .await;
+        Ok(res)
+    }
+
+    // Copies the new `inode` from the primary to the backup and links it as
+    // `name` of `parent` there.
+    #[allow(clippy::too_many_arguments)]
+    async fn copy_new(
+        &self,
+        req: &FileRequest,
+        primary_addr: &str,
+        backup_addr: &str,
+        parent: u64,
+        name: Option<&OsStr>,
+        inode: u64,
+        kind: FileType,
+    ) -> TritonFileResult<()> {
+        let nodes = self.client(primary_addr)?.get_nodes(&[inode]).await?;
+        if nodes.is_empty() {
+            // Removed from the primary already; so is its entry, if any.
+            return Ok(());
+        }
+        let backup = self.client(backup_addr)?;
+        backup.restore_nodes(nodes, &[]).await?;
+        let name = match name {
+            Some(name) => name,
+            None => return Ok(()),
+        };
+        match backup
+            .set_entry(req, parent, name, Some((inode, kind)), None, req.uid)
+            .await?
+        {
+            SUCCESS => Ok(()),
+            // Linked by an earlier attempt that timed out.
+            libc::EEXIST
+                if backup.get_entry(req, parent, name).await?.0.map(|(ino, _)| ino)
+                    == Some(inode) =>
+            {
+                Ok(())
+            }
+            code => Err(Box::new(TritonFileError::UserInterfaceError(code))),
+        }
+    }
+
+    // Re-applies a mutation the backup missed, a bounded number of times. If
+    // the backup still lags behind, it is recorded for repair.
+    async fn redrive<T, F, Fut>(
+        &self,
+        op: &str,
+        inode: u64,
+        primary_addr: &str,
+        backup_addr: &str,
+        f: &F,
+    ) where
+        F: Fn(Box<dyn Storage>) -> Fut,
+        Fut: Future<Output = TritonFileResult<T>>,
+    {
+        for attempt in 1..=REDRIVE_ATTEMPTS {
+            tokio::time::sleep(self.policy.backoff(attempt)).await;
+            let backup = match self.client(backup_addr) {
+                Ok(backup) => backup,
+                Err(_) => break,
             };
+            if let Ok(Ok(_)) = tokio::time::timeout(self.policy.attempt_timeout, f(backup)).await {
+                return;
+            }
+        }
+        self.record_repair(op, inode, primary_addr, backup_addr)
+            .await;
+    }
 
-            let backup = self.backup_store().await?;
-            let mut backup_log = if self.simple {
-                match backup.get(key).await {
-                    Err(err) => {
-                        // info!("Error getting backup {:#?}", err);
-                        continue;
-                    }
-                    Ok(Some(val)) => vec![val],
-                    Ok(None) => vec![],
+    // Marks a backup that missed a mutation of inode suspect, so that reads
+    // and further mutations skip it, and appends the inode to the repair log
+    // on the primary for the keeper to bring the replicas back in sync.
+    async fn record_repair(&self, op: &str, inode: u64, primary_addr: &str, backup_addr: &str) {
+        self.membership.mark_suspect(backup_addr);
+        let record = RepairRecord {
+            backend: backup_addr.to_string(),
+            bin: self.prefix.clone(),
+            inode,
+            op: op.to_string(),
+            clock: self.incr_clock(),
+        };
+        info!("backup {} lags behind, recording {:?}", backup_addr, record);
+        let recorded = match (self.client(primary_addr), serde_json::to_string(&record)) {
+            (Ok(primary), Ok(value)) => primary
+                .list_append(&KeyValue {
+                    key: KEY_REPAIR_LOG.to_string(),
+                    value,
+                })
+                .await
+                .is_ok(),
+            _ => false,
+        };
+        if !recorded {
+            info!("can't record {:?} in the repair log", record);
+        }
+    }
+
+    // Runs a read of inode against the replica(s) chosen by the read policy.
+    async fn read_from<T, F, Fut>(&self, op: &str, inode: u64, f: F) -> TritonFileResult<T>
+    where
+        F: Fn(Box<dyn Storage>) -> Fut,
+        Fut: Future<Output = TritonFileResult<T>>,
+    {
+        let f = &f;
+        self.with_retry(op, || async move {
+            // Reads of an inode whose backup missed a mutation stay on the
+            // primary for a while, whatever the policy.
+            if self.read_policy == ReadPolicy::PrimaryOnly || self.is_lagging(inode) {
+                let (addr, primary) = self.pick_store(1).await?;
+                let res = self.timed(&addr, f(primary)).await?;
+                self.note_version(&addr, inode).await;
+                return Ok(res);
+            }
+            match self.read_policy {
+                ReadPolicy::Hedged { percentile } => self.hedged_read(percentile, f).await,
+                ReadPolicy::AnyWithVersionCheck { max_lag } => {
+                    self.version_checked_read(inode, max_lag, f).await
                 }
-            } else {
-                match backup.list_get(key).await {
-                    Err(err) => {
-                        // info!("Error getting backup {:#?}", err);
-                        continue;
-                    }
-                    Ok(ret) => ret.0,
+                ReadPolicy::ReadRepair => self.repairing_read(op, inode, f).await,
+                _ => {
+                    let (addr, store, _) = self.nearest_store().await?;
+                    self.timed(&addr, f(store)).await
+                }
+            }
+        })
+        .await
+    }
+
+    fn mark_lagging(&self, inode: u64) {
+        let mut lagging = self.lagging.lock().unwrap();
+        make_room(&mut lagging, |at| *at);
+        lagging.insert(inode, Instant::now());
+    }
+
+    fn is_lagging(&self, inode: u64) -> bool {
+        let mut lagging = self.lagging.lock().unwrap();
+        match lagging.get(&inode) {
+            Some(at) if at.elapsed() < LAGGING_READ_WINDOW => true,
+            Some(_) => {
+                lagging.remove(&inode);
+                false
+            }
+            None => false,
+        }
+    }
+
+    // Awaits fut and records how long the backend at addr took to answer.
+    async fn timed<T, Fut>(&self, addr: &str, fut: Fut) -> TritonFileResult<T>
+    where
+        Fut: Future<Output = TritonFileResult<T>>,
+    {
+        let start = Instant::now();
+        let res = fut.await;
+        if res.is_ok() {
+            self.latencies.record(addr, start.elapsed());
+        }
+        res
+    }
+
+    // Returns whichever of primary and backup answered fastest recently, and
+    // whether it is the primary. A replica without samples counts as fastest
+    // so that it gets measured.
+    async fn nearest_store(&self) -> TritonFileResult<(String, Box<dyn Storage>, bool)> {
+        let (primary_addr, primary) = self.pick_store(1).await?;
+        let (backup_addr, backup) = match self.pick_store(2).await {
+            Ok(backup) => backup,
+            Err(_) => return Ok((primary_addr, primary, true)),
+        };
+        let primary_latency = self.latencies.mean(&primary_addr).unwrap_or_default();
+        let backup_latency = self.latencies.mean(&backup_addr).unwrap_or_default();
+        if backup_latency < primary_latency {
+            Ok((backup_addr, backup, false))
+        } else {
+            Ok((primary_addr, primary, true))
+        }
+    }
+
+    // Sends the read to the primary, and to the backup as well if the
+    // primary is slower than its usual percentile latency. The first
+    // successful answer wins.
+    async fn hedged_read<T, F, Fut>(&self, percentile: f64, f: &F) -> TritonFileResult<T>
+    where
+        F: Fn(Box<dyn Storage>) -> Fut,
+        Fut: Future<Output = TritonFileResult<T>>,
+    {
+        let (primary_addr, primary) = self.pick_store(1).await?;
+        let delay = self
+            .latencies
+            .percentile(&primary_addr, percentile)
+            .unwrap_or(DEFAULT_HEDGE_DELAY);
+        let primary_fut = self.timed(&primary_addr, f(primary));
+        tokio::pin!(primary_fut);
+        tokio::select! {
+            res = &mut primary_fut => return res,
+            _ = tokio::time::sleep(delay) => (),
+        }
+
+        let (backup_addr, backup) = match self.pick_store(2).await {
+            Ok(backup) => backup,
+            Err(_) => return primary_fut.await,
+        };
+        info!("hedging read to {} after {:?}", backup_addr, delay);
+        let backup_fut = self.timed(&backup_addr, f(backup));
+        tokio::pin!(backup_fut);
+        let first = tokio::select! {
+            res = &mut primary_fut => (res, true),
+            res = &mut backup_fut => (res, false),
+        };
+        match first {
+            (Ok(res), _) => Ok(res),
+            (Err(_), true) => backup_fut.await,
+            (Err(_), false) => primary_fut.await,
+        }
+    }
+
+    // Reads from the nearest replica. An answer from the backup is only used
+    // if the backup's version of inode is at most max_lag behind the newest
+    // version seen so far, by mutations and reads alike; otherwise the
+    // primary is asked.
+    async fn version_checked_read<T, F, Fut>(
+        &self,
+        inode: u64,
+        max_lag: u64,
+        f: &F,
+    ) -> TritonFileResult<T>
+    where
+        F: Fn(Box<dyn Storage>) -> Fut,
+        Fut: Future<Output = TritonFileResult<T>>,
+    {
+        let (addr, store, is_primary) = self.nearest_store().await?;
+        if !is_primary {
+            let probe = self.client(&addr)?;
+            let (res, version) = tokio::join!(
+                self.timed(&addr, f(store)),
+                probe.getattr_versioned(&VERSION_PROBE_REQUEST, inode)
+            );
+            if let (Ok(res), Ok((_, _, version))) = (res, version) {
+                let seen = self.saw_version(inode, version);
+                if version + max_lag >= seen {
+                    return Ok(res);
                 }
+                info!(
+                    "backup {} is at version {} of {}, {} seen",
+                    addr, version, inode, seen
+                );
+            }
+        }
+        let (addr, primary) = self.pick_store(1).await?;
+        let res = self.timed(&addr, f(primary)).await?;
+        self.note_version(&addr, inode).await;
+        Ok(res)
+    }
+
+    // Records the version of inode on the primary at addr as seen, after a
+    // mutation of or a read from it, for version-checked reads to hold
+    // backups against. Only that policy needs versions.
+    async fn note_version(&self, addr: &str, inode: u64) {
+        if !matches!(self.read_policy, ReadPolicy::AnyWithVersionCheck { .. }) {
+            return;
+        }
+        let probe = match self.client(addr) {
+            Ok(probe) => probe,
+            Err(_) => return,
+        };
+        if let Ok((_, SUCCESS, version)) =
+            probe.getattr_versioned(&VERSION_PROBE_REQUEST, inode).await
+        {
+            self.saw_version(inode, version);
+        }
+    }
+
+    // Records version of inode as seen, and returns the newest seen.
+    fn saw_version(&self, inode: u64, version: u64) -> u64 {
+        let mut versions = self.versions.lock().unwrap();
+        make_room(&mut versions, |(_, at)| *at);
+        let seen = versions.entry(inode).or_insert((0, Instant::now()));
+        *seen = (cmp::max(seen.0, version), Instant::now());
+        seen.0
+    }
+
+    // Reads from both replicas, along with the version of inode on each,
+    // and answers with the replica holding the newer version. If only one
+    // of them has the inode, the primary is right: a mutation the primary
+    // failed is retried, so the backup can't be ahead of it by a create or
+    // an unlink. The stale replica is brought up to date in the background
+    // by copying the inode over, so divergence left by half-completed
+    // mutations heals without waiting for the keeper.
+    async fn repairing_read<T, F, Fut>(&self, op: &str, inode: u64, f: &F) -> TritonFileResult<T>
+    where
+        F: Fn(Box<dyn Storage>) -> Fut,
+        Fut: Future<Output = TritonFileResult<T>>,
+    {
+        let (primary_addr, primary) = self.pick_store(1).await?;
+        let (backup_addr, backup) = match self.pick_store(2).await {
+            Ok(backup) => backup,
+            Err(_) => return self.timed(&primary_addr, f(primary)).await,
+        };
+        let primary_probe = self.client(&primary_addr)?;
+        let backup_probe = self.client(&backup_addr)?;
+        let (primary_res, backup_res, primary_version, backup_version) = tokio::join!(
+            self.timed(&primary_addr, f(primary)),
+            self.timed(&backup_addr, f(backup)),
+            primary_probe.getattr_versioned(&VERSION_PROBE_REQUEST, inode),
+            backup_probe.getattr_versioned(&VERSION_PROBE_REQUEST, inode)
+        );
+        // None if the replica could not be asked, Some(None) if it has no
+        // such inode.
+        let version = |probe: TritonFileResult<(Option<FileAttr>, c_int, u64)>| match probe {
+            Ok((_, SUCCESS, version)) => Some(Some(version)),
+            Ok((_, libc::ENOENT, _)) => Some(None),
+            _ => None,
+        };
+        let stale = match (version(primary_version), version(backup_version)) {
+            (Some(Some(p)), Some(Some(b))) if b > p => {
+                self.saw_version(inode, b);
+                Some((&backup_addr, &primary_addr))
+            }
+            (Some(Some(p)), Some(b)) if b.is_none_or(|b| b < p) => {
+                Some((&primary_addr, &backup_addr))
+            }
+            (Some(None), Some(Some(_))) => Some((&primary_addr, &backup_addr)),
+            _ => None,
+        };
+        let (from, to) = match stale {
+            Some(stale) => stale,
+            None => return primary_res.or(backup_res),
+        };
+        info!("{} of {}: {} is stale, repairing it from {}", op, inode, to, from);
+        self.spawn_repair(inode, from, to);
+        if from == &backup_addr {
+            backup_res.or(primary_res)
+        } else {
+            primary_res.or(backup_res)
+        }
+    }
+
+    // Copies inode from one backend to another in the background, keeping
+    // its number, or removes it from the other if the first has none.
+    fn spawn_repair(&self, inode: u64, from_addr: &str, to_addr: &str) {
+        let (from, to) = match (self.client(from_addr), self.client(to_addr)) {
+            (Ok(from), Ok(to)) => (from, to),
+            _ => return,
+        };
+        let to_addr = to_addr.to_string();
+        tokio::spawn(async move {
+            let repair = async {
+                let nodes = from.get_nodes(&[inode]).await?;
+                let remove = if nodes.is_empty() { vec![inode] } else { vec![] };
+                to.restore_nodes(nodes, &remove).await
             };
-            // Union also removes all duplicates.
-            union(&mut primary_log, &mut backup_log);
-            let mut ops = decode_ops(primary_log)?;
-            sort_ops(&mut ops);
-            // info!("Read: {}:{}", &key, ops.len());
-            return Ok(ops);
+            if let Err(err) = repair.await {
+                info!("read repair of {} on {} failed: {}", inode, to_addr, err);
+            }
+        });
+    }
+
+    // Reads the raw log of key from a store.
+    async fn read_log(&self, store: &dyn Storage, key: &str) -> TritonFileResult<Vec<String>> {
+        if self.simple {
+            Ok(store.get(key).await?.into_iter().collect())
+        } else {
+            Ok(store.list_get(key).await?.0)
         }
     }
 
+    // Get sorted ops for key, key should be already composed.
+    async fn get_sorted_ops(&self, key: &str) -> TritonFileResult<Vec<LogOp>> {
+        let (mut primary_log, mut backup_log) = self
+            .with_retry("get_sorted_ops", || async move {
+                let primary = self.primary_store().await?;
+                let primary_log = self.read_log(primary.as_ref(), key).await?;
+                let backup_log = match self.backup_store().await {
+                    Ok(backup) => self.read_log(backup.as_ref(), key).await?,
+                    // The only live backend has had every write since the
+                    // other left.
+                    Err(err) if is_no_live_store(err.as_ref()) => vec![],
+                    Err(err) => return Err(err),
+                };
+                Ok((primary_log, backup_log))
+            })
+            .await?;
+        // Union also removes all duplicates.
+        union(&mut primary_log, &mut backup_log);
+        let mut ops = decode_ops(primary_log)?;
+        sort_ops(&mut ops);
+        // info!("Read: {}:{}", &key, ops.len());
+        Ok(ops)
+    }
+
     // Returns self.prefix:kind:key.
     fn compose_key(&self, kind: KeyKind, key: &str) -> String {
         compose_key(&self.prefix, kind, key)
@@ -256,14 +794,49 @@ impl ReliableStore {
 
     async fn get_op_clock(
         &self,
-        primary: &Box<dyn Storage>,
-        backup: &Box<dyn Storage>,
+        primary: &dyn Storage,
+        backup: Option<&dyn Storage>,
     ) -> TritonFileResult<u64> {
         let mut op_clock = primary.clock(0).await?;
-        op_clock = backup.clock(op_clock).await?;
+        if let Some(backup) = backup {
+            op_clock = backup.clock(op_clock).await?;
+        }
         Ok(op_clock)
     }
 
+    // Appends `kv` to the log of the key as an op of `kind`, on the primary
+    // and the backup. With a single live backend it goes to that one alone,
+    // as mutations of files do (see fan_out).
+    async fn append_op(
+        &self,
+        op: &str,
+        kv: &storage::KeyValue,
+        kind: OpKind,
+    ) -> TritonFileResult<()> {
+        let kind = &kind;
+        self.with_retry(op, || async move {
+            let primary = self.primary_store().await?;
+            let backup = match self.backup_store().await {
+                Ok(backup) => Some(backup),
+                Err(err) if is_no_live_store(err.as_ref()) => None,
+                Err(err) => return Err(err),
+            };
+            let op_clock = self.get_op_clock(primary.as_ref(), backup.as_deref()).await?;
+            match backup {
+                Some(backup) => {
+                    let (primary_res, backup_res) = tokio::join!(
+                        self.append_to(primary, kv, op_clock, kind.clone()),
+                        self.append_to(backup, kv, op_clock, kind.clone())
+                    );
+                    primary_res?;
+                    backup_res
+                }
+                None => self.append_to(primary, kv, op_clock, kind.clone()).await,
+            }
+        })
+        .await
+    }
+
     async fn append_to<'a>(
         &'a self,
         store: Box<dyn Storage>,
@@ -316,36 +889,16 @@ impl storage::KeyString for ReliableStore {
     }
 
     async fn set(&self, kv: &storage::KeyValue) -> TritonFileResult<bool> {
-        loop {
-            let primary = self.primary_store().await?;
-            let backup = self.backup_store().await?;
-            let op_clock = self.get_op_clock(&primary, &backup).await?;
-            match self
-                .append_to(primary, kv, op_clock, OpKind::KeyString)
-                .await
-            {
-                Err(_) => continue,
-                Ok(_) => (),
-            }
-            match self
-                .append_to(backup, kv, op_clock, OpKind::KeyString)
-                .await
-            {
-                Err(_) => continue,
-                Ok(_) => break,
-            }
-        }
+        self.append_op("set", kv, OpKind::KeyString).await?;
         Ok(true)
     }
 
     async fn keys(&self, p: &storage::Pattern) -> TritonFileResult<storage::List> {
-        loop {
+        self.with_retry("keys", || async move {
             let primary = self.primary_store().await?;
-            match primary.keys(p).await {
-                Err(_) => continue,
-                Ok(result) => return Ok(result),
-            }
-        }
+            primary.keys(p).await
+        })
+        .await
     }
 }
 
@@ -372,60 +925,22 @@ impl storage::KeyList for ReliableStore {
     }
 
     async fn list_keys(&self, p: &storage::Pattern) -> TritonFileResult<storage::List> {
-        loop {
+        self.with_retry("list_keys", || async move {
             let primary = self.primary_store().await?;
-            match primary.list_keys(p).await {
-                Err(_) => continue,
-                Ok(result) => return Ok(result),
-            }
-        }
+            primary.list_keys(p).await
+        })
+        .await
     }
 
     async fn list_append(&self, kv: &storage::KeyValue) -> TritonFileResult<bool> {
-        loop {
-            let primary = self.primary_store().await?;
-            let backup = self.backup_store().await?;
-            let op_clock = self.get_op_clock(&primary, &backup).await?;
-            match self
-                .append_to(primary, kv, op_clock, OpKind::KeyList(ListOp::Append))
-                .await
-            {
-                Err(_) => continue,
-                Ok(_) => (),
-            }
-            match self
-                .append_to(backup, kv, op_clock, OpKind::KeyList(ListOp::Append))
-                .await
-            {
-                Err(_) => continue,
-                Ok(_) => break,
-            }
-        }
+        self.append_op("list_append", kv, OpKind::KeyList(ListOp::Append)).await?;
         Ok(true)
     }
 
     async fn list_remove(&self, kv: &storage::KeyValue) -> TritonFileResult<u32> {
         let prev_list = self.list_get(&kv.key).await?.0;
         let count = prev_list.iter().filter(|&x| x == &kv.value).count();
-        loop {
-            let primary = self.primary_store().await?;
-            let backup = self.backup_store().await?;
-            let op_clock = self.get_op_clock(&primary, &backup).await?;
-            match self
-                .append_to(primary, kv, op_clock, OpKind::KeyList(ListOp::Remove))
-                .await
-            {
-                Err(_) => continue,
-                Ok(_) => (),
-            }
-            match self
-                .append_to(backup, kv, op_clock, OpKind::KeyList(ListOp::Remove))
-                .await
-            {
-                Err(_) => continue,
-                Ok(_) => break,
-            }
-        }
+        self.append_op("list_remove", kv, OpKind::KeyList(ListOp::Remove)).await?;
         Ok(count as u32)
     }
 }
@@ -433,19 +948,119 @@ impl storage::KeyList for ReliableStore {
 #[async_trait]
 impl storage::Storage for ReliableStore {
     async fn clock(&self, at_least: u64) -> TritonFileResult<u64> {
-        loop {
+        self.with_retry("clock", || async move {
             let primary = self.primary_store().await?;
-            match primary.clock(at_least).await {
-                Err(_) => continue,
-                Ok(clk) => return Ok(clk),
-            }
-        }
+            primary.clock(at_least).await
+        })
+        .await
+    }
+
+    async fn get_entry(
+        &self,
+        req: &FileRequest,
+        parent: u64,
+        name: &OsStr,
+    ) -> TritonFileResult<(Option<(u64, FileType)>, c_int)> {
+        self.read_from("get_entry", parent, |store| async move {
+            store.get_entry(req, parent, name).await
+        })
+        .await
+    }
+
+    async fn set_entry(
+        &self,
+        req: &FileRequest,
+        parent: u64,
+        name: &OsStr,
+        entry: Option<(u64, FileType)>,
+        expect: Option<u64>,
+        owner: u32,
+    ) -> TritonFileResult<c_int> {
+        self.fan_out("set_entry", parent, |store| async move {
+            store
+                .set_entry(req, parent, name, entry, expect, owner)
+                .await
+        })
+        .await
+    }
+
+    async fn make_node(
+        &self,
+        req: &FileRequest,
+        parent: &FileAttr,
+        mode: u32,
+        flags: i32,
+    ) -> TritonFileResult<(Option<(FileAttr, u64)>, c_int)> {
+        self.fan_out_new(
+            "make_node",
+            req,
+            parent.ino,
+            None,
+            |store| async move { store.make_node(req, parent, mode, flags).await },
+            made_file,
+        )
+        .await
+    }
+
+    async fn drop_link(&self, req: &FileRequest, ino: u64) -> TritonFileResult<c_int> {
+        self.fan_out("drop_link", ino, |store| async move {
+            store.drop_link(req, ino).await
+        })
+        .await
     }
 }
 
 impl BinStore {
     pub fn new(addrs: Vec<String>) -> BinStore {
-        BinStore { addrs }
+        BinStore::with_read_policy(addrs, ReadPolicy::default())
+    }
+
+    pub fn with_read_policy(addrs: Vec<String>, read_policy: ReadPolicy) -> BinStore {
+        BinStore::with_keepers(addrs, vec![], read_policy)
+    }
+
+    /// A store that takes the backends answering its probes for alive, for
+    /// keepers to keep their state with: they publish the membership views
+    /// others follow, and must not wait for one naming the backends gone.
+    pub fn for_keeper(addrs: Vec<String>) -> BinStore {
+        BinStore::with_membership(addrs.clone(), Membership::probed(addrs), ReadPolicy::default())
+    }
+
+    /// A store that follows the membership view served by `keepers`, and
+    /// only probes the backends itself while no keeper can be reached.
+    pub fn with_keepers(
+        addrs: Vec<String>,
+        keepers: Vec<String>,
+        read_policy: ReadPolicy,
+    ) -> BinStore {
+        let store = BinStore::with_membership(addrs.clone(), Membership::new(addrs), read_policy);
+        if !keepers.is_empty() {
+            store.membership.spawn_watcher(keepers, store.pool.clone());
+        }
+        store
+    }
+
+    fn with_membership(
+        addrs: Vec<String>,
+        membership: Membership,
+        read_policy: ReadPolicy,
+    ) -> BinStore {
+        let pool = Arc::new(ClientPool::new());
+        let membership = Arc::new(membership);
+        membership.spawn_refresher(pool.clone());
+        BinStore {
+            addrs,
+            read_policy,
+            pool,
+            membership,
+            latencies: Arc::new(Latencies::new()),
+        }
+    }
+
+    /// The liveness view shared by every bin of this store, so that callers
+    /// holding a fresher view (e.g. from the keeper) can push it in.
+    pub fn membership(&self) -> Arc<Membership> {
+        self.membership.clone()
     }
 }
 
@@ -455,41 +1070,70 @@ pub fn hash_name_to_idx(name: &str, len: usize) -> usize {
     hasher.finish() as usize % len
 }
 
+/// The backends of bin `name` in the order they are tried: the ring of
+/// `addrs` starting at the bin's hash.
+pub fn ring(name: &str, addrs: &[String]) -> Vec<String> {
+    let length = addrs.len();
+    let idx = hash_name_to_idx(name, length);
+    (0..length).map(|i| addrs[(i + idx) % length].clone()).collect()
+}
+
+/// The primary and backup of bin `name` when only the backends in `live`
+/// are alive.
+pub fn assign(
+    name: &str,
+    addrs: &[String],
+    live: &[String],
+) -> (Option<String>, Option<String>) {
+    let mut replicas = ring(name, addrs)
+        .into_iter()
+        .filter(|addr| live.contains(addr));
+    (replicas.next(), replicas.next())
+}
+
 #[async_trait]
 impl storage::BinStorage for BinStore {
     async fn bin(&self, name: &str) -> TritonFileResult<Box<dyn Storage>> {
-        let length = self.addrs.len();
-        let idx: usize = hash_name_to_idx(name, length);
+        let idx: usize = hash_name_to_idx(name, self.addrs.len());
         // info!("Create bin for {} -> {}", &name, idx);
-        let mut new_addrs = Vec::new();
-        for i in 0..length {
-            new_addrs.push(self.addrs[(i + idx) % length].clone());
-        }
         Ok(Box::new(ReliableStore {
             prefix: name.to_string(),
+            namespace: (name != DEFAULT_NAMESPACE).then(|| name.to_string()),
             index: idx,
-            addrs: new_addrs,
+            pinned: None,
             clock: atomic::AtomicU64::new(0),
             simple: false,
+            policy: RetryPolicy::default(),
+            read_policy: self.read_policy.clone(),
+            pool: self.pool.clone(),
+            membership: self.membership.clone(),
+            latencies: self.latencies.clone(),
+            versions: Mutex::new(HashMap::new()),
+            lagging: Mutex::new(HashMap::new()),
         }))
     }
 }
 
 impl BinStore {
+    /// The bin keepers keep their state in. It stays on the backends the
+    /// store was created with when the cluster config changes, since that
+    /// config is itself kept in this bin.
     pub async fn keeper_bin(&self, name: &str) -> TritonFileResult<Box<dyn Storage>> {
-        let length = self.addrs.len();
-        let idx: usize = hash_name_to_idx(name, length);
-        let mut new_addrs = Vec::new();
-
-        for i in 0..length {
-            new_addrs.push(self.addrs[(i + idx) % length].clone());
-        }
+        let idx: usize = hash_name_to_idx(name, self.addrs.len());
         Ok(Box::new(ReliableStore {
             prefix: name.to_string(),
+            namespace: None,
             index: idx,
-            addrs: new_addrs,
+            pinned: Some(ring(name, &self.addrs)),
             clock: atomic::AtomicU64::new(0),
             simple: true,
+            policy: RetryPolicy::default(),
+            read_policy: self.read_policy.clone(),
+            pool: self.pool.clone(),
+            membership: self.membership.clone(),
+            latencies: self.latencies.clone(),
+            versions: Mutex::new(HashMap::new()),
+            lagging: Mutex::new(HashMap::new()),
         }))
     }
 }
@@ -513,21 +1157,10 @@ impl ServerFileSystem for ReliableStore {
     }
 
     async fn init(&self, _req: &FileRequest) -> TritonFileResult<c_int>{
-        loop {
-            let primary = self.primary_store().await?;
-            let backup = self.backup_store().await?;
-
-            match primary.init(_req).await
-            {
-                Err(_) => continue,
-                Ok(_) => (),
-            }
-            match backup.init(_req).await
-            {
-                Err(_) => continue,
-                Ok(res) => return Ok(res),
-            }
-        }
+        self.fan_out("init", FUSE_ROOT_ID, |store| async move {
+            store.init(_req).await
+        })
+        .await
     }
 
     async fn read(
@@ -540,16 +1173,12 @@ impl ServerFileSystem for ReliableStore {
         _flags: i32,
         _lock_owner: Option<u64>,
     ) -> TritonFileResult<(Option<String>, c_int)> {
-        loop {
-            let primary = self.primary_store().await?;
-            match primary
+        self.read_from("read", inode, |store| async move {
+            store
                 .read(_req, inode, fh, offset, size, _flags, _lock_owner)
                 .await
-            {
-                Err(_) => continue,
-                Ok(res) => return Ok(res),
-            }
-        }
+        })
+        .await
     }
 
     async fn write(
@@ -564,12 +1193,8 @@ impl ServerFileSystem for ReliableStore {
         _lock_owner: Option<u64>,
     ) -> TritonFileResult<(Option<u32>, c_int)> {
         info!("call bin storage write() {}", inode);
-        loop {
-            let primary = self.primary_store().await?;
-            let backup = self.backup_store().await?;
-
-            info!("primary call write() {}", inode);
-            match primary
+        self.fan_out("write", inode, |store| async move {
+            store
                 .write(
                     _req,
                     inode,
@@ -581,34 +1206,8 @@ impl ServerFileSystem for ReliableStore {
                     _lock_owner,
                 )
                 .await
-            {
-                Err(_) => {
-                    info!("binstorage prime write fail");
-                    continue
-                },
-                Ok(_) => (),
-            }
-
-            info!("backup call write() {}", inode);
-            match backup
-                .write(
-                    _req,
-                    inode,
-                    fh,
-                    offset,
-                    data,
-                    _write_flags,
-                    flags,
-                    _lock_owner,
-                )
-                .await
-            {
-                Err(_) => {
-                    info!("binstorage backup write fail");
-                    continue},
-                Ok(res) => return Ok(res),
-            }
-        }
+        })
+        .await
     }
 
     async fn lookup(
@@ -617,13 +1216,10 @@ impl ServerFileSystem for ReliableStore {
         parent: u64,
         name: &OsStr,
     ) -> TritonFileResult<(Option<FileAttr>, c_int)> {
-        loop {
-            let primary = self.primary_store().await?;
-            match primary.lookup(req, parent, name).await {
-                Err(_) => continue,
-                Ok(res) => return Ok(res),
-            }
-        }
+        self.read_from("lookup", parent, |store| async move {
+            store.lookup(req, parent, name).await
+        })
+        .await
     }
 
     async fn unlink(
@@ -632,18 +1228,10 @@ impl ServerFileSystem for ReliableStore {
         parent: u64,
         name: &OsStr,
     ) -> TritonFileResult<c_int> {
-        loop {
-            let primary = self.primary_store().await?;
-            let backup = self.backup_store().await?;
-            match primary.unlink(req, parent, name).await {
-                Err(_) => continue,
-                Ok(_) => (),
-            }
-            match backup.unlink(req, parent, name).await {
-                Err(_) => continue,
-                Ok(res) => return Ok(res),
-            }
-        }
+        self.fan_out("unlink", parent, |store| async move {
+            store.unlink(req, parent, name).await
+        })
+        .await
     }
 
     async fn create(
@@ -656,39 +1244,26 @@ impl ServerFileSystem for ReliableStore {
         flags: i32,
     ) -> TritonFileResult<(Option<(FileAttr, u64)>, c_int)> {
         info!("At binstorage create");
-        loop {
-            let primary = self.primary_store().await?;
-            let backup = self.backup_store().await?;
-
-            match primary.create(req, parent, name, mode, _umask, flags).await {
-                Err(e) => {
-                    info!{"{}", e};
-                    info!("binstorage primary create has error");
-                    continue},
-                Ok(_) => (),
-            }
-            match backup.create(req, parent, name, mode, _umask, flags).await {
-                Err(e) => {
-                    info!{"{}", e};
-                    info!("binstorage backup create has error");
-                    continue},
-                Ok(res) => return Ok(res),
-            }
-        }
+        self.fan_out_new(
+            "create",
+            req,
+            parent,
+            Some(name),
+            |store| async move { store.create(req, parent, name, mode, _umask, flags).await },
+            made_file,
+        )
+        .await
     }
+
     async fn getattr(
         &self,
         _req: &FileRequest,
         ino: u64,
     ) -> TritonFileResult<(Option<FileAttr>, c_int)> {
-        loop {
-            // info!("bin storage getattr");
-            let primary = self.primary_store().await?;
-            match primary.getattr(_req, ino).await {
-                Err(_) => continue,
-                Ok(res) => return Ok(res),
-            }
-        }
+        self.read_from("getattr", ino, |store| async move {
+            store.getattr(_req, ino).await
+        })
+        .await
     }
 
     async fn open(
@@ -697,18 +1272,10 @@ impl ServerFileSystem for ReliableStore {
         _ino: u64,
         _flags: i32,
     ) -> TritonFileResult<(Option<(u64, u32)>, c_int)> {
-        loop {
-            let primary = self.primary_store().await?;
-            let backup = self.backup_store().await?;
-            match primary.open(_req, _ino, _flags).await {
-                Err(_) => continue,
-                Ok(_) => (),
-            }
-            match backup.open(_req, _ino, _flags).await {
-                Err(_) => continue,
-                Ok(res) => return Ok(res),
-            }
-        }
+        self.fan_out("open", _ino, |store| async move {
+            store.open(_req, _ino, _flags).await
+        })
+        .await
     }
 
     async fn release(
@@ -720,24 +1287,12 @@ impl ServerFileSystem for ReliableStore {
         _lock_owner: Option<u64>,
         _flush: bool,
     ) -> TritonFileResult<c_int> {
-        loop {
-            let primary = self.primary_store().await?;
-            let backup = self.backup_store().await?;
-            match primary
-                .release(_req, _ino, _fh, _flags, _lock_owner, _flush)
-                .await
-            {
-                Err(_) => continue,
-                Ok(_) => (),
-            }
-            match backup
+        self.fan_out("release", _ino, |store| async move {
+            store
                 .release(_req, _ino, _fh, _flags, _lock_owner, _flush)
                 .await
-            {
-                Err(_) => continue,
-                Ok(res) => return Ok(res),
-            }
-        }
+        })
+        .await
     }
 
     async fn setxattr(
@@ -749,24 +1304,12 @@ impl ServerFileSystem for ReliableStore {
         flags: i32,
         position: u32,
     ) -> TritonFileResult<(c_int)> {
-        loop {
-            let primary = self.primary_store().await?;
-            let backup = self.backup_store().await?;
-            match primary
+        self.fan_out("setxattr", ino, |store| async move {
+            store
                 .setxattr(_req, ino, name, _value, flags, position)
                 .await
-            {
-                Err(_) => continue,
-                Ok(_) => (),
-            }
-            match backup
-                .setxattr(_req, ino, name, _value, flags, position)
-                .await
-            {
-                Err(_) => continue,
-                Ok(res) => return Ok(res),
-            }
-        }
+        })
+        .await
     }
 
     //reply Vec<u8> as string
@@ -777,13 +1320,11 @@ impl ServerFileSystem for ReliableStore {
         name: &OsStr,
         size: u32,
     ) -> TritonFileResult<(Option<(String, u32)>, c_int)> {
-        loop {
+        self.with_retry("getxattr", || async move {
             let primary = self.primary_store().await?;
-            match primary.getxattr(_req, ino, name, size).await {
-                Err(_) => continue,
-                Ok(res) => return Ok(res),
-            }
-        }
+            primary.getxattr(_req, ino, name, size).await
+        })
+        .await
     }
 
     async fn listxattr(
@@ -792,23 +1333,19 @@ impl ServerFileSystem for ReliableStore {
         ino: u64,
         size: u32,
     ) -> TritonFileResult<(Option<(String, u32)>, c_int)> {
-        loop {
+        self.with_retry("listxattr", || async move {
             let primary = self.primary_store().await?;
-            match primary.listxattr(_req, ino, size).await {
-                Err(_) => continue,
-                Ok(res) => return Ok(res),
-            }
-        }
+            primary.listxattr(_req, ino, size).await
+        })
+        .await
     }
 
     async fn access(&self, _req: &FileRequest, ino: u64, mask: i32) -> TritonFileResult<(c_int)> {
-        loop {
+        self.with_retry("access", || async move {
             let primary = self.primary_store().await?;
-            match primary.access(_req, ino, mask).await {
-                Err(_) => continue,
-                Ok(res) => return Ok(res),
-            }
-        }
+            primary.access(_req, ino, mask).await
+        })
+        .await
     }
 
     async fn rename(
@@ -820,24 +1357,12 @@ impl ServerFileSystem for ReliableStore {
         newname: &OsStr,
         flags: u32,
     ) -> TritonFileResult<c_int> {
-        loop {
-            let primary = self.primary_store().await?;
-            let backup = self.backup_store().await?;
-            match primary
-                .rename(_req, parent, name, newparent, newname, flags)
-                .await
-            {
-                Err(_) => continue,
-                Ok(_) => (),
-            }
-            match backup
+        self.fan_out("rename", parent, |store| async move {
+            store
                 .rename(_req, parent, name, newparent, newname, flags)
                 .await
-            {
-                Err(_) => continue,
-                Ok(res) => return Ok(res),
-            }
-        }
+        })
+        .await
     }
 
     async fn setattr(
@@ -857,30 +1382,15 @@ impl ServerFileSystem for ReliableStore {
         _bkuptime: Option<SystemTime>,
         flags: Option<u32>,
     ) -> TritonFileResult<(Option<FileAttr>, c_int)> {
-        loop {
-            let primary = self.primary_store().await?;
-            let backup = self.backup_store().await?;
-            match primary
+        self.fan_out("setattr", ino, |store| async move {
+            store
                 .setattr(
                     _req, ino, mode, uid, gid, size, _atime, _mtime, _ctime, fh, _crtime, _chgtime,
                     _bkuptime, flags,
                 )
                 .await
-            {
-                Err(_) => continue,
-                Ok(_) => (),
-            }
-            match backup
-                .setattr(
-                    _req, ino, mode, uid, gid, size, _atime, _mtime, _ctime, fh, _crtime, _chgtime,
-                    _bkuptime, flags,
-                )
-                .await
-            {
-                Err(_) => continue,
-                Ok(res) => return Ok(res),
-            }
-        }
+        })
+        .await
     }
 
     async fn opendir(
@@ -889,18 +1399,10 @@ impl ServerFileSystem for ReliableStore {
         inode: u64,
         flags: i32,
     ) -> TritonFileResult<(Option<(u64, u32)>, c_int)>{
-        loop {
-            let primary = self.primary_store().await?;
-            let backup = self.backup_store().await?;
-            match primary.opendir(req, inode, flags).await {
-                Err(_) => continue,
-                Ok(_) => (),
-            }
-            match backup.opendir(req, inode, flags).await {
-                Err(_) => continue,
-                Ok(res) => return Ok(res),
-            }
-        }
+        self.fan_out("opendir", inode, |store| async move {
+            store.opendir(req, inode, flags).await
+        })
+        .await
     }
 
     async fn readdir(
@@ -910,13 +1412,11 @@ impl ServerFileSystem for ReliableStore {
         _fh: u64,
         offset: i64,
     ) -> TritonFileResult<(Option<(u64, i64, FileType, DataList)>, c_int)>{
-        loop {
+        self.with_retry("readdir", || async move {
             let primary = self.primary_store().await?;
-            match primary.readdir(_req, inode, _fh, offset).await {
-                Err(_) => continue,
-                Ok(res) => return Ok(res),
-            }
-        }
+            primary.readdir(_req, inode, _fh, offset).await
+        })
+        .await
     }
 
     async fn releasedir(
@@ -926,24 +1426,10 @@ impl ServerFileSystem for ReliableStore {
         _fh: u64,
         _flags: i32,
     ) -> TritonFileResult<c_int> {
-        loop {
-            let primary = self.primary_store().await?;
-            let backup = self.backup_store().await?;
-            match primary
-                .releasedir(_req, inode, _fh, _flags)
-                .await
-            {
-                Err(_) => continue,
-                Ok(_) => (),
-            }
-            match backup
-                .releasedir(_req, inode, _fh, _flags)
-                .await
-            {
-                Err(_) => continue,
-                Ok(res) => return Ok(res),
-            }
-        }
+        self.fan_out("releasedir", inode, |store| async move {
+            store.releasedir(_req, inode, _fh, _flags).await
+        })
+        .await
     }
 
     async fn mkdir(
@@ -954,23 +1440,38 @@ impl ServerFileSystem for ReliableStore {
         mut mode: u32,
         _umask: u32,
     ) -> TritonFileResult<(Option<FileAttr>, c_int)>{
-        loop {
-            let primary = self.primary_store().await?;
-            let backup = self.backup_store().await?;
-            match primary
-                .mkdir(req, parent, name, mode, _umask)
-                .await
-            {
-                Err(_) => continue,
-                Ok(_) => (),
-            }
-            match backup
-            .mkdir(req, parent, name, mode, _umask)
-            .await
-            {
-                Err(_) => continue,
-                Ok(res) => return Ok(res),
-            }
+        self.fan_out_new(
+            "mkdir",
+            req,
+            parent,
+            Some(name),
+            |store| async move { store.mkdir(req, parent, name, mode, _umask).await },
+            made_dir,
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::time::{Duration, Instant};
+
+    use super::{make_room, TRACKED_INODES};
+
+    #[test]
+    fn test_make_room_forgets_oldest() {
+        let start = Instant::now();
+        let mut map = HashMap::new();
+        for ino in 0..TRACKED_INODES as u64 * 3 {
+            make_room(&mut map, |at| *at);
+            map.insert(ino, start + Duration::from_millis(ino));
         }
+        assert!(map.len() <= TRACKED_INODES);
+        // the newest inode and those just before it are kept
+        let last = TRACKED_INODES as u64 * 3 - 1;
+        assert!(map.contains_key(&last));
+        assert!(map.contains_key(&(last - 100)));
+        assert!(!map.contains_key(&0));
     }
 }
@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::time::Instant;
+
+use fuser::FileAttr;
+use tribbler::config::CacheConfig;
+
+/// The attributes and directory entries a front-end has looked up
+/// recently, each kept until its TTL runs out or the front-end changes it.
+///
+/// Entries are shared by every caller of the front-end. That is safe as
+/// long as the kernel checks permissions itself, which it does for shared
+/// namespaces; in a private one every request is made as the same user.
+pub struct AttrCache {
+    config: CacheConfig,
+    attrs: HashMap<u64, (FileAttr, Instant)>,
+    // None marks a name known to be missing.
+    entries: HashMap<(u64, OsString), (Option<u64>, Instant)>,
+}
+
+/// What the cache knows about a name in a directory.
+#[derive(Debug, PartialEq)]
+pub enum Cached {
+    /// The name is there, with these attributes.
+    Found(FileAttr),
+    /// The name is known to be missing.
+    Missing,
+    /// Nothing, or nothing fresh enough: ask the backends.
+    Unknown,
+}
+
+impl AttrCache {
+    pub fn new(config: CacheConfig) -> AttrCache {
+        AttrCache {
+            config,
+            attrs: HashMap::new(),
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn config(&self) -> &CacheConfig {
+        &self.config
+    }
+
+    /// The attributes of `ino`, if they are fresh.
+    pub fn attr(&self, ino: u64) -> Option<FileAttr> {
+        match self.attrs.get(&ino) {
+            Some((attr, expires)) if *expires > Instant::now() => Some(*attr),
+            _ => None,
+        }
+    }
+
+    /// What entry `name` of `parent` was found to be. A name found there
+    /// only counts while the attributes of its inode are fresh too.
+    pub fn lookup(&self, parent: u64, name: &OsStr) -> Cached {
+        match self.entries.get(&(parent, name.to_os_string())) {
+            Some((entry, expires)) if *expires > Instant::now() => match entry {
+                Some(ino) => match self.attr(*ino) {
+                    Some(attr) => Cached::Found(attr),
+                    None => Cached::Unknown,
+                },
+                None => Cached::Missing,
+            },
+            _ => Cached::Unknown,
+        }
+    }
+
+    pub fn insert_attr(&mut self, attr: &FileAttr) {
+        self.make_room();
+        let expires = Instant::now() + self.config.attr_ttl();
+        self.attrs.insert(attr.ino, (*attr, expires));
+    }
+
+    /// Records that entry `name` of `parent` is `attr`'s inode.
+    pub fn insert_entry(&mut self, parent: u64, name: &OsStr, attr: &FileAttr) {
+        self.insert_attr(attr);
+        let expires = Instant::now() + self.config.entry_ttl();
+        self.entries
+            .insert((parent, name.to_os_string()), (Some(attr.ino), expires));
+    }
+
+    /// Records that `parent` has no entry `name`.
+    pub fn insert_missing(&mut self, parent: u64, name: &OsStr) {
+        self.make_room();
+        let expires = Instant::now() + self.config.negative_ttl();
+        self.entries
+            .insert((parent, name.to_os_string()), (None, expires));
+    }
+
+    /// Forgets the attributes of `ino`.
+    pub fn invalidate(&mut self, ino: u64) {
+        self.attrs.remove(&ino);
+    }
+
+    /// Forgets entry `name` of `parent`, and the attributes of the inode it
+    /// pointed at, whose link count changes with it.
+    pub fn invalidate_entry(&mut self, parent: u64, name: &OsStr) {
+        if let Some((Some(ino), _)) = self.entries.remove(&(parent, name.to_os_string())) {
+            self.attrs.remove(&ino);
+        }
+    }
+
+    /// Forgets everything.
+    pub fn clear(&mut self) {
+        self.attrs.clear();
+        self.entries.clear();
+    }
+
+    // Drops what has expired once the cache is full, and everything if
+    // that is not enough.
+    fn make_room(&mut self) {
+        if self.attrs.len() + self.entries.len() < self.config.capacity {
+            return;
+        }
+        let now = Instant::now();
+        self.attrs.retain(|_, (_, expires)| *expires > now);
+        self.entries.retain(|_, (_, expires)| *expires > now);
+        if self.attrs.len() + self.entries.len() >= self.config.capacity {
+            self.attrs.clear();
+            self.entries.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::OsStr;
+    use std::time::{Duration, SystemTime};
+
+    use fuser::{FileAttr, FileType};
+    use tribbler::config::CacheConfig;
+
+    use super::{AttrCache, Cached};
+
+    fn attr(ino: u64) -> FileAttr {
+        FileAttr {
+            ino,
+            size: 0,
+            blocks: 0,
+            atime: SystemTime::UNIX_EPOCH,
+            mtime: SystemTime::UNIX_EPOCH,
+            ctime: SystemTime::UNIX_EPOCH,
+            crtime: SystemTime::UNIX_EPOCH,
+            kind: FileType::RegularFile,
+            perm: 0o644,
+            nlink: 1,
+            uid: 0,
+            gid: 0,
+            rdev: 0,
+            blksize: 512,
+            flags: 0,
+        }
+    }
+
+    #[test]
+    fn test_entries_expire_and_invalidate() {
+        let mut cache = AttrCache::new(CacheConfig {
+            attr_ttl_ms: 60_000,
+            entry_ttl_ms: 60_000,
+            negative_ttl_ms: 50,
+            capacity: 16,
+        });
+        let (a, b) = (OsStr::new("a"), OsStr::new("b"));
+        cache.insert_entry(1, a, &attr(2));
+        cache.insert_missing(1, b);
+        assert_eq!(Cached::Found(attr(2)), cache.lookup(1, a));
+        assert_eq!(Cached::Missing, cache.lookup(1, b));
+        assert_eq!(Cached::Unknown, cache.lookup(2, a));
+
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(Cached::Unknown, cache.lookup(1, b));
+        assert_eq!(Cached::Found(attr(2)), cache.lookup(1, a));
+
+        cache.invalidate_entry(1, a);
+        assert_eq!(Cached::Unknown, cache.lookup(1, a));
+        assert_eq!(None, cache.attr(2));
+
+        // a full cache starts over
+        for ino in 10..30 {
+            cache.insert_attr(&attr(ino));
+        }
+        assert!(cache.attrs.len() < 16);
+    }
+}
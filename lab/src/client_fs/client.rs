@@ -1,5 +1,8 @@
 use std::cmp::min;
+use std::collections::HashMap;
 use std::ffi::OsStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
 use std::time::UNIX_EPOCH;
 
 use fuser::FileType;
@@ -21,24 +24,70 @@ use std::time::SystemTime;
 use tokio::sync::Mutex;
 use tokio_stream::Stream;
 use tokio_stream::StreamExt;
+use tonic::codegen::InterceptedService;
+use tonic::metadata::MetadataValue;
+use tonic::service::Interceptor;
 use tonic::transport::{Channel, Endpoint};
-use tonic::Code;
+use tonic::{Code, Status};
 use tribbler::disfuser::disfuser_client::DisfuserClient;
 use tribbler::disfuser::{
     Access, Create, FRequest, Getattr, Getxattr, Listxattr, LookUp, Open, Read, Release, Rename,
     Setxattr, Unlink, Write, Setattr, OpenDir, ReadDir, ReleaseDir, MkDir
 };
-use tribbler::disfuser_server::slice_size;
-use tribbler::error::{TritonFileResult, SUCCESS};
+use tribbler::disfuser_server::{
+    slice_size, CLIENT_METADATA, EPOCH_METADATA, NAMESPACE_METADATA,
+};
+use tribbler::error::{TritonFileError, TritonFileResult, SUCCESS};
 use tribbler::rpc;
 use tribbler::rpc::trib_storage_client::TribStorageClient;
-use tribbler::storage::{self, FileRequest, ServerFileSystem};
+use tribbler::storage::{self, FileRequest, MembershipView, ServerFileSystem};
 use tribbler::storage::{KeyList, KeyString, Storage};
 
 pub const DEFAULT_LOCK_OWNER: u64 = 0;
 
+/// Names this process to the backends, which grant it leases and leave it
+/// out of the invalidations of its own changes.
+pub fn client_id() -> &'static str {
+    static ID: OnceLock<String> = OnceLock::new();
+    ID.get_or_init(|| format!("{:016x}", rand::random::<u64>()))
+}
+
 pub struct StorageClient {
     channel: Mutex<Channel>,
+    epoch: Arc<AtomicU64>,
+    // Namespace whose files the requests are about, if not the backend's
+    // own.
+    namespace: Option<String>,
+}
+
+/// Stamps every request with the caller's membership epoch, so backends can
+/// reject mutations made under a stale view. Epoch 0 means the caller never
+/// learned one and sends none. Requests of a namespaced client also name
+/// their namespace, and every request names the [client_id] it comes from.
+#[derive(Clone)]
+pub struct EpochInterceptor {
+    epoch: Arc<AtomicU64>,
+    namespace: Option<String>,
+}
+
+impl Interceptor for EpochInterceptor {
+    fn call(&mut self, mut request: tonic::Request<()>) -> Result<tonic::Request<()>, Status> {
+        let epoch = self.epoch.load(Ordering::SeqCst);
+        if epoch > 0 {
+            request
+                .metadata_mut()
+                .insert(EPOCH_METADATA, MetadataValue::from(epoch));
+        }
+        request
+            .metadata_mut()
+            .insert(CLIENT_METADATA, MetadataValue::from_static(client_id()));
+        if let Some(namespace) = &self.namespace {
+            let value = MetadataValue::from_str(namespace)
+                .map_err(|_| Status::invalid_argument("malformed namespace"))?;
+            request.metadata_mut().insert(NAMESPACE_METADATA, value);
+        }
+        Ok(request)
+    }
 }
 
 pub async fn new_client(addr: &str) -> TritonFileResult<Box<dyn Storage>> {
@@ -48,17 +97,87 @@ pub async fn new_client(addr: &str) -> TritonFileResult<Box<dyn Storage>> {
 impl StorageClient {
     pub fn new(addr: &str) -> TritonFileResult<StorageClient> {
         let channel = Endpoint::from_shared(format!("http://{}", addr))?.connect_lazy();
-        Ok(StorageClient {
+        Ok(StorageClient::from_channel(channel))
+    }
+
+    pub fn from_channel(channel: Channel) -> StorageClient {
+        StorageClient::with_epoch(channel, Arc::new(AtomicU64::new(0)))
+    }
+
+    /// A client whose requests carry whatever `epoch` currently holds.
+    pub fn with_epoch(channel: Channel, epoch: Arc<AtomicU64>) -> StorageClient {
+        StorageClient {
             channel: Mutex::new(channel),
-        })
+            epoch,
+            namespace: None,
+        }
     }
 
     // pub async fn client(&self) -> TribStorageClient<Channel> {
     //     TribStorageClient::new(self.channel.lock().await.clone())
     // }
 
-    pub async fn disfuser_client(&self) -> DisfuserClient<Channel> {
-        DisfuserClient::new(self.channel.lock().await.clone())
+    pub async fn disfuser_client(
+        &self,
+    ) -> DisfuserClient<InterceptedService<Channel, EpochInterceptor>> {
+        DisfuserClient::with_interceptor(
+            self.channel.lock().await.clone(),
+            EpochInterceptor {
+                epoch: Arc::clone(&self.epoch),
+                namespace: self.namespace.clone(),
+            },
+        )
+    }
+}
+
+/// Long-lived channels, one per backend address. Clients built from the pool
+/// share the underlying HTTP/2 connection instead of dialing a new one.
+#[derive(Default)]
+pub struct ClientPool {
+    channels: std::sync::Mutex<HashMap<String, Channel>>,
+    // Membership epoch stamped on requests of every client of the pool.
+    epoch: Arc<AtomicU64>,
+}
+
+impl ClientPool {
+    pub fn new() -> ClientPool {
+        ClientPool::default()
+    }
+
+    pub fn client(&self, addr: &str) -> TritonFileResult<Box<dyn Storage>> {
+        self.client_in(addr, None)
+    }
+
+    /// A client of `addr` for the files of `namespace`, or for the backend's
+    /// own files if there is none.
+    pub fn client_in(
+        &self,
+        addr: &str,
+        namespace: Option<&str>,
+    ) -> TritonFileResult<Box<dyn Storage>> {
+        let mut channels = self.channels.lock().map_err(|e| e.to_string())?;
+        let channel = match channels.get(addr) {
+            Some(channel) => channel.clone(),
+            None => {
+                let channel = Endpoint::from_shared(format!("http://{}", addr))?.connect_lazy();
+                channels.insert(addr.to_string(), channel.clone());
+                channel
+            }
+        };
+        Ok(Box::new(StorageClient {
+            channel: Mutex::new(channel),
+            epoch: Arc::clone(&self.epoch),
+            namespace: namespace.map(|name| name.to_string()),
+        }))
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.epoch.load(Ordering::SeqCst)
+    }
+
+    /// Moves the pool to `epoch` if it is newer than the one it has.
+    pub fn observe_epoch(&self, epoch: u64) {
+        self.epoch.fetch_max(epoch, Ordering::SeqCst);
     }
 }
 
@@ -213,7 +332,7 @@ impl ServerFileSystem for StorageClient {
 
         let mut init_request = client.init(Init{
             frequest: freq
-        }).await.unwrap().into_inner();
+        }).await?.into_inner();
         if init_request.errcode == SUCCESS{
             return Ok(SUCCESS);
         }
@@ -247,13 +366,12 @@ impl ServerFileSystem for StorageClient {
                 flags: _flags,
                 lock_owner: _lock_owner,
             })
-            .await
-            .unwrap()
+            .await?
             .into_inner();
         let mut received: Vec<String> = Vec::new();
         let mut error_code: c_int;
         while let Some(item) = stream.next().await {
-            let reply = item.unwrap();
+            let reply = item?;
             received.push(reply.message);
             error_code = reply.errcode;
             if error_code != SUCCESS {
@@ -309,7 +427,7 @@ impl ServerFileSystem for StorageClient {
         let result = match res{
             Ok(value) =>value,
             Err(e) => {
-                return Ok((None, libc::ENETDOWN));
+                return Err(e.into());
             }
         };
 
@@ -428,6 +546,15 @@ impl ServerFileSystem for StorageClient {
         _req: &FileRequest,
         ino: u64,
     ) -> TritonFileResult<(Option<FileAttr>, c_int)> {
+        let (attr, error_code, _) = self.getattr_versioned(_req, ino).await?;
+        Ok((attr, error_code))
+    }
+
+    async fn getattr_versioned(
+        &self,
+        _req: &FileRequest,
+        ino: u64,
+    ) -> TritonFileResult<(Option<FileAttr>, c_int, u64)> {
         let freq = FRequest {
             uid: _req.uid,
             gid: _req.gid,
@@ -444,11 +571,12 @@ impl ServerFileSystem for StorageClient {
         let getattr_reply = result.into_inner();
         let attr = getattr_reply.file_attr;
         let error_code = getattr_reply.errcode;
+        let version = getattr_reply.version.unwrap_or(0);
         if error_code != SUCCESS {
-            return Ok((None, error_code));
+            return Ok((None, error_code, version));
         }
         let fileattr = serde_json::from_str::<FileAttr>(&attr).unwrap();
-        Ok((Some(fileattr), SUCCESS))
+        Ok((Some(fileattr), SUCCESS, version))
     }
 
     async fn open(
@@ -569,13 +697,13 @@ impl ServerFileSystem for StorageClient {
                 name: name_string,
                 size: size,
             })
-            .await.unwrap().into_inner();
+            .await?.into_inner();
 
         let mut received: Vec<String> = Vec::new();
         let mut error_code: c_int;
         let mut size: u32 = 0; 
         while let Some(item) = stream.next().await {
-            let reply = item.unwrap();
+            let reply = item?;
             received.push(reply.data);
             size = reply.size; 
             error_code = reply.errcode;
@@ -607,13 +735,13 @@ impl ServerFileSystem for StorageClient {
             ino: ino,
             size: size,
         })
-        .await.unwrap().into_inner();
+        .await?.into_inner();
 
         let mut received: Vec<String> = Vec::new();
         let mut error_code: c_int;
         let mut size: u32 = 0;
         while let Some(item) = stream.next().await {
-            let reply = item.unwrap();
+            let reply = item?;
             received.push(reply.data);
             size = reply.size; 
             error_code = reply.errcode;
@@ -851,14 +979,18 @@ impl ServerFileSystem for StorageClient {
         let name = readdir_reply.name; 
 
         match ino{
-            Some(_) => {
-                let ino_ = ino.unwrap(); 
-                let offset_ = offset.unwrap();
-                let filetype_str = filetype.unwrap();
-                let name_str = name.unwrap();
+            Some(ino_) => {
+                let (offset_, filetype_str, name_str) = match (offset, filetype, name) {
+                    (Some(offset), Some(filetype), Some(name)) => (offset, filetype, name),
+                    _ => {
+                        return Err(Box::new(TritonFileError::RpcError(
+                            "incomplete readdir reply".to_string(),
+                        )))
+                    }
+                };
                 let name_ptr: Vec<u8> = serde_json::from_str(&name_str)?;
 
-                let filetype_ = serde_json::from_str::<FileType>(&filetype_str).unwrap();
+                let filetype_ = serde_json::from_str::<FileType>(&filetype_str)?;
                 let datalist_ = storage::DataList(name_ptr);
 
                 return  Ok((Some((ino_, offset_, filetype_, datalist_)), SUCCESS))
@@ -1033,4 +1165,244 @@ impl Storage for StorageClient {
             .await?;
         Ok(result.into_inner().timestamp)
     }
+
+    async fn fence(&self, view: &MembershipView) -> TritonFileResult<MembershipView> {
+        let mut client = self.disfuser_client().await;
+        let result = client
+            .fence(disfuser::MembershipView {
+                epoch: view.epoch,
+                live: view.live.clone(),
+                backs: view.backs.clone(),
+                config_version: Some(view.config_version),
+            })
+            .await?
+            .into_inner();
+        Ok(MembershipView {
+            epoch: result.epoch,
+            live: result.live,
+            backs: result.backs,
+            config_version: result.config_version.unwrap_or(0),
+        })
+    }
+
+    async fn digest(&self, lo: u64, hi: u64, leaves: bool) -> TritonFileResult<storage::Digest> {
+        let mut client = self.disfuser_client().await;
+        let result = client
+            .digest(disfuser::DigestRange { lo, hi, leaves })
+            .await?
+            .into_inner();
+        Ok(storage::Digest {
+            hash: result.hash,
+            count: result.count,
+            children: result.children,
+            inodes: result
+                .inodes
+                .into_iter()
+                .map(|d| (d.inode, d.hash))
+                .collect(),
+        })
+    }
+
+    async fn get_nodes(&self, inodes: &[u64]) -> TritonFileResult<Vec<storage::NodeCopy>> {
+        let mut client = self.disfuser_client().await;
+        let result = client
+            .get_nodes(disfuser::Inodes {
+                inodes: inodes.to_vec(),
+            })
+            .await?
+            .into_inner();
+        let mut nodes = vec![];
+        for node in result.nodes {
+            nodes.push(storage::NodeCopy {
+                attrs: serde_json::from_str::<InodeAttributes>(&node.attrs)?,
+                content: node.content,
+            });
+        }
+        Ok(nodes)
+    }
+
+    async fn restore_nodes(
+        &self,
+        nodes: Vec<storage::NodeCopy>,
+        remove: &[u64],
+    ) -> TritonFileResult<()> {
+        let mut client = self.disfuser_client().await;
+        client
+            .restore_nodes(disfuser::NodeCopies {
+                nodes: nodes
+                    .into_iter()
+                    .map(|node| disfuser::NodeCopy {
+                        attrs: serde_json::to_string(&node.attrs).unwrap(),
+                        content: node.content,
+                    })
+                    .collect(),
+                remove: remove.to_vec(),
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn get_entry(
+        &self,
+        req: &FileRequest,
+        parent: u64,
+        name: &OsStr,
+    ) -> TritonFileResult<(Option<(u64, FileType)>, c_int)> {
+        let mut client = self.disfuser_client().await;
+        let result = client
+            .get_entry(LookUp {
+                frequest: FRequest {
+                    uid: req.uid,
+                    gid: req.gid,
+                    pid: req.pid,
+                },
+                parent,
+                name: name.to_string_lossy().to_string(),
+            })
+            .await?
+            .into_inner();
+        match (result.ino, result.file_type) {
+            (Some(ino), Some(kind)) if result.errcode == SUCCESS => Ok((
+                Some((ino, serde_json::from_str::<FileType>(&kind)?)),
+                SUCCESS,
+            )),
+            _ => Ok((None, result.errcode)),
+        }
+    }
+
+    async fn set_entry(
+        &self,
+        req: &FileRequest,
+        parent: u64,
+        name: &OsStr,
+        entry: Option<(u64, FileType)>,
+        expect: Option<u64>,
+        owner: u32,
+    ) -> TritonFileResult<c_int> {
+        let mut client = self.disfuser_client().await;
+        let file_type = match entry {
+            Some((_, kind)) => Some(serde_json::to_string(&kind)?),
+            None => None,
+        };
+        let result = client
+            .set_entry(disfuser::SetEntry {
+                frequest: FRequest {
+                    uid: req.uid,
+                    gid: req.gid,
+                    pid: req.pid,
+                },
+                parent,
+                name: name.to_string_lossy().to_string(),
+                ino: entry.map(|(ino, _)| ino),
+                file_type,
+                expect,
+                owner,
+            })
+            .await?;
+        Ok(result.into_inner().errcode)
+    }
+
+    async fn make_node(
+        &self,
+        req: &FileRequest,
+        parent: &FileAttr,
+        mode: u32,
+        flags: i32,
+    ) -> TritonFileResult<(Option<(FileAttr, u64)>, c_int)> {
+        let mut client = self.disfuser_client().await;
+        let result = client
+            .make_node(disfuser::MakeNode {
+                frequest: FRequest {
+                    uid: req.uid,
+                    gid: req.gid,
+                    pid: req.pid,
+                },
+                parent_attr: serde_json::to_string(parent)?,
+                mode,
+                flags,
+            })
+            .await?
+            .into_inner();
+        if result.errcode != SUCCESS {
+            return Ok((None, result.errcode));
+        }
+        let attr = serde_json::from_str::<FileAttr>(&result.file_attr)?;
+        Ok((Some((attr, result.fh)), SUCCESS))
+    }
+
+    async fn drop_link(&self, req: &FileRequest, ino: u64) -> TritonFileResult<c_int> {
+        let mut client = self.disfuser_client().await;
+        let result = client
+            .drop_link(Getattr {
+                frequest: FRequest {
+                    uid: req.uid,
+                    gid: req.gid,
+                    pid: req.pid,
+                },
+                ino,
+            })
+            .await?;
+        Ok(result.into_inner().errcode)
+    }
+
+    async fn namespace(&self, name: &str) -> TritonFileResult<Box<dyn Storage>> {
+        Ok(Box::new(StorageClient {
+            channel: Mutex::new(self.channel.lock().await.clone()),
+            epoch: Arc::clone(&self.epoch),
+            namespace: Some(name.to_string()),
+        }))
+    }
+
+    async fn namespaces(&self, p: &storage::Pattern) -> TritonFileResult<storage::List> {
+        let mut client = self.disfuser_client().await;
+        let result = client
+            .namespaces(disfuser::Pattern {
+                prefix: p.prefix.clone(),
+                suffix: p.suffix.clone(),
+            })
+            .await?;
+        Ok(storage::List(result.into_inner().list))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::OsStr;
+    use tribbler::error::errno;
+    use tribbler::storage::FileRequest;
+
+    use super::ClientPool;
+
+    #[tokio::test]
+    async fn test_pool_reuses_channels() {
+        let pool = ClientPool::new();
+        pool.client("127.0.0.1:32621").unwrap();
+        pool.client_in("127.0.0.1:32621", Some("team")).unwrap();
+        assert_eq!(1, pool.channels.lock().unwrap().len());
+
+        pool.client("127.0.0.1:32622").unwrap();
+        assert_eq!(2, pool.channels.lock().unwrap().len());
+
+        // every client stamps the epoch the pool last observed
+        pool.observe_epoch(3);
+        pool.observe_epoch(2);
+        assert_eq!(3, pool.epoch());
+    }
+
+    #[tokio::test]
+    async fn test_dead_backend_errors() {
+        // nothing listens there
+        let client = ClientPool::new().client("127.0.0.1:32626").unwrap();
+        let req = FileRequest {
+            uid: 0,
+            gid: 0,
+            pid: 0,
+        };
+        let err = client.init(&req).await.unwrap_err();
+        assert_eq!(libc::ENETDOWN, errno(err.as_ref()));
+        assert!(client.read(&req, 1, 0, 0, 16, 0, None).await.is_err());
+        assert!(client.getxattr(&req, 1, OsStr::new("user.a"), 16).await.is_err());
+        assert!(client.listxattr(&req, 1, 16).await.is_err());
+        assert!(client.readdir(&req, 1, 0, 0).await.is_err());
+    }
 }
@@ -0,0 +1,258 @@
+//! What the requests a front-end runs at once share: the bin its files are
+//! in, and what it keeps of them.
+//!
+//! Data held back for an inode is sent on while holding the inode's turn,
+//! which reads of the inode take too, so that a read never overtakes the
+//! writes it should see.
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use fuser::FileAttr;
+use libc::c_int;
+use tokio::sync::{Mutex as TurnLock, MutexGuard as Turn};
+use tribbler::error::{errno, SUCCESS};
+use tribbler::storage::{FileRequest, Storage};
+
+use super::cache::AttrCache;
+use super::readahead::{BlockCache, Fetch, Stamp};
+use super::writeback::{Dirty, WriteBack};
+
+const BLOCK_SIZE: u64 = 512;
+/// Inodes share this many turns between them.
+const TURNS: usize = 64;
+
+#[derive(Clone)]
+pub struct Files {
+    pub bin: Arc<dyn Storage>,
+    cache: Arc<Mutex<AttrCache>>,
+    write_back: Arc<Mutex<WriteBack>>,
+    blocks: Arc<Mutex<BlockCache>>,
+    turns: Arc<Vec<TurnLock<()>>>,
+}
+
+impl Files {
+    pub fn new(
+        bin: Arc<dyn Storage>,
+        cache: Arc<Mutex<AttrCache>>,
+        write_back: Arc<Mutex<WriteBack>>,
+        blocks: Arc<Mutex<BlockCache>>,
+    ) -> Files {
+        Files {
+            bin,
+            cache,
+            write_back,
+            blocks,
+            turns: Arc::new((0..TURNS).map(|_| TurnLock::new(())).collect()),
+        }
+    }
+
+    pub fn cache(&self) -> MutexGuard<'_, AttrCache> {
+        self.cache.lock().unwrap()
+    }
+
+    pub fn write_back(&self) -> MutexGuard<'_, WriteBack> {
+        self.write_back.lock().unwrap()
+    }
+
+    pub fn blocks(&self) -> MutexGuard<'_, BlockCache> {
+        self.blocks.lock().unwrap()
+    }
+
+    async fn turn(&self, inode: u64) -> Turn<'_, ()> {
+        self.turns[(inode % TURNS as u64) as usize].lock().await
+    }
+
+    /// Sends on what was held back for handle `fh` of `inode`. Fails with
+    /// the error of this send, or of an earlier one made for the handle.
+    pub async fn flush_handle(&self, inode: u64, fh: u64) -> Result<(), c_int> {
+        let _turn = self.turn(inode).await;
+        let dirty = self.write_back().take(inode, fh);
+        if let Some(dirty) = dirty {
+            self.send(inode, fh, dirty).await?;
+        }
+        match self.write_back().take_error(inode, fh) {
+            Some(errcode) => Err(errcode),
+            None => Ok(()),
+        }
+    }
+
+    /// Sends on what was held back for every handle of `inode`, before the
+    /// backends are asked about its data.
+    pub async fn flush_inode(&self, inode: u64) -> Result<(), c_int> {
+        let _turn = self.turn(inode).await;
+        let mut result = Ok(());
+        let handles = self.write_back().take_inode(inode);
+        for (fh, dirty) in handles {
+            if let Err(errcode) = self.send(inode, fh, dirty).await {
+                self.write_back().fail(inode, fh, errcode);
+                result = Err(errcode);
+            }
+        }
+        result
+    }
+
+    /// Sends on what was held back for handle `fh`, if it holds back as
+    /// much as it may, then for the handles holding back the most until all
+    /// of them together are within their budget.
+    pub async fn flush_full(&self, inode: u64, fh: u64) -> Result<(), c_int> {
+        let file_full = self.write_back().file_full(inode, fh);
+        if file_full {
+            self.flush_handle(inode, fh).await?;
+        }
+        loop {
+            let largest = self.write_back().largest();
+            let (ino, handle) = match largest {
+                Some(largest) => largest,
+                None => return Ok(()),
+            };
+            let _turn = self.turn(ino).await;
+            let dirty = {
+                let mut write_back = self.write_back();
+                if !write_back.full() {
+                    return Ok(());
+                }
+                write_back.take(ino, handle)
+            };
+            let dirty = match dirty {
+                Some(dirty) => dirty,
+                // sent by someone else meanwhile
+                None => continue,
+            };
+            if let Err(errcode) = self.send(ino, handle, dirty).await {
+                if (ino, handle) == (inode, fh) {
+                    return Err(errcode);
+                }
+                self.write_back().fail(ino, handle, errcode);
+            }
+        }
+    }
+
+    /// Sends on everything held back, as the front goes away.
+    pub async fn flush_all(&self) -> Vec<(u64, c_int)> {
+        let mut lost = vec![];
+        loop {
+            let largest = self.write_back().largest();
+            let (inode, fh) = match largest {
+                Some(largest) => largest,
+                None => return lost,
+            };
+            if let Err(errcode) = self.flush_handle(inode, fh).await {
+                lost.push((inode, errcode));
+            }
+        }
+    }
+
+    // Sends `dirty`, holding the turn of `inode`.
+    async fn send(&self, inode: u64, fh: u64, dirty: Dirty) -> Result<(), c_int> {
+        let chunk_bytes = self.write_back().config().chunk_bytes;
+        let writer = &dirty.writer;
+        for (offset, data) in dirty.chunks(chunk_bytes) {
+            let write = self.bin.write(
+                &writer.request,
+                inode,
+                fh,
+                offset as i64,
+                data,
+                writer.write_flags,
+                writer.flags,
+                writer.lock_owner,
+            );
+            match write.await {
+                Ok((_, SUCCESS)) => (),
+                Ok((_, errcode)) => return Err(errcode),
+                Err(e) => return Err(errno(e.as_ref())),
+            }
+        }
+        self.cache().invalidate(inode);
+        self.blocks().invalidate(inode);
+        Ok(())
+    }
+
+    /// `attrs` as they are with what was held back for them written.
+    pub fn with_held_back(&self, mut attrs: FileAttr) -> FileAttr {
+        if let Some(end) = self.write_back().end(attrs.ino) {
+            attrs.size = attrs.size.max(end);
+            attrs.blocks = attrs.size.div_ceil(BLOCK_SIZE);
+        }
+        attrs
+    }
+
+    // What `inode` looks like now, as cached or as the backends say.
+    async fn stamp(&self, freq: &FileRequest, inode: u64) -> Result<Stamp, c_int> {
+        let cached = self.cache().attr(inode);
+        let attrs = match cached {
+            Some(attrs) => attrs,
+            None => match self.bin.getattr(freq, inode).await {
+                Ok((Some(attrs), SUCCESS)) => {
+                    self.cache().insert_attr(&attrs);
+                    attrs
+                }
+                Ok((_, SUCCESS)) => return Err(libc::EIO),
+                Ok((_, errcode)) => return Err(errcode),
+                Err(e) => return Err(errno(e.as_ref())),
+            },
+        };
+        Ok(Stamp {
+            mtime: attrs.mtime,
+            size: attrs.size,
+        })
+    }
+
+    /// Reads `size` bytes at `offset` of the file `fetch` reads through the
+    /// blocks kept of it, fetching those missing, then fetches ahead if the
+    /// handle reads the file in sequence.
+    pub async fn read_blocks(
+        &self,
+        fetch: &Fetch,
+        offset: u64,
+        size: u32,
+    ) -> Result<Vec<u8>, c_int> {
+        let (inode, fh) = (fetch.ino, fetch.fh);
+        let stamp = self.stamp(&fetch.request, inode).await?;
+        let end = stamp.size.min(offset + size as u64);
+        if offset >= end {
+            return Ok(vec![]);
+        }
+        let read_ahead_blocks = self.blocks().config().read_ahead_blocks as u64;
+        let block_bytes = fetch.block_bytes as u64;
+        let (first, last) = (offset / block_bytes, (end - 1) / block_bytes);
+        let mut data = Vec::with_capacity((end - offset) as usize);
+        for index in first..=last {
+            let cached = self.blocks().get(inode, index, &stamp);
+            let block = match cached {
+                Some(block) => block,
+                None => {
+                    let block = fetch.block(index).await?;
+                    self.blocks().insert(inode, index, stamp, block.clone());
+                    block
+                }
+            };
+            let start = index * block_bytes;
+            let from = (offset.max(start) - start) as usize;
+            let to = ((end - start) as usize).min(block.len());
+            if from >= to {
+                break;
+            }
+            data.extend_from_slice(&block[from..to]);
+        }
+
+        let sequential = self.blocks().read(inode, fh, offset, end);
+        if sequential {
+            let ahead = (last + 1)..=(last + read_ahead_blocks);
+            for index in ahead.take_while(|index| index * block_bytes < stamp.size) {
+                if !self.blocks().start(inode, index) {
+                    continue;
+                }
+                let (fetch, blocks) = (fetch.clone(), self.blocks.clone());
+                tokio::spawn(async move {
+                    let block = fetch.block(index).await;
+                    let mut blocks = blocks.lock().unwrap();
+                    match block {
+                        Ok(block) => blocks.insert(fetch.ino, index, stamp, block),
+                        Err(_) => blocks.abandon(fetch.ino, index),
+                    }
+                });
+            }
+        }
+        Ok(data)
+    }
+}
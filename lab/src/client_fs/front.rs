@@ -4,79 +4,222 @@
 use fuser::consts::FUSE_HANDLE_KILLPRIV;
 #[cfg(feature = "abi-7-31")]
 use fuser::consts::FUSE_WRITE_KILL_PRIV;
+use fuser::consts::FOPEN_DIRECT_IO;
 use fuser::{Filesystem, ReplyCreate, ReplyData, ReplyEmpty, ReplyEntry, ReplyWrite, Request, TimeOrNow, ReplyAttr, ReplyXattr, ReplyOpen, KernelConfig, ReplyStatfs, ReplyDirectory};
 use libc::c_int;
 use log::{info, warn};
 #[cfg(feature = "abi-7-26")]
 use log::info;
 use std::ffi::OsStr;
+use std::collections::HashMap;
+use std::future::Future;
 use std::path::Path;
+use std::sync::{Arc, Mutex};
 #[cfg(target_os = "linux")]
 use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::Semaphore;
 use tribbler::storage::FileRequest;
-use std::time::{Duration, SystemTime};
-use std::sync::atomic;
+use std::time::SystemTime;
 use std::os::unix::ffi::OsStrExt;
 
-use tribbler::error::{TritonFileError, TritonFileResult, SUCCESS};
+use tribbler::config::{
+    shard_name, CacheConfig, ConcurrencyConfig, Namespace, NamespaceKind, OfflineConfig,
+    ReadCacheConfig, WriteBackConfig, DEFAULT_NAMESPACE,
+};
+use tribbler::error::{errno, TritonFileResult, SUCCESS};
 use tribbler::storage;
 
+use super::cache::{AttrCache, Cached};
+use super::files::Files;
+use super::invalidate::Invalidator;
+use super::offline::OfflineStore;
+use super::queue::InodeQueues;
+use super::readahead::{BlockCache, Fetch};
+use super::shard::ShardedStore;
+use super::writeback::{WriteBack, Writer};
+
 const BLOCK_SIZE: u64 = 512;
 const MAX_NAME_LENGTH: u32 = 255;
 const MAX_FILE_SIZE: u64 = 1024 * 1024 * 1024 * 1024;
-pub const USERNAME: &str = "Tan-cha";
+pub const USERNAME: &str = DEFAULT_NAMESPACE;
 
+/// Serves the files of a namespace to the kernel. Every request the kernel
+/// makes is run on the runtime, which answers it whenever the backends do,
+/// while fuser's session thread goes on to the next one.
 pub struct Front {
     binstore: Box<dyn storage::BinStorage>,
-    clock: atomic::AtomicU64,
+    bins: HashMap<String, Arc<dyn storage::Storage>>,
     runtime: tokio::runtime::Runtime,
-    username: String, 
+    namespace: Namespace,
+    // The user and group that mounted the front; files of a private
+    // namespace are accessed as them.
+    owner: (u32, u32),
+    // Shared with the invalidations the backends push.
+    cache: Arc<Mutex<AttrCache>>,
+    write_back: Arc<Mutex<WriteBack>>,
+    // Shared with the blocks being read ahead.
+    blocks: Arc<Mutex<BlockCache>>,
+    files: Option<Files>,
+    offline: Option<OfflineConfig>,
+    // Flags files are opened with, telling the kernel how to cache them.
+    open_flags: u32,
+    // One permit for each request that may run at once.
+    requests: Arc<Semaphore>,
+    // Requests that read or change the data of a file, by inode.
+    queues: InodeQueues,
 }
 
 impl Front {
     pub fn new(binstore: Box<dyn storage::BinStorage>, runtime: tokio::runtime::Runtime, username: String) -> Front {
-        #[cfg(feature = "abi-7-26")]
-        {
-            Front {
-                binstore,
-                clock: atomic::AtomicU64::new(1),
-                runtime,
-                username, 
-            }
+        let namespace = Namespace {
+            name: username,
+            kind: NamespaceKind::Private,
+            shards: 1,
+        };
+        Front::with_namespace(
+            binstore,
+            runtime,
+            namespace,
+            CacheConfig::default(),
+            WriteBackConfig::default(),
+            ReadCacheConfig::default(),
+            ConcurrencyConfig::default().max_requests,
+        )
+    }
+
+    /// A front serving the files of `namespace`, caching attributes and
+    /// directory entries as `cache` says, holding writes back as
+    /// `write_back` says and keeping file data as `read_cache` says. At
+    /// most `max_requests` requests run at once, on `runtime`, which has to
+    /// be a multi-threaded one.
+    pub fn with_namespace(
+        binstore: Box<dyn storage::BinStorage>,
+        runtime: tokio::runtime::Runtime,
+        namespace: Namespace,
+        cache: CacheConfig,
+        write_back: WriteBackConfig,
+        read_cache: ReadCacheConfig,
+        max_requests: usize,
+    ) -> Front {
+        Front {
+            binstore,
+            bins: HashMap::new(),
+            runtime,
+            namespace,
+            owner: (users::get_current_uid(), users::get_current_gid()),
+            cache: Arc::new(Mutex::new(AttrCache::new(cache))),
+            write_back: Arc::new(Mutex::new(WriteBack::new(write_back))),
+            blocks: Arc::new(Mutex::new(BlockCache::new(read_cache))),
+            files: None,
+            offline: None,
+            open_flags: 0,
+            requests: Arc::new(Semaphore::new(max_requests.max(1))),
+            queues: InodeQueues::new(),
         }
-        #[cfg(not(feature = "abi-7-26"))]
-        {
-            Front {
-                binstore,
-                clock: atomic::AtomicU64::new(1),
-                runtime,
-                username, 
-            }
+    }
+
+    /// What applies the invalidations of the front's namespace to its
+    /// cache.
+    pub fn invalidator(&self) -> Invalidator {
+        Invalidator::new(self.cache.clone(), self.namespace.name.clone())
+    }
+
+    /// Goes on working from local copies while no backend can be reached,
+    /// if `offline` is enabled. Has to be set before the front is mounted.
+    pub fn set_offline(&mut self, offline: OfflineConfig) {
+        self.offline = Some(offline).filter(|offline| offline.enabled);
+    }
+
+    /// Has reads and writes of files bypass the kernel's page cache, if
+    /// `direct_io` is set.
+    pub fn set_direct_io(&mut self, direct_io: bool) {
+        self.open_flags = if direct_io { FOPEN_DIRECT_IO } else { 0 };
+    }
+
+    // The caller a backend checks permissions for: whoever made the
+    // request in a shared namespace, the owner of the mount in a private
+    // one.
+    fn request(&self, req: &Request) -> FileRequest {
+        let (uid, gid) = match self.namespace.kind {
+            NamespaceKind::Shared => (req.uid(), req.gid()),
+            NamespaceKind::Private => self.owner,
+        };
+        FileRequest {
+            uid,
+            gid,
+            pid: req.pid(),
         }
     }
 
-    // Sync my clock to at least at_least, if increment is true,
-    // increment my clock to at least at_least. Return the new clock.
-    fn clock(&self, at_least: u64, increment: bool) -> TritonFileResult<u64> {
-        let my_clock = self.clock.load(atomic::Ordering::SeqCst);
-        if my_clock == u64::MAX {
-            return Err(Box::new(TritonFileError::MaxedSeq));
+    // Returns the bin of this user, opening it on first use only so that
+    // callbacks reuse the same store (and its pooled connections).
+    fn bin(&mut self) -> TritonFileResult<Arc<dyn storage::Storage>> {
+        if let Some(bin) = self.bins.get(&self.namespace.name) {
+            return Ok(bin.clone());
         }
-        let new_clock = self
-            .clock
-            .fetch_update(atomic::Ordering::SeqCst, atomic::Ordering::SeqCst, |v| {
-                if v < at_least {
-                    Some(at_least)
-                } else {
-                    if increment {
-                        Some(v)
-                    } else {
-                        Some(v + 1)
-                    }
-                }
-            })
+        let bin: Arc<dyn storage::Storage> = if self.namespace.shards > 1 {
+            let mut shards = vec![];
+            for shard in 0..self.namespace.shards {
+                let name = shard_name(&self.namespace.name, shard);
+                shards.push(self.runtime.block_on(self.binstore.bin(&name))?.into());
+            }
+            Arc::new(ShardedStore::new(shards))
+        } else {
+            self.runtime
+                .block_on(self.binstore.bin(self.namespace.name.as_str()))?
+                .into()
+        };
+        let bin: Arc<dyn storage::Storage> = match self.offline.clone() {
+            Some(offline) => {
+                let dir = Path::new(&offline.cache_dir).join(&self.namespace.name);
+                Arc::new(OfflineStore::new(bin, &dir, offline)?)
+            }
+            None => bin,
+        };
+        self.bins.insert(self.namespace.name.clone(), bin.clone());
+        Ok(bin)
+    }
+
+    // What the requests running at once share, made on first use.
+    fn files(&mut self) -> TritonFileResult<Files> {
+        if let Some(files) = &self.files {
+            return Ok(files.clone());
+        }
+        let files = Files::new(
+            self.bin()?,
+            self.cache.clone(),
+            self.write_back.clone(),
+            self.blocks.clone(),
+        );
+        self.files = Some(files.clone());
+        Ok(files)
+    }
+
+    // Runs `request` on the runtime once fewer than `max_requests` are
+    // running, and goes back to the kernel meanwhile. `request` owns the
+    // reply and sends it when done.
+    fn spawn<F>(&self, request: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let permit = self
+            .runtime
+            .block_on(self.requests.clone().acquire_owned())
             .unwrap();
-        Ok(new_clock)
+        self.runtime.spawn(async move {
+            request.await;
+            drop(permit);
+        });
+    }
+
+    // Runs `request` as `spawn` does, after the requests on `inode` the
+    // kernel made before it, so that reads and writes of a file reach the
+    // backends in the order they were made.
+    fn spawn_ordered<F>(&self, inode: u64, request: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.spawn(self.queues.queue(inode, request));
     }
 }
 
@@ -92,16 +235,11 @@ impl Filesystem for Front {
         
         // ReliableStore
         let gid = _req.gid().to_string().clone();
-        let bin_pre = self.binstore.bin(self.username.as_str());
-        let bin_res = self.runtime.block_on(bin_pre);
+        let bin_res = self.bin();
 
         match bin_res {
             Ok(bin) => {
-                let freq = &FileRequest {
-                    uid: _req.uid(),
-                    gid: _req.gid(),
-                    pid: _req.pid(),
-                };
+                let freq = &self.request(_req);
                 let bin_init_pre = bin.init(freq);
 
                 let res = self.runtime.block_on(bin_init_pre);
@@ -113,48 +251,59 @@ impl Filesystem for Front {
                             return Ok(())
                         }
                     }
-                    Err(_) => return Err(libc::ENETDOWN),
+                    Err(e) => return Err(errno(e.as_ref())),
                 }
             }
             Err(_) => return Err(libc::EACCES)
         }
-        Ok(())
     }
 
     fn lookup(&mut self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
         info!("front Look up called with name {:?} and parent indoe {:?}", name, parent);
         // ReliableStore
-        let gid = req.gid().to_string().clone();
-        let bin_pre = self.binstore.bin(self.username.as_str());
-        let bin_res = self.runtime.block_on(bin_pre);
-
-        match bin_res {
-            Ok(bin) => {
-                let freq = &FileRequest {
-                    uid: req.uid(),
-                    gid: req.gid(),
-                    pid: req.pid(),
-                };
-                let bin_lookup_pre = bin.lookup(freq, parent, name);
-
-                let res = self.runtime.block_on(bin_lookup_pre);
-
-                //FileAttr
-                match res {
-                    Ok((attrs_op, error_code)) => {
-                        if error_code != SUCCESS {
-                            info!("front lookup function failed error_code {}", error_code);
-                            reply.error(error_code);
-                        } else {
-                            info!("front lookup function success and fileAttr is {:?}", attrs_op.unwrap());
-                            let attrs = attrs_op.unwrap();
-                            reply.entry(&Duration::new(0, 0), &attrs, 0);
+        let files = match self.files() {
+            Ok(files) => files,
+            Err(e) => return reply.error(errno(e.as_ref())),
+        };
+        let ttl = files.cache().config().entry_ttl();
+        let cached = files.cache().lookup(parent, name);
+        match cached {
+            Cached::Found(attrs) => return reply.entry(&ttl, &files.with_held_back(attrs), 0),
+            Cached::Missing => return reply.error(libc::ENOENT),
+            Cached::Unknown => (),
+        }
+        let freq = self.request(req);
+        let name = name.to_os_string();
+        self.spawn(async move {
+            let res = files.bin.lookup(&freq, parent, &name).await;
+
+            //FileAttr
+            match res {
+                Ok((attrs_op, error_code)) => {
+                    if error_code != SUCCESS {
+                        info!("front lookup function failed error_code {}", error_code);
+                        if error_code == libc::ENOENT {
+                            files.cache().insert_missing(parent, &name);
                         }
+                        reply.error(error_code);
+                    } else {
+                        info!("front lookup function success and fileAttr is {:?}", attrs_op.unwrap());
+                        let attrs = attrs_op.unwrap();
+                        files.cache().insert_entry(parent, &name, &attrs);
+                        reply.entry(&ttl, &files.with_held_back(attrs), 0);
                     }
-                    Err(_) => reply.error(libc::ENETDOWN),
                 }
+                Err(e) => reply.error(errno(e.as_ref())),
+            }
+        });
+    }
+
+    fn destroy(&mut self) {
+        // Whatever is still held back would be lost with the front.
+        if let Some(files) = self.files.clone() {
+            for (inode, errcode) in self.runtime.block_on(files.flush_all()) {
+                warn!("lost writes to {} on unmount: {}", inode, errcode);
             }
-            Err(_) => reply.error(libc::ENETDOWN),
         }
     }
 
@@ -173,39 +322,52 @@ impl Filesystem for Front {
     ) {
         info!("front  front read function {}", inode);
         // ReliableStore
-        let gid = _req.gid().to_string().clone();
-        let bin_pre = self.binstore.bin(self.username.as_str());
-        let bin_res = self.runtime.block_on(bin_pre);
-
-        match bin_res {
-            Ok(bin) => {
-                let freq = &FileRequest {
-                    uid: _req.uid(),
-                    gid: _req.gid(),
-                    pid: _req.pid(),
+        let files = match self.files() {
+            Ok(files) => files,
+            Err(e) => return reply.error(errno(e.as_ref())),
+        };
+        let freq = self.request(_req);
+        let (enabled, block_bytes) = {
+            let blocks = files.blocks();
+            (blocks.config().enabled, blocks.config().block_bytes)
+        };
+        self.spawn_ordered(inode, async move {
+            if let Err(errcode) = files.flush_inode(inode).await {
+                return reply.error(errcode);
+            }
+            if enabled && offset >= 0 {
+                let fetch = Fetch {
+                    bin: files.bin.clone(),
+                    request: freq,
+                    ino: inode,
+                    fh,
+                    flags: _flags,
+                    lock_owner: _lock_owner,
+                    block_bytes,
                 };
-                let bin_read_pre = bin.read(freq, inode, fh, offset, size, _flags, _lock_owner);
-
-                let res = self.runtime.block_on(bin_read_pre);
+                return match files.read_blocks(&fetch, offset as u64, size).await {
+                    Ok(data) => reply.data(&data),
+                    Err(errcode) => reply.error(errcode),
+                };
+            }
+            let res = files.bin.read(&freq, inode, fh, offset, size, _flags, _lock_owner).await;
 
-                match res {
-                    Ok((string_data_op, error_code)) => {
-                        if error_code != SUCCESS {
-                            reply.error(error_code);
-                        } else {
-                            let string_data = string_data_op.unwrap();
-                            let str_data: &str = &string_data;
-                            let data: Vec<u8> = serde_json::from_str(str_data).unwrap();
-                            // str_data.as_bytes().to_vec();
-                            info!("read data is {:?}", data);
-                            reply.data(&data)
-                        }
+            match res {
+                Ok((string_data_op, error_code)) => {
+                    if error_code != SUCCESS {
+                        reply.error(error_code);
+                    } else {
+                        let string_data = string_data_op.unwrap();
+                        let str_data: &str = &string_data;
+                        let data: Vec<u8> = serde_json::from_str(str_data).unwrap();
+                        // str_data.as_bytes().to_vec();
+                        info!("read data is {:?}", data);
+                        reply.data(&data)
                     }
-                    Err(_) => reply.error(libc::ENETDOWN),
                 }
+                Err(e) => reply.error(errno(e.as_ref())),
             }
-            Err(_) => reply.error(libc::ENETDOWN),
-        }
+        });
     }
 
     fn write(
@@ -222,44 +384,53 @@ impl Filesystem for Front {
     ) {
         info!("call front write() {}", inode);
         // ReliableStore
-        let freq = &FileRequest {
-            uid: _req.uid(),
-            gid: _req.gid(),
-            pid: _req.pid(),
+        let files = match self.files() {
+            Ok(files) => files,
+            Err(e) => return reply.error(errno(e.as_ref())),
         };
-        let bin_pre = self.binstore.bin(self.username.as_str());
-        let bin_res = self.runtime.block_on(bin_pre);
-
-        match bin_res {
-            Ok(bin) => {
-                let bin_write_pre = bin.write(
-                    freq,
-                    inode,
-                    fh,
-                    offset,
-                    data,
-                    _write_flags,
-                    flags,
-                    _lock_owner,
-                );
-
-                let res = self.runtime.block_on(bin_write_pre);
-
-                match res {
-                    Ok((written_op, error_code)) => {
-                        if error_code != SUCCESS {
-                            info!("write error {:?}", error_code);
-                            reply.error(error_code)
-                        } else {
-                            let written = written_op.unwrap();
-                            reply.written(written)
-                        }
+        let freq = self.request(_req);
+        if files.write_back().config().enabled {
+            // Held back here, on the session thread, so that writes are
+            // laid over each other in the order the kernel made them.
+            let writer = Writer {
+                request: freq,
+                write_flags: _write_flags,
+                flags,
+                lock_owner: _lock_owner,
+            };
+            files.write_back().write(inode, fh, writer, offset as u64, data);
+            files.cache().invalidate(inode);
+            files.blocks().invalidate(inode);
+            let written = data.len() as u32;
+            return self.spawn_ordered(inode, async move {
+                match files.flush_full(inode, fh).await {
+                    Ok(()) => reply.written(written),
+                    Err(errcode) => reply.error(errcode),
+                }
+            });
+        }
+        let data = data.to_vec();
+        self.spawn_ordered(inode, async move {
+            let res = files
+                .bin
+                .write(&freq, inode, fh, offset, &data, _write_flags, flags, _lock_owner)
+                .await;
+
+            match res {
+                Ok((written_op, error_code)) => {
+                    if error_code != SUCCESS {
+                        info!("write error {:?}", error_code);
+                        reply.error(error_code)
+                    } else {
+                        let written = written_op.unwrap();
+                        files.cache().invalidate(inode);
+                        files.blocks().invalidate(inode);
+                        reply.written(written)
                     }
-                    Err(_) => reply.error(libc::ENETDOWN),
                 }
+                Err(e) => reply.error(errno(e.as_ref())),
             }
-            Err(_) => reply.error(libc::ENETDOWN),
-        }
+        });
     }
 
     fn create(
@@ -273,137 +444,133 @@ impl Filesystem for Front {
         reply: ReplyCreate,
     ) {
         info!("front create() called with {:?} {:?}", parent, name);
-        let freq = &FileRequest {
-            uid: req.uid(),
-            gid: req.gid(),
-            pid: req.pid(),
+        let files = match self.files() {
+            Ok(files) => files,
+            Err(e) => {
+                info!{"front create error?"};
+                info!{"{}", e};
+                return reply.error(errno(e.as_ref()));
+            }
         };
-        let bin_pre = self.binstore.bin(self.username.as_str());
-        let bin_res = self.runtime.block_on(bin_pre);
-
-        match bin_res {
-            Ok(bin) => {
-                let bin_create_pre = bin.create(freq, parent, name, mode, _umask, flags);
-
-                let res = self.runtime.block_on(bin_create_pre);
-
-                match res {
-                    Ok(res_info) => {
-                        let (attrs_fh_op, error_code) = res_info;
-                        if error_code != SUCCESS {
-                            reply.error(error_code);
-                        } else {
-                            let (attrs, fh) = attrs_fh_op.unwrap();
-                            reply.created(&Duration::new(0, 0), &attrs, 0, fh, 0)
-                        }
+        let freq = self.request(req);
+        let name = name.to_os_string();
+        let open_flags = self.open_flags;
+        self.spawn(async move {
+            let res = files.bin.create(&freq, parent, &name, mode, _umask, flags).await;
+
+            match res {
+                Ok(res_info) => {
+                    let (attrs_fh_op, error_code) = res_info;
+                    if error_code != SUCCESS {
+                        reply.error(error_code);
+                    } else {
+                        let (attrs, fh) = attrs_fh_op.unwrap();
+                        let ttl = files.cache().config().entry_ttl();
+                        files.cache().invalidate(parent);
+                        files.cache().insert_entry(parent, &name, &attrs);
+                        reply.created(&ttl, &attrs, 0, fh, open_flags)
                     }
-                    Err(_) => reply.error(libc::ENETDOWN),
                 }
+                Err(e) => reply.error(errno(e.as_ref())),
             }
-            Err(e) => {
-                info!{"front create error?"};
-                info!{"{}", e};
-                reply.error(libc::ENETDOWN)},
-        }
+        });
     }
 
     fn unlink(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
         info!("front  unlink function {}", parent);
-        let freq = &FileRequest {
-            uid: _req.uid(),
-            gid: _req.gid(),
-            pid: _req.pid(),
+        let files = match self.files() {
+            Ok(files) => files,
+            Err(e) => return reply.error(errno(e.as_ref())),
         };
-        let bin_pre = self.binstore.bin(self.username.as_str());
-        let bin_res = self.runtime.block_on(bin_pre);
-
-        match bin_res {
-            Ok(bin) => {
-                let bin_unlink_pre = bin.unlink(freq, parent, name);
-
-                let res = self.runtime.block_on(bin_unlink_pre);
-
-                match res {
-                    Ok(error_code) => {
-                        if error_code != SUCCESS {
-                            reply.error(error_code)
-                        } else {
-                            reply.ok();
-                        }
+        let freq = self.request(_req);
+        let name = name.to_os_string();
+        self.spawn(async move {
+            let res = files.bin.unlink(&freq, parent, &name).await;
+
+            match res {
+                Ok(error_code) => {
+                    if error_code != SUCCESS {
+                        reply.error(error_code)
+                    } else {
+                        let mut cache = files.cache();
+                        cache.invalidate(parent);
+                        cache.invalidate_entry(parent, &name);
+                        cache.insert_missing(parent, &name);
+                        drop(cache);
+                        reply.ok();
                     }
-                    Err(_) => reply.error(libc::ENETDOWN),
                 }
+                Err(e) => reply.error(errno(e.as_ref())),
             }
-            Err(_) => reply.error(libc::ENETDOWN),
-        }
+        });
     }
 
     fn getattr(&mut self, _req: &Request, inode: u64, reply: ReplyAttr) {
         // info!("front  get attr function {}", inode);
-        let freq = &FileRequest {
-            uid: _req.uid(),
-            gid: _req.gid(),
-            pid: _req.pid(),
+        let files = match self.files() {
+            Ok(files) => files,
+            Err(e) => return reply.error(errno(e.as_ref())),
         };
-        let bin_pre = self.binstore.bin(self.username.as_str());
-        let bin_res = self.runtime.block_on(bin_pre);
-
-        match bin_res {
-            Ok(bin) => {
-                let bin_getattr_pre = bin.getattr(freq, inode);
-
-                let res = self.runtime.block_on(bin_getattr_pre);
-
-                match res {
-                    Ok(res_info) => {
-                        let (attrs_op, error_code) = res_info;
-                        if error_code != SUCCESS {
-                            info!("getattr called error");
-                            reply.error(error_code);
-                        } else {
-                            let attrs = attrs_op.unwrap();
-                            info!("getattr called success with file attrs {:?}", attrs);
-                            reply.attr(&Duration::new(0, 0), &attrs);
-                        }
+        let ttl = files.cache().config().attr_ttl();
+        let cached = files.cache().attr(inode);
+        if let Some(attrs) = cached {
+            return reply.attr(&ttl, &files.with_held_back(attrs));
+        }
+        let freq = self.request(_req);
+        self.spawn(async move {
+            let res = files.bin.getattr(&freq, inode).await;
+
+            match res {
+                Ok(res_info) => {
+                    let (attrs_op, error_code) = res_info;
+                    if error_code != SUCCESS {
+                        info!("getattr called error");
+                        reply.error(error_code);
+                    } else {
+                        let attrs = attrs_op.unwrap();
+                        info!("getattr called success with file attrs {:?}", attrs);
+                        files.cache().insert_attr(&attrs);
+                        reply.attr(&ttl, &files.with_held_back(attrs));
                     }
-                    Err(_) => reply.error(libc::ENETDOWN),
                 }
+                Err(e) => reply.error(errno(e.as_ref())),
             }
-            Err(_) => reply.error(libc::ENETDOWN),
-        }
+        });
     }
 
     fn open(&mut self, req: &Request, inode: u64, flags: i32, reply: ReplyOpen) {
         info!("front  open function {}", inode);
-        let freq = &FileRequest {
-            uid: req.uid(),
-            gid: req.gid(),
-            pid: req.pid(),
+        let files = match self.files() {
+            Ok(files) => files,
+            Err(e) => return reply.error(errno(e.as_ref())),
         };
-        let bin_pre = self.binstore.bin(self.username.as_str());
-        let bin_res = self.runtime.block_on(bin_pre);
-
-        match bin_res {
-            Ok(bin) => {
-                let bin_open_pre = bin.open(freq, inode, flags);
-
-                let res = self.runtime.block_on(bin_open_pre);
+        let freq = self.request(req);
+        let front_flags = self.open_flags;
+        self.spawn_ordered(inode, async move {
+            if flags & libc::O_TRUNC != 0 {
+                if let Err(errcode) = files.flush_inode(inode).await {
+                    return reply.error(errcode);
+                }
+            }
+            let res = files.bin.open(&freq, inode, flags).await;
 
-                match res {
-                    Ok(res_info) => {
-                        let (attrs_op, error_code) = res_info;
-                        if error_code != SUCCESS {
-                            reply.error(error_code);
-                        } else {
-                            let (fh, open_flags) = attrs_op.unwrap();
-                            reply.opened(fh, open_flags);
+            match res {
+                Ok(res_info) => {
+                    let (attrs_op, error_code) = res_info;
+                    if error_code != SUCCESS {
+                        reply.error(error_code);
+                    } else {
+                        let (fh, open_flags) = attrs_op.unwrap();
+                        if flags & libc::O_TRUNC != 0 {
+                            files.cache().invalidate(inode);
+                            files.blocks().invalidate(inode);
                         }
+                        reply.opened(fh, open_flags | front_flags);
                     }
-                    Err(_) => reply.error(libc::ENETDOWN),
                 }
+                Err(e) => reply.error(errno(e.as_ref())),
             }
-            Err(_) => reply.error(libc::ENETDOWN),
-        }
+        });
     }
 
     fn release(
@@ -417,34 +584,58 @@ impl Filesystem for Front {
         reply: ReplyEmpty,
     ) {
         info!("front  release {}", inode);
-        let freq = &FileRequest {
-            uid: _req.uid(),
-            gid: _req.gid(),
-            pid: _req.pid(),
+        let files = match self.files() {
+            Ok(files) => files,
+            Err(e) => return reply.error(errno(e.as_ref())),
         };
-        
-        let bin_pre = self.binstore.bin(self.username.as_str());
-        let bin_res = self.runtime.block_on(bin_pre);
-
-        match bin_res {
-            Ok(bin) => {
-                let bin_release_pre = bin.release(freq, inode, _fh, _flags, _lock_owner, _flush);
-
-                let res = self.runtime.block_on(bin_release_pre);
+        files.blocks().release(inode, _fh);
+        let freq = self.request(_req);
+        self.spawn_ordered(inode, async move {
+            if let Err(errcode) = files.flush_handle(inode, _fh).await {
+                return reply.error(errcode);
+            }
+            let res = files.bin.release(&freq, inode, _fh, _flags, _lock_owner, _flush).await;
 
-                match res {
-                    Ok(error_code) => {
-                        if error_code != SUCCESS {
-                            reply.error(error_code)
-                        } else {
-                            reply.ok();
-                        }
+            match res {
+                Ok(error_code) => {
+                    if error_code != SUCCESS {
+                        reply.error(error_code)
+                    } else {
+                        reply.ok();
                     }
-                    Err(_) => reply.error(libc::ENETDOWN),
                 }
+                Err(e) => reply.error(errno(e.as_ref())),
             }
-            Err(_) => reply.error(libc::ENETDOWN),
-        }
+        });
+    }
+
+    fn flush(&mut self, _req: &Request<'_>, inode: u64, fh: u64, _lock_owner: u64, reply: ReplyEmpty) {
+        info!("front flush {}", inode);
+        let files = match self.files() {
+            Ok(files) => files,
+            Err(e) => return reply.error(errno(e.as_ref())),
+        };
+        self.spawn_ordered(inode, async move {
+            match files.flush_handle(inode, fh).await {
+                Ok(()) => reply.ok(),
+                Err(errcode) => reply.error(errcode),
+            }
+        });
+    }
+
+    fn fsync(&mut self, _req: &Request<'_>, inode: u64, fh: u64, _datasync: bool, reply: ReplyEmpty) {
+        info!("front fsync {}", inode);
+        let files = match self.files() {
+            Ok(files) => files,
+            Err(e) => return reply.error(errno(e.as_ref())),
+        };
+        self.spawn_ordered(inode, async move {
+            let result = files.flush_inode(inode).await;
+            match result.and(files.flush_handle(inode, fh).await) {
+                Ok(()) => reply.ok(),
+                Err(errcode) => reply.error(errcode),
+            }
+        });
     }
 
     fn statfs(&mut self, _req: &Request, _ino: u64, reply: ReplyStatfs) {
@@ -473,34 +664,27 @@ impl Filesystem for Front {
         reply: ReplyEmpty,
     ) {
         info!("front  set x attr {}", inode);
-        let freq = &FileRequest {
-            uid: request.uid(),
-            gid: request.gid(),
-            pid: request.pid(),
+        let files = match self.files() {
+            Ok(files) => files,
+            Err(e) => return reply.error(errno(e.as_ref())),
         };
-        let gid = request.gid().to_string().clone();
-        let bin_pre = self.binstore.bin(self.username.as_str());
-        let bin_res = self.runtime.block_on(bin_pre);
-
-        match bin_res {
-            Ok(bin) => {
-                let bin_setxattr_pre = bin.setxattr(freq, inode, key, value, _flags, _position);
-
-                let res = self.runtime.block_on(bin_setxattr_pre);
-
-                match res {
-                    Ok(error_code) => {
-                        if error_code != SUCCESS {
-                            reply.error(error_code)
-                        } else {
-                            reply.ok();
-                        }
+        let freq = self.request(request);
+        let (key, value) = (key.to_os_string(), value.to_vec());
+        self.spawn(async move {
+            let res = files.bin.setxattr(&freq, inode, &key, &value, _flags, _position).await;
+
+            match res {
+                Ok(error_code) => {
+                    if error_code != SUCCESS {
+                        reply.error(error_code)
+                    } else {
+                        files.cache().invalidate(inode);
+                        reply.ok();
                     }
-                    Err(_) => reply.error(libc::ENETDOWN),
                 }
+                Err(e) => reply.error(errno(e.as_ref())),
             }
-            Err(_) => reply.error(libc::ENETDOWN),
-        }
+        });
     }
 
     fn getxattr(
@@ -512,121 +696,96 @@ impl Filesystem for Front {
         reply: ReplyXattr,
     ) {
         // info!("front  get x attr {}", inode);
-        let freq = &FileRequest {
-            uid: request.uid(),
-            gid: request.gid(),
-            pid: request.pid(),
+        let files = match self.files() {
+            Ok(files) => files,
+            Err(e) => return reply.error(errno(e.as_ref())),
         };
-        let gid = request.gid().to_string().clone();
-        let bin_pre = self.binstore.bin(self.username.as_str());
-        let bin_res = self.runtime.block_on(bin_pre);
-
-        match bin_res {
-            Ok(bin) => {
-                let bin_getxattr_pre = bin.getxattr(freq, inode, key, size);
-                let res = self.runtime.block_on(bin_getxattr_pre);
-
-                match res {
-                    Ok((data_op, error_code)) => {
-                        if error_code != SUCCESS {
-                            reply.error(error_code)
-                        } else {
-                            let (string_data, data_len) = data_op.unwrap();
-                            let str_data: &str = &string_data;
-                            let data: Vec<u8> = str_data.as_bytes().to_vec();
-
-                            if size == 0 {
-                                reply.size(data_len);
-                            } else if data_len <= size {
-                                reply.data(&data);
-                            } 
-                        }
+        let freq = self.request(request);
+        let key = key.to_os_string();
+        self.spawn(async move {
+            let res = files.bin.getxattr(&freq, inode, &key, size).await;
+
+            match res {
+                Ok((data_op, error_code)) => {
+                    if error_code != SUCCESS {
+                        reply.error(error_code)
+                    } else {
+                        let (string_data, data_len) = data_op.unwrap();
+                        let str_data: &str = &string_data;
+                        let data: Vec<u8> = str_data.as_bytes().to_vec();
+
+                        if size == 0 {
+                            reply.size(data_len);
+                        } else if data_len <= size {
+                            reply.data(&data);
+                        } 
                     }
-                    Err(_) => reply.error(libc::ENETDOWN),
                 }
+                Err(e) => reply.error(errno(e.as_ref())),
             }
-            Err(_) => reply.error(libc::ENETDOWN),
-        }
+        });
     }
 
     fn listxattr(&mut self, _req: &Request<'_>, inode: u64, size: u32, reply: ReplyXattr) {
         info!("front  list x attr {}", inode);
-        let freq = &FileRequest {
-            uid: _req.uid(),
-            gid: _req.gid(),
-            pid: _req.pid(),
+        let files = match self.files() {
+            Ok(files) => files,
+            Err(e) => return reply.error(errno(e.as_ref())),
         };
-        let gid = _req.gid().to_string().clone();
-        let bin_pre = self.binstore.bin(self.username.as_str());
-        let bin_res = self.runtime.block_on(bin_pre);
-
-        match bin_res {
-            Ok(bin) => {
-                let bin_listxattr_pre = bin.listxattr(freq, inode, size);
-                let res = self.runtime.block_on(bin_listxattr_pre);
-
-                match res {
-                    Ok((data_op, error_code)) => {
-                        if error_code != SUCCESS {
-                            reply.error(error_code)
-                        } else {
-                            let (string_data, data_len) = data_op.unwrap();
-                            let str_data: &str = &string_data;
-                            let data: Vec<u8> = str_data.as_bytes().to_vec();
-
-                            if size == 0 {
-                                reply.size(data_len);
-                            } else if data_len <= size {
-                                reply.data(&data);
-                            } 
-                        }
+        let freq = self.request(_req);
+        self.spawn(async move {
+            let res = files.bin.listxattr(&freq, inode, size).await;
+
+            match res {
+                Ok((data_op, error_code)) => {
+                    if error_code != SUCCESS {
+                        reply.error(error_code)
+                    } else {
+                        let (string_data, data_len) = data_op.unwrap();
+                        let str_data: &str = &string_data;
+                        let data: Vec<u8> = str_data.as_bytes().to_vec();
+
+                        if size == 0 {
+                            reply.size(data_len);
+                        } else if data_len <= size {
+                            reply.data(&data);
+                        } 
                     }
-                    Err(_) => reply.error(libc::ENETDOWN),
                 }
+                Err(e) => reply.error(errno(e.as_ref())),
             }
-            Err(_) => reply.error(libc::ENETDOWN),
-        }
+        });
     }
 
     fn access(&mut self, req: &Request, inode: u64, mask: i32, reply: ReplyEmpty) {
         // info!("front  access {}", inode);
-        let freq = &FileRequest {
-            uid: req.uid(),
-            gid: req.gid(),
-            pid: req.pid(),
+        let files = match self.files() {
+            Ok(files) => files,
+            Err(e) => {
+                info!("access error 2 {}", e); 
+                return reply.error(errno(e.as_ref()));
+            }
         };
-        let gid = req.gid().to_string().clone();
-        let bin_pre = self.binstore.bin(self.username.as_str());
-        let bin_res = self.runtime.block_on(bin_pre);
-        
-        // info!("front  access: get bin_res");
-        match bin_res {
-            Ok(bin) => {
-                let bin_access_pre = bin.access(freq, inode, mask);
-
-                let res = self.runtime.block_on(bin_access_pre);
-
-                match res {
-                    Ok(error_code) => {
-                        if error_code != SUCCESS {
-                            info!("check access error");
-                            reply.error(error_code)
-                        } else {
-                            info!("check access ok");
-                            reply.ok();
-                        }
+        let freq = self.request(req);
+        self.spawn(async move {
+            let res = files.bin.access(&freq, inode, mask).await;
+
+            match res {
+                Ok(error_code) => {
+                    if error_code != SUCCESS {
+                        info!("check access error");
+                        reply.error(error_code)
+                    } else {
+                        info!("check access ok");
+                        reply.ok();
                     }
-                    Err(e) => {
-                        info!("access error 1 {}", e); 
-                        reply.error(libc::ENETDOWN)
-                    },
                 }
+                Err(e) => {
+                    info!("access error 1 {}", e); 
+                    reply.error(errno(e.as_ref()))
+                },
             }
-            Err(e) => {
-                info!("access error 2 {}", e); 
-                reply.error(libc::ENETDOWN)
-            },
-        }
+        });
     }
 
     fn rename(
@@ -640,34 +799,32 @@ impl Filesystem for Front {
         reply: ReplyEmpty,
     ) {
         info!("front rename function {}", parent);
-        let freq = &FileRequest {
-            uid: req.uid(),
-            gid: req.gid(),
-            pid: req.pid(),
+        let files = match self.files() {
+            Ok(files) => files,
+            Err(e) => return reply.error(errno(e.as_ref())),
         };
-        let gid = req.gid().to_string().clone();
-        let bin_pre = self.binstore.bin(self.username.as_str());
-        let bin_res = self.runtime.block_on(bin_pre);
-
-        match bin_res {
-            Ok(bin) => {
-                let bin_rename_pre = bin.rename(freq, parent, name, new_parent, new_name, flags);
-
-                let res = self.runtime.block_on(bin_rename_pre);
-
-                match res {
-                    Ok(error_code) => {
-                        if error_code != SUCCESS {
-                            reply.error(error_code)
-                        } else {
-                            reply.ok();
-                        }
+        let freq = self.request(req);
+        let (name, new_name) = (name.to_os_string(), new_name.to_os_string());
+        self.spawn(async move {
+            let res = files.bin.rename(&freq, parent, &name, new_parent, &new_name, flags).await;
+
+            match res {
+                Ok(error_code) => {
+                    if error_code != SUCCESS {
+                        reply.error(error_code)
+                    } else {
+                        let mut cache = files.cache();
+                        cache.invalidate(parent);
+                        cache.invalidate(new_parent);
+                        cache.invalidate_entry(parent, &name);
+                        cache.invalidate_entry(new_parent, &new_name);
+                        drop(cache);
+                        reply.ok();
                     }
-                    Err(_) => reply.error(libc::ENETDOWN),
                 }
+                Err(e) => reply.error(errno(e.as_ref())),
             }
-            Err(_) => reply.error(libc::ENETDOWN),
-        }
+        });
     }
 
     fn setattr(
@@ -689,80 +846,69 @@ impl Filesystem for Front {
         reply: ReplyAttr,
     ) {
         info!("front set attr {}", inode);
-        let freq = &FileRequest {
-            uid: req.uid(),
-            gid: req.gid(),
-            pid: req.pid(),
+        let files = match self.files() {
+            Ok(files) => files,
+            Err(e) => return reply.error(errno(e.as_ref())),
         };
-        let gid_string = req.gid().to_string().clone();
-        let bin_pre = self.binstore.bin(gid_string.as_str());
-        let bin_res = self.runtime.block_on(bin_pre);
-
-        match bin_res {
-            Ok(bin) => {
-                let bin_setattr_pre = bin.setattr(
-                    freq, inode, mode, uid, gid, size, atime, mtime, _ctime, fh, _crtime, _chgtime,
+        let freq = self.request(req);
+        self.spawn_ordered(inode, async move {
+            if let Err(errcode) = files.flush_inode(inode).await {
+                return reply.error(errcode);
+            }
+            let res = files
+                .bin
+                .setattr(
+                    &freq, inode, mode, uid, gid, size, atime, mtime, _ctime, fh, _crtime, _chgtime,
                     _bkuptime, _flags,
-                );
-
-                let res = self.runtime.block_on(bin_setattr_pre);
-
-                match res {
-                    Ok(res_info) => {
-                        let (attrs_op, error_code) = res_info;
-                        if error_code != SUCCESS {
-                            reply.error(error_code);
-                        } else {
-                            let attrs = attrs_op.unwrap();
-                            reply.attr(&Duration::new(0, 0), &attrs);
-                        }
+                )
+                .await;
+
+            match res {
+                Ok(res_info) => {
+                    let (attrs_op, error_code) = res_info;
+                    if error_code != SUCCESS {
+                        reply.error(error_code);
+                    } else {
+                        let attrs = attrs_op.unwrap();
+                        let ttl = files.cache().config().attr_ttl();
+                        files.cache().insert_attr(&attrs);
+                        files.blocks().invalidate(inode);
+                        reply.attr(&ttl, &attrs);
                     }
-                    Err(_) => reply.error(libc::ENETDOWN),
                 }
+                Err(e) => reply.error(errno(e.as_ref())),
             }
-            Err(_) => reply.error(libc::ENETDOWN),
-        }
+        });
     }
 
     fn opendir(&mut self, _req: &Request<'_>, _ino: u64, _flags: i32, reply: ReplyOpen) {
         info!("front opendir {}", _ino);
-        let freq = &FileRequest {
-            uid: _req.uid(),
-            gid: _req.gid(),
-            pid: _req.pid(),
+        let files = match self.files() {
+            Ok(files) => files,
+            Err(e) => {
+                info!("opendir error 2 {}", e); 
+                return reply.error(errno(e.as_ref()));
+            }
         };
-
-        let gid = _req.gid().to_string().clone();
-        let bin_pre = self.binstore.bin(self.username.as_str());
-        let bin_res = self.runtime.block_on(bin_pre);
-        
-        info!("front opendir: get bin_res");
-        match bin_res {
-            Ok(bin) => {
-                let bin_opendir_pre = bin.opendir(freq, _ino, _flags);
-
-                let res = self.runtime.block_on(bin_opendir_pre);
-
-                match res {
-                    Ok((res_op, error_code)) => {
-                        if error_code != SUCCESS {
-                            reply.error(error_code);
-                        } else {
-                            let (fh, open_flags) = res_op.unwrap();
-                            reply.opened(fh, open_flags);
-                        }
+        let freq = self.request(_req);
+        self.spawn(async move {
+            let res = files.bin.opendir(&freq, _ino, _flags).await;
+
+            match res {
+                Ok((res_op, error_code)) => {
+                    if error_code != SUCCESS {
+                        reply.error(error_code);
+                    } else {
+                        let (fh, open_flags) = res_op.unwrap();
+                        reply.opened(fh, open_flags);
                     }
-                    Err(e) => {
-                        info!("opendir error 1 {}", e); 
-                        reply.error(libc::ENETDOWN)
-                    },
                 }
+                Err(e) => {
+                    info!("opendir error 1 {}", e); 
+                    reply.error(errno(e.as_ref()))
+                },
             }
-            Err(e) => {
-                info!("opendir error 2 {}", e); 
-                reply.error(libc::ENETDOWN)
-            },
-        }
+        });
     }
 
     fn readdir(
@@ -774,68 +920,57 @@ impl Filesystem for Front {
         mut reply: ReplyDirectory,
     ) {
         info!("front readdir {}", &inode);
-        let freq = &FileRequest {
-            uid: _req.uid(),
-            gid: _req.gid(),
-            pid: _req.pid(),
+        let files = match self.files() {
+            Ok(files) => files,
+            Err(e) => {
+                info!("readdir error 2 {}", e); 
+                return reply.error(errno(e.as_ref()));
+            }
         };
+        let freq = self.request(_req);
 
-        let gid = _req.gid().to_string().clone();
-        
         let mut offset = offset.clone();
 
-        loop{
-            let bin_pre = self.binstore.bin(self.username.as_str());
-            let bin_res = self.runtime.block_on(bin_pre);
-            match bin_res {
-                Ok(bin) => {
-                    let bin_readdir_pre = bin.readdir(freq, inode, _fh, offset);
-                    offset = offset + 1;
-                    let res = self.runtime.block_on(bin_readdir_pre);
-                    match res {
-                        Ok((res_op, error_code)) => {
-                            if error_code != SUCCESS {
-                                reply.error(error_code);
-                                return 
-                            } else {
-                                match res_op{
-                                    Some((inode, offset, filetype, datalist)) =>{
-                                        let name_vec = datalist.0; 
-                                        info!("readdir is success with name {:?}", &name_vec);
-                                        let isfull = reply.add(
-                                            inode, 
-                                            offset,
-                                            filetype,
-                                            OsStr::from_bytes(&name_vec),
-                                        ); 
-                                        if isfull{
-                                            reply.ok();
-                                            return
-                                        }          
-                                    }, 
-                                    None => {
+        self.spawn(async move {
+            loop {
+                let res = files.bin.readdir(&freq, inode, _fh, offset).await;
+                offset += 1;
+                match res {
+                    Ok((res_op, error_code)) => {
+                        if error_code != SUCCESS {
+                            reply.error(error_code);
+                            return 
+                        } else {
+                            match res_op{
+                                Some((inode, offset, filetype, datalist)) =>{
+                                    let name_vec = datalist.0; 
+                                    info!("readdir is success with name {:?}", &name_vec);
+                                    let isfull = reply.add(
+                                        inode, 
+                                        offset,
+                                        filetype,
+                                        OsStr::from_bytes(&name_vec),
+                                    ); 
+                                    if isfull{
                                         reply.ok();
                                         return
-                                    }
+                                    }          
+                                }, 
+                                None => {
+                                    reply.ok();
+                                    return
                                 }
                             }
                         }
-                        Err(e) => {
-                            info!("readdir error 1 {}", e); 
-                            reply.error(libc::ENETDOWN);
-                            return
-                        },
                     }
+                    Err(e) => {
+                        info!("readdir error 1 {}", e); 
+                        reply.error(errno(e.as_ref()));
+                        return
+                    },
                 }
-                Err(e) => {
-                    info!("readdir error 2 {}", e); 
-                    reply.error(libc::ENETDOWN);
-                    return
-                },
             }
-
-        }
-        
+        });
     }
 
     fn releasedir(
@@ -847,40 +982,31 @@ impl Filesystem for Front {
         reply: ReplyEmpty,
     ) {
         info!("front releasedir function {}", _ino);
-        let freq = &FileRequest {
-            uid: _req.uid(),
-            gid: _req.gid(),
-            pid: _req.pid(),
+        let files = match self.files() {
+            Ok(files) => files,
+            Err(e) => {
+                info!("releasedir error 2 {}", e); 
+                return reply.error(errno(e.as_ref()));
+            }
         };
-        let gid = _req.gid().to_string().clone();
-        let bin_pre = self.binstore.bin(self.username.as_str());
-        let bin_res = self.runtime.block_on(bin_pre);
-
-        match bin_res {
-            Ok(bin) => {
-                let bin_releasedir_pre = bin.releasedir(freq, _ino, _fh, _flags);
-
-                let res = self.runtime.block_on(bin_releasedir_pre);
-
-                match res {
-                    Ok(error_code) => {
-                        if error_code != SUCCESS {
-                            reply.error(error_code)
-                        } else {
-                            reply.ok();
-                        }
+        let freq = self.request(_req);
+        self.spawn(async move {
+            let res = files.bin.releasedir(&freq, _ino, _fh, _flags).await;
+
+            match res {
+                Ok(error_code) => {
+                    if error_code != SUCCESS {
+                        reply.error(error_code)
+                    } else {
+                        reply.ok();
                     }
-                    Err(e) => {
-                        info!("releasedir error 1 {}", e); 
-                        reply.error(libc::ENETDOWN);
-                    },                
                 }
+                Err(e) => {
+                    info!("releasedir error 1 {}", e); 
+                    reply.error(errno(e.as_ref()));
+                },                
             }
-            Err(e) => {
-                info!("releasedir error 2 {}", e); 
-                reply.error(libc::ENETDOWN);
-            }
-        }
+        });
     }
 
 
@@ -894,40 +1020,36 @@ impl Filesystem for Front {
         reply: ReplyEntry,
     ) {
         info!("front mkdir function {}", parent);
-        let freq = &FileRequest {
-            uid: req.uid(),
-            gid: req.gid(),
-            pid: req.pid(),
+        let files = match self.files() {
+            Ok(files) => files,
+            Err(e) => {
+                info!("mkdir error 2 {}", e); 
+                return reply.error(errno(e.as_ref()));
+            }
         };
-        let gid = req.gid().to_string().clone();
-        let bin_pre = self.binstore.bin(self.username.as_str());
-        let bin_res = self.runtime.block_on(bin_pre);
-
-        match bin_res {
-            Ok(bin) => {
-                let bin_mkdir_pre = bin.mkdir(freq, parent, name, mode, _umask);
-                let res = self.runtime.block_on(bin_mkdir_pre);
-
-                match res {
-                    Ok((attrs_op, error_code)) => {
-                        if error_code != SUCCESS {
-                            reply.error(error_code);
-                        } else {
-                            let attrs = attrs_op.unwrap();
-                            reply.entry(&Duration::new(0, 0), &attrs, 0);
-                        }
+        let freq = self.request(req);
+        let name = name.to_os_string();
+        self.spawn(async move {
+            let res = files.bin.mkdir(&freq, parent, &name, mode, _umask).await;
+
+            match res {
+                Ok((attrs_op, error_code)) => {
+                    if error_code != SUCCESS {
+                        reply.error(error_code);
+                    } else {
+                        let attrs = attrs_op.unwrap();
+                        let ttl = files.cache().config().entry_ttl();
+                        files.cache().invalidate(parent);
+                        files.cache().insert_entry(parent, &name, &attrs);
+                        reply.entry(&ttl, &attrs, 0);
                     }
-                    Err(e) => {
-                        info!("mkdir error 1 {}", e); 
-                        reply.error(libc::ENETDOWN);
-                    },
                 }
+                Err(e) => {
+                    info!("mkdir error 1 {}", e); 
+                    reply.error(errno(e.as_ref()));
+                },
             }
-            Err(e) => {
-                info!("mkdir error 2 {}", e); 
-                reply.error(libc::ENETDOWN);
-            }
-        }
+        });
     }
 
     fn mknod(
@@ -0,0 +1,244 @@
+//! Applies the invalidations backends push to a front-end.
+//!
+//! Every backend hands the front a read lease on what it reads through it,
+//! and tells it over a watch stream when another client changes one of
+//! those inodes, or an entry of one of those directories. The front then
+//! drops what it cached, and has the kernel drop its copy too.
+//!
+//! A stream that breaks, or stays silent past its heartbeats, may have
+//! lost invalidations, so the whole cache is dropped before watching again.
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::sync::{Arc, Mutex};
+
+use fuser::Notifier;
+use log::info;
+use tonic::transport::Endpoint;
+use tribbler::config::{parse_shard_name, DEFAULT_NAMESPACE};
+use tribbler::disfuser::disfuser_client::DisfuserClient;
+use tribbler::disfuser::{Invalidation, Watch};
+use tribbler::error::{TritonFileError, TritonFileResult};
+
+use super::cache::AttrCache;
+use super::client::client_id;
+use super::membership::{Membership, REFRESH_INTERVAL, WATCH_TIMEOUT};
+
+/// The cache of one front-end, as seen by the invalidations of its
+/// namespace.
+#[derive(Clone)]
+pub struct Invalidator {
+    cache: Arc<Mutex<AttrCache>>,
+    namespace: String,
+    notifier: Option<Arc<Notifier>>,
+}
+
+impl Invalidator {
+    pub fn new(cache: Arc<Mutex<AttrCache>>, namespace: String) -> Invalidator {
+        Invalidator {
+            cache,
+            namespace,
+            notifier: None,
+        }
+    }
+
+    /// Has the kernel drop its copy, through the `notifier` of the session
+    /// the front is mounted with, whenever the front drops its own.
+    pub fn with_notifier(self, notifier: Notifier) -> Invalidator {
+        Invalidator {
+            notifier: Some(Arc::new(notifier)),
+            ..self
+        }
+    }
+
+    // Whether files of `namespace`, as a backend names it, belong to the
+    // front's: the default namespace is the backends' own files, the others
+    // are kept in a bin for each shard.
+    fn concerns(&self, namespace: Option<&str>) -> bool {
+        match namespace {
+            None => self.namespace == DEFAULT_NAMESPACE,
+            Some(namespace) => match parse_shard_name(namespace) {
+                Some((name, _)) => name == self.namespace,
+                None => false,
+            },
+        }
+    }
+
+    /// Drops from the cache what `invalidation` is about, then from the
+    /// kernel's. Returns whether it concerns the front at all, heartbeats
+    /// never do.
+    pub fn forget(&self, invalidation: &Invalidation) -> bool {
+        if invalidation.ino == 0 || !self.concerns(invalidation.namespace.as_deref()) {
+            return false;
+        }
+        let ino = invalidation.ino;
+        let name = invalidation.name.as_deref().map(OsStr::new);
+        {
+            let mut cache = self.cache.lock().unwrap();
+            if let Some(name) = name {
+                cache.invalidate_entry(ino, name);
+            }
+            cache.invalidate(ino);
+        }
+        // The kernel may not know the inode, or the name, anymore, which is
+        // fine.
+        if let Some(notifier) = &self.notifier {
+            if let Some(name) = name {
+                let _ = notifier.inval_entry(ino, name);
+            }
+            let _ = notifier.inval_inode(ino, 0, 0);
+        }
+        true
+    }
+
+    /// Drops the whole cache.
+    pub fn clear(&self) {
+        self.cache.lock().unwrap().clear();
+    }
+
+    /// Watches every backend of `membership`, as it changes, from a thread
+    /// of its own. The kernel is only told from that thread, never from
+    /// within a request it is waiting on.
+    pub fn spawn(self, membership: Arc<Membership>) -> std::thread::JoinHandle<()> {
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            runtime.block_on(self.follow(membership));
+        })
+    }
+
+    /// Watches every backend of `membership`, as it changes, until dropped.
+    pub async fn follow(self, membership: Arc<Membership>) {
+        let mut watched = HashMap::new();
+        loop {
+            let backs = membership.backs();
+            watched.retain(|addr: &String, task: &mut tokio::task::JoinHandle<()>| {
+                let keep = backs.contains(addr);
+                if !keep {
+                    task.abort();
+                }
+                keep
+            });
+            for addr in backs {
+                if watched.contains_key(&addr) {
+                    continue;
+                }
+                let invalidator = self.clone();
+                let task = tokio::spawn({
+                    let addr = addr.clone();
+                    async move {
+                        loop {
+                            if let Err(e) = invalidator.watch(&addr).await {
+                                info!("watching backend {} failed: {}", addr, e);
+                            }
+                            invalidator.clear();
+                            tokio::time::sleep(REFRESH_INTERVAL).await;
+                        }
+                    }
+                });
+                watched.insert(addr, task);
+            }
+            tokio::time::sleep(REFRESH_INTERVAL).await;
+        }
+    }
+
+    // Applies the invalidations streamed by the backend at `addr` until the
+    // stream breaks.
+    async fn watch(&self, addr: &str) -> TritonFileResult<()> {
+        let channel = Endpoint::from_shared(format!("http://{}", addr))?.connect_lazy();
+        let mut stream = DisfuserClient::new(channel)
+            .watch(Watch {
+                client: client_id().to_string(),
+            })
+            .await?
+            .into_inner();
+        loop {
+            let invalidation = match tokio::time::timeout(WATCH_TIMEOUT, stream.message()).await {
+                Ok(invalidation) => invalidation?,
+                Err(_) => {
+                    return Err(Box::new(TritonFileError::DeadlineExceeded(format!(
+                        "watch on backend {}",
+                        addr
+                    ))))
+                }
+            };
+            match invalidation {
+                Some(invalidation) => {
+                    self.forget(&invalidation);
+                }
+                None => {
+                    return Err(Box::new(TritonFileError::RpcError(
+                        "watch closed".to_string(),
+                    )))
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::OsStr;
+    use std::sync::{Arc, Mutex};
+    use std::time::UNIX_EPOCH;
+
+    use fuser::{FileAttr, FileType};
+    use tribbler::config::{CacheConfig, DEFAULT_NAMESPACE};
+    use tribbler::disfuser::Invalidation;
+
+    use super::Invalidator;
+    use crate::client_fs::cache::{AttrCache, Cached};
+
+    fn attr(ino: u64) -> FileAttr {
+        FileAttr {
+            ino,
+            size: 0,
+            blocks: 0,
+            atime: UNIX_EPOCH,
+            mtime: UNIX_EPOCH,
+            ctime: UNIX_EPOCH,
+            crtime: UNIX_EPOCH,
+            kind: FileType::RegularFile,
+            perm: 0o644,
+            nlink: 1,
+            uid: 0,
+            gid: 0,
+            rdev: 0,
+            blksize: 512,
+            flags: 0,
+        }
+    }
+
+    fn invalidation(namespace: Option<&str>, ino: u64, name: Option<&str>) -> Invalidation {
+        Invalidation {
+            namespace: namespace.map(|namespace| namespace.to_string()),
+            ino,
+            name: name.map(|name| name.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_only_own_namespace_is_forgotten() {
+        let cache = Arc::new(Mutex::new(AttrCache::new(CacheConfig::default())));
+        cache
+            .lock()
+            .unwrap()
+            .insert_entry(1, OsStr::new("a"), &attr(2));
+        let team = Invalidator::new(cache.clone(), "team".to_string());
+        let default = Invalidator::new(cache.clone(), DEFAULT_NAMESPACE.to_string());
+
+        assert!(!team.forget(&invalidation(None, 2, None)));
+        assert!(!team.forget(&invalidation(Some("other"), 2, None)));
+        assert!(!team.forget(&invalidation(Some("team"), 0, None)));
+        assert!(team.forget(&invalidation(Some("team@3"), 5, None)));
+        assert!(cache.lock().unwrap().attr(2).is_some());
+
+        assert!(default.forget(&invalidation(None, 1, Some("a"))));
+        assert_eq!(
+            Cached::Unknown,
+            cache.lock().unwrap().lookup(1, OsStr::new("a"))
+        );
+        assert_eq!(None, cache.lock().unwrap().attr(2));
+    }
+}
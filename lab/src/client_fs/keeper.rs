@@ -1,8 +1,13 @@
 use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    net::ToSocketAddrs,
+    pin::Pin,
     sync::{atomic, Arc},
+    time::{Duration, SystemTime, UNIX_EPOCH},
     vec,
 };
 
+use async_trait::async_trait;
 use core::sync::atomic::AtomicU64;
 use log::info;
 use serde::{Deserialize, Serialize};
@@ -10,20 +15,37 @@ use std::sync::mpsc::Sender;
 use tokio::{
     sync::{
         mpsc::{error::TryRecvError, Receiver},
-        Mutex,
+        watch, Mutex,
     },
+    net::TcpListener,
     time::{self, timeout},
 };
+use tokio_stream::{
+    wrappers::{ReceiverStream, TcpListenerStream},
+    Stream,
+};
+use tonic::transport::{Endpoint, Server};
+use tonic::{Code, Request, Response, Status};
 use tribbler::{
-    config::KeeperConfig,
-    error::TritonFileResult,
-    storage::{KeyValue, FileRequest},
+    colon,
+    config::{shard_name, KeeperConfig, Namespace, DEFAULT_NAMESPACE},
+    error::{TritonFileError, TritonFileResult},
+    keeper::admin_client::AdminClient,
+    keeper::admin_server::{Admin, AdminServer},
+    keeper::membership_server::{Membership, MembershipServer},
+    keeper::{
+        Assignment, Backend, Bin, ClusterConfig as ConfigReply, Empty, Job, JobList,
+        ReplicationStatus, View,
+    },
+    storage::{KeyValue, MembershipView, NodeCopy},
     storage::{self, Storage, hash_name_to_idx},
 };
 
 use crate::client_fs::client::new_client;
 
-use super::binstore::{self, BinStore};
+use super::binstore::{self, BinStore, KEY_REPAIR_LOG};
+use super::merkle::{self, Divergence};
+use super::ops::RepairRecord;
 #[derive(Serialize, Deserialize, Debug)]
 enum LiveState {
     True(bool),
@@ -32,36 +54,234 @@ enum LiveState {
 
 const KEY_KEEPER: &str = "KEEPER";
 const KEY_TIMESTAMP: &str = "TIMESTAMP";
-const KEY_KEEPER_REPLICATE: &str = "LIVE_LIST_STATE";
+const KEY_KEEPER_STATE: &str = "KEEPER_STATE";
+const KEY_LEADER_LEASE: &str = "LEADER_LEASE";
+const KEY_LEADER_CLAIM: &str = "LEADER_CLAIM";
+const KEY_MEMBERSHIP_VIEW: &str = "MEMBERSHIP_VIEW";
+const KEY_CLUSTER_CONFIG: &str = "CLUSTER_CONFIG";
+const KEY_UNDER_REPLICATED: &str = "UNDER_REPLICATED";
+const KEY_ANTI_ENTROPY: &str = "ANTI_ENTROPY";
+
+/// Tries a replication job gets in one round before it is marked failed.
+const JOB_ATTEMPTS: u32 = 3;
+/// Delay before the first retry of a job, doubled for every later one.
+const JOB_RETRY_DELAY: Duration = Duration::from_millis(500);
+/// Inodes a file copy restores on the target between two checkpoints.
+const FILE_BATCH: usize = 64;
+/// Finished jobs kept in the keeper state, so admins can list them.
+const FINISHED_JOBS_KEPT: usize = 16;
+/// How long the leader waits for a backend to accept a membership view.
+const FENCE_TIMEOUT: Duration = Duration::from_secs(1);
+/// How often the leader compares the file replicas of the namespaces.
+pub const ANTI_ENTROPY_INTERVAL: Duration = Duration::from_secs(30);
+/// A watch stream resends the current view at least this often, so clients
+/// can tell a quiet keeper from a dead one.
+const WATCH_KEEPALIVE: Duration = Duration::from_secs(1);
+
+/// How long a leader lease is valid after it was acquired or renewed. A
+/// leader renews it every round, so a follower takes over at most
+/// `LEASE_DURATION` plus one round after the leader died.
+pub const LEASE_DURATION: Duration = Duration::from_secs(10);
+/// A leader stops acting this long before its lease expires, so that clock
+/// skew between keepers cannot make two of them act at the same time.
+const LEASE_GUARD: Duration = Duration::from_secs(2);
+/// Time a keeper waits between claiming or renewing a lease and reading the
+/// claims back to see whether a concurrent candidate overwrote it.
+const LEASE_SETTLE: Duration = Duration::from_millis(500);
+
+/// The leader lease stored in the `KEEPER` bin. `epoch` is bumped every time
+/// leadership changes hands.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Lease {
+    pub leader: usize,
+    /// Incarnation id of the leader, so a restarted keeper does not mistake
+    /// the lease of its previous incarnation for its own.
+    pub id: u128,
+    pub epoch: u64,
+    /// Milliseconds since the Unix epoch.
+    pub expires_at: u64,
+}
+
+/// The backends of the cluster, stored in the `KEEPER` bin once an admin
+/// changed them. Until then the backends of the config file are used, as
+/// version 0.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ClusterConfig {
+    pub version: u64,
+    pub backs: Vec<String>,
+    /// Backends no bin is placed on any more. They count as dead in the
+    /// membership view, but stay reachable so their bins can be copied.
+    #[serde(default)]
+    pub draining: Vec<String>,
+    /// Draining backends whose bins have all been copied elsewhere, and
+    /// which are safe to remove.
+    #[serde(default)]
+    pub drained: Vec<String>,
+}
+
+/// A copy the leader makes to bring replicas in line with a new live list:
+/// the key-value data or the files that backend `for_addr` is primary for,
+/// from backend `from` to backend `to` (indices into the cluster config).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ReplicationTask {
+    pub from: usize,
+    pub to: usize,
+    pub for_addr: usize,
+    pub files: bool,
+}
+
+/// Where a [ReplicationJob] is at.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum JobState {
+    Pending,
+    Running,
+    Done,
+    Failed,
+}
+
+/// A [ReplicationTask] as the leader tracks it. Key-value copies go bin by
+/// bin and record the last bin copied, file copies go in batches of inodes
+/// and record the last inode copied, so a retried or resumed job skips
+/// what is already done.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ReplicationJob {
+    pub id: u64,
+    pub task: ReplicationTask,
+    pub state: JobState,
+    /// Tries made so far, across rounds and leaders.
+    pub attempts: u32,
+    /// The last bin whose keys were copied, or for a file copy the number
+    /// of the last inode copied.
+    pub checkpoint: Option<String>,
+    /// Values or inodes copied so far.
+    pub copied: u64,
+    /// Why the last try failed.
+    pub error: Option<String>,
+}
+
+/// The leader's progress, saved in the `KEEPER` bin after every step, so
+/// that a keeper taking over, or the same keeper after a restart, resumes
+/// where the last leader stopped instead of starting over.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct KeeperState {
+    /// Epoch of the lease the state was saved under.
+    pub epoch: u64,
+    /// Version of the cluster config the live lists and tasks index into.
+    pub config_version: u64,
+    /// Live list of the last round that completed.
+    pub live_list: Vec<bool>,
+    /// Live list the replication in progress moves to, if any.
+    #[serde(default)]
+    pub target: Option<Vec<bool>>,
+    /// Up to [FINISHED_JOBS_KEPT] done jobs of earlier replications, then
+    /// the jobs of this one in the order they were planned.
+    #[serde(default)]
+    pub jobs: Vec<ReplicationJob>,
+    #[serde(default)]
+    pub next_job: u64,
+}
+
+impl KeeperState {
+    fn add_jobs(&mut self, tasks: Vec<ReplicationTask>) {
+        for task in tasks {
+            self.jobs.push(ReplicationJob {
+                id: self.next_job,
+                task,
+                state: JobState::Pending,
+                attempts: 0,
+                checkpoint: None,
+                copied: 0,
+                error: None,
+            });
+            self.next_job += 1;
+        }
+    }
+
+    // Forgets the jobs that are not done, and the done ones beyond the
+    // newest [FINISHED_JOBS_KEPT].
+    fn keep_finished(&mut self) {
+        self.jobs.retain(|job| job.state == JobState::Done);
+        let extra = self.jobs.len().saturating_sub(FINISHED_JOBS_KEPT);
+        self.jobs.drain(..extra);
+    }
+
+    fn unfinished(&self) -> usize {
+        self.jobs
+            .iter()
+            .filter(|job| job.state != JobState::Done)
+            .count()
+    }
+}
+
+/// A change to the backends of the cluster, made through the leader keeper.
+#[derive(Debug, Clone)]
+pub enum ConfigChange {
+    AddBackend(String),
+    RemoveBackend(String),
+    DrainBackend(String),
+    /// Only fetch the current config.
+    Show,
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
 
 struct Keeper {
     clock: Arc<AtomicU64>,
     // Addresses for keepers.
     addrs: Arc<Mutex<Vec<String>>>,
-    // Addresses for backends.
+    // Addresses for backends. Always lock `live_list` first when both are
+    // needed.
     backs: Arc<Mutex<Vec<String>>>,
+    // Backends of the config file, which hold the keeper bin and so can't be
+    // retired.
+    origin: Vec<String>,
+    // The cluster config `backs` comes from.
+    config: Mutex<ClusterConfig>,
     this: usize,
+    id: u128,
     keep_bin: Box<dyn storage::Storage>,
     timestamp: Arc<AtomicU64>,
     live_list: Arc<Mutex<Vec<bool>>>,
+    // The bins files are kept in: the default namespace's and those of the
+    // shards of every namespace of the config.
+    file_bins: Vec<String>,
+    // The lease this keeper currently holds, if it is the leader.
+    lease: Mutex<Option<Lease>>,
+    // The newest membership view this keeper knows of, served to clients.
+    view: watch::Sender<MembershipView>,
 }
 
 impl Keeper {
     async fn new(
         kp_addrs: Vec<String>,
         bk_addrs: Vec<String>,
-        ready: Option<Sender<bool>>,
+        this: usize,
+        id: u128,
+        namespaces: &[Namespace],
         storage: Box<dyn Storage>,
     ) -> Keeper {
-        let r = send_signal(&ready, true);
         Keeper {
             clock: Arc::new(atomic::AtomicU64::new(1)),
             addrs: Arc::new(Mutex::new(kp_addrs.clone())),
             backs: Arc::new(Mutex::new(bk_addrs.clone())),
-            this: 0,
+            origin: bk_addrs.clone(),
+            config: Mutex::new(ClusterConfig {
+                backs: bk_addrs.clone(),
+                ..Default::default()
+            }),
+            this,
+            id,
             keep_bin: storage,
             timestamp: Arc::new(atomic::AtomicU64::new(1)),
             live_list: Arc::new(Mutex::new(vec![false; bk_addrs.len()])),
+            file_bins: file_bins(namespaces),
+            lease: Mutex::new(None),
+            view: watch::channel(MembershipView::default()).0,
         }
     }
 }
@@ -91,29 +311,84 @@ fn should_shutdown(shutdown: &mut Option<Receiver<()>>) -> TritonFileResult<bool
 
 pub async fn serve_keeper(kc: KeeperConfig) -> TritonFileResult<()> {
     info!("Serve_keeper request, config: {:?}", &kc);
+    let addr = match kc.addr().to_socket_addrs()?.next() {
+        Some(addr) => addr,
+        None => {
+            send_signal(&kc.ready, false)?;
+            return Err(Box::new(TritonFileError::Unknown(format!(
+                "can't resolve {}",
+                kc.addr()
+            ))));
+        }
+    };
+    // Bound before anyone is told the keeper is ready, so that they can
+    // reach it as soon as they are.
+    let incoming = match TcpListener::bind(addr).await {
+        Ok(listener) => TcpListenerStream::new(listener),
+        Err(e) => {
+            send_signal(&kc.ready, false)?;
+            return Err(Box::new(e));
+        }
+    };
     let backs = kc.backs.clone();
-    let keep_bin = BinStore::new(backs).keeper_bin(KEY_KEEPER).await?;
-    let keeper = Arc::new(Keeper::new(kc.addrs, kc.backs, kc.ready, keep_bin).await);
+    let keep_bin = match BinStore::for_keeper(backs).keeper_bin(KEY_KEEPER).await {
+        Ok(keep_bin) => keep_bin,
+        Err(e) => {
+            send_signal(&kc.ready, false)?;
+            return Err(e);
+        }
+    };
+    let keeper = Arc::new(Keeper::new(kc.addrs, kc.backs, kc.this, kc.id, &kc.namespaces, keep_bin).await);
+    if let Err(err) = keeper.load_timestamp().await {
+        info!("can't load the keeper clock: {}", err);
+    }
     let mut shutdown = kc.shutdown;
 
+    let mut server = tokio::spawn(
+        Server::builder()
+            .add_service(MembershipServer::new(MembershipService {
+                keeper: Arc::clone(&keeper),
+            }))
+            .add_service(AdminServer::new(AdminService {
+                keeper: Arc::clone(&keeper),
+            }))
+            .serve_with_incoming(incoming),
+    );
     let (sender, receiver) = tokio::sync::mpsc::channel(1);
-    let _ = tokio::spawn(background_update_clock(Arc::clone(&keeper), receiver));
-    loop {
-        if should_shutdown(&mut shutdown)? {
-            let _ = sender.send(());
-            return Ok(());
-        }
+    tokio::spawn(background_update_clock(Arc::clone(&keeper), receiver));
+    let (verify_sender, verify_receiver) = tokio::sync::mpsc::channel(1);
+    tokio::spawn(background_anti_entropy(Arc::clone(&keeper), verify_receiver));
+    send_signal(&kc.ready, true)?;
+
+    let rounds = async {
+        loop {
+            if should_shutdown(&mut shutdown)? {
+                return Ok(());
+            }
 
-        // 3 seconds between each round.
-        let wait = time::sleep_until(time::Instant::now() + time::Duration::from_secs(3));
-        match keeper.serve_one_round().await {
-            Err(err) => {
+            // 3 seconds between each round.
+            let wait = time::sleep_until(time::Instant::now() + time::Duration::from_secs(3));
+            if let Err(err) = keeper.serve_one_round().await {
                 info!("Encountered error: {}", err.to_string());
-                continue;
             }
-            Ok(_) => wait.await,
+            wait.await;
         }
-    }
+    };
+    // A server that stops takes the keeper down with it.
+    let result = tokio::select! {
+        served = &mut server => match served {
+            Ok(served) => served.map_err(|e| e.into()),
+            Err(e) => Err(e.into()),
+        },
+        result = rounds => result,
+    };
+    let _ = sender.try_send(());
+    let _ = verify_sender.try_send(());
+    // Wait for the server to be gone, so that its address is free again once
+    // the keeper returned.
+    server.abort();
+    let _ = server.await;
+    result
 }
 
 async fn background_update_clock(
@@ -134,6 +409,23 @@ async fn background_update_clock(
     }
 }
 
+async fn background_anti_entropy(
+    keeper: Arc<Keeper>,
+    shutdown: Receiver<()>,
+) -> TritonFileResult<()> {
+    let mut shutdown = Some(shutdown);
+    loop {
+        if should_shutdown(&mut shutdown)? {
+            return Ok(());
+        }
+        let wait = time::sleep_until(time::Instant::now() + ANTI_ENTROPY_INTERVAL);
+        if let Err(err) = keeper.verify_replicas().await {
+            info!("Anti-entropy error: {}", err.to_string());
+        }
+        wait.await;
+    }
+}
+
 fn next_live(this: usize, live_list: &Vec<bool>, step: i32) -> Option<usize> {
     let mut this = this;
     // Only check for one round.
@@ -162,8 +454,8 @@ fn next_live(this: usize, live_list: &Vec<bool>, step: i32) -> Option<usize> {
 
 fn key_primary_idx(key_id: usize, live_list: &Vec<bool>) -> Option<usize> {
     let mut this = key_id;
-    // Only check for one round.
-    for _ in 0..live_list.len() - 1 {
+    // Only check for one round, including the key's own backend.
+    for _ in 0..live_list.len() {
         // If the key is right on the live server
         if live_list[this] {
             return Some(this);
@@ -178,6 +470,40 @@ fn key_primary_idx(key_id: usize, live_list: &Vec<bool>) -> Option<usize> {
     None
 }
 
+// A backend joining between `primary` and `old_backup` takes over as
+// backup of both.
+fn join_tasks(primary: usize, old_backup: usize, new_backup: usize) -> Vec<ReplicationTask> {
+    let task = |from, for_addr, files| ReplicationTask {
+        from,
+        to: new_backup,
+        for_addr,
+        files,
+    };
+    vec![
+        task(old_backup, new_backup, false),
+        task(primary, primary, false),
+        task(old_backup, new_backup, true),
+        task(primary, primary, true),
+    ]
+}
+
+// A backend leaving between `before` and `after`: `after` becomes the
+// primary of what it backed up and `after_after` its backup.
+fn leave_tasks(before: usize, after: usize, after_after: usize) -> Vec<ReplicationTask> {
+    let task = |from, to, files| ReplicationTask {
+        from,
+        to,
+        for_addr: from,
+        files,
+    };
+    vec![
+        task(after, after_after, false),
+        task(before, after, false),
+        task(after, after_after, true),
+        task(before, after, true),
+    ]
+}
+
 impl Keeper {
     fn print_name(&self) -> String {
         format!(
@@ -189,70 +515,897 @@ impl Keeper {
 
     async fn serve_one_round(&self) -> TritonFileResult<()> {
         info!("{} serving one round", self.print_name(),);
-        let living_keepers = self.living_keepers().await?;
-        // I'm leader, serve and return.
-        info!("{}: I'm leader", self.print_name());
+        let lease = match self.acquire_lease().await? {
+            Some(lease) => lease,
+            None => {
+                info!("{}: following", self.print_name());
+                return self.follow_view().await;
+            }
+        };
+        info!("{}: I'm leader of epoch {}", self.print_name(), lease.epoch);
         let mut old_live_list = self.live_list.lock().await;
+        self.load_config(&mut old_live_list).await?;
+        // Finish the replication an earlier round or leader left behind.
+        self.resume_replication(&lease, &mut old_live_list).await?;
         // Get new live list, save it, and serve as keeper.
         let new_live_list = self.broadcast(time::Duration::from_millis(1000)).await?;
-        self.serve_as_leader(&old_live_list, &new_live_list).await?;
+        // Fence before replicating, so clients stop writing to backends that
+        // left as soon as possible.
+        check_lease(&lease)?;
+        self.publish_view(&new_live_list).await?;
+        self.serve_as_leader(&lease, &old_live_list, &new_live_list)
+            .await?;
+        self.track_replication(&lease, &new_live_list).await?;
         // It is important that we save the live list AFTER
         // doing the work. So that we don't miss the work if
         // we crash mid way.
-        self.save_live_list_backup(&new_live_list).await?;
+        check_lease(&lease)?;
+        self.save_live_list(&lease, &new_live_list).await?;
         *old_live_list = new_live_list;
-        //return Ok(());
-        // If we reach here, we might become the leader.
-        // let min_living_keeper = living_keepers.iter().min().unwrap();
-        // if *min_living_keeper == self.this {
-        //     info!("{}: leader dead, I'm the new leader", self.print_name());
-        //     // When we become leader, inherit live_list from last
-        //     // leader, or use default (all dead).
-        //     let last_leader_live_list = match self.get_live_list_backup().await? {
-        //         None => vec![false; self.backs.lock().await.len()],
-        //         Some(list) => list,
-        //     };
-        //     let mut keeper_live_list = self.live_list.lock().await;
-        //     *keeper_live_list = last_leader_live_list;
-        // } else {
-        //     info!("{}: leader dead, but I'm not min idx", self.print_name());
-        // }
         Ok(())
     }
 
+    // Acquire the leader lease, or renew it if we already hold it. Returns
+    // the lease if we are the leader for this round.
+    //
+    // Each epoch is taken through a claim keyed on it: candidates write their
+    // claim, wait for concurrent ones to land and read it back, and only the
+    // keeper whose claim survived writes the lease. A leader renews only while
+    // its claim still stands and nobody claimed the next epoch, so a stalled
+    // leader cannot overwrite the lease of the keeper that replaced it.
+    async fn acquire_lease(&self) -> TritonFileResult<Option<Lease>> {
+        let mut held = self.lease.lock().await;
+        let now = now_millis();
+        let mut current = self.get_lease().await?;
+        // A claim on a later epoch stands in for a lease its winner has not
+        // written yet, or that a deposed leader overwrote.
+        while let Some(claim) = self
+            .get_claim(current.as_ref().map_or(1, |l| l.epoch + 1))
+            .await?
+        {
+            current = Some(claim);
+        }
+        let candidate = match &current {
+            Some(lease) if lease.leader == self.this && lease.id == self.id => {
+                if held.as_ref().map(|l| l.epoch) != Some(lease.epoch) {
+                    // We hold a lease we did not know about, e.g. the round
+                    // that acquired it failed half way. Inherit state again.
+                    *held = None;
+                }
+                Lease {
+                    expires_at: now + LEASE_DURATION.as_millis() as u64,
+                    ..lease.clone()
+                }
+            }
+            Some(lease) if now < lease.expires_at => {
+                if held.take().is_some() {
+                    info!(
+                        "{}: deposed by keeper {} in epoch {}",
+                        self.print_name(),
+                        lease.leader,
+                        lease.epoch
+                    );
+                }
+                return Ok(None);
+            }
+            _ => {
+                // The lease is vacant or expired. Only the lowest living
+                // keeper runs for it, so candidates rarely collide.
+                let living_keepers = self.living_keepers().await?;
+                if living_keepers.iter().min() != Some(&self.this) {
+                    info!("{}: leader dead, but I'm not min idx", self.print_name());
+                    return Ok(None);
+                }
+                let candidate = Lease {
+                    leader: self.this,
+                    id: self.id,
+                    epoch: current.as_ref().map_or(1, |l| l.epoch + 1),
+                    expires_at: now + LEASE_DURATION.as_millis() as u64,
+                };
+                self.keep_bin
+                    .set(&KeyValue {
+                        key: claim_key(candidate.epoch),
+                        value: serde_json::to_string(&candidate)?,
+                    })
+                    .await?;
+                *held = None;
+                candidate
+            }
+        };
+
+        // Let concurrent claims land, then check ours is the one that stuck.
+        time::sleep(LEASE_SETTLE).await;
+        if !self.still_holds(&candidate).await? {
+            if held.take().is_some() {
+                info!(
+                    "{}: deposed in epoch {}",
+                    self.print_name(),
+                    candidate.epoch
+                );
+            } else {
+                info!("{}: lost the election", self.print_name());
+            }
+            return Ok(None);
+        }
+        self.keep_bin
+            .set(&KeyValue {
+                key: KEY_LEADER_LEASE.to_string(),
+                value: serde_json::to_string(&candidate)?,
+            })
+            .await?;
+
+        if held.is_none() {
+            info!(
+                "{}: leader dead, I'm the new leader of epoch {}",
+                self.print_name(),
+                candidate.epoch
+            );
+            // When we become leader, inherit the cluster config and the
+            // live_list from last leader, or use default (all dead). Its
+            // unfinished replication is resumed by the next round.
+            let mut keeper_live_list = self.live_list.lock().await;
+            self.load_config(&mut keeper_live_list).await?;
+            let config = self.current_config().await;
+            let last_leader_live_list = match self.get_state().await? {
+                Some(state)
+                    if state.config_version == config.version
+                        && state.live_list.len() == config.backs.len() =>
+                {
+                    state.live_list
+                }
+                _ => vec![false; config.backs.len()],
+            };
+            *keeper_live_list = last_leader_live_list;
+        }
+        *held = Some(candidate.clone());
+        Ok(Some(candidate))
+    }
+
+    // Whether `lease` is still ours: the claim of its epoch names this keeper
+    // and no keeper claimed the next epoch.
+    async fn still_holds(&self, lease: &Lease) -> TritonFileResult<bool> {
+        let claim = self.get_claim(lease.epoch).await?;
+        if claim.map(|c| (c.leader, c.id)) != Some((self.this, self.id)) {
+            return Ok(false);
+        }
+        Ok(self.get_claim(lease.epoch + 1).await?.is_none())
+    }
+
+    async fn get_claim(&self, epoch: u64) -> TritonFileResult<Option<Lease>> {
+        match self.keep_bin.get(&claim_key(epoch)).await? {
+            None => Ok(None),
+            Some(claim) => Ok(Some(serde_json::from_str(&claim)?)),
+        }
+    }
+
+    async fn get_lease(&self) -> TritonFileResult<Option<Lease>> {
+        match self.keep_bin.get(KEY_LEADER_LEASE).await? {
+            None => Ok(None),
+            Some(lease) => Ok(Some(serde_json::from_str(&lease)?)),
+        }
+    }
+
+    // Publishes the live list as a membership view, bumping its epoch if the
+    // list changed, and fences every live backend with it. Backends are fenced
+    // every round so that restarted ones catch up with the current epoch.
+    async fn publish_view(&self, live_list: &[bool]) -> TritonFileResult<MembershipView> {
+        let live: Vec<String> = {
+            let backs = self.backs.lock().await;
+            (0..backs.len())
+                .filter(|&idx| live_list[idx])
+                .map(|idx| backs[idx].clone())
+                .collect()
+        };
+        let ClusterConfig {
+            version: config_version,
+            backs,
+            ..
+        } = self.current_config().await;
+        let view = match self.get_view().await? {
+            Some(view) if view.live == live && view.config_version == config_version => view,
+            old => {
+                let view = MembershipView {
+                    epoch: old.map_or(1, |view| view.epoch + 1),
+                    live,
+                    backs,
+                    config_version,
+                };
+                self.keep_bin
+                    .set(&KeyValue {
+                        key: KEY_MEMBERSHIP_VIEW.to_string(),
+                        value: serde_json::to_string(&view)?,
+                    })
+                    .await?;
+                info!(
+                    "{}: membership epoch {}, live: {:?}",
+                    self.print_name(),
+                    view.epoch,
+                    &view.live
+                );
+                view
+            }
+        };
+
+        if *self.view.borrow() != view {
+            self.view.send_replace(view.clone());
+        }
+        for addr in view.live.iter() {
+            let client = new_client(addr).await?;
+            match timeout(FENCE_TIMEOUT, client.fence(&view)).await {
+                Ok(Ok(fenced)) if fenced.epoch == view.epoch => (),
+                Ok(Ok(fenced)) => info!(
+                    "{}: back {} is at epoch {}, ahead of {}",
+                    self.print_name(),
+                    addr,
+                    fenced.epoch,
+                    view.epoch
+                ),
+                Ok(Err(e)) => info!("{}: fencing {} failed: {}", self.print_name(), addr, e),
+                Err(_) => info!("{}: fencing {} timed out", self.print_name(), addr),
+            }
+        }
+        Ok(view)
+    }
+
+    // Picks up the view and the cluster config published by the leader, so
+    // that followers serve them to clients as well.
+    async fn follow_view(&self) -> TritonFileResult<()> {
+        {
+            let mut live_list = self.live_list.lock().await;
+            self.load_config(&mut live_list).await?;
+        }
+        if let Some(view) = self.get_view().await? {
+            if view.epoch > self.view.borrow().epoch {
+                self.view.send_replace(view);
+            }
+        }
+        Ok(())
+    }
+
+    async fn get_view(&self) -> TritonFileResult<Option<MembershipView>> {
+        match self.keep_bin.get(KEY_MEMBERSHIP_VIEW).await? {
+            None => Ok(None),
+            Some(view) => Ok(Some(serde_json::from_str(&view)?)),
+        }
+    }
+
+    async fn get_config(&self) -> TritonFileResult<Option<ClusterConfig>> {
+        match self.keep_bin.get(KEY_CLUSTER_CONFIG).await? {
+            None => Ok(None),
+            Some(config) => Ok(Some(serde_json::from_str(&config)?)),
+        }
+    }
+
+    async fn current_config(&self) -> ClusterConfig {
+        self.config.lock().await.clone()
+    }
+
+    async fn save_config(&self, config: &ClusterConfig) -> TritonFileResult<bool> {
+        self.keep_bin
+            .set(&KeyValue {
+                key: KEY_CLUSTER_CONFIG.to_string(),
+                value: serde_json::to_string(config)?,
+            })
+            .await
+    }
+
+    // Picks up a cluster config written by an earlier leader. The caller
+    // holds the live list, which is remapped onto the new backends.
+    async fn load_config(&self, live_list: &mut Vec<bool>) -> TritonFileResult<()> {
+        if let Some(config) = self.get_config().await? {
+            if config.version > self.config.lock().await.version {
+                self.adopt_config(live_list, &config).await;
+            }
+        }
+        Ok(())
+    }
+
+    async fn adopt_config(&self, live_list: &mut Vec<bool>, config: &ClusterConfig) {
+        let mut backs = self.backs.lock().await;
+        *live_list = config
+            .backs
+            .iter()
+            .map(|addr| match backs.iter().position(|back| back == addr) {
+                Some(idx) => live_list.get(idx).cloned().unwrap_or(false),
+                None => false,
+            })
+            .collect();
+        *backs = config.backs.clone();
+        *self.config.lock().await = config.clone();
+        info!(
+            "{}: cluster config {}, backends: {:?}",
+            self.print_name(),
+            config.version,
+            &config.backs
+        );
+    }
+
+    // Adds, drains or retires a backend. The bins whose replicas change are
+    // copied to the backends that newly hold them before the new config is
+    // published, and the key-value data once more after, to pick up writes
+    // that went to the old replicas in the meantime. A drained backend is
+    // then marked safe to remove. The live list is held throughout, so no
+    // round runs concurrently.
+    async fn reconfigure(&self, change: &ConfigChange) -> TritonFileResult<ClusterConfig> {
+        // The lease is taken before the live list, like in acquire_lease.
+        let lease = match self.lease.lock().await.clone() {
+            Some(lease) => lease,
+            None => return Err(Box::new(TritonFileError::NotLeader(0))),
+        };
+        let mut live_list = self.live_list.lock().await;
+        check_lease(&lease)?;
+        self.resume_replication(&lease, &mut live_list).await?;
+        let current = self.current_config().await;
+        let mut config = ClusterConfig {
+            version: current.version + 1,
+            ..current.clone()
+        };
+        match change {
+            ConfigChange::AddBackend(addr) if !config.backs.contains(addr) => {
+                config.backs.push(addr.clone())
+            }
+            ConfigChange::RemoveBackend(addr) if config.backs.contains(addr) => {
+                config.backs.retain(|back| back != addr);
+                config.draining.retain(|back| back != addr);
+                config.drained.retain(|back| back != addr);
+            }
+            // Draining again after a failed drain copies the bins again.
+            ConfigChange::DrainBackend(addr)
+                if config.backs.contains(addr) && !config.drained.contains(addr) =>
+            {
+                if !config.draining.contains(addr) {
+                    config.draining.push(addr.clone());
+                }
+            }
+            // Nothing to change.
+            _ => return Ok(current),
+        }
+        let time_limit = time::Duration::from_millis(1000);
+        // Backends that are still draining hold bins that may not have been
+        // copied yet, so they count as old replicas.
+        let old_live_list = self.probe(&current.backs, &current.drained, time_limit).await?;
+        let new_live_list = self.probe(&config.backs, &config.draining, time_limit).await?;
+        for (idx, addr) in config.backs.iter().enumerate() {
+            if !current.backs.contains(addr) && !new_live_list[idx] {
+                return Err(Box::new(TritonFileError::RpcError(format!(
+                    "backend {} is not reachable",
+                    addr
+                ))));
+            }
+        }
+        info!(
+            "{}: moving to cluster config {}, backends: {:?}",
+            self.print_name(),
+            config.version,
+            &config.backs
+        );
+
+        let old = (&current.backs[..], &old_live_list[..]);
+        let new = (&config.backs[..], &new_live_list[..]);
+        self.rebalance_keys(&lease, old, new).await?;
+        self.rebalance_files(&lease, old, new).await?;
+
+        check_lease(&lease)?;
+        self.save_config(&config).await?;
+        self.adopt_config(&mut live_list, &config).await;
+        *live_list = new_live_list.clone();
+        let view = self.publish_view(&live_list).await?;
+        self.save_live_list(&lease, &live_list).await?;
+        // Retired and draining backends are fenced as well, so clients that
+        // still use the old config can't write to them any more.
+        for addr in current.backs.iter().filter(|addr| !view.live.contains(addr)) {
+            let client = new_client(addr).await?;
+            match timeout(FENCE_TIMEOUT, client.fence(&view)).await {
+                Ok(Ok(_)) => (),
+                Ok(Err(e)) => info!("{}: fencing {} failed: {}", self.print_name(), addr, e),
+                Err(_) => info!("{}: fencing {} timed out", self.print_name(), addr),
+            }
+        }
+        self.rebalance_keys(&lease, old, new).await?;
+
+        if let ConfigChange::DrainBackend(addr) = change {
+            check_lease(&lease)?;
+            info!("{}: back {} is drained", self.print_name(), addr);
+            config.version += 1;
+            config.drained.push(addr.clone());
+            self.save_config(&config).await?;
+            self.adopt_config(&mut live_list, &config).await;
+            self.publish_view(&live_list).await?;
+        }
+        Ok(config)
+    }
+
+    // Copies the key-value data of every bin to the backends that are its
+    // replicas under the new config but were not under the old one, from
+    // both of its old replicas.
+    async fn rebalance_keys(
+        &self,
+        lease: &Lease,
+        (old_backs, old_live): (&[String], &[bool]),
+        (new_backs, new_live): (&[String], &[bool]),
+    ) -> TritonFileResult<()> {
+        let old_alive = alive_addrs(old_backs, old_live);
+        let new_alive = alive_addrs(new_backs, new_live);
+        let p = storage::Pattern {
+            prefix: "".to_string(),
+            suffix: "".to_string(),
+        };
+        let mut clients = HashMap::new();
+        for from in old_alive.iter() {
+            check_lease(lease)?;
+            let from_cli = new_client(from).await?;
+            for key in from_cli.list_keys(&p).await?.0 {
+                // Keys without a bin prefix belong to the backend itself.
+                let bin = match key.split_once(':') {
+                    Some((bin, _)) => colon::unescape(bin),
+                    None => continue,
+                };
+                // The keeper bin stays where it is.
+                if bin == KEY_KEEPER {
+                    continue;
+                }
+                let (old_primary, old_backup) = binstore::assign(&bin, old_backs, &old_alive);
+                let old_replicas = [old_primary, old_backup];
+                if !old_replicas.contains(&Some(from.clone())) {
+                    continue;
+                }
+                let (new_primary, new_backup) = binstore::assign(&bin, new_backs, &new_alive);
+                for to in [new_primary, new_backup].into_iter().flatten() {
+                    if old_replicas.contains(&Some(to.clone())) {
+                        continue;
+                    }
+                    if !clients.contains_key(&to) {
+                        clients.insert(to.clone(), new_client(&to).await?);
+                    }
+                    copy_list(from_cli.as_ref(), clients[&to].as_ref(), &key).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Copies files to the backends that newly serve them. Files are grouped
+    // into buckets by the index of the backend serving them (see
+    // `get_all_nodes`), each kept on that backend and the next live one, so
+    // this follows what a join or leave does. A backend that drains leaves
+    // the same way as one that is retired.
+    async fn rebalance_files(
+        &self,
+        lease: &Lease,
+        (old_backs, old_live): (&[String], &[bool]),
+        (new_backs, new_live): (&[String], &[bool]),
+    ) -> TritonFileResult<()> {
+        let old_alive = alive_addrs(old_backs, old_live);
+        let new_alive = alive_addrs(new_backs, new_live);
+        let old_live = old_live.to_vec();
+        let new_live = new_live.to_vec();
+        // A leaving backend hands its bucket to the next live backend.
+        for (idx, addr) in old_backs.iter().enumerate() {
+            check_lease(lease)?;
+            if !old_live[idx] || new_alive.contains(addr) {
+                continue;
+            }
+            if let Some(after) = next_live(idx, &old_live, 1) {
+                if new_alive.contains(&old_backs[after]) {
+                    copy_files(addr, &old_backs[after], idx, old_backs.len()).await?;
+                }
+            }
+        }
+        // A new backend receives its own bucket and the one of the backend
+        // before it.
+        for (idx, addr) in new_backs.iter().enumerate() {
+            check_lease(lease)?;
+            if !new_live[idx] || old_alive.contains(addr) {
+                continue;
+            }
+            if let Some(after) = next_live(idx, &new_live, 1) {
+                if old_alive.contains(&new_backs[after]) {
+                    copy_files(&new_backs[after], addr, idx, new_backs.len()).await?;
+                }
+            }
+            if let Some(before) = next_live(idx, &new_live, -1) {
+                if old_alive.contains(&new_backs[before]) {
+                    copy_files(&new_backs[before], addr, before, new_backs.len()).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Keeps track of the bins that have fewer than two copies, and repairs
+    // them once at least two backends are alive. While only one backend is
+    // alive every bin on it is under-replicated; backups that missed a
+    // mutation are learned from the repair logs of the backends.
+    async fn track_replication(&self, lease: &Lease, live_list: &[bool]) -> TritonFileResult<()> {
+        let config = self.current_config().await;
+        let alive = alive_addrs(&config.backs, live_list);
+        let mut bins = self.get_under_replicated().await?;
+        let before = bins.len();
+        let mut records = vec![];
+        for addr in alive.iter() {
+            let client = new_client(addr).await?;
+            if alive.len() < 2 {
+                bins.extend(bins_on(client.as_ref()).await?);
+            }
+            for raw in client.list_get(KEY_REPAIR_LOG).await?.0 {
+                match serde_json::from_str::<RepairRecord>(&raw) {
+                    Ok(record) => {
+                        bins.insert(record.bin);
+                    }
+                    Err(e) => info!("{}: bad repair record {}: {}", self.print_name(), raw, e),
+                }
+                records.push((addr.clone(), raw));
+            }
+        }
+        bins.remove(KEY_KEEPER);
+        if bins.len() != before {
+            self.save_under_replicated(&bins).await?;
+        }
+        if bins.is_empty() {
+            return Ok(());
+        }
+        if alive.len() < 2 {
+            info!(
+                "{}: {} bins under-replicated, live backends: {:?}",
+                self.print_name(),
+                bins.len(),
+                &alive
+            );
+            return Ok(());
+        }
+
+        info!("{}: repairing {} bins", self.print_name(), bins.len());
+        for bin in bins.clone() {
+            check_lease(lease)?;
+            self.repair_bin(&bin, &config.backs, &alive).await?;
+            bins.remove(&bin);
+            self.save_under_replicated(&bins).await?;
+        }
+        for (addr, raw) in records {
+            new_client(&addr)
+                .await?
+                .list_remove(&KeyValue {
+                    key: KEY_REPAIR_LOG.to_string(),
+                    value: raw,
+                })
+                .await?;
+        }
+        info!("{}: all bins have two copies again", self.print_name());
+        Ok(())
+    }
+
+    // Copies the key-value data of `bin` from every live backend holding
+    // some of it to both of its replicas.
+    async fn repair_bin(
+        &self,
+        bin: &str,
+        backs: &[String],
+        alive: &[String],
+    ) -> TritonFileResult<()> {
+        let (primary, backup) = binstore::assign(bin, backs, alive);
+        let p = storage::Pattern {
+            prefix: format!("{}:", colon::escape(bin)),
+            suffix: "".to_string(),
+        };
+        for from in alive.iter() {
+            let from_cli = new_client(from).await?;
+            let keys = from_cli.list_keys(&p).await?.0;
+            if keys.is_empty() {
+                continue;
+            }
+            for to in [&primary, &backup].into_iter().flatten() {
+                if to == from {
+                    continue;
+                }
+                let to_cli = new_client(to).await?;
+                for key in keys.iter() {
+                    copy_list(from_cli.as_ref(), to_cli.as_ref(), key).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn get_under_replicated(&self) -> TritonFileResult<BTreeSet<String>> {
+        match self.keep_bin.get(KEY_UNDER_REPLICATED).await? {
+            None => Ok(BTreeSet::new()),
+            Some(bins) => Ok(serde_json::from_str(&bins)?),
+        }
+    }
+
+    async fn save_under_replicated(&self, bins: &BTreeSet<String>) -> TritonFileResult<bool> {
+        self.keep_bin
+            .set(&KeyValue {
+                key: KEY_UNDER_REPLICATED.to_string(),
+                value: serde_json::to_string(bins)?,
+            })
+            .await
+    }
+
+    // Compares the file replicas of every bin files are kept in, brings the
+    // one behind up to date, and adds what was found to the anti-entropy
+    // metrics. A namespace's files follow its bin to new replicas this way.
+    // Only the leader does this.
+    async fn verify_replicas(&self) -> TritonFileResult<()> {
+        let lease = match self.lease.lock().await.clone() {
+            Some(lease) => lease,
+            None => return Ok(()),
+        };
+        check_lease(&lease)?;
+        let live = self.view.borrow().live.clone();
+        let config = self.current_config().await;
+        let mut metrics = self.get_anti_entropy().await?;
+        for name in self.file_bins.iter() {
+            let (primary, backup) = match binstore::assign(name, &config.backs, &live) {
+                (Some(primary), Some(backup)) => (primary, backup),
+                // With one copy there is nothing to compare; track_replication
+                // takes care of it.
+                _ => continue,
+            };
+            let found = merkle::reconcile(
+                namespace_client(&primary, name).await?.as_ref(),
+                namespace_client(&backup, name).await?.as_ref(),
+            )
+            .await?;
+            check_lease(&lease)?;
+            if found.divergent > 0 {
+                info!(
+                    "{}: repaired {} inodes of {} between {} and {}",
+                    self.print_name(),
+                    found.repaired,
+                    name,
+                    primary,
+                    backup
+                );
+            }
+            metrics.add(&found);
+        }
+        self.keep_bin
+            .set(&KeyValue {
+                key: KEY_ANTI_ENTROPY.to_string(),
+                value: serde_json::to_string(&metrics)?,
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn get_anti_entropy(&self) -> TritonFileResult<Divergence> {
+        match self.keep_bin.get(KEY_ANTI_ENTROPY).await? {
+            None => Ok(Divergence::default()),
+            Some(metrics) => Ok(serde_json::from_str(&metrics)?),
+        }
+    }
+
     async fn hash_name_to_idx(&self, name: &str) -> usize {
         let length = { self.backs.lock().await.len() };
         hash_name_to_idx(name, length)
     }
 
-    // Serve a round in loop as a leader. When we return, the old live
-    // list is replaced in-place by the new live list.
+    // Serve a round in loop as a leader: plans the copies the change from
+    // the old to the new live list needs as jobs, saves them, and runs
+    // them.
     async fn serve_as_leader(
         &self,
+        lease: &Lease,
         old_live_list: &Vec<bool>,
         new_live_list: &Vec<bool>,
     ) -> TritonFileResult<()> {
         info!("{} serving as leader", self.print_name());
-        // This should return in at most 1+e seconds.
-        for i in 0..new_live_list.len() {
-            if new_live_list[i] != old_live_list[i] {
-                info!("new live list is not equal to old live list");
-                self.manage_replicate(&new_live_list, old_live_list).await?;
-                // let _ = self.set_replicate_state(&Vec::new());
-                break;
+        if new_live_list == old_live_list {
+            return Ok(());
+        }
+        info!("new live list is not equal to old live list");
+        let mut state = self.get_state().await?.unwrap_or_default();
+        state.keep_finished();
+        state.epoch = lease.epoch;
+        state.config_version = self.current_config().await.version;
+        state.live_list = old_live_list.clone();
+        state.target = Some(new_live_list.clone());
+        state.add_jobs(self.plan_replication(new_live_list, old_live_list));
+        check_lease(lease)?;
+        self.save_state(&state).await?;
+        // Failed jobs are left to the next round to try again.
+        let failed = self.run_jobs(lease, state).await?;
+        if failed > 0 {
+            return Err(Box::new(TritonFileError::RpcError(format!(
+                "{} replication jobs failed",
+                failed
+            ))));
+        }
+        Ok(())
+    }
+
+    // Runs the jobs of `state` that are not done yet, and returns how many
+    // of them failed every try. The leader runs at most two jobs at a time.
+    async fn run_jobs(&self, lease: &Lease, state: KeeperState) -> TritonFileResult<usize> {
+        let target = state.target.clone().unwrap_or_default();
+        let state = Mutex::new(state);
+        let (first, second) = tokio::join!(
+            self.job_worker(lease, &target, &state),
+            self.job_worker(lease, &target, &state)
+        );
+        first?;
+        second?;
+        let failed = state
+            .lock()
+            .await
+            .jobs
+            .iter()
+            .filter(|job| job.state == JobState::Failed)
+            .count();
+        Ok(failed)
+    }
+
+    // Starts pending jobs one after another until none is left that can
+    // start. A job waits while another one copies to the same backend, so
+    // the copies to a backend are made in the order they were planned.
+    async fn job_worker(
+        &self,
+        lease: &Lease,
+        target: &Vec<bool>,
+        state: &Mutex<KeeperState>,
+    ) -> TritonFileResult<()> {
+        loop {
+            let job = {
+                let mut state = state.lock().await;
+                let busy: Vec<usize> = state
+                    .jobs
+                    .iter()
+                    .filter(|job| job.state == JobState::Running)
+                    .map(|job| job.task.to)
+                    .collect();
+                let idx = match state.jobs.iter().position(|job| {
+                    job.state == JobState::Pending && !busy.contains(&job.task.to)
+                }) {
+                    Some(idx) => idx,
+                    None => return Ok(()),
+                };
+                state.jobs[idx].state = JobState::Running;
+                state.epoch = lease.epoch;
+                check_lease(lease)?;
+                self.save_state(&state).await?;
+                state.jobs[idx].clone()
+            };
+            self.run_job(lease, target, state, job).await?;
+        }
+    }
+
+    // Makes the copy of `job`, retrying it with a growing delay, and records
+    // how it went. Only losing the lease is returned as an error.
+    async fn run_job(
+        &self,
+        lease: &Lease,
+        target: &Vec<bool>,
+        state: &Mutex<KeeperState>,
+        mut job: ReplicationJob,
+    ) -> TritonFileResult<()> {
+        let mut delay = JOB_RETRY_DELAY;
+        let mut tries = 0;
+        loop {
+            // A deposed leader must not keep replicating.
+            check_lease(lease)?;
+            tries += 1;
+            job.attempts += 1;
+            let result = if job.task.files {
+                self.replicate_file(lease, state, &mut job).await
+            } else {
+                self.replicate(lease, target, state, &mut job).await
+            };
+            let err = match result {
+                Ok(()) => {
+                    job.state = JobState::Done;
+                    job.error = None;
+                    return self.update_job(lease, state, &job).await;
+                }
+                Err(e) if matches!(
+                    e.downcast_ref::<TritonFileError>(),
+                    Some(TritonFileError::NotLeader(_))
+                ) =>
+                {
+                    return Err(e)
+                }
+                Err(e) => e,
+            };
+            info!("{}: job {} failed: {}", self.print_name(), job.id, err);
+            job.error = Some(err.to_string());
+            if tries == JOB_ATTEMPTS {
+                job.state = JobState::Failed;
+                return self.update_job(lease, state, &job).await;
             }
+            self.update_job(lease, state, &job).await?;
+            time::sleep(delay).await;
+            delay *= 2;
         }
+    }
+
+    // Saves the progress of `job`.
+    async fn update_job(
+        &self,
+        lease: &Lease,
+        state: &Mutex<KeeperState>,
+        job: &ReplicationJob,
+    ) -> TritonFileResult<()> {
+        let mut state = state.lock().await;
+        if let Some(saved) = state.jobs.iter_mut().find(|saved| saved.id == job.id) {
+            *saved = job.clone();
+        }
+        state.epoch = lease.epoch;
+        check_lease(lease)?;
+        self.save_state(&state).await?;
         Ok(())
     }
 
+    // Finishes a replication that was saved but not completed, because the
+    // leader failed half way or a round hit an error, and moves `live_list`
+    // to the live list it was heading to. Jobs that were running or failed
+    // start over from their checkpoint. Jobs planned under an older cluster
+    // config are dropped, since reconfiguring copies the bins that moved by
+    // itself. If jobs still fail, e.g. because a backend of the target left
+    // again, the target is dropped too and `live_list` is kept, so that the
+    // round plans the replication again from what is live now.
+    async fn resume_replication(
+        &self,
+        lease: &Lease,
+        live_list: &mut Vec<bool>,
+    ) -> TritonFileResult<()> {
+        let mut state = match self.get_state().await? {
+            Some(state) => state,
+            None => return Ok(()),
+        };
+        let target = match state.target.clone() {
+            Some(target) => target,
+            None => return Ok(()),
+        };
+        let config = self.current_config().await;
+        if state.config_version != config.version || target.len() != config.backs.len() {
+            info!(
+                "{}: dropping {} jobs planned under config {}",
+                self.print_name(),
+                state.unfinished(),
+                state.config_version
+            );
+            return Ok(());
+        }
+        info!(
+            "{}: resuming {} jobs left by epoch {}",
+            self.print_name(),
+            state.unfinished(),
+            state.epoch
+        );
+        for job in state.jobs.iter_mut() {
+            if job.state == JobState::Running || job.state == JobState::Failed {
+                job.state = JobState::Pending;
+            }
+        }
+        let failed = self.run_jobs(lease, state).await?;
+        if failed > 0 {
+            info!(
+                "{}: {} jobs failed, replanning from the live list",
+                self.print_name(),
+                failed
+            );
+            check_lease(lease)?;
+            self.save_live_list(lease, live_list).await?;
+            return Ok(());
+        }
+        check_lease(lease)?;
+        self.save_live_list(lease, &target).await?;
+        *live_list = target;
+        Ok(())
+    }
+
+    // Copies the key-value data of the bins backend `for_addr` is primary
+    // for under `live_list`, bin by bin in name order. Bins up to the job's
+    // checkpoint are skipped, and the checkpoint is saved after each bin.
     async fn replicate(
         &self,
-        from: usize,
-        to: usize,
-        for_addr: usize,
+        lease: &Lease,
         live_list: &Vec<bool>,
+        state: &Mutex<KeeperState>,
+        job: &mut ReplicationJob,
     ) -> TritonFileResult<()> {
-        info!("{}: replicating {} to {}", self.print_name(), from, to);
+        let task = job.task.clone();
+        info!("{}: replicating {} to {}", self.print_name(), task.from, task.to);
         // get all keys from the 'from'
         let p = storage::Pattern {
             prefix: "".to_string(),
@@ -262,129 +1415,143 @@ impl Keeper {
         let (from_cli, to_cli) = {
             let backs = self.backs.lock().await;
             (
-                new_client(&backs[from]).await?,
-                new_client(&backs[to]).await?,
+                new_client(&backs[task.from]).await?,
+                new_client(&backs[task.to]).await?,
             )
         };
 
-        let keys = from_cli.list_keys(&p).await?.0;
+        // Key has the shape of username:kind:key.
+        let mut bins: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for key in from_cli.list_keys(&p).await?.0 {
+            let bin = key.split(':').next().unwrap_or_default().to_string();
+            bins.entry(bin).or_default().push(key);
+        }
 
-        for key in keys {
-            // Key has the shape of username:kind:key.
-            let key_name = key.split(':').collect::<Vec<&str>>()[0];
-            let idx = self.hash_name_to_idx(key_name).await;
+        for (bin, keys) in bins {
+            if job.checkpoint.as_ref().is_some_and(|done| bin <= *done) {
+                continue;
+            }
+            let idx = self.hash_name_to_idx(&bin).await;
             // if the hash key is right on the primary
-            let primary_for_key = key_primary_idx(idx, live_list).unwrap();
-
-            if primary_for_key == for_addr {
-                    let values_in_from = from_cli.list_get(&key.as_str()).await?.0;
-                    let values_in_to = to_cli.list_get(&key.as_str()).await?.0;
-                    for val in values_in_from {
-                        if !values_in_to.contains(&val) {
-                            let kv = storage::KeyValue {
-                                key: key.clone(),
-                                value: val.clone(),
-                            };
-                            to_cli.list_append(&kv).await?;
-                        }
+            if key_primary_idx(idx, live_list) != Some(task.for_addr) {
+                continue;
+            }
+            for key in keys {
+                let values_in_from = from_cli.list_get(key.as_str()).await?.0;
+                let values_in_to = to_cli.list_get(key.as_str()).await?.0;
+                for val in values_in_from {
+                    if !values_in_to.contains(&val) {
+                        let kv = storage::KeyValue {
+                            key: key.clone(),
+                            value: val.clone(),
+                        };
+                        to_cli.list_append(&kv).await?;
+                        job.copied += 1;
+                    }
                 }
             }
+            job.checkpoint = Some(bin);
+            self.update_job(lease, state, job).await?;
         }
         Ok(())
     }
 
-    async fn replicate_file(&self,
-        from: usize,
-        to: usize,
-        for_addr: usize,)
-    -> TritonFileResult<()> {
-        info!("{}: replicating {} to {}", self.print_name(), from, to);
-        let len = self.backs.lock().await.len();
-        let (from_cli, to_cli) = {
+    // Copies the files backend `for_addr` is primary for, in inode order and
+    // in batches of [FILE_BATCH]. They keep their numbers and versions, so
+    // copying an inode again just overwrites it and a retried or resumed job
+    // does not duplicate the files. Inodes up to the job's checkpoint are
+    // skipped, and the checkpoint is saved after each batch.
+    async fn replicate_file(
+        &self,
+        lease: &Lease,
+        state: &Mutex<KeeperState>,
+        job: &mut ReplicationJob,
+    ) -> TritonFileResult<()> {
+        let task = job.task.clone();
+        info!("{}: replicating {} to {}", self.print_name(), task.from, task.to);
+        let (from_cli, to_cli, len) = {
             let backs = self.backs.lock().await;
             (
-                new_client(&backs[from]).await?,
-                new_client(&backs[to]).await?,
+                new_client(&backs[task.from]).await?,
+                new_client(&backs[task.to]).await?,
+                backs.len(),
             )
         };
 
-        let (inode_list, content_list) = from_cli.get_all_nodes(for_addr, len).await?.unwrap();
-        to_cli.write_all_nodes(inode_list, content_list).await?;
-
+        let done = job.checkpoint.as_ref().and_then(|inode| inode.parse::<u64>().ok());
+        let mut nodes = files_for(from_cli.as_ref(), task.for_addr, len).await?;
+        nodes.retain(|node| done.is_none_or(|done| node.attrs.inode > done));
+        while !nodes.is_empty() {
+            let batch: Vec<NodeCopy> = nodes.drain(..nodes.len().min(FILE_BATCH)).collect();
+            let (count, last) = (batch.len() as u64, batch[batch.len() - 1].attrs.inode);
+            to_cli.restore_nodes(batch, &[]).await?;
+            job.copied += count;
+            job.checkpoint = Some(last.to_string());
+            self.update_job(lease, state, job).await?;
+        }
         Ok(())
     }
 
-    async fn manage_replicate(
+    // The copies a change from the old to the new live list needs, in the
+    // order they must be made.
+    fn plan_replication(
         &self,
         new_live_list: &Vec<bool>,
         old_live_list: &Vec<bool>,
-    ) -> TritonFileResult<()> {
-        // Only replicate if there are at least 3 backends alive.
-        if new_live_list.iter().filter(|&x| *x).count() >= 3 {
-            for idx in 0..new_live_list.len() {
-                if !old_live_list[idx] && new_live_list[idx] {
-                    info!("{}: back {} joined", self.print_name(), idx);
-                    // Join. We assume there are always three machines.
-                    let before = next_live(idx, new_live_list, -1).unwrap();
-                    let after = next_live(idx, new_live_list, 1).unwrap();
-                    self.handle_join(before, after, idx, new_live_list).await?;
-                } else if old_live_list[idx] && !new_live_list[idx] {
-                    info!("{}: back {} left", self.print_name(), idx);
-                    // Leave.
-                    let before = next_live(idx, new_live_list, -1).unwrap();
-                    let after = next_live(idx, new_live_list, 1).unwrap();
-                    let after_after = next_live(after, new_live_list, 1).unwrap();
-                    self.handle_leave(before, after, after_after, new_live_list)
-                        .await?;
+    ) -> Vec<ReplicationTask> {
+        let mut tasks = vec![];
+        // With fewer than three backends alive the neighbours of a backend
+        // may be the same one, or not exist at all. Whatever can't be copied
+        // is tracked by track_replication and repaired once capacity returns.
+        for idx in 0..new_live_list.len() {
+            if !old_live_list[idx] && new_live_list[idx] {
+                info!("{}: back {} joined", self.print_name(), idx);
+                // Join.
+                match (
+                    next_live(idx, new_live_list, -1),
+                    next_live(idx, new_live_list, 1),
+                ) {
+                    (Some(before), Some(after)) => tasks.extend(join_tasks(before, after, idx)),
+                    _ => info!("{}: no live peer to copy to back {}", self.print_name(), idx),
+                }
+            } else if old_live_list[idx] && !new_live_list[idx] {
+                info!("{}: back {} left", self.print_name(), idx);
+                // Leave.
+                let before = next_live(idx, new_live_list, -1);
+                let after = next_live(idx, new_live_list, 1);
+                match (before, after) {
+                    (Some(before), Some(after)) if before != after => {
+                        // With two backends left, the one after the next is
+                        // the one before.
+                        let after_after = next_live(after, new_live_list, 1).unwrap_or(before);
+                        tasks.extend(leave_tasks(before, after, after_after));
+                    }
+                    _ => info!(
+                        "{}: back {} left no second replica behind",
+                        self.print_name(),
+                        idx
+                    ),
                 }
             }
         }
-        Ok(())
-    }
-
-    async fn handle_join(
-        &self,
-        primary: usize,
-        old_backup: usize,
-        new_backup: usize,
-        live_list: &Vec<bool>,
-    ) -> TritonFileResult<()> {
-        self.replicate(old_backup, new_backup, new_backup, live_list)
-            .await?;
-        self.replicate(primary, new_backup, primary, live_list)
-            .await?;
-        self.replicate_file(old_backup, new_backup, new_backup)
-            .await?;
-        self.replicate_file(primary, new_backup, primary)
-            .await?;
-        // self.replicate(old_backup, new_backup, primary, live_list)
-        //     .await?;
-
-        Ok(())
-    }
-
-    async fn handle_leave(
-        &self,
-        before: usize,
-        after: usize,
-        after_after: usize,
-        live_list: &Vec<bool>,
-    ) -> TritonFileResult<()> {
-        self.replicate(after, after_after, after, live_list)
-            .await?;
-        self.replicate(before, after, before, live_list)
-            .await?;
-        self.replicate_file(after, after_after, after)
-            .await?;
-        self.replicate_file(before, after, before)
-            .await?;
-        Ok(())
+        tasks
     }
 
     // Broadcast to sync the clock and return a live list.
     async fn broadcast(&self, time_limit: time::Duration) -> TritonFileResult<Vec<bool>> {
         info!("Broadcast request");
-        let backs = self.backs.lock().await;
+        let config = self.current_config().await;
+        self.probe(&config.backs, &config.draining, time_limit).await
+    }
+
+    // Syncs the clock with `backs` and returns which of them answered.
+    // Backends in `excluded` count as dead even if they answer.
+    async fn probe(
+        &self,
+        backs: &[String],
+        excluded: &[String],
+        time_limit: time::Duration,
+    ) -> TritonFileResult<Vec<bool>> {
         let mut handles = vec![];
         let mut live_list = vec![false; backs.len()];
         for idx in 0..backs.len() {
@@ -405,7 +1572,7 @@ impl Keeper {
             match handle.await {
                 Ok(ret) => {
                     if let Ok(idx) = ret {
-                        live_list[idx] = true
+                        live_list[idx] = !excluded.contains(&backs[idx])
                     }
                 }
                 // Thread panic, throw.
@@ -474,10 +1641,6 @@ impl Keeper {
         format!("{}_{}", KEY_TIMESTAMP, idx)
     }
 
-    fn compose_replicate_state_key(&self) -> String {
-        return KEY_KEEPER_REPLICATE.to_string();
-    }
-
     async fn collect_keeper_clock(&self) -> TritonFileResult<Vec<u64>> {
         let mut clocks = vec![];
         let addrs = { self.addrs.lock().await.clone() };
@@ -499,22 +1662,434 @@ impl Keeper {
         Ok(clocks)
     }
 
-    async fn save_live_list_backup(&self, live_list: &Vec<bool>) -> TritonFileResult<bool> {
-        let keep_bin = &self.keep_bin;
-        let kv = KeyValue {
-            key: self.compose_replicate_state_key(),
-            value: serde_json::to_string(&live_list.clone()).unwrap(),
-        };
-        keep_bin.set(&kv).await
+    // Saves `live_list` as the outcome of a completed round, with nothing
+    // left to replicate. Finished jobs are kept for admins to list.
+    async fn save_live_list(&self, lease: &Lease, live_list: &[bool]) -> TritonFileResult<bool> {
+        let mut state = self.get_state().await?.unwrap_or_default();
+        state.keep_finished();
+        self.save_state(&KeeperState {
+            epoch: lease.epoch,
+            config_version: self.current_config().await.version,
+            live_list: live_list.to_vec(),
+            target: None,
+            ..state
+        })
+        .await
     }
 
-    async fn get_live_list_backup(&self) -> TritonFileResult<Option<Vec<bool>>> {
-        let keep_bin = &self.keep_bin;
-        match keep_bin.get(&self.compose_replicate_state_key()).await? {
+    async fn save_state(&self, state: &KeeperState) -> TritonFileResult<bool> {
+        self.keep_bin
+            .set(&KeyValue {
+                key: KEY_KEEPER_STATE.to_string(),
+                value: serde_json::to_string(state)?,
+            })
+            .await
+    }
+
+    async fn get_state(&self) -> TritonFileResult<Option<KeeperState>> {
+        match self.keep_bin.get(KEY_KEEPER_STATE).await? {
             None => Ok(None),
-            Some(ret) => Ok(Some(serde_json::from_str(&ret).unwrap())),
+            Some(state) => Ok(Some(serde_json::from_str(&state)?)),
+        }
+    }
+
+    // Picks up the clock this keeper published before it restarted, so that
+    // the other keepers don't take it for dead until it catches up.
+    async fn load_timestamp(&self) -> TritonFileResult<()> {
+        let key = self.compose_timestamp_key(self.this);
+        if let Some(clock) = self.keep_bin.get(&key).await? {
+            let clock: u64 = clock.parse()?;
+            self.timestamp.fetch_max(clock, atomic::Ordering::SeqCst);
         }
+        Ok(())
+    }
+}
+
+type ViewStream = Pin<Box<dyn Stream<Item = Result<View, Status>> + Send>>;
+
+// Serves the keeper's membership view to clients.
+struct MembershipService {
+    keeper: Arc<Keeper>,
+}
+
+fn to_view(view: &MembershipView) -> View {
+    View {
+        epoch: view.epoch,
+        live: view.live.clone(),
+        backs: view.backs.clone(),
+        config_version: Some(view.config_version),
+    }
+}
+
+#[async_trait]
+impl Membership for MembershipService {
+    type WatchStream = ViewStream;
+
+    async fn view(&self, _request: Request<Empty>) -> Result<Response<View>, Status> {
+        Ok(Response::new(to_view(&self.keeper.view.borrow())))
+    }
+
+    async fn assignment(&self, request: Request<Bin>) -> Result<Response<Assignment>, Status> {
+        let name = request.into_inner().name;
+        let view = self.keeper.view.borrow().clone();
+        let backs = { self.keeper.backs.lock().await.clone() };
+        let (primary, backup) = binstore::assign(&name, &backs, &view.live);
+        Ok(Response::new(Assignment {
+            epoch: view.epoch,
+            primary,
+            backup,
+        }))
+    }
+
+    async fn watch(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<Self::WatchStream>, Status> {
+        let mut views = self.keeper.view.subscribe();
+        let (tx, rx) = tokio::sync::mpsc::channel(1);
+        tokio::spawn(async move {
+            loop {
+                let view = views.borrow_and_update().clone();
+                // Nothing was published yet, don't make clients think every
+                // backend is dead.
+                if view.epoch > 0 && tx.send(Ok(to_view(&view))).await.is_err() {
+                    // The client went away.
+                    return;
+                }
+                if let Ok(Err(_)) = timeout(WATCH_KEEPALIVE, views.changed()).await {
+                    // The keeper shut down.
+                    return;
+                }
+            }
+        });
+        Ok(Response::new(Box::pin(ReceiverStream::new(rx))))
+    }
+}
+
+// Changes the backends of the cluster on behalf of an admin. Only the
+// leader accepts changes; the others answer `failed_precondition` so that
+// admins move on to the next keeper.
+struct AdminService {
+    keeper: Arc<Keeper>,
+}
+
+fn to_config_reply(config: ClusterConfig) -> ConfigReply {
+    ConfigReply {
+        version: config.version,
+        backs: config.backs,
+        draining: config.draining,
+        drained: config.drained,
+    }
+}
+
+fn to_job_reply(job: ReplicationJob, backs: &[String]) -> Job {
+    let addr = |idx: usize| backs.get(idx).cloned().unwrap_or_else(|| format!("#{}", idx));
+    Job {
+        id: job.id,
+        from: addr(job.task.from),
+        to: addr(job.task.to),
+        for_addr: addr(job.task.for_addr),
+        files: job.task.files,
+        state: format!("{:?}", job.state).to_lowercase(),
+        attempts: job.attempts,
+        checkpoint: job.checkpoint,
+        copied: job.copied,
+        error: job.error,
+    }
+}
+
+impl AdminService {
+    async fn change(&self, change: ConfigChange) -> Result<Response<ConfigReply>, Status> {
+        if self.keeper.lease.lock().await.is_none() {
+            return Err(Status::failed_precondition("not the leader"));
+        }
+        match &change {
+            ConfigChange::RemoveBackend(addr) | ConfigChange::DrainBackend(addr)
+                if self.keeper.origin.contains(addr) =>
+            {
+                return Err(Status::invalid_argument(format!(
+                    "backend {} holds the keeper state and can't be retired",
+                    addr
+                )));
+            }
+            _ => (),
+        }
+        info!("admin request: {:?}", &change);
+        match self.keeper.reconfigure(&change).await {
+            Ok(config) => Ok(Response::new(to_config_reply(config))),
+            Err(e) => match e.downcast_ref::<TritonFileError>() {
+                Some(TritonFileError::NotLeader(_)) => {
+                    Err(Status::failed_precondition("not the leader"))
+                }
+                _ => Err(Status::aborted(e.to_string())),
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl Admin for AdminService {
+    async fn add_backend(
+        &self,
+        request: Request<Backend>,
+    ) -> Result<Response<ConfigReply>, Status> {
+        self.change(ConfigChange::AddBackend(request.into_inner().addr))
+            .await
+    }
+
+    async fn remove_backend(
+        &self,
+        request: Request<Backend>,
+    ) -> Result<Response<ConfigReply>, Status> {
+        self.change(ConfigChange::RemoveBackend(request.into_inner().addr))
+            .await
+    }
+
+    async fn drain_backend(
+        &self,
+        request: Request<Backend>,
+    ) -> Result<Response<ConfigReply>, Status> {
+        self.change(ConfigChange::DrainBackend(request.into_inner().addr))
+            .await
+    }
+
+    async fn config(&self, _request: Request<Empty>) -> Result<Response<ConfigReply>, Status> {
+        Ok(Response::new(to_config_reply(
+            self.keeper.current_config().await,
+        )))
+    }
+
+    async fn replication(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<ReplicationStatus>, Status> {
+        let live = self.keeper.view.borrow().live.len() as u64;
+        let status = async {
+            let bins = self.keeper.get_under_replicated().await?;
+            let metrics = self.keeper.get_anti_entropy().await?;
+            TritonFileResult::Ok(ReplicationStatus {
+                live,
+                under_replicated: bins.into_iter().collect(),
+                verify_rounds: Some(metrics.rounds),
+                inodes_verified: Some(metrics.inodes),
+                divergent: Some(metrics.divergent),
+                repaired: Some(metrics.repaired),
+            })
+        };
+        match status.await {
+            Ok(status) => Ok(Response::new(status)),
+            Err(e) => Err(Status::unavailable(e.to_string())),
+        }
+    }
+
+    async fn jobs(&self, _request: Request<Empty>) -> Result<Response<JobList>, Status> {
+        let state = match self.keeper.get_state().await {
+            Ok(state) => state.unwrap_or_default(),
+            Err(e) => return Err(Status::unavailable(e.to_string())),
+        };
+        // Jobs index into the backends of the config they were planned
+        // under, which is the current one unless they were dropped.
+        let backs = self.keeper.current_config().await.backs;
+        Ok(Response::new(JobList {
+            jobs: state
+                .jobs
+                .into_iter()
+                .map(|job| to_job_reply(job, &backs))
+                .collect(),
+        }))
+    }
+}
+
+/// Sends `change` to the `keepers` in turn until the leader accepts it, and
+/// returns the resulting cluster config.
+pub async fn change_config(
+    keepers: &[String],
+    change: ConfigChange,
+) -> TritonFileResult<ClusterConfig> {
+    let mut last_err: Option<Box<dyn std::error::Error + Send + Sync>> = None;
+    for addr in keepers.iter() {
+        let channel = Endpoint::from_shared(format!("http://{}", addr))?.connect_lazy();
+        let mut client = AdminClient::new(channel);
+        let reply = match &change {
+            ConfigChange::AddBackend(back) => {
+                client.add_backend(Backend { addr: back.clone() }).await
+            }
+            ConfigChange::RemoveBackend(back) => {
+                client.remove_backend(Backend { addr: back.clone() }).await
+            }
+            ConfigChange::DrainBackend(back) => {
+                client.drain_backend(Backend { addr: back.clone() }).await
+            }
+            ConfigChange::Show => client.config(Empty {}).await,
+        };
+        match reply {
+            Ok(reply) => {
+                let reply = reply.into_inner();
+                return Ok(ClusterConfig {
+                    version: reply.version,
+                    backs: reply.backs,
+                    draining: reply.draining,
+                    drained: reply.drained,
+                });
+            }
+            // Not the leader, or not reachable: try the next keeper.
+            Err(status)
+                if status.code() == Code::FailedPrecondition
+                    || status.code() == Code::Unavailable =>
+            {
+                info!("keeper {}: {}", addr, status.message());
+                last_err = Some(Box::new(status));
+            }
+            Err(status) => return Err(Box::new(status)),
+        }
+    }
+    Err(last_err.unwrap_or_else(|| Box::new(TritonFileError::Unknown("no keepers".to_string()))))
+}
+
+/// Fetches the replication status from the first keeper that answers.
+pub async fn replication_status(keepers: &[String]) -> TritonFileResult<ReplicationStatus> {
+    let mut last_err: Option<Box<dyn std::error::Error + Send + Sync>> = None;
+    for addr in keepers.iter() {
+        let channel = Endpoint::from_shared(format!("http://{}", addr))?.connect_lazy();
+        match AdminClient::new(channel).replication(Empty {}).await {
+            Ok(status) => return Ok(status.into_inner()),
+            Err(status) => {
+                info!("keeper {}: {}", addr, status.message());
+                last_err = Some(Box::new(status));
+            }
+        }
+    }
+    Err(last_err.unwrap_or_else(|| Box::new(TritonFileError::Unknown("no keepers".to_string()))))
+}
+
+/// Fetches the replication jobs from the first keeper that answers.
+pub async fn replication_jobs(keepers: &[String]) -> TritonFileResult<Vec<Job>> {
+    let mut last_err: Option<Box<dyn std::error::Error + Send + Sync>> = None;
+    for addr in keepers.iter() {
+        let channel = Endpoint::from_shared(format!("http://{}", addr))?.connect_lazy();
+        match AdminClient::new(channel).jobs(Empty {}).await {
+            Ok(list) => return Ok(list.into_inner().jobs),
+            Err(status) => {
+                info!("keeper {}: {}", addr, status.message());
+                last_err = Some(Box::new(status));
+            }
+        }
+    }
+    Err(last_err.unwrap_or_else(|| Box::new(TritonFileError::Unknown("no keepers".to_string()))))
+}
+
+// The bins that have key-value data on a backend.
+async fn bins_on(client: &dyn Storage) -> TritonFileResult<BTreeSet<String>> {
+    let p = storage::Pattern {
+        prefix: "".to_string(),
+        suffix: "".to_string(),
+    };
+    Ok(client
+        .list_keys(&p)
+        .await?
+        .0
+        .iter()
+        .filter_map(|key| key.split_once(':'))
+        .map(|(bin, _)| colon::unescape(bin))
+        .collect())
+}
+
+// The bins the files of the default namespace and of every shard of
+// `namespaces` are kept in.
+fn file_bins(namespaces: &[Namespace]) -> Vec<String> {
+    let mut bins = vec![DEFAULT_NAMESPACE.to_string()];
+    for namespace in namespaces {
+        for shard in 0..namespace.shards {
+            let bin = shard_name(&namespace.name, shard);
+            if !bins.contains(&bin) {
+                bins.push(bin);
+            }
+        }
+    }
+    bins
+}
+
+// A client of the files of namespace `name` on a backend; the default
+// namespace is the backend's own file tree.
+async fn namespace_client(addr: &str, name: &str) -> TritonFileResult<Box<dyn Storage>> {
+    let client = new_client(addr).await?;
+    if name == DEFAULT_NAMESPACE {
+        return Ok(client);
+    }
+    client.namespace(name).await
+}
+
+fn alive_addrs(backs: &[String], live_list: &[bool]) -> Vec<String> {
+    (0..backs.len())
+        .filter(|&idx| live_list[idx])
+        .map(|idx| backs[idx].clone())
+        .collect()
+}
+
+// Appends the values of list `key` on `from` that `to` doesn't have yet.
+async fn copy_list(from: &dyn Storage, to: &dyn Storage, key: &str) -> TritonFileResult<()> {
+    let values_in_to = to.list_get(key).await?.0;
+    for val in from.list_get(key).await?.0 {
+        if !values_in_to.contains(&val) {
+            let kv = storage::KeyValue {
+                key: key.to_string(),
+                value: val,
+            };
+            to.list_append(&kv).await?;
+        }
+    }
+    Ok(())
+}
+
+// Copies the files of bucket `bucket` (out of `len`) from one backend to
+// another, keeping their numbers.
+async fn copy_files(from: &str, to: &str, bucket: usize, len: usize) -> TritonFileResult<()> {
+    info!("copying files of bucket {} from {} to {}", bucket, from, to);
+    let from_cli = new_client(from).await?;
+    let to_cli = new_client(to).await?;
+    let nodes = files_for(from_cli.as_ref(), bucket, len).await?;
+    to_cli.restore_nodes(nodes, &[]).await
+}
+
+// The files of bucket `bucket` (out of `len`) on a backend, in inode order.
+async fn files_for(
+    client: &dyn Storage,
+    bucket: usize,
+    len: usize,
+) -> TritonFileResult<Vec<NodeCopy>> {
+    let (inode_list, content_list) = match client.get_all_nodes(bucket, len).await? {
+        Some(nodes) => nodes,
+        None => {
+            return Err(Box::new(TritonFileError::Unknown(
+                "backend listed no files".to_string(),
+            )))
+        }
+    };
+    let mut nodes: Vec<NodeCopy> = inode_list
+        .0
+        .into_iter()
+        .zip(content_list.0)
+        .map(|(attrs, content)| NodeCopy {
+            attrs,
+            content: content.0,
+        })
+        .collect();
+    nodes.sort_by_key(|node| node.attrs.inode);
+    Ok(nodes)
+}
+
+// Key of the claim on the lease of `epoch`.
+fn claim_key(epoch: u64) -> String {
+    format!("{}_{}", KEY_LEADER_CLAIM, epoch)
+}
+
+// Fails if the lease is about to expire, in which case another keeper may
+// take over and we must stop acting as leader.
+fn check_lease(lease: &Lease) -> TritonFileResult<()> {
+    if now_millis() + LEASE_GUARD.as_millis() as u64 >= lease.expires_at {
+        return Err(Box::new(TritonFileError::NotLeader(lease.epoch)));
     }
+    Ok(())
 }
 
 // Send a clock() request to client to sync up the clock, if
@@ -540,9 +2115,14 @@ async fn sync_clock_with(
             });
         }
         // We get here if timeout is triggered before result comes
-        // in. In that case just give up.
+        // in. In that case just give up, and don't count the backend
+        // as alive: a partitioned backend must not stay in the view.
         Err(_) => {
             info!("Clock sync timed out");
+            return Err(Box::new(TritonFileError::DeadlineExceeded(format!(
+                "clock sync with back {}",
+                idx
+            ))));
         }
     };
     Ok(idx)
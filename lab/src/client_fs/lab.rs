@@ -3,6 +3,7 @@ use crate::client_fs::front::Front;
 use fuser::Filesystem;
 use log::info;
 use tribbler::config::KeeperConfig;
+use tribbler::config::ReadPolicy;
 use std::net::ToSocketAddrs;
 use std::sync::mpsc::Sender;
 use std::{
@@ -78,6 +79,21 @@ pub async fn new_bin_client(backs: Vec<String>) -> TritonFileResult<Box<dyn BinS
     Ok(Box::new(BinStore::new(backs)))
 }
 
+pub async fn new_bin_client_with_policy(
+    backs: Vec<String>,
+    read_policy: ReadPolicy,
+) -> TritonFileResult<Box<dyn BinStorage>> {
+    Ok(Box::new(BinStore::with_read_policy(backs, read_policy)))
+}
+
+pub async fn new_bin_client_with_keepers(
+    backs: Vec<String>,
+    keepers: Vec<String>,
+    read_policy: ReadPolicy,
+) -> TritonFileResult<Box<dyn BinStorage>> {
+    Ok(Box::new(BinStore::with_keepers(backs, keepers, read_policy)))
+}
+
 pub async fn serve_keeper(kc: KeeperConfig) -> TritonFileResult<()> {
     keeper::serve_keeper(kc).await
 }
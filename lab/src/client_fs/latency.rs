@@ -0,0 +1,66 @@
+//! Recent per-backend latencies, used to pick the nearest replica and to
+//! decide when a hedged read should also go to the backup.
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Number of samples kept per backend.
+pub const LATENCY_SAMPLES: usize = 64;
+
+#[derive(Default)]
+pub struct Latencies {
+    samples: Mutex<HashMap<String, VecDeque<Duration>>>,
+}
+
+impl Latencies {
+    pub fn new() -> Latencies {
+        Latencies::default()
+    }
+
+    pub fn record(&self, addr: &str, latency: Duration) {
+        let mut samples = self.samples.lock().unwrap();
+        let window = samples.entry(addr.to_string()).or_default();
+        if window.len() == LATENCY_SAMPLES {
+            window.pop_front();
+        }
+        window.push_back(latency);
+    }
+
+    /// Mean of the recent samples of a backend, [None] if it has none.
+    pub fn mean(&self, addr: &str) -> Option<Duration> {
+        let samples = self.samples.lock().unwrap();
+        let window = samples.get(addr).filter(|w| !w.is_empty())?;
+        Some(window.iter().sum::<Duration>() / window.len() as u32)
+    }
+
+    /// The given percentile (0 to 100) of the recent samples of a backend,
+    /// [None] if it has none.
+    pub fn percentile(&self, addr: &str, percentile: f64) -> Option<Duration> {
+        let samples = self.samples.lock().unwrap();
+        let window = samples.get(addr).filter(|w| !w.is_empty())?;
+        let mut sorted: Vec<Duration> = window.iter().cloned().collect();
+        sorted.sort();
+        let rank = (percentile.clamp(0.0, 100.0) / 100.0 * (sorted.len() - 1) as f64).round();
+        Some(sorted[rank as usize])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::Latencies;
+
+    #[test]
+    fn test_percentile() {
+        let latencies = Latencies::new();
+        assert_eq!(None, latencies.percentile("a", 95.0));
+        for ms in 1..=100 {
+            latencies.record("a", Duration::from_millis(ms));
+        }
+        // only the last LATENCY_SAMPLES are kept
+        assert_eq!(Some(Duration::from_millis(37)), latencies.percentile("a", 0.0));
+        assert_eq!(Some(Duration::from_millis(100)), latencies.percentile("a", 100.0));
+        assert!(latencies.mean("a").unwrap() > Duration::from_millis(60));
+    }
+}
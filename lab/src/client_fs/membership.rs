@@ -0,0 +1,363 @@
+//! Client-side view of which backends are currently alive.
+//!
+//! A [Membership] is shared by every bin handed out by one
+//! [BinStore](super::binstore::BinStore), so the liveness of a backend is
+//! learned once instead of being probed before every operation.
+//!
+//! Once the keeper has published a membership view, that view is followed
+//! as is, so that every client agrees on the primary of each bin within an
+//! epoch. The view is normally pushed by a keeper's watch stream; backends
+//! are only probed while no keeper can be reached.
+//!
+//! Keepers, which publish the views, keep their own state on the backends
+//! they find alive by probing, since the views the backends were fenced
+//! with may name some that left.
+//!
+//! The set of backends itself may change while clients run: a view carrying
+//! a newer cluster config replaces the backends the membership started with.
+use log::info;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock, Weak};
+use std::time::{Duration, Instant};
+use tonic::transport::Endpoint;
+use tribbler::error::{TritonFileError, TritonFileResult};
+use tribbler::keeper::membership_client::MembershipClient;
+use tribbler::keeper::Empty;
+use tribbler::storage::MembershipView;
+
+use super::client::ClientPool;
+
+/// How often the background task re-probes the backends.
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+/// A view older than this is refreshed inline before it is used.
+pub const STALE_AFTER: Duration = Duration::from_secs(3);
+/// How long a single liveness probe may take before the backend is
+/// considered dead.
+pub const PROBE_TIMEOUT: Duration = Duration::from_millis(500);
+/// A keeper's watch stream resends the view at least this often; a stream
+/// silent for longer is dropped and the next keeper is tried.
+pub const WATCH_TIMEOUT: Duration = Duration::from_secs(3);
+
+pub struct Membership {
+    addrs: RwLock<Vec<String>>,
+    // Version of the cluster config `addrs` comes from, 0 for the backends
+    // the membership was created with.
+    config_version: AtomicU64,
+    alive: RwLock<HashMap<String, bool>>,
+    refreshed_at: RwLock<Option<Instant>>,
+    // Whether a keeper's watch stream currently keeps the view up to date,
+    // in which case the backends are not probed.
+    watched: AtomicBool,
+    // Whether liveness is judged by the probes alone, never by the views
+    // the backends were fenced with.
+    probed: bool,
+}
+
+impl Membership {
+    /// Every backend starts out alive, but the view is stale so the first
+    /// operation refreshes it.
+    pub fn new(addrs: Vec<String>) -> Membership {
+        let alive = addrs.iter().map(|addr| (addr.clone(), true)).collect();
+        Membership {
+            addrs: RwLock::new(addrs),
+            config_version: AtomicU64::new(0),
+            alive: RwLock::new(alive),
+            refreshed_at: RwLock::new(None),
+            watched: AtomicBool::new(false),
+            probed: false,
+        }
+    }
+
+    /// A membership which takes the backends that answer its probes for
+    /// alive, whatever view they were fenced with, as keepers need.
+    pub fn probed(addrs: Vec<String>) -> Membership {
+        Membership {
+            probed: true,
+            ..Membership::new(addrs)
+        }
+    }
+
+    /// All backends of the cluster, alive or not.
+    pub fn backs(&self) -> Vec<String> {
+        self.addrs.read().unwrap().clone()
+    }
+
+    pub fn config_version(&self) -> u64 {
+        self.config_version.load(Ordering::SeqCst)
+    }
+
+    pub fn is_alive(&self, addr: &str) -> bool {
+        match self.alive.read() {
+            Ok(alive) => *alive.get(addr).unwrap_or(&true),
+            Err(_) => true,
+        }
+    }
+
+    pub fn is_stale(&self) -> bool {
+        match *self.refreshed_at.read().unwrap() {
+            Some(at) => at.elapsed() > STALE_AFTER,
+            None => true,
+        }
+    }
+
+    /// Forces the next user of the view to refresh it, e.g. after an RPC
+    /// to a backend we believed alive failed.
+    pub fn invalidate(&self) {
+        *self.refreshed_at.write().unwrap() = None;
+    }
+
+    /// Takes a backend out of the view until the next refresh, e.g. when it
+    /// keeps failing mutations the other replica accepted.
+    pub fn mark_suspect(&self, addr: &str) {
+        self.alive.write().unwrap().insert(addr.to_string(), false);
+    }
+
+    /// Replaces the view with one pushed from elsewhere (usually the
+    /// keeper). Backends not in `view.live` are considered dead.
+    pub fn update(&self, view: &MembershipView) {
+        self.adopt_config(view);
+        {
+            let mut alive = self.alive.write().unwrap();
+            for addr in self.addrs.read().unwrap().iter() {
+                alive.insert(addr.clone(), view.live.contains(addr));
+            }
+        }
+        *self.refreshed_at.write().unwrap() = Some(Instant::now());
+    }
+
+    // Switches to the backends of the view if it comes from a newer cluster
+    // config than ours.
+    fn adopt_config(&self, view: &MembershipView) {
+        if view.backs.is_empty() || view.config_version <= self.config_version() {
+            return;
+        }
+        let mut addrs = self.addrs.write().unwrap();
+        // Checked again under the lock, in case a concurrent update already
+        // moved past this config.
+        if self.config_version.fetch_max(view.config_version, Ordering::SeqCst)
+            >= view.config_version
+        {
+            return;
+        }
+        info!(
+            "moving to cluster config {}, backends: {:?}",
+            view.config_version, &view.backs
+        );
+        *addrs = view.backs.clone();
+    }
+
+    /// Probes every backend concurrently with a `fence` query, which also
+    /// returns the newest membership view the backend was fenced with. The
+    /// newest view found is adopted, and its epoch stamped on the requests of
+    /// `pool`. Without a published view, or if the membership is
+    /// [probed](Membership::probed), the backends that answered within
+    /// [PROBE_TIMEOUT] are considered alive. A probed membership stamps no
+    /// epoch, so that keepers can still write to a backend that came back
+    /// before they fenced it.
+    pub async fn refresh(&self, pool: &Arc<ClientPool>) {
+        let mut probes = vec![];
+        for addr in self.backs() {
+            let pool = pool.clone();
+            probes.push(tokio::spawn(async move {
+                let view = match pool.client(&addr) {
+                    Ok(client) => {
+                        match tokio::time::timeout(
+                            PROBE_TIMEOUT,
+                            client.fence(&MembershipView::default()),
+                        )
+                        .await
+                        {
+                            Ok(Ok(view)) => Some(view),
+                            _ => None,
+                        }
+                    }
+                    Err(_) => None,
+                };
+                (addr, view)
+            }));
+        }
+
+        let mut results = vec![];
+        for probe in probes {
+            if let Ok(result) = probe.await {
+                results.push(result);
+            }
+        }
+
+        let newest = results
+            .iter()
+            .filter_map(|(_, view)| view.as_ref())
+            .max_by_key(|view| view.epoch)
+            .filter(|view| view.epoch > 0)
+            .cloned();
+        let liveness: Vec<(String, bool)> = match &newest {
+            Some(newest) if !self.probed => {
+                // The view may name backends we did not probe, if it comes
+                // from a newer cluster config.
+                self.adopt_config(newest);
+                self.backs()
+                    .into_iter()
+                    .map(|addr| {
+                        let is_alive = newest.live.contains(&addr);
+                        (addr, is_alive)
+                    })
+                    .collect()
+            }
+            _ => results
+                .into_iter()
+                .map(|(addr, view)| (addr, view.is_some()))
+                .collect(),
+        };
+        {
+            let mut alive = self.alive.write().unwrap();
+            for (addr, is_alive) in liveness {
+                if alive.insert(addr.clone(), is_alive) != Some(is_alive) {
+                    info!("backend {} is now {}", addr, if is_alive { "up" } else { "down" });
+                }
+            }
+        }
+        if let Some(newest) = newest.filter(|_| !self.probed) {
+            if newest.epoch > pool.epoch() {
+                info!("moving to membership epoch {}", newest.epoch);
+            }
+            pool.observe_epoch(newest.epoch);
+        }
+        *self.refreshed_at.write().unwrap() = Some(Instant::now());
+    }
+
+    /// Keeps the view fresh from a background task. Does nothing outside a
+    /// tokio runtime, in which case callers fall back to refreshing inline
+    /// whenever the view is stale. The task exits once the membership is
+    /// dropped.
+    pub fn spawn_refresher(self: &Arc<Self>, pool: Arc<ClientPool>) {
+        let handle = match tokio::runtime::Handle::try_current() {
+            Ok(handle) => handle,
+            Err(_) => return,
+        };
+        let membership = Arc::downgrade(self);
+        handle.spawn(async move {
+            loop {
+                tokio::time::sleep(REFRESH_INTERVAL).await;
+                match membership.upgrade() {
+                    Some(membership) if membership.watched.load(Ordering::SeqCst) => (),
+                    Some(membership) => membership.refresh(&pool).await,
+                    None => break,
+                }
+            }
+        });
+    }
+
+    /// Follows the view served by `keepers` from a background task, moving
+    /// on to the next keeper whenever a watch stream breaks. Does nothing
+    /// outside a tokio runtime.
+    pub fn spawn_watcher(self: &Arc<Self>, keepers: Vec<String>, pool: Arc<ClientPool>) {
+        let handle = match tokio::runtime::Handle::try_current() {
+            Ok(handle) => handle,
+            Err(_) => return,
+        };
+        let membership = Arc::downgrade(self);
+        handle.spawn(async move {
+            for addr in keepers.iter().cycle() {
+                if let Err(e) = watch(&membership, addr, &pool).await {
+                    info!("watching keeper {} failed: {}", addr, e);
+                }
+                match membership.upgrade() {
+                    Some(membership) => membership.watched.store(false, Ordering::SeqCst),
+                    None => break,
+                }
+                tokio::time::sleep(REFRESH_INTERVAL).await;
+            }
+        });
+    }
+}
+
+// Applies the views streamed by the keeper at `addr` until the stream breaks
+// or the membership is dropped.
+async fn watch(
+    membership: &Weak<Membership>,
+    addr: &str,
+    pool: &ClientPool,
+) -> TritonFileResult<()> {
+    let channel = Endpoint::from_shared(format!("http://{}", addr))?.connect_lazy();
+    let mut stream = MembershipClient::new(channel)
+        .watch(Empty {})
+        .await?
+        .into_inner();
+    loop {
+        let view = match tokio::time::timeout(WATCH_TIMEOUT, stream.message()).await {
+            Ok(view) => view?,
+            Err(_) => {
+                return Err(Box::new(TritonFileError::DeadlineExceeded(format!(
+                    "watch on keeper {}",
+                    addr
+                ))))
+            }
+        };
+        let view = match view {
+            Some(view) => view,
+            None => return Err(Box::new(TritonFileError::RpcError("watch closed".to_string()))),
+        };
+        let membership = match membership.upgrade() {
+            Some(membership) => membership,
+            None => return Ok(()),
+        };
+        // A follower keeper may lag a round behind the leader; never go
+        // back to an older view than one we already acted on.
+        if view.epoch >= pool.epoch() {
+            if view.epoch > pool.epoch() {
+                info!("moving to membership epoch {} from keeper {}", view.epoch, addr);
+            }
+            pool.observe_epoch(view.epoch);
+            membership.update(&MembershipView {
+                epoch: view.epoch,
+                live: view.live,
+                backs: view.backs,
+                config_version: view.config_version.unwrap_or(0),
+            });
+        }
+        membership.watched.store(true, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::Membership;
+    use crate::client_fs::binstore::BinStore;
+    use crate::client_fs::client::ClientPool;
+
+    // nothing listens on these
+    fn dead_backs() -> Vec<String> {
+        vec!["127.0.0.1:32623".to_string(), "127.0.0.1:32624".to_string()]
+    }
+
+    #[tokio::test]
+    async fn test_view_is_reused_until_invalidated() {
+        let pool = Arc::new(ClientPool::new());
+        let membership = Membership::new(dead_backs());
+        assert!(membership.is_stale());
+        assert!(membership.is_alive("127.0.0.1:32623"));
+
+        membership.refresh(&pool).await;
+        assert!(!membership.is_stale());
+        for addr in dead_backs() {
+            assert!(!membership.is_alive(&addr));
+        }
+
+        membership.invalidate();
+        assert!(membership.is_stale());
+    }
+
+    #[tokio::test]
+    async fn test_store_shares_one_view() {
+        let store = BinStore::new(dead_backs());
+        let membership = store.membership();
+        assert!(Arc::ptr_eq(&membership, &store.membership()));
+
+        membership.mark_suspect("127.0.0.1:32623");
+        assert!(!store.membership().is_alive("127.0.0.1:32623"));
+        assert!(store.membership().is_alive("127.0.0.1:32624"));
+    }
+}
@@ -0,0 +1,316 @@
+//! Anti-entropy between the two file replicas of a bin. Both backends hash
+//! their inodes, directory entries and content blocks into a tree over the
+//! inode numbers (see [storage::Digest]); only subtrees whose hashes differ
+//! are descended into. Of each inode found to differ, the copy with the
+//! newer version is copied over the other one, keeping its number, so that
+//! the directory entries pointing to it stay right on both replicas.
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::time::Duration;
+
+use log::info;
+use serde::{Deserialize, Serialize};
+use tribbler::error::TritonFileResult;
+use tribbler::storage::{self, listed_inodes, node_hash, split_range, NodeCopy, Storage};
+
+/// How long an inode must keep differing before it is repaired, so that a
+/// mutation still on its way to the backup is not mistaken for divergence.
+pub const SETTLE_DELAY: Duration = Duration::from_millis(500);
+
+/// What anti-entropy passes compared, found and fixed.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Divergence {
+    pub rounds: u64,
+    /// Subtrees whose hashes were compared.
+    pub ranges: u64,
+    /// Inodes compared one by one.
+    pub inodes: u64,
+    /// Inodes that still differed after [SETTLE_DELAY].
+    pub divergent: u64,
+    /// Inodes copied to the replica that was behind, or removed from it.
+    pub repaired: u64,
+}
+
+impl Divergence {
+    pub fn add(&mut self, other: &Divergence) {
+        self.rounds += other.rounds;
+        self.ranges += other.ranges;
+        self.inodes += other.inodes;
+        self.divergent += other.divergent;
+        self.repaired += other.repaired;
+    }
+}
+
+/// Returns the inodes whose copies on `primary` and `backup` differ,
+/// including the ones only one of them has.
+pub async fn diff(
+    primary: &dyn Storage,
+    backup: &dyn Storage,
+    stats: &mut Divergence,
+) -> TritonFileResult<BTreeSet<u64>> {
+    let mut differing = BTreeSet::new();
+    let mut ranges = vec![(0, u64::MAX)];
+    while let Some((lo, hi)) = ranges.pop() {
+        stats.ranges += 1;
+        let ours = primary.digest(lo, hi, false).await?;
+        let theirs = backup.digest(lo, hi, false).await?;
+        if ours.hash == theirs.hash && ours.count == theirs.count {
+            continue;
+        }
+        if !ours.children.is_empty() && !theirs.children.is_empty() {
+            let children = ours.children.iter().zip(theirs.children.iter());
+            for (range, (a, b)) in split_range(lo, hi).into_iter().zip(children) {
+                if a != b {
+                    ranges.push(range);
+                }
+            }
+            continue;
+        }
+        // One side is small enough to list its inodes, so list the other's
+        // as well.
+        let ours = list_inodes(primary, ours, lo, hi).await?;
+        let theirs = list_inodes(backup, theirs, lo, hi).await?;
+        let mut both: BTreeMap<u64, (Option<u64>, Option<u64>)> = BTreeMap::new();
+        for (inode, hash) in ours {
+            both.entry(inode).or_default().0 = Some(hash);
+        }
+        for (inode, hash) in theirs {
+            both.entry(inode).or_default().1 = Some(hash);
+        }
+        stats.inodes += both.len() as u64;
+        differing.extend(
+            both.into_iter()
+                .filter(|(_, (a, b))| a != b)
+                .map(|(inode, _)| inode),
+        );
+    }
+    Ok(differing)
+}
+
+async fn list_inodes(
+    store: &dyn Storage,
+    digest: storage::Digest,
+    lo: u64,
+    hi: u64,
+) -> TritonFileResult<Vec<(u64, u64)>> {
+    if digest.children.is_empty() {
+        return Ok(digest.inodes);
+    }
+    Ok(store.digest(lo, hi, true).await?.inodes)
+}
+
+/// Runs one anti-entropy pass: finds the inodes that differ between
+/// `primary` and `backup`, and once they still differ after
+/// [SETTLE_DELAY], brings the replica that is behind up to date.
+///
+/// Of an inode both replicas have, the copy with the newer version wins;
+/// the primary's wins a tie. An inode only one replica has is a create the
+/// other one missed, or an unlink it missed itself: it is kept if the
+/// winning copy of a differing directory lists it, or if no copy of one
+/// does, and removed otherwise.
+///
+/// A mutation racing with the repair may be undone; the next pass finds
+/// and repairs it again.
+pub async fn reconcile(
+    primary: &dyn Storage,
+    backup: &dyn Storage,
+) -> TritonFileResult<Divergence> {
+    let mut stats = Divergence {
+        rounds: 1,
+        ..Default::default()
+    };
+    let differing: Vec<u64> = diff(primary, backup, &mut stats)
+        .await?
+        .into_iter()
+        .collect();
+    if differing.is_empty() {
+        return Ok(stats);
+    }
+    tokio::time::sleep(SETTLE_DELAY).await;
+
+    let on_primary = by_inode(primary.get_nodes(&differing).await?);
+    let on_backup = by_inode(backup.get_nodes(&differing).await?);
+    // Copies for the backup and the primary, and inodes to remove from them.
+    let mut to_backup = vec![];
+    let mut to_primary = vec![];
+    let mut remove_from_backup = vec![];
+    let mut remove_from_primary = vec![];
+    let mut losers = vec![];
+    for inode in differing.iter() {
+        if let (Some(p), Some(b)) = (on_primary.get(inode), on_backup.get(inode)) {
+            if node_hash(&p.attrs, &p.content) == node_hash(&b.attrs, &b.content) {
+                continue;
+            }
+            if b.attrs.version > p.attrs.version {
+                to_primary.push(b.clone());
+                losers.push(p);
+            } else {
+                to_backup.push(p.clone());
+                losers.push(b);
+            }
+        }
+    }
+    let listed: BTreeSet<u64> = to_backup
+        .iter()
+        .chain(to_primary.iter())
+        .flat_map(listed_inodes)
+        .collect();
+    let unlisted: BTreeSet<u64> = losers.into_iter().flat_map(listed_inodes).collect();
+    for inode in differing.iter() {
+        let (node, on_primary) = match (on_primary.get(inode), on_backup.get(inode)) {
+            (Some(node), None) => (node, true),
+            (None, Some(node)) => (node, false),
+            _ => continue,
+        };
+        let keep = listed.contains(inode) || !unlisted.contains(inode);
+        match (keep, on_primary) {
+            (true, true) => to_backup.push(node.clone()),
+            (true, false) => to_primary.push(node.clone()),
+            (false, true) => remove_from_primary.push(*inode),
+            (false, false) => remove_from_backup.push(*inode),
+        }
+    }
+
+    let copies = to_backup.len() + to_primary.len();
+    stats.divergent = (copies + remove_from_backup.len() + remove_from_primary.len()) as u64;
+    if stats.divergent == 0 {
+        return Ok(stats);
+    }
+    info!(
+        "{} inodes diverged: copying {:?} to the backup and {:?} to the primary, removing {:?} from the backup and {:?} from the primary",
+        stats.divergent,
+        inodes_of(&to_backup),
+        inodes_of(&to_primary),
+        &remove_from_backup,
+        &remove_from_primary
+    );
+    if !to_backup.is_empty() || !remove_from_backup.is_empty() {
+        backup.restore_nodes(to_backup, &remove_from_backup).await?;
+    }
+    if !to_primary.is_empty() || !remove_from_primary.is_empty() {
+        primary
+            .restore_nodes(to_primary, &remove_from_primary)
+            .await?;
+    }
+    stats.repaired = stats.divergent;
+    Ok(stats)
+}
+
+fn by_inode(nodes: Vec<NodeCopy>) -> HashMap<u64, NodeCopy> {
+    nodes
+        .into_iter()
+        .map(|node| (node.attrs.inode, node))
+        .collect()
+}
+
+fn inodes_of(nodes: &[NodeCopy]) -> Vec<u64> {
+    nodes.iter().map(|node| node.attrs.inode).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::OsStr;
+
+    use fuser::FUSE_ROOT_ID;
+    use tempfile::TempDir;
+    use tribbler::error::{TritonFileResult, SUCCESS};
+    use tribbler::storage::{FileRequest, RemoteFileSystem, Storage};
+
+    use super::reconcile;
+
+    const REQ: FileRequest = FileRequest {
+        uid: 0,
+        gid: 0,
+        pid: 0,
+    };
+
+    async fn create(store: &dyn Storage, name: &str) -> TritonFileResult<(u64, u64)> {
+        let (attr, code) = store
+            .create(
+                &REQ,
+                FUSE_ROOT_ID,
+                OsStr::new(name),
+                0o100644,
+                0,
+                libc::O_RDWR,
+            )
+            .await?;
+        assert_eq!(SUCCESS, code);
+        let (attr, fh) = attr.unwrap();
+        Ok((attr.ino, fh))
+    }
+
+    // A backend of its own, kept in `dir`.
+    fn backend(dir: &TempDir, num: usize) -> RemoteFileSystem {
+        RemoteFileSystem::in_dir(dir.path().to_string_lossy().to_string(), num)
+    }
+
+    async fn lookup(store: &dyn Storage, name: &str) -> TritonFileResult<Option<u64>> {
+        let (attr, _) = store.lookup(&REQ, FUSE_ROOT_ID, OsStr::new(name)).await?;
+        Ok(attr.map(|attr| attr.ino))
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_reconcile_repairs_backup() -> TritonFileResult<()> {
+        let (primary_dir, backup_dir) = (tempfile::tempdir()?, tempfile::tempdir()?);
+        let primary: Box<dyn Storage> = Box::new(backend(&primary_dir, 37));
+        let backup: Box<dyn Storage> = Box::new(backend(&backup_dir, 38));
+        let name = "diverged";
+        let (inode, _) = create(primary.as_ref(), name).await?;
+
+        // the new file and the root directory listing it differ
+        let found = reconcile(primary.as_ref(), backup.as_ref()).await?;
+        assert_eq!(2, found.divergent);
+        assert_eq!(2, found.repaired);
+        // the copy keeps its number
+        assert_eq!(Some(inode), lookup(backup.as_ref(), name).await?);
+
+        let found = reconcile(primary.as_ref(), backup.as_ref()).await?;
+        assert_eq!(0, found.divergent);
+        assert!(found.inodes == 0 && found.ranges == 1);
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_reconcile_follows_versions() -> TritonFileResult<()> {
+        let (primary_dir, backup_dir) = (tempfile::tempdir()?, tempfile::tempdir()?);
+        let primary = backend(&primary_dir, 37).namespace("versions").await?;
+        let backup = backend(&backup_dir, 38).namespace("versions").await?;
+        let (primary, backup) = (primary.as_ref(), backup.as_ref());
+        let (inode, _) = create(primary, "a").await?;
+        let (_, fh) = create(backup, "a").await?;
+        let found = reconcile(primary, backup).await?;
+        assert_eq!(0, found.divergent);
+
+        // A write only the backup got makes the backup's copy the newer one.
+        let (written, code) = backup
+            .write(&REQ, inode, fh, 0, b"newer", 0, 0, None)
+            .await?;
+        assert_eq!((Some(5), SUCCESS), (written, code));
+        let found = reconcile(primary, backup).await?;
+        assert_eq!(1, found.divergent);
+        assert_eq!(1, found.repaired);
+        let (attr, _) = primary.getattr(&REQ, inode).await?;
+        assert_eq!(5, attr.unwrap().size);
+
+        // A create the backup missed is copied to it, along with the root
+        // listing it.
+        let (created, _) = create(primary, "b").await?;
+        let found = reconcile(primary, backup).await?;
+        assert_eq!((2, 2), (found.divergent, found.repaired));
+        assert_eq!(Some(created), lookup(backup, "b").await?);
+
+        // An unlink the primary missed removes the file from it.
+        assert_eq!(
+            SUCCESS,
+            backup.unlink(&REQ, FUSE_ROOT_ID, OsStr::new("a")).await?
+        );
+        let found = reconcile(primary, backup).await?;
+        assert_eq!((2, 2), (found.divergent, found.repaired));
+        assert_eq!(None, lookup(primary, "a").await?);
+        assert_eq!(Some(created), lookup(primary, "b").await?);
+
+        let found = reconcile(primary, backup).await?;
+        assert_eq!(0, found.divergent);
+        Ok(())
+    }
+}
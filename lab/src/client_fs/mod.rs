@@ -1,7 +1,19 @@
 pub mod binstore;
+pub mod cache;
 pub mod client;
+pub mod files;
 pub mod front;
+pub mod invalidate;
 pub mod keeper;
 pub mod lab;
+pub mod latency;
+pub mod membership;
+pub mod merkle;
+pub mod offline;
 pub mod ops;
+pub mod queue;
+pub mod readahead;
+pub mod retry;
+pub mod shard;
 pub mod utils;
+pub mod writeback;
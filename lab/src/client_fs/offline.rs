@@ -0,0 +1,2294 @@
+//! Keeps a front-end working while no backend can be reached.
+//!
+//! Online, the store passes every operation on, and keeps copies of what
+//! comes back in a local directory: the attributes of the inodes it sees,
+//! the entries of the directories it lists or makes, and the data of the
+//! files it opens, up to a size. Once an operation fails because no backend
+//! can be reached, the store goes offline and answers from these copies;
+//! what they don't know fails as before, with `ENETDOWN`.
+//!
+//! Changes made offline are made to the copies and logged. The store checks
+//! every so often whether the backends are back, then replays the log in
+//! order, answering from the copies until it is done. A file changed
+//! offline is sent whole. If it changed on the backends too since it was
+//! copied, their version is kept and the offline one is saved next to it,
+//! as `<name>.conflict-<time>`; so is a file or directory whose name was
+//! taken meanwhile, except a directory made on both sides, which is merged.
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::error::Error;
+use std::ffi::OsStr;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use fuser::{FileAttr, FileType, TimeOrNow, FUSE_ROOT_ID};
+use libc::c_int;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tribbler::config::OfflineConfig;
+use tribbler::error::{is_retryable, TritonFileError, TritonFileResult, SUCCESS};
+use tribbler::simple::check_access;
+use tribbler::storage::{
+    self, ContentList, DataList, FileRequest, InodeList, KeyValue, ServerFileSystem, Storage,
+};
+
+/// Inodes made offline are numbered from here on, until the backends give
+/// them numbers of their own.
+pub const LOCAL_INODES: u64 = 1 << 63;
+/// Handles opened offline are numbered from here on. They all have this
+/// bit set, which those of the backends never do: they count up from 0 and
+/// only mark their access in the two bits above it.
+pub const LOCAL_HANDLES: u64 = 1 << 61;
+// Files are fetched and sent in pieces of this size.
+const CHUNK_BYTES: usize = 1 << 20;
+const BLOCK_SIZE: u64 = 512;
+// How many names a conflicting copy tries before it gives up.
+const CONFLICT_TRIES: usize = 16;
+// Whether the backends are back is asked as root.
+const PROBE_REQUEST: FileRequest = FileRequest {
+    uid: 0,
+    gid: 0,
+    pid: 0,
+};
+
+type Entries = BTreeMap<Vec<u8>, (u64, FileType)>;
+// An entry of a listing: its inode, the offset of the next one, its kind
+// and its name.
+type Listed = (u64, i64, FileType, Vec<u8>);
+
+/// What a file was like on the backends when it was copied.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+struct Stamp {
+    version: u64,
+    mtime: SystemTime,
+    size: u64,
+}
+
+impl Stamp {
+    fn of(attr: &FileAttr, version: u64) -> Stamp {
+        Stamp {
+            version,
+            mtime: attr.mtime,
+            size: attr.size,
+        }
+    }
+
+    // Whether the file is still as stamped. Versions are only compared if
+    // both are known.
+    fn matches(&self, other: &Stamp) -> bool {
+        self.mtime == other.mtime
+            && self.size == other.size
+            && (self.version == 0 || other.version == 0 || self.version == other.version)
+    }
+}
+
+/// The copy of an inode.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Node {
+    attr: FileAttr,
+    // Where the inode was last seen, by parent and name; none once it was
+    // unlinked.
+    parent: Option<(u64, Vec<u8>)>,
+    // All entries of a directory, once it was listed or made.
+    entries: Option<Entries>,
+    // Whether the data of a file is kept.
+    data: bool,
+    // What the file was like on the backends when its data was copied,
+    // none for files made offline.
+    stamp: Option<Stamp>,
+    // Whether the data was changed offline and not sent yet.
+    dirty: bool,
+    // Counts the changes made offline.
+    changes: u64,
+    #[serde(skip)]
+    used: u64,
+}
+
+impl Node {
+    fn new(attr: FileAttr) -> Node {
+        Node {
+            attr,
+            parent: None,
+            entries: None,
+            data: false,
+            stamp: None,
+            dirty: false,
+            changes: 0,
+            used: 0,
+        }
+    }
+}
+
+/// A change made offline, as replayed. Inodes are named by the numbers
+/// they had when it was made.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+enum Change {
+    Create {
+        parent: u64,
+        name: Vec<u8>,
+        ino: u64,
+        mode: u32,
+        umask: u32,
+    },
+    Mkdir {
+        parent: u64,
+        name: Vec<u8>,
+        ino: u64,
+        mode: u32,
+        umask: u32,
+    },
+    /// Sends the data of the file as it is when replayed.
+    Store {
+        ino: u64,
+    },
+    Mode {
+        ino: u64,
+        mode: u32,
+    },
+    Unlink {
+        parent: u64,
+        name: Vec<u8>,
+        ino: u64,
+    },
+    Rename {
+        parent: u64,
+        name: Vec<u8>,
+        new_parent: u64,
+        new_name: Vec<u8>,
+        ino: u64,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Logged {
+    uid: u32,
+    gid: u32,
+    change: Change,
+}
+
+fn now() -> SystemTime {
+    SystemTime::now()
+}
+
+fn time(time: TimeOrNow) -> SystemTime {
+    match time {
+        TimeOrNow::SpecificTime(time) => time,
+        TimeOrNow::Now => now(),
+    }
+}
+
+// The name of the `attempt`th conflicting copy of `name`.
+fn conflict_name(name: &[u8], attempt: usize) -> Vec<u8> {
+    let secs = now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or(0);
+    let mut copy = name.to_vec();
+    match attempt {
+        0 | 1 => copy.extend(format!(".conflict-{}", secs).bytes()),
+        _ => copy.extend(format!(".conflict-{}-{}", secs, attempt).bytes()),
+    }
+    copy
+}
+
+fn lossy(name: &[u8]) -> std::borrow::Cow<'_, str> {
+    String::from_utf8_lossy(name)
+}
+
+// Whether `err` says no backend could be reached: none is live, or the
+// live ones failed to answer in time or at all.
+fn unreachable(err: &(dyn Error + Send + Sync + 'static)) -> bool {
+    is_retryable(err)
+        || matches!(
+            err.downcast_ref::<TritonFileError>(),
+            Some(TritonFileError::RetriesExhausted(_))
+        )
+}
+
+// Whether `err` is a backend refusing a change with an errcode, which it
+// would refuse again.
+fn refused(err: &(dyn Error + Send + Sync + 'static)) -> bool {
+    matches!(
+        err.downcast_ref::<TritonFileError>(),
+        Some(TritonFileError::UserInterfaceError(_))
+    )
+}
+
+// Whether `fh` was opened offline.
+fn local_handle(fh: u64) -> bool {
+    fh & LOCAL_HANDLES != 0
+}
+
+fn unreachable_err<T>() -> TritonFileResult<T> {
+    Err(Box::new(TritonFileError::NoLiveStore))
+}
+
+// Answers from the copies: what they say, or that the backends can't be
+// reached if they don't know.
+fn answer<T>(result: Result<T, c_int>) -> TritonFileResult<(Option<T>, c_int)> {
+    match result {
+        Ok(value) => Ok((Some(value), SUCCESS)),
+        Err(libc::ENETDOWN) => unreachable_err(),
+        Err(errcode) => Ok((None, errcode)),
+    }
+}
+
+fn answer_code(result: Result<(), c_int>) -> TritonFileResult<c_int> {
+    let (_, errcode) = answer(result)?;
+    Ok(errcode)
+}
+
+fn eio(e: io::Error) -> c_int {
+    warn!("offline copies: {}", e);
+    libc::EIO
+}
+
+/// The copies, and the log of changes made offline, as kept on disk under
+/// one directory: a `.json` file for each inode, a `.data` file for the
+/// data of each file kept, the numbers the backends gave inodes made
+/// offline, and the log.
+struct Local {
+    dir: PathBuf,
+    config: OfflineConfig,
+    nodes: HashMap<u64, Node>,
+    aliases: HashMap<u64, u64>,
+    log: VecDeque<Logged>,
+    // Listings in progress, by directory and handle: the offset of the
+    // next entry, and the entries so far.
+    listings: HashMap<(u64, u64), (i64, Entries)>,
+    // Ticks at every use of a copy.
+    clock: u64,
+    next_ino: u64,
+    next_fh: u64,
+    offline: bool,
+    probing: bool,
+}
+
+impl Local {
+    fn load(dir: PathBuf, config: OfflineConfig) -> io::Result<Local> {
+        fs::create_dir_all(dir.join("nodes"))?;
+        let mut local = Local {
+            dir,
+            config,
+            nodes: HashMap::new(),
+            aliases: HashMap::new(),
+            log: VecDeque::new(),
+            listings: HashMap::new(),
+            clock: 0,
+            next_ino: LOCAL_INODES,
+            next_fh: LOCAL_HANDLES,
+            offline: false,
+            probing: false,
+        };
+        for entry in fs::read_dir(local.dir.join("nodes"))? {
+            let path = entry?.path();
+            if path.extension() != Some(OsStr::new("json")) {
+                continue;
+            }
+            let node: Node = match serde_json::from_slice(&fs::read(&path)?) {
+                Ok(node) => node,
+                Err(e) => {
+                    warn!("dropping offline copy {:?}: {}", path, e);
+                    continue;
+                }
+            };
+            let ino = node.attr.ino;
+            if ino >= LOCAL_INODES {
+                local.next_ino = local.next_ino.max(ino + 1);
+            }
+            local.nodes.insert(ino, node);
+        }
+        let aliases = local.dir.join("aliases.json");
+        if aliases.exists() {
+            let aliases: Vec<(u64, u64)> =
+                serde_json::from_slice(&fs::read(aliases)?).map_err(io::Error::from)?;
+            local.aliases = aliases.into_iter().collect();
+        }
+        let log = local.dir.join("log");
+        if log.exists() {
+            for line in BufReader::new(File::open(log)?).lines() {
+                let logged = serde_json::from_str(&line?).map_err(io::Error::from)?;
+                local.log.push_back(logged);
+            }
+        }
+        // What is left to replay is replayed once the backends are reached.
+        local.offline = !local.log.is_empty();
+        Ok(local)
+    }
+
+    fn node_path(&self, ino: u64) -> PathBuf {
+        self.dir.join("nodes").join(format!("{}.json", ino))
+    }
+
+    fn data_path(&self, ino: u64) -> PathBuf {
+        self.dir.join("nodes").join(format!("{}.data", ino))
+    }
+
+    fn data_file(&self, ino: u64) -> io::Result<File> {
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(self.data_path(ino))
+    }
+
+    fn save(&self, ino: u64) {
+        let result = match self.nodes.get(&ino) {
+            Some(node) => serde_json::to_vec(node)
+                .map_err(io::Error::from)
+                .and_then(|json| fs::write(self.node_path(ino), json)),
+            None => Ok(()),
+        };
+        if let Err(e) = result {
+            warn!("saving offline copy of {}: {}", ino, e);
+        }
+    }
+
+    fn save_aliases(&self) {
+        let aliases: Vec<(u64, u64)> = self.aliases.iter().map(|(a, b)| (*a, *b)).collect();
+        let result = serde_json::to_vec(&aliases)
+            .map_err(io::Error::from)
+            .and_then(|json| fs::write(self.dir.join("aliases.json"), json));
+        if let Err(e) = result {
+            warn!("saving offline inode numbers: {}", e);
+        }
+    }
+
+    fn save_log(&self) {
+        let mut lines = vec![];
+        for logged in self.log.iter() {
+            lines.extend(serde_json::to_vec(logged).unwrap());
+            lines.push(b'\n');
+        }
+        if let Err(e) = fs::write(self.dir.join("log"), lines) {
+            warn!("saving offline log: {}", e);
+        }
+    }
+
+    // Logs `change`, made offline on behalf of `req`.
+    fn append(&mut self, req: &FileRequest, change: Change) {
+        let logged = Logged {
+            uid: req.uid,
+            gid: req.gid,
+            change,
+        };
+        let result = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join("log"))
+            .and_then(|mut log| {
+                let mut line = serde_json::to_vec(&logged).unwrap();
+                line.push(b'\n');
+                log.write_all(&line)
+            });
+        if let Err(e) = result {
+            warn!("logging offline change: {}", e);
+        }
+        self.log.push_back(logged);
+    }
+
+    // Drops the change replayed last.
+    fn done(&mut self) {
+        self.log.pop_front();
+        self.save_log();
+    }
+
+    fn resolve(&self, ino: u64) -> u64 {
+        self.aliases.get(&ino).copied().unwrap_or(ino)
+    }
+
+    fn node(&mut self, ino: u64) -> Result<&mut Node, c_int> {
+        self.clock += 1;
+        let clock = self.clock;
+        let node = self.nodes.get_mut(&ino).ok_or(libc::ENETDOWN)?;
+        node.used = clock;
+        Ok(node)
+    }
+
+    fn entries(&mut self, ino: u64) -> Result<&mut Entries, c_int> {
+        let node = self.node(ino)?;
+        if node.attr.kind != FileType::Directory {
+            return Err(libc::ENOTDIR);
+        }
+        node.entries.as_mut().ok_or(libc::ENETDOWN)
+    }
+
+    // Sets entry `name` of `dir`, if all its entries are known.
+    fn enter(&mut self, dir: u64, name: &[u8], entry: Option<(u64, FileType)>) {
+        if let Some(Some(entries)) = self.nodes.get_mut(&dir).map(|node| node.entries.as_mut()) {
+            match entry {
+                Some(entry) => entries.insert(name.to_vec(), entry),
+                None => entries.remove(name),
+            };
+            self.save(dir);
+        }
+    }
+
+    /// Keeps `attr`, found as `name` of `parent` if given.
+    fn remember(&mut self, attr: &FileAttr, parent: Option<(u64, &[u8])>) {
+        let ino = attr.ino;
+        let node = self.nodes.entry(ino).or_insert_with(|| Node::new(*attr));
+        // changes made offline win until they are sent
+        if !node.dirty {
+            node.attr = *attr;
+        }
+        if let Some((parent, name)) = parent {
+            node.parent = Some((parent, name.to_vec()));
+        }
+        self.save(ino);
+        if let Some((parent, name)) = parent {
+            self.enter(parent, name, Some((ino, attr.kind)));
+        }
+    }
+
+    /// Keeps `data` as that of the file `attr` describes.
+    fn keep(&mut self, attr: &FileAttr, version: u64, data: &[u8]) {
+        let ino = attr.ino;
+        if let Err(e) = fs::write(self.data_path(ino), data) {
+            warn!("keeping data of {}: {}", ino, e);
+            return;
+        }
+        let node = self.nodes.entry(ino).or_insert_with(|| Node::new(*attr));
+        node.attr = *attr;
+        node.data = true;
+        node.stamp = Some(Stamp::of(attr, version));
+        node.dirty = false;
+        self.save(ino);
+        self.evict();
+    }
+
+    // Whether the data kept of `ino` is that of the file `attr` describes.
+    fn kept(&self, attr: &FileAttr, version: u64) -> bool {
+        match self.nodes.get(&attr.ino) {
+            Some(node) => match node.stamp {
+                Some(stamp) => node.data && stamp.matches(&Stamp::of(attr, version)),
+                None => false,
+            },
+            None => false,
+        }
+    }
+
+    fn drop_data(&mut self, ino: u64) {
+        if let Some(node) = self.nodes.get_mut(&ino) {
+            if node.data && !node.dirty {
+                node.data = false;
+                node.stamp = None;
+                let _ = fs::remove_file(self.data_path(ino));
+                self.save(ino);
+            }
+        }
+    }
+
+    fn forget(&mut self, ino: u64) {
+        if self.nodes.remove(&ino).is_some() {
+            let _ = fs::remove_file(self.node_path(ino));
+            let _ = fs::remove_file(self.data_path(ino));
+        }
+    }
+
+    // Drops the data of the files used least recently while more is kept
+    // than allowed. Data changed offline stays.
+    fn evict(&mut self) {
+        loop {
+            let bytes: u64 = self
+                .nodes
+                .values()
+                .filter(|node| node.data)
+                .map(|node| node.attr.size)
+                .sum();
+            if bytes <= self.config.capacity_bytes {
+                return;
+            }
+            let oldest = self
+                .nodes
+                .iter()
+                .filter(|(_, node)| node.data && !node.dirty)
+                .min_by_key(|(_, node)| node.used)
+                .map(|(ino, _)| *ino);
+            match oldest {
+                Some(ino) => self.drop_data(ino),
+                None => return,
+            }
+        }
+    }
+
+    /// Moves entry `name` of `parent` to `new_name` of `new_parent`.
+    fn moved(&mut self, parent: u64, name: &[u8], new_parent: u64, new_name: &[u8]) {
+        let entry = match self
+            .nodes
+            .get(&parent)
+            .and_then(|node| node.entries.as_ref())
+        {
+            Some(entries) => entries.get(name).copied(),
+            None => None,
+        };
+        let moved = self
+            .nodes
+            .iter()
+            .find(|(_, node)| {
+                node.parent.as_ref().map(|(p, n)| (*p, &n[..])) == Some((parent, name))
+            })
+            .map(|(ino, node)| (*ino, node.attr.kind));
+        let entry = entry.or(moved);
+        self.enter(parent, name, None);
+        let (ino, kind) = match entry {
+            Some(entry) => entry,
+            None => return,
+        };
+        let replaced = self
+            .nodes
+            .get(&new_parent)
+            .and_then(|node| node.entries.as_ref())
+            .and_then(|entries| entries.get(new_name).copied());
+        if let Some((replaced, _)) = replaced.filter(|(replaced, _)| *replaced != ino) {
+            if let Some(node) = self.nodes.get_mut(&replaced) {
+                node.parent = None;
+            }
+            self.save(replaced);
+        }
+        self.enter(new_parent, new_name, Some((ino, kind)));
+        if let Some(node) = self.nodes.get_mut(&ino) {
+            node.parent = Some((new_parent, new_name.to_vec()));
+        }
+        self.save(ino);
+        if kind == FileType::Directory {
+            self.enter(ino, b"..", Some((new_parent, FileType::Directory)));
+        }
+    }
+
+    /// Gives inode `local`, made offline, the number `attr.ino` the
+    /// backends gave it as `name` of `parent`.
+    fn alias(&mut self, local: u64, attr: &FileAttr, parent: u64, name: &[u8]) {
+        let ino = attr.ino;
+        self.aliases.insert(local, ino);
+        self.save_aliases();
+        let mut node = self
+            .nodes
+            .remove(&local)
+            .unwrap_or_else(|| Node::new(*attr));
+        let _ = fs::remove_file(self.node_path(local));
+        if node.data {
+            let _ = fs::rename(self.data_path(local), self.data_path(ino));
+        }
+        node.attr.ino = ino;
+        let unlinked = node.parent.is_none();
+        if !unlinked {
+            node.parent = Some((parent, name.to_vec()));
+        }
+        let kind = node.attr.kind;
+        self.nodes.insert(ino, node);
+        if !unlinked {
+            if let Some(Some(entries)) = self
+                .nodes
+                .get_mut(&parent)
+                .map(|node| node.entries.as_mut())
+            {
+                entries.retain(|_, (entry, _)| *entry != local);
+                entries.insert(name.to_vec(), (ino, kind));
+            }
+        }
+        let mut changed = vec![];
+        for (at, node) in self.nodes.iter_mut() {
+            let mut change = false;
+            if let Some(entries) = node.entries.as_mut() {
+                for (entry, _) in entries.values_mut() {
+                    if *entry == local {
+                        *entry = ino;
+                        change = true;
+                    }
+                }
+            }
+            if let Some((of, _)) = node.parent.as_mut() {
+                if *of == local {
+                    *of = ino;
+                    change = true;
+                }
+            }
+            if change || *at == ino || *at == parent {
+                changed.push(*at);
+            }
+        }
+        for at in changed {
+            self.save(at);
+        }
+    }
+
+    /// Records an entry of `dir` read through handle `fh` at `offset`, or
+    /// the end of its entries.
+    fn listed(&mut self, dir: u64, fh: u64, offset: i64, entry: Option<(u64, FileType, &[u8])>) {
+        if offset == 0 {
+            self.listings.insert((dir, fh), (0, Entries::new()));
+        }
+        let in_order = self
+            .listings
+            .get(&(dir, fh))
+            .is_some_and(|(next, _)| *next == offset);
+        if !in_order {
+            // entries were skipped
+            self.listings.remove(&(dir, fh));
+            return;
+        }
+        let (next, entries) = self.listings.get_mut(&(dir, fh)).unwrap();
+        match entry {
+            Some((ino, kind, name)) => {
+                entries.insert(name.to_vec(), (ino, kind));
+                *next += 1;
+            }
+            None => {
+                let (_, entries) = self.listings.remove(&(dir, fh)).unwrap();
+                if let Some(node) = self.nodes.get_mut(&dir) {
+                    if !node.dirty {
+                        node.entries = Some(entries);
+                        self.save(dir);
+                    }
+                }
+            }
+        }
+    }
+
+    fn handle(&mut self) -> u64 {
+        self.next_fh += 1;
+        self.next_fh
+    }
+
+    fn attr(&mut self, ino: u64) -> Result<FileAttr, c_int> {
+        Ok(self.node(ino)?.attr)
+    }
+
+    fn lookup(&mut self, parent: u64, name: &[u8]) -> Result<FileAttr, c_int> {
+        let listed = self
+            .node(parent)?
+            .entries
+            .as_ref()
+            .map(|entries| entries.get(name).copied());
+        let entry = match listed {
+            Some(Some((ino, _))) => ino,
+            Some(None) => return Err(libc::ENOENT),
+            None => self
+                .nodes
+                .iter()
+                .find(|(_, node)| {
+                    node.parent.as_ref().map(|(p, n)| (*p, &n[..])) == Some((parent, name))
+                })
+                .map(|(ino, _)| *ino)
+                .ok_or(libc::ENETDOWN)?,
+        };
+        self.attr(entry)
+    }
+
+    fn readdir(
+        &mut self,
+        dir: u64,
+        offset: i64,
+    ) -> Result<Option<Listed>, c_int> {
+        let entries = self.entries(dir)?;
+        Ok(entries
+            .iter()
+            .nth(offset.max(0) as usize)
+            .map(|(name, (ino, kind))| (*ino, offset + 1, *kind, name.clone())))
+    }
+
+    fn read(&mut self, ino: u64, offset: i64, size: u32) -> Result<Vec<u8>, c_int> {
+        let node = self.node(ino)?;
+        if !node.data {
+            return Err(libc::ENETDOWN);
+        }
+        let end = node.attr.size.min(offset.max(0) as u64 + size as u64);
+        let offset = offset.max(0) as u64;
+        if offset >= end {
+            return Ok(vec![]);
+        }
+        let mut data = vec![0; (end - offset) as usize];
+        let read = self
+            .data_file(ino)
+            .and_then(|file| file.read_at(&mut data, offset));
+        data.truncate(read.map_err(eio)?);
+        Ok(data)
+    }
+
+    fn read_all(&self, ino: u64) -> io::Result<Vec<u8>> {
+        fs::read(self.data_path(ino))
+    }
+
+    // Records that the data of `ino` was changed offline.
+    fn changed(&mut self, req: &FileRequest, ino: u64) {
+        let node = match self.nodes.get_mut(&ino) {
+            Some(node) => node,
+            None => return,
+        };
+        node.changes += 1;
+        let first = !node.dirty;
+        node.dirty = true;
+        self.save(ino);
+        if first {
+            self.append(req, Change::Store { ino });
+        }
+    }
+
+    fn write(
+        &mut self,
+        req: &FileRequest,
+        ino: u64,
+        offset: i64,
+        data: &[u8],
+    ) -> Result<u32, c_int> {
+        if !self.node(ino)?.data {
+            return Err(libc::ENETDOWN);
+        }
+        let offset = offset.max(0) as u64;
+        self.data_file(ino)
+            .and_then(|file| file.write_all_at(data, offset))
+            .map_err(eio)?;
+        let node = self.node(ino)?;
+        node.attr.size = node.attr.size.max(offset + data.len() as u64);
+        node.attr.blocks = node.attr.size.div_ceil(BLOCK_SIZE);
+        node.attr.mtime = now();
+        node.attr.ctime = node.attr.mtime;
+        self.changed(req, ino);
+        Ok(data.len() as u32)
+    }
+
+    fn open(&mut self, req: &FileRequest, ino: u64, flags: i32) -> Result<u64, c_int> {
+        let node = self.node(ino)?;
+        if node.attr.kind == FileType::Directory {
+            return Err(libc::EISDIR);
+        }
+        if !node.data {
+            return Err(libc::ENETDOWN);
+        }
+        if flags & libc::O_TRUNC != 0 {
+            self.truncate(req, ino, 0)?;
+        }
+        Ok(self.handle())
+    }
+
+    fn truncate(&mut self, req: &FileRequest, ino: u64, size: u64) -> Result<(), c_int> {
+        if !self.node(ino)?.data {
+            return Err(libc::ENETDOWN);
+        }
+        self.data_file(ino)
+            .and_then(|file| file.set_len(size))
+            .map_err(eio)?;
+        let node = self.node(ino)?;
+        node.attr.size = size;
+        node.attr.blocks = size.div_ceil(BLOCK_SIZE);
+        node.attr.mtime = now();
+        node.attr.ctime = node.attr.mtime;
+        self.changed(req, ino);
+        Ok(())
+    }
+
+    fn setattr(
+        &mut self,
+        req: &FileRequest,
+        ino: u64,
+        mode: Option<u32>,
+        size: Option<u64>,
+        atime: Option<TimeOrNow>,
+        mtime: Option<TimeOrNow>,
+    ) -> Result<FileAttr, c_int> {
+        self.node(ino)?;
+        if let Some(size) = size {
+            self.truncate(req, ino, size)?;
+        }
+        let node = self.node(ino)?;
+        if let Some(mode) = mode {
+            node.attr.perm = (mode & 0o7777) as u16;
+            node.attr.ctime = now();
+        }
+        // times are only set on the copy, the data sent sets them anew
+        if let Some(atime) = atime {
+            node.attr.atime = time(atime);
+        }
+        if let Some(mtime) = mtime {
+            node.attr.mtime = time(mtime);
+        }
+        let attr = node.attr;
+        self.save(ino);
+        if let Some(mode) = mode {
+            self.append(req, Change::Mode { ino, mode });
+        }
+        Ok(attr)
+    }
+
+    fn make(
+        &mut self,
+        req: &FileRequest,
+        parent: u64,
+        name: &[u8],
+        mode: u32,
+        umask: u32,
+        kind: FileType,
+    ) -> Result<FileAttr, c_int> {
+        if self.entries(parent)?.contains_key(name) {
+            return Err(libc::EEXIST);
+        }
+        let ino = self.next_ino;
+        self.next_ino += 1;
+        let made = now();
+        let attr = FileAttr {
+            ino,
+            size: 0,
+            blocks: 0,
+            atime: made,
+            mtime: made,
+            ctime: made,
+            crtime: made,
+            kind,
+            perm: (mode & !umask & 0o7777) as u16,
+            nlink: if kind == FileType::Directory { 2 } else { 1 },
+            uid: req.uid,
+            gid: req.gid,
+            rdev: 0,
+            blksize: BLOCK_SIZE as u32,
+            flags: 0,
+        };
+        let mut node = Node::new(attr);
+        node.parent = Some((parent, name.to_vec()));
+        if kind == FileType::Directory {
+            let mut entries = Entries::new();
+            entries.insert(b".".to_vec(), (ino, FileType::Directory));
+            entries.insert(b"..".to_vec(), (parent, FileType::Directory));
+            node.entries = Some(entries);
+        } else {
+            self.data_file(ino).map_err(eio)?;
+            node.data = true;
+        }
+        self.nodes.insert(ino, node);
+        self.save(ino);
+        self.enter(parent, name, Some((ino, kind)));
+        let (parent, name) = (parent, name.to_vec());
+        let change = match kind {
+            FileType::Directory => Change::Mkdir {
+                parent,
+                name,
+                ino,
+                mode,
+                umask,
+            },
+            _ => Change::Create {
+                parent,
+                name,
+                ino,
+                mode,
+                umask,
+            },
+        };
+        self.append(req, change);
+        Ok(attr)
+    }
+
+    fn unlink(&mut self, req: &FileRequest, parent: u64, name: &[u8]) -> Result<(), c_int> {
+        let (ino, kind) = *self.entries(parent)?.get(name).ok_or(libc::ENOENT)?;
+        if kind == FileType::Directory {
+            return Err(libc::EISDIR);
+        }
+        self.enter(parent, name, None);
+        if let Some(node) = self.nodes.get_mut(&ino) {
+            node.parent = None;
+            node.dirty = false;
+            node.attr.nlink = node.attr.nlink.saturating_sub(1);
+            self.save(ino);
+        }
+        let name = name.to_vec();
+        self.append(req, Change::Unlink { parent, name, ino });
+        Ok(())
+    }
+
+    fn rename(
+        &mut self,
+        req: &FileRequest,
+        parent: u64,
+        name: &[u8],
+        new_parent: u64,
+        new_name: &[u8],
+        flags: u32,
+    ) -> Result<(), c_int> {
+        if flags & !libc::RENAME_NOREPLACE != 0 {
+            return Err(libc::ENETDOWN);
+        }
+        let (ino, _) = *self.entries(parent)?.get(name).ok_or(libc::ENOENT)?;
+        if let Some((replaced, kind)) = self.entries(new_parent)?.get(new_name).copied() {
+            if replaced == ino {
+                return Ok(());
+            }
+            if flags != 0 {
+                return Err(libc::EEXIST);
+            }
+            if kind == FileType::Directory {
+                // whether it is empty isn't known for sure
+                return Err(libc::ENETDOWN);
+            }
+            self.unlink(req, new_parent, new_name)?;
+        }
+        self.moved(parent, name, new_parent, new_name);
+        let (name, new_name) = (name.to_vec(), new_name.to_vec());
+        self.append(
+            req,
+            Change::Rename {
+                parent,
+                name,
+                new_parent,
+                new_name,
+                ino,
+            },
+        );
+        Ok(())
+    }
+
+    // Records that the data of `ino`, as it was after `changes` changes,
+    // was sent and the backends now have it as `attr` says. Returns
+    // whether it changed again meanwhile.
+    fn sent(&mut self, ino: u64, changes: u64, attr: Option<(FileAttr, u64)>) -> bool {
+        let node = match self.nodes.get_mut(&ino) {
+            Some(node) => node,
+            None => return false,
+        };
+        if node.changes != changes {
+            return true;
+        }
+        node.dirty = false;
+        match attr {
+            Some((attr, version)) => {
+                node.attr = attr;
+                node.stamp = Some(Stamp::of(&attr, version));
+            }
+            None => node.stamp = None,
+        }
+        self.save(ino);
+        self.evict();
+        false
+    }
+}
+
+/// The files of a namespace, as kept by [Local] copies whenever the
+/// backends of `bin` can't be reached.
+#[derive(Clone)]
+pub struct OfflineStore {
+    bin: Arc<dyn Storage>,
+    local: Arc<Mutex<Local>>,
+}
+
+impl OfflineStore {
+    /// A store over `bin`, keeping its copies under `dir`, where those of
+    /// an earlier run may be.
+    pub fn new(
+        bin: Arc<dyn Storage>,
+        dir: &Path,
+        config: OfflineConfig,
+    ) -> TritonFileResult<OfflineStore> {
+        let local = Local::load(dir.to_path_buf(), config)?;
+        if !local.log.is_empty() {
+            info!("{} changes made offline are left to send", local.log.len());
+        }
+        Ok(OfflineStore {
+            bin,
+            local: Arc::new(Mutex::new(local)),
+        })
+    }
+
+    fn local(&self) -> MutexGuard<'_, Local> {
+        self.local.lock().unwrap()
+    }
+
+    // Whether to answer from the copies: while offline, while changes are
+    // left to send, and for inodes and handles the backends don't know.
+    fn here(&self, inodes: &[u64], fh: Option<u64>) -> bool {
+        let local = self.local();
+        local.offline
+            || !local.log.is_empty()
+            || inodes.iter().any(|ino| local.resolve(*ino) >= LOCAL_INODES)
+            || fh.is_some_and(local_handle)
+    }
+
+    // Goes offline if `err` says no backend can be reached. Returns whether
+    // it does.
+    fn went_offline(&self, err: &(dyn Error + Send + Sync + 'static)) -> bool {
+        if !unreachable(err) {
+            return false;
+        }
+        let mut local = self.local();
+        if !local.config.enabled {
+            return false;
+        }
+        if !local.offline {
+            warn!("no backend can be reached, going on offline");
+            local.offline = true;
+        }
+        drop(local);
+        self.probe();
+        true
+    }
+
+    // Starts checking whether the backends are back, unless it already is.
+    fn probe(&self) {
+        let mut local = self.local();
+        if local.probing {
+            return;
+        }
+        local.probing = true;
+        drop(local);
+        let store = self.clone();
+        tokio::spawn(async move { store.reconnect().await });
+    }
+
+    async fn reconnect(self) {
+        let interval = self.local().config.retry_interval();
+        loop {
+            tokio::time::sleep(interval).await;
+            if let Err(e) = self.bin.getattr(&PROBE_REQUEST, FUSE_ROOT_ID).await {
+                info!("backends still unreachable: {}", e);
+                continue;
+            }
+            if self.reconcile().await {
+                return;
+            }
+        }
+    }
+
+    /// Replays the changes made offline, in order, and goes back online
+    /// once none are left. Returns false if the backends can't be reached
+    /// again first.
+    pub async fn reconcile(&self) -> bool {
+        loop {
+            let logged = {
+                let mut local = self.local();
+                let next = local.log.front().cloned();
+                match next {
+                    Some(logged) => logged,
+                    None => {
+                        if local.offline {
+                            info!("backends reached again, changes made offline sent");
+                        }
+                        local.offline = false;
+                        local.probing = false;
+                        return true;
+                    }
+                }
+            };
+            match self.replay(&logged).await {
+                Ok(()) => (),
+                Err(e) if refused(e.as_ref()) => {
+                    warn!("dropping offline change {:?}: {}", logged.change, e)
+                }
+                // Kept to be sent again on the next try.
+                Err(e) => {
+                    info!("offline change {:?} not sent: {}", logged.change, e);
+                    return false;
+                }
+            }
+            self.local().done();
+        }
+    }
+
+    async fn replay(&self, logged: &Logged) -> TritonFileResult<()> {
+        let req = FileRequest {
+            uid: logged.uid,
+            gid: logged.gid,
+            pid: 0,
+        };
+        match &logged.change {
+            Change::Create {
+                parent,
+                name,
+                ino,
+                mode,
+                umask,
+            } => {
+                self.replay_make(&req, *parent, name, *ino, *mode, *umask, false)
+                    .await
+            }
+            Change::Mkdir {
+                parent,
+                name,
+                ino,
+                mode,
+                umask,
+            } => {
+                self.replay_make(&req, *parent, name, *ino, *mode, *umask, true)
+                    .await
+            }
+            Change::Store { ino } => self.replay_store(&req, *ino).await,
+            Change::Mode { ino, mode } => {
+                let ino = self.local().resolve(*ino);
+                let (_, errcode) = self
+                    .bin
+                    .setattr(
+                        &req,
+                        ino,
+                        Some(*mode),
+                        None,
+                        None,
+                        None,
+                        None,
+                        None,
+                        None,
+                        None,
+                        None,
+                        None,
+                        None,
+                        None,
+                    )
+                    .await?;
+                if errcode != SUCCESS {
+                    warn!("mode of {} set offline not sent: {}", ino, errcode);
+                }
+                Ok(())
+            }
+            Change::Unlink { parent, name, ino } => {
+                self.replay_unlink(&req, *parent, name, *ino).await
+            }
+            Change::Rename {
+                parent,
+                name,
+                new_parent,
+                new_name,
+                ino,
+            } => {
+                self.replay_rename(&req, *parent, name, *new_parent, new_name, *ino)
+                    .await
+            }
+        }
+    }
+
+    // Makes the file or directory `ino` made offline, under another name if
+    // its own was taken meanwhile. A directory of the same name is used as
+    // is.
+    #[allow(clippy::too_many_arguments)]
+    async fn replay_make(
+        &self,
+        req: &FileRequest,
+        parent: u64,
+        name: &[u8],
+        ino: u64,
+        mode: u32,
+        umask: u32,
+        dir: bool,
+    ) -> TritonFileResult<()> {
+        let parent = self.local().resolve(parent);
+        for attempt in 0..CONFLICT_TRIES {
+            let tried = match attempt {
+                0 => name.to_vec(),
+                _ => conflict_name(name, attempt),
+            };
+            let (made, errcode) = if dir {
+                self.bin
+                    .mkdir(req, parent, OsStr::from_bytes(&tried), mode, umask)
+                    .await?
+            } else {
+                let (made, errcode) = self
+                    .bin
+                    .create(
+                        req,
+                        parent,
+                        OsStr::from_bytes(&tried),
+                        mode,
+                        umask,
+                        libc::O_WRONLY,
+                    )
+                    .await?;
+                if let Some((attr, fh)) = made {
+                    self.bin
+                        .release(req, attr.ino, fh, libc::O_WRONLY, None, false)
+                        .await?;
+                }
+                (made.map(|(attr, _)| attr), errcode)
+            };
+            match (made, errcode) {
+                (Some(attr), SUCCESS) => {
+                    if attempt > 0 {
+                        warn!(
+                            "{} was taken, made offline as {}",
+                            lossy(name),
+                            lossy(&tried)
+                        );
+                    }
+                    self.local().alias(ino, &attr, parent, &tried);
+                    return Ok(());
+                }
+                (_, libc::EEXIST) if dir && attempt == 0 => {
+                    let (found, errcode) = self
+                        .bin
+                        .lookup(req, parent, OsStr::from_bytes(name))
+                        .await?;
+                    if let (Some(found), SUCCESS) = (found, errcode) {
+                        if found.kind == FileType::Directory {
+                            let mut local = self.local();
+                            local.alias(ino, &found, parent, name);
+                            // what else it has is listed anew
+                            if let Some(node) = local.nodes.get_mut(&found.ino) {
+                                node.attr = found;
+                                node.entries = None;
+                            }
+                            local.save(found.ino);
+                            return Ok(());
+                        }
+                    }
+                }
+                (_, libc::EEXIST) => (),
+                (_, errcode) => {
+                    warn!("{} made offline not sent: {}", lossy(name), errcode);
+                    return Ok(());
+                }
+            }
+        }
+        warn!("{} made offline not sent: no free name", lossy(name));
+        Ok(())
+    }
+
+    // Sends the data of `ino` changed offline, or saves it as a conflicting
+    // copy if the file changed on the backends too.
+    async fn replay_store(&self, req: &FileRequest, ino: u64) -> TritonFileResult<()> {
+        let (ino, node, data) = {
+            let local = self.local();
+            let ino = local.resolve(ino);
+            let node = match local.nodes.get(&ino) {
+                Some(node) if node.dirty && node.data && node.parent.is_some() => node.clone(),
+                _ => return Ok(()),
+            };
+            let data = local.read_all(ino)?;
+            (ino, node, data)
+        };
+        let (parent, name) = node.parent.clone().unwrap();
+        let parent = self.local().resolve(parent);
+        let (remote, errcode, version) = self.bin.getattr_versioned(req, ino).await?;
+        let conflict = match (remote, errcode, node.stamp) {
+            (Some(remote), SUCCESS, Some(stamp)) => !stamp.matches(&Stamp::of(&remote, version)),
+            (Some(_), SUCCESS, None) => false,
+            // gone from the backends
+            _ => true,
+        };
+        if conflict {
+            let copy = self
+                .save_copy(req, parent, &name, node.attr.perm, &data)
+                .await?;
+            warn!(
+                "{} changed both offline and elsewhere, the offline version is saved as {}",
+                lossy(&name),
+                lossy(&copy)
+            );
+            self.local().forget(ino);
+            return Ok(());
+        }
+        let errcode = self.send(req, ino, &data).await?;
+        if errcode != SUCCESS {
+            warn!("{} changed offline not sent: {}", lossy(&name), errcode);
+            return Ok(());
+        }
+        let (remote, errcode, version) = self.bin.getattr_versioned(req, ino).await?;
+        let attr = match (remote, errcode) {
+            (Some(remote), SUCCESS) => Some((remote, version)),
+            _ => None,
+        };
+        let mut local = self.local();
+        if local.sent(ino, node.changes, attr) {
+            local.append(req, Change::Store { ino });
+        }
+        Ok(())
+    }
+
+    // Replaces the data of `ino` on the backends with `data`.
+    async fn send(&self, req: &FileRequest, ino: u64, data: &[u8]) -> TritonFileResult<c_int> {
+        let (opened, errcode) = self.bin.open(req, ino, libc::O_WRONLY).await?;
+        let fh = match (opened, errcode) {
+            (Some((fh, _)), SUCCESS) => fh,
+            (_, errcode) => return Ok(errcode),
+        };
+        let (_, mut errcode) = self
+            .bin
+            .setattr(
+                req,
+                ino,
+                None,
+                None,
+                None,
+                Some(data.len() as u64),
+                None,
+                None,
+                None,
+                Some(fh),
+                None,
+                None,
+                None,
+                None,
+            )
+            .await?;
+        if errcode == SUCCESS {
+            for (i, chunk) in data.chunks(CHUNK_BYTES).enumerate() {
+                let offset = (i * CHUNK_BYTES) as i64;
+                let (_, written) = self
+                    .bin
+                    .write(req, ino, fh, offset, chunk, 0, libc::O_WRONLY, None)
+                    .await?;
+                if written != SUCCESS {
+                    errcode = written;
+                    break;
+                }
+            }
+        }
+        self.bin
+            .release(req, ino, fh, libc::O_WRONLY, None, false)
+            .await?;
+        Ok(errcode)
+    }
+
+    // Saves `data` next to `name` of `parent`, as a conflicting copy.
+    // Returns the name of the copy.
+    async fn save_copy(
+        &self,
+        req: &FileRequest,
+        parent: u64,
+        name: &[u8],
+        perm: u16,
+        data: &[u8],
+    ) -> TritonFileResult<Vec<u8>> {
+        let mode = libc::S_IFREG | perm as u32;
+        for attempt in 1..=CONFLICT_TRIES {
+            let copy = conflict_name(name, attempt);
+            let (made, errcode) = self
+                .bin
+                .create(
+                    req,
+                    parent,
+                    OsStr::from_bytes(&copy),
+                    mode,
+                    0,
+                    libc::O_WRONLY,
+                )
+                .await?;
+            let (attr, fh) = match (made, errcode) {
+                (Some(made), SUCCESS) => made,
+                (_, libc::EEXIST) => continue,
+                (_, errcode) => return Err(Box::new(TritonFileError::UserInterfaceError(errcode))),
+            };
+            self.bin
+                .release(req, attr.ino, fh, libc::O_WRONLY, None, false)
+                .await?;
+            let errcode = self.send(req, attr.ino, data).await?;
+            if errcode != SUCCESS {
+                return Err(Box::new(TritonFileError::UserInterfaceError(errcode)));
+            }
+            self.local().remember(&attr, Some((parent, &copy)));
+            return Ok(copy);
+        }
+        Err(Box::new(TritonFileError::UserInterfaceError(libc::EEXIST)))
+    }
+
+    // Unlinks `name` of `parent`, unless it is no longer the file unlinked
+    // offline, or that file changed elsewhere since.
+    async fn replay_unlink(
+        &self,
+        req: &FileRequest,
+        parent: u64,
+        name: &[u8],
+        ino: u64,
+    ) -> TritonFileResult<()> {
+        let (parent, ino) = {
+            let local = self.local();
+            (local.resolve(parent), local.resolve(ino))
+        };
+        let (found, errcode) = self
+            .bin
+            .lookup(req, parent, OsStr::from_bytes(name))
+            .await?;
+        let found = match (found, errcode) {
+            (Some(found), SUCCESS) => found,
+            _ => return Ok(()),
+        };
+        if found.ino != ino {
+            warn!("{} was replaced elsewhere, not unlinked", lossy(name));
+            return Ok(());
+        }
+        let stamp = self.local().nodes.get(&ino).and_then(|node| node.stamp);
+        if let Some(stamp) = stamp {
+            if !stamp.matches(&Stamp::of(&found, 0)) {
+                warn!("{} changed elsewhere, not unlinked", lossy(name));
+                return Ok(());
+            }
+        }
+        let errcode = self
+            .bin
+            .unlink(req, parent, OsStr::from_bytes(name))
+            .await?;
+        if errcode != SUCCESS {
+            warn!("{} unlinked offline not sent: {}", lossy(name), errcode);
+        }
+        self.local().forget(ino);
+        Ok(())
+    }
+
+    // Renames `name` of `parent`, if it is still the inode renamed offline,
+    // to another name if `new_name` was taken meanwhile.
+    async fn replay_rename(
+        &self,
+        req: &FileRequest,
+        parent: u64,
+        name: &[u8],
+        new_parent: u64,
+        new_name: &[u8],
+        ino: u64,
+    ) -> TritonFileResult<()> {
+        let (parent, new_parent, ino) = {
+            let local = self.local();
+            (
+                local.resolve(parent),
+                local.resolve(new_parent),
+                local.resolve(ino),
+            )
+        };
+        let (found, errcode) = self
+            .bin
+            .lookup(req, parent, OsStr::from_bytes(name))
+            .await?;
+        match (found, errcode) {
+            (Some(found), SUCCESS) if found.ino == ino => (),
+            _ => {
+                warn!("{} was moved elsewhere, not renamed", lossy(name));
+                return Ok(());
+            }
+        }
+        for attempt in 0..CONFLICT_TRIES {
+            let target = match attempt {
+                0 => new_name.to_vec(),
+                _ => conflict_name(new_name, attempt),
+            };
+            let errcode = self
+                .bin
+                .rename(
+                    req,
+                    parent,
+                    OsStr::from_bytes(name),
+                    new_parent,
+                    OsStr::from_bytes(&target),
+                    libc::RENAME_NOREPLACE,
+                )
+                .await?;
+            match errcode {
+                SUCCESS => {
+                    if attempt > 0 {
+                        warn!(
+                            "{} was taken, renamed to {}",
+                            lossy(new_name),
+                            lossy(&target)
+                        );
+                        self.local()
+                            .moved(new_parent, new_name, new_parent, &target);
+                    }
+                    return Ok(());
+                }
+                libc::EEXIST => (),
+                errcode => {
+                    warn!("{} renamed offline not sent: {}", lossy(name), errcode);
+                    return Ok(());
+                }
+            }
+        }
+        warn!("{} renamed offline not sent: no free name", lossy(name));
+        Ok(())
+    }
+
+    // Keeps the data of the file `ino`, opened online as `fh`, unless it is
+    // kept already or too large.
+    async fn fetch(&self, req: &FileRequest, ino: u64, fh: u64) -> TritonFileResult<()> {
+        let (attr, errcode, version) = self.bin.getattr_versioned(req, ino).await?;
+        let attr = match (attr, errcode) {
+            (Some(attr), SUCCESS) if attr.kind == FileType::RegularFile => attr,
+            _ => return Ok(()),
+        };
+        {
+            let local = self.local();
+            if attr.size > local.config.file_bytes || local.kept(&attr, version) {
+                return Ok(());
+            }
+        }
+        let mut data = Vec::with_capacity(attr.size as usize);
+        while (data.len() as u64) < attr.size {
+            let read = self
+                .bin
+                .read(
+                    req,
+                    ino,
+                    fh,
+                    data.len() as i64,
+                    CHUNK_BYTES as u32,
+                    libc::O_RDONLY,
+                    None,
+                )
+                .await?;
+            let chunk: Vec<u8> = match read {
+                (Some(chunk), SUCCESS) => serde_json::from_str(&chunk)?,
+                _ => return Ok(()),
+            };
+            if chunk.is_empty() {
+                break;
+            }
+            data.extend(chunk);
+        }
+        self.local().keep(&attr, version, &data);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl storage::KeyString for OfflineStore {
+    async fn get(&self, key: &str) -> TritonFileResult<Option<String>> {
+        self.bin.get(key).await
+    }
+
+    async fn set(&self, kv: &KeyValue) -> TritonFileResult<bool> {
+        self.bin.set(kv).await
+    }
+
+    async fn keys(&self, p: &storage::Pattern) -> TritonFileResult<storage::List> {
+        self.bin.keys(p).await
+    }
+}
+
+#[async_trait]
+impl storage::KeyList for OfflineStore {
+    async fn list_get(&self, key: &str) -> TritonFileResult<storage::List> {
+        self.bin.list_get(key).await
+    }
+
+    async fn list_append(&self, kv: &KeyValue) -> TritonFileResult<bool> {
+        self.bin.list_append(kv).await
+    }
+
+    async fn list_remove(&self, kv: &KeyValue) -> TritonFileResult<u32> {
+        self.bin.list_remove(kv).await
+    }
+
+    async fn list_keys(&self, p: &storage::Pattern) -> TritonFileResult<storage::List> {
+        self.bin.list_keys(p).await
+    }
+}
+
+#[async_trait]
+impl Storage for OfflineStore {
+    async fn clock(&self, at_least: u64) -> TritonFileResult<u64> {
+        self.bin.clock(at_least).await
+    }
+}
+
+#[async_trait]
+impl ServerFileSystem for OfflineStore {
+    async fn get_all_nodes(
+        &self,
+        for_addr: usize,
+        len: usize,
+    ) -> TritonFileResult<Option<(InodeList, ContentList)>> {
+        self.bin.get_all_nodes(for_addr, len).await
+    }
+
+    async fn write_all_nodes(
+        &self,
+        inode_list: InodeList,
+        content_list: ContentList,
+    ) -> TritonFileResult<()> {
+        self.bin.write_all_nodes(inode_list, content_list).await
+    }
+
+    async fn init(&self, req: &FileRequest) -> TritonFileResult<c_int> {
+        // changes an earlier run made offline are sent once a backend answers
+        if self.here(&[], None) {
+            self.probe();
+        }
+        match self.bin.init(req).await {
+            Err(e) if self.went_offline(e.as_ref()) => {
+                // mounted offline, with what an earlier run kept
+                if self.local().nodes.contains_key(&FUSE_ROOT_ID) {
+                    return Ok(SUCCESS);
+                }
+                Err(e)
+            }
+            result => result,
+        }
+    }
+
+    async fn read(
+        &self,
+        req: &FileRequest,
+        inode: u64,
+        fh: u64,
+        offset: i64,
+        size: u32,
+        flags: i32,
+        lock_owner: Option<u64>,
+    ) -> TritonFileResult<(Option<String>, c_int)> {
+        if !self.here(&[inode], Some(fh)) {
+            match self
+                .bin
+                .read(req, inode, fh, offset, size, flags, lock_owner)
+                .await
+            {
+                Err(e) if self.went_offline(e.as_ref()) => (),
+                result => return result,
+            }
+        }
+        let mut local = self.local();
+        let inode = local.resolve(inode);
+        let data = local.read(inode, offset, size);
+        answer(data.map(|data| serde_json::to_string(&data).unwrap()))
+    }
+
+    async fn write(
+        &self,
+        req: &FileRequest,
+        inode: u64,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+        write_flags: u32,
+        flags: i32,
+        lock_owner: Option<u64>,
+    ) -> TritonFileResult<(Option<u32>, c_int)> {
+        if !self.here(&[inode], Some(fh)) {
+            match self
+                .bin
+                .write(req, inode, fh, offset, data, write_flags, flags, lock_owner)
+                .await
+            {
+                Err(e) if self.went_offline(e.as_ref()) => (),
+                result => {
+                    if let Ok((_, SUCCESS)) = result {
+                        self.local().drop_data(inode);
+                    }
+                    return result;
+                }
+            }
+        }
+        let written = {
+            let mut local = self.local();
+            let inode = local.resolve(inode);
+            local.write(req, inode, offset, data)
+        };
+        self.probe();
+        answer(written)
+    }
+
+    async fn lookup(
+        &self,
+        req: &FileRequest,
+        parent: u64,
+        name: &OsStr,
+    ) -> TritonFileResult<(Option<FileAttr>, c_int)> {
+        if !self.here(&[parent], None) {
+            match self.bin.lookup(req, parent, name).await {
+                Err(e) if self.went_offline(e.as_ref()) => (),
+                result => {
+                    match &result {
+                        Ok((Some(attr), SUCCESS)) => {
+                            self.local().remember(attr, Some((parent, name.as_bytes())))
+                        }
+                        Ok((_, libc::ENOENT)) => self.local().enter(parent, name.as_bytes(), None),
+                        _ => (),
+                    }
+                    return result;
+                }
+            }
+        }
+        let mut local = self.local();
+        let parent = local.resolve(parent);
+        answer(local.lookup(parent, name.as_bytes()))
+    }
+
+    async fn unlink(
+        &self,
+        req: &FileRequest,
+        parent: u64,
+        name: &OsStr,
+    ) -> TritonFileResult<c_int> {
+        if !self.here(&[parent], None) {
+            match self.bin.unlink(req, parent, name).await {
+                Err(e) if self.went_offline(e.as_ref()) => (),
+                result => {
+                    if let Ok(SUCCESS) = result {
+                        let mut local = self.local();
+                        if let Ok(attr) = local.lookup(parent, name.as_bytes()) {
+                            local.forget(attr.ino);
+                        }
+                        local.enter(parent, name.as_bytes(), None);
+                    }
+                    return result;
+                }
+            }
+        }
+        let unlinked = {
+            let mut local = self.local();
+            let parent = local.resolve(parent);
+            local.unlink(req, parent, name.as_bytes())
+        };
+        self.probe();
+        answer_code(unlinked)
+    }
+
+    async fn create(
+        &self,
+        req: &FileRequest,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+        flags: i32,
+    ) -> TritonFileResult<(Option<(FileAttr, u64)>, c_int)> {
+        if !self.here(&[parent], None) {
+            match self.bin.create(req, parent, name, mode, umask, flags).await {
+                Err(e) if self.went_offline(e.as_ref()) => (),
+                result => {
+                    if let Ok((Some((attr, _)), SUCCESS)) = &result {
+                        let mut local = self.local();
+                        local.remember(attr, Some((parent, name.as_bytes())));
+                        local.keep(attr, 0, &[]);
+                    }
+                    return result;
+                }
+            }
+        }
+        let made = {
+            let mut local = self.local();
+            let parent = local.resolve(parent);
+            local
+                .make(
+                    req,
+                    parent,
+                    name.as_bytes(),
+                    mode,
+                    umask,
+                    FileType::RegularFile,
+                )
+                .map(|attr| (attr, local.handle()))
+        };
+        self.probe();
+        answer(made)
+    }
+
+    async fn getattr(
+        &self,
+        req: &FileRequest,
+        ino: u64,
+    ) -> TritonFileResult<(Option<FileAttr>, c_int)> {
+        if !self.here(&[ino], None) {
+            match self.bin.getattr(req, ino).await {
+                Err(e) if self.went_offline(e.as_ref()) => (),
+                result => {
+                    if let Ok((Some(attr), SUCCESS)) = &result {
+                        self.local().remember(attr, None);
+                    }
+                    return result;
+                }
+            }
+        }
+        let mut local = self.local();
+        let local_ino = local.resolve(ino);
+        let attr = local.attr(local_ino);
+        answer(attr.map(|attr| FileAttr { ino, ..attr }))
+    }
+
+    async fn getattr_versioned(
+        &self,
+        req: &FileRequest,
+        ino: u64,
+    ) -> TritonFileResult<(Option<FileAttr>, c_int, u64)> {
+        if !self.here(&[ino], None) {
+            return self.bin.getattr_versioned(req, ino).await;
+        }
+        let (attr, errcode) = self.getattr(req, ino).await?;
+        Ok((attr, errcode, 0))
+    }
+
+    async fn open(
+        &self,
+        req: &FileRequest,
+        ino: u64,
+        flags: i32,
+    ) -> TritonFileResult<(Option<(u64, u32)>, c_int)> {
+        if !self.here(&[ino], None) {
+            match self.bin.open(req, ino, flags).await {
+                Err(e) if self.went_offline(e.as_ref()) => (),
+                result => {
+                    if let Ok((Some((fh, _)), SUCCESS)) = &result {
+                        if flags & libc::O_ACCMODE != libc::O_WRONLY {
+                            if let Err(e) = self.fetch(req, ino, *fh).await {
+                                info!("no offline copy of {}: {}", ino, e);
+                            }
+                        }
+                    }
+                    return result;
+                }
+            }
+        }
+        let opened = {
+            let mut local = self.local();
+            let ino = local.resolve(ino);
+            local.open(req, ino, flags)
+        };
+        if flags & libc::O_TRUNC != 0 {
+            self.probe();
+        }
+        answer(opened.map(|fh| (fh, 0)))
+    }
+
+    async fn release(
+        &self,
+        req: &FileRequest,
+        ino: u64,
+        fh: u64,
+        flags: i32,
+        lock_owner: Option<u64>,
+        flush: bool,
+    ) -> TritonFileResult<c_int> {
+        if !local_handle(fh) && !self.local().offline {
+            match self
+                .bin
+                .release(req, ino, fh, flags, lock_owner, flush)
+                .await
+            {
+                Err(e) if self.went_offline(e.as_ref()) => (),
+                result => return result,
+            }
+        }
+        Ok(SUCCESS)
+    }
+
+    async fn setxattr(
+        &self,
+        req: &FileRequest,
+        ino: u64,
+        name: &OsStr,
+        value: &[u8],
+        flags: i32,
+        position: u32,
+    ) -> TritonFileResult<c_int> {
+        if self.here(&[ino], None) {
+            return unreachable_err();
+        }
+        let result = self
+            .bin
+            .setxattr(req, ino, name, value, flags, position)
+            .await;
+        if let Err(e) = &result {
+            self.went_offline(e.as_ref());
+        }
+        result
+    }
+
+    async fn getxattr(
+        &self,
+        req: &FileRequest,
+        ino: u64,
+        name: &OsStr,
+        size: u32,
+    ) -> TritonFileResult<(Option<(String, u32)>, c_int)> {
+        if self.here(&[ino], None) {
+            return unreachable_err();
+        }
+        let result = self.bin.getxattr(req, ino, name, size).await;
+        if let Err(e) = &result {
+            self.went_offline(e.as_ref());
+        }
+        result
+    }
+
+    async fn listxattr(
+        &self,
+        req: &FileRequest,
+        ino: u64,
+        size: u32,
+    ) -> TritonFileResult<(Option<(String, u32)>, c_int)> {
+        if self.here(&[ino], None) {
+            return unreachable_err();
+        }
+        let result = self.bin.listxattr(req, ino, size).await;
+        if let Err(e) = &result {
+            self.went_offline(e.as_ref());
+        }
+        result
+    }
+
+    async fn access(&self, req: &FileRequest, ino: u64, mask: i32) -> TritonFileResult<c_int> {
+        if !self.here(&[ino], None) {
+            match self.bin.access(req, ino, mask).await {
+                Err(e) if self.went_offline(e.as_ref()) => (),
+                result => return result,
+            }
+        }
+        let mut local = self.local();
+        let local_ino = local.resolve(ino);
+        let attr = local.attr(local_ino);
+        answer_code(attr.and_then(|attr| {
+            match check_access(attr.uid, attr.gid, attr.perm, req.uid, req.gid, mask) {
+                true => Ok(()),
+                false => Err(libc::EACCES),
+            }
+        }))
+    }
+
+    async fn rename(
+        &self,
+        req: &FileRequest,
+        parent: u64,
+        name: &OsStr,
+        new_parent: u64,
+        new_name: &OsStr,
+        flags: u32,
+    ) -> TritonFileResult<c_int> {
+        if !self.here(&[parent, new_parent], None) {
+            match self
+                .bin
+                .rename(req, parent, name, new_parent, new_name, flags)
+                .await
+            {
+                Err(e) if self.went_offline(e.as_ref()) => (),
+                result => {
+                    if let Ok(SUCCESS) = result {
+                        let mut local = self.local();
+                        if flags & libc::RENAME_EXCHANGE != 0 {
+                            // both entries are looked up anew
+                            local.enter(parent, name.as_bytes(), None);
+                            local.enter(new_parent, new_name.as_bytes(), None);
+                        } else {
+                            local.moved(parent, name.as_bytes(), new_parent, new_name.as_bytes());
+                        }
+                    }
+                    return result;
+                }
+            }
+        }
+        let renamed = {
+            let mut local = self.local();
+            let (parent, new_parent) = (local.resolve(parent), local.resolve(new_parent));
+            local.rename(
+                req,
+                parent,
+                name.as_bytes(),
+                new_parent,
+                new_name.as_bytes(),
+                flags,
+            )
+        };
+        self.probe();
+        answer_code(renamed)
+    }
+
+    async fn setattr(
+        &self,
+        req: &FileRequest,
+        ino: u64,
+        mode: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        size: Option<u64>,
+        atime: Option<TimeOrNow>,
+        mtime: Option<TimeOrNow>,
+        ctime: Option<SystemTime>,
+        fh: Option<u64>,
+        crtime: Option<SystemTime>,
+        chgtime: Option<SystemTime>,
+        bkuptime: Option<SystemTime>,
+        flags: Option<u32>,
+    ) -> TritonFileResult<(Option<FileAttr>, c_int)> {
+        let fh_here = fh.filter(|fh| !local_handle(*fh));
+        if !self.here(&[ino], fh) {
+            match self
+                .bin
+                .setattr(
+                    req, ino, mode, uid, gid, size, atime, mtime, ctime, fh_here, crtime, chgtime,
+                    bkuptime, flags,
+                )
+                .await
+            {
+                Err(e) if self.went_offline(e.as_ref()) => (),
+                result => {
+                    if let Ok((Some(attr), SUCCESS)) = &result {
+                        let mut local = self.local();
+                        local.remember(attr, None);
+                        if size.is_some() {
+                            local.drop_data(ino);
+                        }
+                    }
+                    return result;
+                }
+            }
+        }
+        // owners are only changed online
+        if uid.is_some() || gid.is_some() {
+            return unreachable_err();
+        }
+        let attr = {
+            let mut local = self.local();
+            let local_ino = local.resolve(ino);
+            local.setattr(req, local_ino, mode, size, atime, mtime)
+        };
+        self.probe();
+        answer(attr.map(|attr| FileAttr { ino, ..attr }))
+    }
+
+    async fn readdir(
+        &self,
+        req: &FileRequest,
+        inode: u64,
+        fh: u64,
+        offset: i64,
+    ) -> TritonFileResult<(Option<(u64, i64, FileType, DataList)>, c_int)> {
+        if !self.here(&[inode], Some(fh)) {
+            match self.bin.readdir(req, inode, fh, offset).await {
+                Err(e) if self.went_offline(e.as_ref()) => (),
+                result => {
+                    match &result {
+                        Ok((Some((ino, _, kind, name)), SUCCESS)) => {
+                            self.local()
+                                .listed(inode, fh, offset, Some((*ino, *kind, &name.0)))
+                        }
+                        Ok((None, SUCCESS)) => self.local().listed(inode, fh, offset, None),
+                        _ => (),
+                    }
+                    return result;
+                }
+            }
+        }
+        let mut local = self.local();
+        let inode = local.resolve(inode);
+        let (entry, errcode) = answer(local.readdir(inode, offset))?;
+        let entry = entry
+            .flatten()
+            .map(|(ino, next, kind, name)| (ino, next, kind, DataList(name)));
+        Ok((entry, errcode))
+    }
+
+    async fn releasedir(
+        &self,
+        req: &FileRequest,
+        inode: u64,
+        fh: u64,
+        flags: i32,
+    ) -> TritonFileResult<c_int> {
+        if !local_handle(fh) && !self.local().offline {
+            match self.bin.releasedir(req, inode, fh, flags).await {
+                Err(e) if self.went_offline(e.as_ref()) => (),
+                result => return result,
+            }
+        }
+        Ok(SUCCESS)
+    }
+
+    async fn opendir(
+        &self,
+        req: &FileRequest,
+        inode: u64,
+        flags: i32,
+    ) -> TritonFileResult<(Option<(u64, u32)>, c_int)> {
+        if !self.here(&[inode], None) {
+            match self.bin.opendir(req, inode, flags).await {
+                Err(e) if self.went_offline(e.as_ref()) => (),
+                result => {
+                    // entries listed are only kept for a directory kept
+                    if let Ok((Some(_), SUCCESS)) = &result {
+                        if !self.local().nodes.contains_key(&inode) {
+                            if let Ok((Some(attr), SUCCESS)) = self.bin.getattr(req, inode).await {
+                                self.local().remember(&attr, None);
+                            }
+                        }
+                    }
+                    return result;
+                }
+            }
+        }
+        let mut local = self.local();
+        let inode = local.resolve(inode);
+        let opened = local.entries(inode).map(|_| ());
+        answer(opened.map(|()| (local.handle(), 0)))
+    }
+
+    async fn mkdir(
+        &self,
+        req: &FileRequest,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+    ) -> TritonFileResult<(Option<FileAttr>, c_int)> {
+        if !self.here(&[parent], None) {
+            match self.bin.mkdir(req, parent, name, mode, umask).await {
+                Err(e) if self.went_offline(e.as_ref()) => (),
+                result => {
+                    if let Ok((Some(attr), SUCCESS)) = &result {
+                        let mut local = self.local();
+                        local.remember(attr, Some((parent, name.as_bytes())));
+                        let mut entries = Entries::new();
+                        entries.insert(b".".to_vec(), (attr.ino, FileType::Directory));
+                        entries.insert(b"..".to_vec(), (parent, FileType::Directory));
+                        if let Some(node) = local.nodes.get_mut(&attr.ino) {
+                            node.entries = Some(entries);
+                        }
+                        local.save(attr.ino);
+                    }
+                    return result;
+                }
+            }
+        }
+        let made = {
+            let mut local = self.local();
+            let parent = local.resolve(parent);
+            local.make(
+                req,
+                parent,
+                name.as_bytes(),
+                mode,
+                umask,
+                FileType::Directory,
+            )
+        };
+        self.probe();
+        answer(made)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::OsStr;
+    use std::sync::Arc;
+
+    use fuser::FUSE_ROOT_ID;
+    use tribbler::config::OfflineConfig;
+    use tribbler::error::{TritonFileError, TritonFileResult, SUCCESS};
+    use tribbler::storage::{FileRequest, RemoteFileSystem, ServerFileSystem, Storage};
+
+    use super::{refused, unreachable, OfflineStore, LOCAL_INODES};
+
+    #[test]
+    fn test_unreachable_or_refused() {
+        let unreachable_errs: Vec<Box<dyn std::error::Error + Send + Sync>> = vec![
+            Box::new(TritonFileError::NoLiveStore),
+            Box::new(TritonFileError::RetriesExhausted("write".to_string())),
+            Box::new(TritonFileError::DeadlineExceeded("write".to_string())),
+            Box::new(tonic::Status::unavailable("down")),
+        ];
+        for err in unreachable_errs {
+            assert!(unreachable(err.as_ref()), "{}", err);
+            assert!(!refused(err.as_ref()), "{}", err);
+        }
+        let err = TritonFileError::UserInterfaceError(libc::EACCES);
+        assert!(!unreachable(&err));
+        assert!(refused(&err));
+        // a backend failing on its own is neither: the change is kept
+        let err = tonic::Status::unknown("disk full");
+        assert!(!unreachable(&err));
+        assert!(!refused(&err));
+    }
+
+    async fn read_all(
+        store: &dyn Storage,
+        req: &FileRequest,
+        ino: u64,
+    ) -> TritonFileResult<Vec<u8>> {
+        let (opened, code) = store.open(req, ino, libc::O_RDONLY).await?;
+        assert_eq!(SUCCESS, code);
+        let fh = opened.unwrap().0;
+        let (data, code) = store.read(req, ino, fh, 0, 1 << 10, 0, None).await?;
+        assert_eq!(SUCCESS, code);
+        store.release(req, ino, fh, 0, None, false).await?;
+        Ok(serde_json::from_str(&data.unwrap())?)
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_offline_changes_are_reconciled() -> TritonFileResult<()> {
+        let backend_dir = tempfile::tempdir()?;
+        let backend_path = backend_dir.path().to_string_lossy().to_string();
+        let backend = RemoteFileSystem::in_dir(backend_path, 44);
+        let bin: Arc<dyn Storage> = backend.namespace("offline").await?.into();
+        let dir = tempfile::tempdir()?;
+        let config = OfflineConfig {
+            enabled: true,
+            // reconciled by hand below
+            retry_ms: 60_000,
+            ..OfflineConfig::default()
+        };
+        let store = OfflineStore::new(bin.clone(), dir.path(), config)?;
+        let req = FileRequest {
+            uid: 0,
+            gid: 0,
+            pid: 0,
+        };
+        let (a, b) = (OsStr::new("a"), OsStr::new("b"));
+
+        // listed and opened online, so both are kept
+        let (made, code) = store
+            .create(&req, FUSE_ROOT_ID, a, 0o100644, 0, libc::O_RDWR)
+            .await?;
+        assert_eq!(SUCCESS, code);
+        let (attr, fh) = made.unwrap();
+        store
+            .write(&req, attr.ino, fh, 0, b"ours", 0, 0, None)
+            .await?;
+        store.release(&req, attr.ino, fh, 0, None, false).await?;
+        assert_eq!(b"ours".to_vec(), read_all(&store, &req, attr.ino).await?);
+        let (fh, _) = store.opendir(&req, FUSE_ROOT_ID, 0).await?.0.unwrap();
+        let mut offset = 0;
+        while let (Some(_), SUCCESS) = store.readdir(&req, FUSE_ROOT_ID, fh, offset).await? {
+            offset += 1;
+        }
+
+        store.local().offline = true;
+        let (opened, code) = store.open(&req, attr.ino, libc::O_WRONLY).await?;
+        assert_eq!(SUCCESS, code);
+        let fh = opened.unwrap().0;
+        store
+            .write(&req, attr.ino, fh, 0, b"offline", 0, 0, None)
+            .await?;
+        let (made, code) = store
+            .create(&req, FUSE_ROOT_ID, b, 0o100644, 0, libc::O_RDWR)
+            .await?;
+        assert_eq!(SUCCESS, code);
+        let (new, fh) = made.unwrap();
+        assert!(new.ino >= LOCAL_INODES);
+        store
+            .write(&req, new.ino, fh, 0, b"new", 0, 0, None)
+            .await?;
+        let (found, _) = store.lookup(&req, FUSE_ROOT_ID, b).await?;
+        assert_eq!(new.ino, found.unwrap().ino);
+        assert_eq!(b"offline".to_vec(), read_all(&store, &req, attr.ino).await?);
+
+        // meanwhile, someone else changes a
+        let (opened, _) = bin.open(&req, attr.ino, libc::O_WRONLY).await?;
+        let fh = opened.unwrap().0;
+        bin.write(&req, attr.ino, fh, 0, b"theirs!", 0, 0, None)
+            .await?;
+        bin.release(&req, attr.ino, fh, 0, None, false).await?;
+
+        assert!(store.reconcile().await);
+        assert_eq!(
+            b"theirs!".to_vec(),
+            read_all(bin.as_ref(), &req, attr.ino).await?
+        );
+        let (found, code) = bin.lookup(&req, FUSE_ROOT_ID, b).await?;
+        assert_eq!(SUCCESS, code);
+        assert_eq!(
+            b"new".to_vec(),
+            read_all(bin.as_ref(), &req, found.unwrap().ino).await?
+        );
+
+        let (fh, _) = bin.opendir(&req, FUSE_ROOT_ID, 0).await?.0.unwrap();
+        let mut copies = vec![];
+        let mut offset = 0;
+        while let (Some((ino, _, _, name)), SUCCESS) =
+            bin.readdir(&req, FUSE_ROOT_ID, fh, offset).await?
+        {
+            if name.0.starts_with(b"a.conflict-") {
+                copies.push(ino);
+            }
+            offset += 1;
+        }
+        assert_eq!(1, copies.len());
+        assert_eq!(
+            b"offline".to_vec(),
+            read_all(bin.as_ref(), &req, copies[0]).await?
+        );
+        Ok(())
+    }
+}
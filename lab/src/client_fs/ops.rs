@@ -1,6 +1,7 @@
+use fuser::FileType;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum ListOp {
     Append,
     Remove,
@@ -16,7 +17,7 @@ pub enum FileOp {
     Unlink,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum OpKind {
     KeyString,
     KeyList(ListOp),
@@ -37,6 +38,43 @@ pub struct FileLogOp{
     pub kind: FileOp,
 }
 
+// A mutation a backup replica missed, kept in the repair log until the
+// keeper has re-synced that inode.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RepairRecord {
+    pub backend: String,
+    pub bin: String,
+    pub inode: u64,
+    pub op: String,
+    pub clock: u64,
+}
+
+// A directory entry changed by a rename: entry `name` of `dir` goes from
+// pointing at `from` to pointing at `to`, None being no entry. `owner` is
+// the uid of the inode `from` points at, for the sticky bit check.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EntryChange {
+    pub dir: u64,
+    pub name: Vec<u8>,
+    pub from: Option<(u64, FileType)>,
+    pub to: Option<(u64, FileType)>,
+    pub owner: u32,
+}
+
+// A rename across shards, logged before any of its entries is changed and
+// removed once all of them are. `replaced` is the inode the rename
+// unlinks, whose link is dropped after the record is removed. Until
+// `expires_at`, in milliseconds since the Unix epoch, the front that
+// logged the rename is still making it and no other front recovers it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RenameIntent {
+    pub id: u64,
+    pub changes: Vec<EntryChange>,
+    pub replaced: Option<u64>,
+    #[serde(default)]
+    pub expires_at: u64,
+}
+
 // Union b into a.
 pub fn union<T: Ord>(a: &mut Vec<T>, b: &mut Vec<T>) {
     a.append(b);
@@ -0,0 +1,104 @@
+//! Keeps the requests a front makes on one inode in the order the kernel
+//! made them.
+//!
+//! Requests run concurrently, so two writes to the same file could reach
+//! the backends the other way round, or a read could pass the write before
+//! it. Each request on an inode waits for the one queued on it before, and
+//! lets the next one go once it is done; requests on other inodes do not
+//! wait for it.
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::oneshot;
+
+#[derive(Default)]
+struct Queued {
+    // How many requests were queued so far.
+    count: u64,
+    // The last request queued on each inode, by the number it was queued
+    // as, and what tells the next one it is done.
+    last: HashMap<u64, (u64, oneshot::Receiver<()>)>,
+}
+
+/// The requests queued on each inode.
+#[derive(Clone, Default)]
+pub struct InodeQueues {
+    queued: Arc<Mutex<Queued>>,
+}
+
+impl InodeQueues {
+    pub fn new() -> InodeQueues {
+        InodeQueues::default()
+    }
+
+    /// Queues `request` on `inode`, behind the requests queued on it
+    /// before. The request runs once the returned future is run and those
+    /// are done.
+    pub fn queue<F>(&self, inode: u64, request: F) -> impl Future<Output = ()>
+    where
+        F: Future<Output = ()>,
+    {
+        let (done, finished) = oneshot::channel();
+        let (number, previous) = {
+            let mut queued = self.queued.lock().unwrap();
+            queued.count += 1;
+            let number = queued.count;
+            let previous = queued.last.insert(inode, (number, finished));
+            (number, previous.map(|(_, previous)| previous))
+        };
+        let queued = self.queued.clone();
+        async move {
+            if let Some(previous) = previous {
+                // Dropped without a value once the request is done.
+                let _ = previous.await;
+            }
+            request.await;
+            drop(done);
+            let mut queued = queued.lock().unwrap();
+            if queued.last.get(&inode).map(|(last, _)| *last) == Some(number) {
+                queued.last.remove(&inode);
+            }
+        }
+    }
+
+    /// Whether no request is queued, or waiting, on any inode.
+    pub fn is_empty(&self) -> bool {
+        self.queued.lock().unwrap().last.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use tokio::time;
+
+    use super::InodeQueues;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_requests_on_an_inode_keep_their_order() {
+        let queues = InodeQueues::new();
+        let ran = Arc::new(Mutex::new(vec![]));
+
+        let write = |ran: Arc<Mutex<Vec<&'static str>>>, name, delay| async move {
+            time::sleep(Duration::from_millis(delay)).await;
+            ran.lock().unwrap().push(name);
+        };
+        let first = queues.queue(1, write(ran.clone(), "first", 100));
+        let second = queues.queue(1, write(ran.clone(), "second", 0));
+        let other = queues.queue(2, write(ran.clone(), "other", 0));
+        // run in the opposite order to that they were queued in
+        let other = tokio::spawn(other);
+        let second = tokio::spawn(second);
+        time::sleep(Duration::from_millis(20)).await;
+        let first = tokio::spawn(first);
+        for task in [other, second, first] {
+            task.await.unwrap();
+        }
+
+        assert_eq!(vec!["other", "first", "second"], *ran.lock().unwrap());
+        assert!(queues.is_empty());
+    }
+}
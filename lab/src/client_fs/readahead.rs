@@ -0,0 +1,224 @@
+//! File data a front-end has read, kept in blocks, and read ahead of
+//! files read in sequence.
+//!
+//! Every block is stamped with the mtime and size its file had when the
+//! block was read, and used only while the file still has them, so a block
+//! is dropped once the file changes. The least recently used blocks make
+//! room for new ones.
+//!
+//! Reading a handle where its last read ended counts as reading in
+//! sequence: the blocks following the read are then fetched in the
+//! background, so that the next reads find them here.
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use libc::c_int;
+use tribbler::config::ReadCacheConfig;
+use tribbler::error::{errno, SUCCESS};
+use tribbler::storage::{FileRequest, Storage};
+
+/// What a file looked like when a block of it was read.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stamp {
+    pub mtime: SystemTime,
+    pub size: u64,
+}
+
+struct Block {
+    data: Vec<u8>,
+    stamp: Stamp,
+    used: u64,
+}
+
+pub struct BlockCache {
+    config: ReadCacheConfig,
+    // By inode and the number of the block in the file.
+    blocks: HashMap<(u64, u64), Block>,
+    bytes: usize,
+    // Ticks at every use of a block.
+    clock: u64,
+    // Blocks being fetched ahead.
+    fetching: HashSet<(u64, u64)>,
+    // Where the last read through each handle of each inode ended.
+    ends: HashMap<(u64, u64), u64>,
+}
+
+impl BlockCache {
+    pub fn new(config: ReadCacheConfig) -> BlockCache {
+        BlockCache {
+            config,
+            blocks: HashMap::new(),
+            bytes: 0,
+            clock: 0,
+            fetching: HashSet::new(),
+            ends: HashMap::new(),
+        }
+    }
+
+    pub fn config(&self) -> &ReadCacheConfig {
+        &self.config
+    }
+
+    /// Block `index` of `ino`, if it was read while the file was as
+    /// `stamp` says. A block read otherwise is dropped.
+    pub fn get(&mut self, ino: u64, index: u64, stamp: &Stamp) -> Option<Vec<u8>> {
+        let block = self.blocks.get_mut(&(ino, index))?;
+        if block.stamp != *stamp {
+            self.remove(ino, index);
+            return None;
+        }
+        self.clock += 1;
+        block.used = self.clock;
+        Some(block.data.clone())
+    }
+
+    /// Keeps `data` as block `index` of `ino`, read while the file was as
+    /// `stamp` says, dropping the least recently used blocks as needed.
+    pub fn insert(&mut self, ino: u64, index: u64, stamp: Stamp, data: Vec<u8>) {
+        self.fetching.remove(&(ino, index));
+        self.remove(ino, index);
+        while !self.blocks.is_empty() && self.bytes + data.len() > self.config.capacity_bytes {
+            let oldest = self
+                .blocks
+                .iter()
+                .min_by_key(|(_, block)| block.used)
+                .map(|(key, _)| *key);
+            if let Some((ino, index)) = oldest {
+                self.remove(ino, index);
+            }
+        }
+        self.clock += 1;
+        self.bytes += data.len();
+        let block = Block {
+            data,
+            stamp,
+            used: self.clock,
+        };
+        self.blocks.insert((ino, index), block);
+    }
+
+    /// Marks block `index` of `ino` as being fetched ahead. Returns false
+    /// if it is kept or being fetched already.
+    pub fn start(&mut self, ino: u64, index: u64) -> bool {
+        if self.blocks.contains_key(&(ino, index)) {
+            return false;
+        }
+        self.fetching.insert((ino, index))
+    }
+
+    /// Gives up fetching block `index` of `ino` ahead.
+    pub fn abandon(&mut self, ino: u64, index: u64) {
+        self.fetching.remove(&(ino, index));
+    }
+
+    /// Drops every block of `ino`.
+    pub fn invalidate(&mut self, ino: u64) {
+        let indexes: Vec<u64> = self
+            .blocks
+            .keys()
+            .filter(|(of, _)| *of == ino)
+            .map(|(_, index)| *index)
+            .collect();
+        for index in indexes {
+            self.remove(ino, index);
+        }
+    }
+
+    /// Records a read of `offset..end` through handle `fh` of `ino`.
+    /// Returns whether it picks up where the last read through the handle
+    /// ended.
+    pub fn read(&mut self, ino: u64, fh: u64, offset: u64, end: u64) -> bool {
+        let last = self.ends.insert((ino, fh), end);
+        offset > 0 && last == Some(offset)
+    }
+
+    /// Forgets the reads through handle `fh` of `ino`.
+    pub fn release(&mut self, ino: u64, fh: u64) {
+        self.ends.remove(&(ino, fh));
+    }
+
+    fn remove(&mut self, ino: u64, index: u64) {
+        if let Some(block) = self.blocks.remove(&(ino, index)) {
+            self.bytes -= block.data.len();
+        }
+    }
+}
+
+/// Reads blocks of a file through a handle of it.
+#[derive(Clone)]
+pub struct Fetch {
+    pub bin: Arc<dyn Storage>,
+    pub request: FileRequest,
+    pub ino: u64,
+    pub fh: u64,
+    pub flags: i32,
+    pub lock_owner: Option<u64>,
+    pub block_bytes: usize,
+}
+
+impl Fetch {
+    /// Block `index` of the file, short only where the file ends.
+    pub async fn block(&self, index: u64) -> Result<Vec<u8>, c_int> {
+        let offset = index * self.block_bytes as u64;
+        let read = self.bin.read(
+            &self.request,
+            self.ino,
+            self.fh,
+            offset as i64,
+            self.block_bytes as u32,
+            self.flags,
+            self.lock_owner,
+        );
+        match read.await {
+            Ok((Some(data), SUCCESS)) => serde_json::from_str(&data).map_err(|_| libc::EIO),
+            Ok((None, SUCCESS)) => Ok(vec![]),
+            Ok((_, errcode)) => Err(errcode),
+            Err(e) => Err(errno(e.as_ref())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, SystemTime};
+
+    use tribbler::config::ReadCacheConfig;
+
+    use super::{BlockCache, Stamp};
+
+    #[test]
+    fn test_blocks_are_stamped_and_evicted() {
+        let mut blocks = BlockCache::new(ReadCacheConfig {
+            enabled: true,
+            block_bytes: 4,
+            read_ahead_blocks: 2,
+            capacity_bytes: 8,
+        });
+        let stamp = Stamp {
+            mtime: SystemTime::UNIX_EPOCH,
+            size: 12,
+        };
+        blocks.insert(1, 0, stamp, b"aaaa".to_vec());
+        blocks.insert(1, 1, stamp, b"bbbb".to_vec());
+        assert_eq!(Some(b"aaaa".to_vec()), blocks.get(1, 0, &stamp));
+
+        // block 1 is used least recently
+        assert!(blocks.start(1, 2));
+        assert!(!blocks.start(1, 2));
+        blocks.insert(1, 2, stamp, b"cccc".to_vec());
+        assert_eq!(None, blocks.get(1, 1, &stamp));
+        assert!(blocks.get(1, 0, &stamp).is_some());
+
+        let changed = Stamp {
+            mtime: SystemTime::UNIX_EPOCH + Duration::from_secs(1),
+            size: 12,
+        };
+        assert_eq!(None, blocks.get(1, 2, &changed));
+        assert_eq!(None, blocks.get(1, 2, &stamp));
+
+        assert!(!blocks.read(1, 7, 0, 4));
+        assert!(blocks.read(1, 7, 4, 8));
+        assert!(!blocks.read(1, 7, 0, 4));
+    }
+}
@@ -0,0 +1,161 @@
+//! Deadlines and bounded retries for operations against the backends.
+use log::info;
+use std::cmp::min;
+use std::error::Error;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tribbler::error::{is_retryable, TritonFileError, TritonFileResult};
+
+/// How long and how often an operation is retried before giving up.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Overall time budget of an operation, across all attempts.
+    pub deadline: Duration,
+    /// Time budget of a single attempt.
+    pub attempt_timeout: Duration,
+    /// Backoff after the first failed attempt; doubled after every further
+    /// failure up to `max_backoff`.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub max_attempts: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            deadline: Duration::from_secs(10),
+            attempt_timeout: Duration::from_secs(3),
+            initial_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(1),
+            max_attempts: 8,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Backoff to wait after `attempt` (starting at 1) failed.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 1u32.checked_shl(attempt.saturating_sub(1)).unwrap_or(u32::MAX);
+        min(
+            self.initial_backoff.saturating_mul(factor),
+            self.max_backoff,
+        )
+    }
+}
+
+/// Runs `f` until it succeeds, fails with a non-retryable error, or the
+/// policy's attempt or time budget is used up. `on_error` is called after
+/// every retryable failure, e.g. to invalidate a cached liveness view.
+///
+/// When the budget is exhausted the error is
+/// [TritonFileError::DeadlineExceeded] or [TritonFileError::RetriesExhausted]
+/// (or the last failure itself if it was [TritonFileError::NoLiveStore] or a
+/// timed out attempt), so that
+/// callers can map it to a meaningful errno.
+pub async fn with_retry<T, F, Fut, E>(
+    policy: &RetryPolicy,
+    op: &str,
+    on_error: E,
+    mut f: F,
+) -> TritonFileResult<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = TritonFileResult<T>>,
+    E: Fn(),
+{
+    let deadline = Instant::now() + policy.deadline;
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let attempt_timeout = min(remaining, policy.attempt_timeout);
+        let err: Box<dyn Error + Send + Sync> = match tokio::time::timeout(attempt_timeout, f()).await {
+            Ok(Ok(res)) => return Ok(res),
+            Ok(Err(err)) => err,
+            Err(_) => Box::new(TritonFileError::DeadlineExceeded(op.to_string())),
+        };
+        if !is_retryable(err.as_ref()) {
+            return Err(err);
+        }
+        on_error();
+
+        info!("{} failed on attempt {}: {}", op, attempt, err);
+        if attempt >= policy.max_attempts {
+            return match err.downcast_ref::<TritonFileError>() {
+                Some(TritonFileError::NoLiveStore)
+                | Some(TritonFileError::DeadlineExceeded(_)) => Err(err),
+                _ => Err(Box::new(TritonFileError::RetriesExhausted(format!(
+                    "{}: {}",
+                    op, err
+                )))),
+            };
+        }
+        let backoff = policy.backoff(attempt);
+        if Instant::now() + backoff >= deadline {
+            return Err(Box::new(TritonFileError::DeadlineExceeded(op.to_string())));
+        }
+        tokio::time::sleep(backoff).await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::time::Duration;
+    use tribbler::error::{errno, TritonFileResult};
+
+    use super::{with_retry, RetryPolicy};
+
+    fn fast_policy() -> RetryPolicy {
+        RetryPolicy {
+            deadline: Duration::from_secs(2),
+            attempt_timeout: Duration::from_millis(200),
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(5),
+            max_attempts: 3,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry_gives_up() -> TritonFileResult<()> {
+        let attempts = AtomicU32::new(0);
+        let res: TritonFileResult<()> = with_retry(&fast_policy(), "test", || {}, || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err(tonic::Status::unavailable("down").into())
+        })
+        .await;
+        assert_eq!(3, attempts.load(Ordering::SeqCst));
+        assert_eq!(libc::EIO, errno(res.unwrap_err().as_ref()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_retry_fatal_and_timeout() -> TritonFileResult<()> {
+        let attempts = AtomicU32::new(0);
+        let res: TritonFileResult<()> = with_retry(&fast_policy(), "test", || {}, || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err(tonic::Status::invalid_argument("bad").into())
+        })
+        .await;
+        assert_eq!(1, attempts.load(Ordering::SeqCst));
+        assert!(res.is_err());
+
+        // a backend failing on its own answers with Unknown
+        let attempts = AtomicU32::new(0);
+        let res: TritonFileResult<()> = with_retry(&fast_policy(), "test", || {}, || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err(tonic::Status::unknown("no such key").into())
+        })
+        .await;
+        assert_eq!(1, attempts.load(Ordering::SeqCst));
+        assert!(res.is_err());
+
+        let res: TritonFileResult<()> = with_retry(&fast_policy(), "test", || {}, || async {
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            Ok(())
+        })
+        .await;
+        assert_eq!(libc::ETIMEDOUT, errno(res.unwrap_err().as_ref()));
+        Ok(())
+    }
+}
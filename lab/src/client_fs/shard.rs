@@ -0,0 +1,1001 @@
+use async_trait::async_trait;
+use fuser::{FileAttr, FileType, TimeOrNow};
+use libc::c_int;
+use log::{info, warn};
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tribbler::error::{TritonFileError, TritonFileResult, SUCCESS};
+use tribbler::simple::check_access;
+use tribbler::storage::{
+    self, inode_shard, ContentList, DataList, FileRequest, InodeList, KeyValue, ServerFileSystem,
+    Storage,
+};
+
+use super::binstore::hash_name_to_idx;
+use super::ops::{EntryChange, RenameIntent};
+use super::retry::RetryPolicy;
+
+// Raw KV list on shard 0 holding the RenameIntents of renames across
+// shards still in progress.
+pub const KEY_RENAME_INTENTS: &str = "RENAME_INTENTS";
+// Logged renames are finished or undone as root: their permission checks
+// passed before they were logged.
+const RECOVERY_REQUEST: FileRequest = FileRequest {
+    uid: 0,
+    gid: 0,
+    pid: 0,
+};
+
+// How long a front has to make a rename across shards of `changes`
+// entries it logged. Only renames logged longer ago are recovered by
+// others. Each call to a shard may be retried until the default deadline,
+// and a rename takes one call to log it, then up to a set_entry and a
+// get_entry for each change, and as many again to undo them.
+fn intent_lease(changes: usize) -> Duration {
+    RetryPolicy::default().deadline * (1 + 4 * changes as u32)
+}
+
+/// The files of one namespace spread over the bins of its shards. A new
+/// file or directory goes to the shard its parent and name hash to, and
+/// the number of every inode tells the shard it is on (see
+/// [storage::inode_shard]), so operations on an inode go straight to it.
+///
+/// A directory entry is kept with its directory and may point at an inode
+/// on another shard. Creating and unlinking across shards take one step
+/// per shard; they are ordered so that a failure in between leaves an
+/// inode no entry points at, but never an entry pointing nowhere. A rename
+/// across shards is logged before any entry is changed, and a rename
+/// interrupted halfway is finished once its lease expired, by the next
+/// front that initializes the store or renames across shards.
+pub struct ShardedStore {
+    shards: Vec<Arc<dyn Storage>>,
+}
+
+impl ShardedStore {
+    /// A store over `shards`, in the order of their numbers. Shard 0 holds
+    /// the root directory and the key-value data.
+    pub fn new(shards: Vec<Arc<dyn Storage>>) -> ShardedStore {
+        ShardedStore { shards }
+    }
+
+    // The shard keeping inode `ino`.
+    fn shard(&self, ino: u64) -> TritonFileResult<&Arc<dyn Storage>> {
+        match self.shards.get(inode_shard(ino)) {
+            Some(shard) => Ok(shard),
+            None => Err(Box::new(TritonFileError::Unknown(format!(
+                "inode {} is on shard {} of {}",
+                ino,
+                inode_shard(ino),
+                self.shards.len()
+            )))),
+        }
+    }
+
+    // The number of the shard a new entry `name` of `parent` gets its
+    // inode on.
+    fn place(&self, parent: u64, name: &OsStr) -> usize {
+        let key = format!("{}/{}", parent, name.to_string_lossy());
+        hash_name_to_idx(&key, self.shards.len())
+    }
+
+    // The attributes of `ino`, or the error code if it can't be read.
+    async fn attr(&self, req: &FileRequest, ino: u64) -> TritonFileResult<Result<FileAttr, c_int>> {
+        match self.shard(ino)?.getattr(req, ino).await? {
+            (Some(attr), SUCCESS) => Ok(Ok(attr)),
+            (_, SUCCESS) => Ok(Err(libc::ENOENT)),
+            (_, code) => Ok(Err(code)),
+        }
+    }
+
+    // The inode and kind entry `name` of `parent` points at, None if there
+    // is no such entry, or the error code if it can't be read.
+    async fn entry(
+        &self,
+        req: &FileRequest,
+        parent: u64,
+        name: &OsStr,
+    ) -> TritonFileResult<Result<Option<(u64, FileType)>, c_int>> {
+        match self.shard(parent)?.get_entry(req, parent, name).await? {
+            (Some(entry), SUCCESS) => Ok(Ok(Some(entry))),
+            (_, SUCCESS) | (_, libc::ENOENT) => Ok(Ok(None)),
+            (_, code) => Ok(Err(code)),
+        }
+    }
+
+    // Changes one entry of a logged rename. A change found already made,
+    // by an earlier try of the same rename, succeeds.
+    async fn apply(&self, req: &FileRequest, change: &EntryChange) -> TritonFileResult<c_int> {
+        let name = OsStr::from_bytes(&change.name);
+        let expect = change.from.map(|(ino, _)| ino);
+        let code = self
+            .shard(change.dir)?
+            .set_entry(req, change.dir, name, change.to, expect, change.owner)
+            .await?;
+        if code == SUCCESS {
+            return Ok(SUCCESS);
+        }
+        match self.entry(req, change.dir, name).await? {
+            Ok(current) if current.map(|(ino, _)| ino) == change.to.map(|(ino, _)| ino) => {
+                Ok(SUCCESS)
+            }
+            _ => Ok(code),
+        }
+    }
+
+    // Makes every change of a logged rename, in order. If one can't be
+    // made, the ones before it are undone and its error code returned.
+    async fn commit(&self, req: &FileRequest, intent: &RenameIntent) -> TritonFileResult<c_int> {
+        for (done, change) in intent.changes.iter().enumerate() {
+            let code = self.apply(req, change).await?;
+            if code == SUCCESS {
+                continue;
+            }
+            info!("rename {} stopped at {:?}: {}", intent.id, change, code);
+            for change in intent.changes[..done].iter().rev() {
+                let undo = EntryChange {
+                    from: change.to,
+                    to: change.from,
+                    ..change.clone()
+                };
+                let undone = self.apply(&RECOVERY_REQUEST, &undo).await?;
+                if undone != SUCCESS {
+                    warn!("rename {} could not undo {:?}: {}", intent.id, undo, undone);
+                }
+            }
+            return Ok(code);
+        }
+        Ok(SUCCESS)
+    }
+
+    // Removes the record of a rename that is done or undone, then drops the
+    // link of the inode it replaced. Whoever removes the record drops the
+    // link, so it is dropped once however many times the rename is
+    // finished.
+    async fn finish(
+        &self,
+        req: &FileRequest,
+        intent: &RenameIntent,
+        committed: bool,
+    ) -> TritonFileResult<()> {
+        let removed = self.shards[0]
+            .list_remove(&KeyValue {
+                key: KEY_RENAME_INTENTS.to_string(),
+                value: serde_json::to_string(intent)?,
+            })
+            .await?;
+        if let (true, Some(replaced)) = (committed && removed > 0, intent.replaced) {
+            match self.shard(replaced)?.drop_link(req, replaced).await? {
+                SUCCESS | libc::ENOENT => (),
+                code => warn!("rename {} left inode {}: {}", intent.id, replaced, code),
+            }
+        }
+        Ok(())
+    }
+
+    // Logs a rename, makes its changes and finishes it. Renames other
+    // fronts left behind are recovered first, so they don't wait for the
+    // next init.
+    async fn rename_logged(
+        &self,
+        req: &FileRequest,
+        changes: Vec<EntryChange>,
+        replaced: Option<u64>,
+    ) -> TritonFileResult<c_int> {
+        self.recover().await?;
+        let lease = intent_lease(changes.len());
+        let intent = RenameIntent {
+            id: self.shards[0].clock(0).await?,
+            changes,
+            replaced,
+            expires_at: millis_since_epoch(SystemTime::now() + lease),
+        };
+        self.shards[0]
+            .list_append(&KeyValue {
+                key: KEY_RENAME_INTENTS.to_string(),
+                value: serde_json::to_string(&intent)?,
+            })
+            .await?;
+        let code = self.commit(req, &intent).await?;
+        self.finish(req, &intent, code == SUCCESS).await?;
+        Ok(code)
+    }
+
+    /// Finishes the renames across shards that were logged but not
+    /// finished, such as those of a front that failed halfway through.
+    /// Each is made in full where its entries still allow, and undone
+    /// otherwise. Renames whose lease has not expired yet are left to the
+    /// front making them.
+    pub async fn recover(&self) -> TritonFileResult<()> {
+        let now = millis_since_epoch(SystemTime::now());
+        for raw in self.shards[0].list_get(KEY_RENAME_INTENTS).await?.0 {
+            let intent: RenameIntent = match serde_json::from_str(&raw) {
+                Ok(intent) => intent,
+                Err(err) => {
+                    warn!("bad rename record {:?}: {}", raw, err);
+                    continue;
+                }
+            };
+            if intent.expires_at > now {
+                continue;
+            }
+            info!("recovering rename {:?}", intent);
+            let code = self.commit(&RECOVERY_REQUEST, &intent).await?;
+            self.finish(&RECOVERY_REQUEST, &intent, code == SUCCESS)
+                .await?;
+        }
+        Ok(())
+    }
+
+    // Creates a file or directory on the shard it is placed on, and links
+    // it from `parent`. On another shard than the parent's, the inode is
+    // made first and dropped again if linking it fails.
+    async fn make_linked(
+        &self,
+        req: &FileRequest,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        flags: i32,
+    ) -> TritonFileResult<(Option<(FileAttr, u64)>, c_int)> {
+        let parent_attr = match self.attr(req, parent).await? {
+            Ok(attr) => attr,
+            Err(code) => return Ok((None, code)),
+        };
+        // The usual failures are found before anything is made.
+        match self.entry(req, parent, name).await? {
+            Ok(None) => (),
+            Ok(Some(_)) => return Ok((None, libc::EEXIST)),
+            Err(code) => return Ok((None, code)),
+        }
+        if !check_access(
+            parent_attr.uid,
+            parent_attr.gid,
+            parent_attr.perm,
+            req.uid,
+            req.gid,
+            libc::W_OK,
+        ) {
+            return Ok((None, libc::EACCES));
+        }
+
+        let shard = &self.shards[self.place(parent, name)];
+        let (attr, fh) = match shard.make_node(req, &parent_attr, mode, flags).await? {
+            (Some(made), SUCCESS) => made,
+            (_, code) => return Ok((None, code)),
+        };
+        let code = self
+            .shard(parent)?
+            .set_entry(req, parent, name, Some((attr.ino, attr.kind)), None, attr.uid)
+            .await?;
+        if code != SUCCESS {
+            shard.drop_link(req, attr.ino).await?;
+            return Ok((None, code));
+        }
+        Ok((Some((attr, fh)), SUCCESS))
+    }
+}
+
+#[async_trait]
+impl storage::KeyString for ShardedStore {
+    async fn get(&self, key: &str) -> TritonFileResult<Option<String>> {
+        self.shards[0].get(key).await
+    }
+
+    async fn set(&self, kv: &KeyValue) -> TritonFileResult<bool> {
+        self.shards[0].set(kv).await
+    }
+
+    async fn keys(&self, p: &storage::Pattern) -> TritonFileResult<storage::List> {
+        self.shards[0].keys(p).await
+    }
+}
+
+#[async_trait]
+impl storage::KeyList for ShardedStore {
+    async fn list_get(&self, key: &str) -> TritonFileResult<storage::List> {
+        self.shards[0].list_get(key).await
+    }
+
+    async fn list_append(&self, kv: &KeyValue) -> TritonFileResult<bool> {
+        self.shards[0].list_append(kv).await
+    }
+
+    async fn list_remove(&self, kv: &KeyValue) -> TritonFileResult<u32> {
+        self.shards[0].list_remove(kv).await
+    }
+
+    async fn list_keys(&self, p: &storage::Pattern) -> TritonFileResult<storage::List> {
+        self.shards[0].list_keys(p).await
+    }
+}
+
+#[async_trait]
+impl Storage for ShardedStore {
+    async fn clock(&self, at_least: u64) -> TritonFileResult<u64> {
+        self.shards[0].clock(at_least).await
+    }
+}
+
+#[async_trait]
+impl ServerFileSystem for ShardedStore {
+    async fn get_all_nodes(
+        &self,
+        _for_addr: usize,
+        _len: usize,
+    ) -> TritonFileResult<Option<(InodeList, ContentList)>> {
+        Ok(None)
+    }
+
+    async fn write_all_nodes(
+        &self,
+        _inode_list: InodeList,
+        _content_list: ContentList,
+    ) -> TritonFileResult<()> {
+        Ok(())
+    }
+
+    async fn init(&self, req: &FileRequest) -> TritonFileResult<c_int> {
+        for shard in self.shards.iter() {
+            let code = shard.init(req).await?;
+            if code != SUCCESS {
+                return Ok(code);
+            }
+        }
+        self.recover().await?;
+        Ok(SUCCESS)
+    }
+
+    async fn read(
+        &self,
+        req: &FileRequest,
+        inode: u64,
+        fh: u64,
+        offset: i64,
+        size: u32,
+        flags: i32,
+        lock_owner: Option<u64>,
+    ) -> TritonFileResult<(Option<String>, c_int)> {
+        self.shard(inode)?
+            .read(req, inode, fh, offset, size, flags, lock_owner)
+            .await
+    }
+
+    async fn write(
+        &self,
+        req: &FileRequest,
+        inode: u64,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+        write_flags: u32,
+        flags: i32,
+        lock_owner: Option<u64>,
+    ) -> TritonFileResult<(Option<u32>, c_int)> {
+        self.shard(inode)?
+            .write(req, inode, fh, offset, data, write_flags, flags, lock_owner)
+            .await
+    }
+
+    async fn lookup(
+        &self,
+        req: &FileRequest,
+        parent: u64,
+        name: &OsStr,
+    ) -> TritonFileResult<(Option<FileAttr>, c_int)> {
+        let ino = match self.entry(req, parent, name).await? {
+            Ok(Some((ino, _))) => ino,
+            Ok(None) => return Ok((None, libc::ENOENT)),
+            Err(code) => return Ok((None, code)),
+        };
+        match self.attr(req, ino).await? {
+            Ok(attr) => Ok((Some(attr), SUCCESS)),
+            Err(code) => Ok((None, code)),
+        }
+    }
+
+    async fn unlink(
+        &self,
+        req: &FileRequest,
+        parent: u64,
+        name: &OsStr,
+    ) -> TritonFileResult<c_int> {
+        let ino = match self.entry(req, parent, name).await? {
+            Ok(Some((ino, _))) => ino,
+            Ok(None) => return Ok(libc::ENOENT),
+            Err(code) => return Ok(code),
+        };
+        if inode_shard(ino) == inode_shard(parent) {
+            return self.shard(parent)?.unlink(req, parent, name).await;
+        }
+
+        // An inode that is already gone leaves an entry anyone may remove.
+        let owner = match self.attr(req, ino).await? {
+            Ok(attr) => attr.uid,
+            Err(_) => req.uid,
+        };
+        let code = self
+            .shard(parent)?
+            .set_entry(req, parent, name, None, Some(ino), owner)
+            .await?;
+        if code != SUCCESS {
+            return Ok(code);
+        }
+        match self.shard(ino)?.drop_link(req, ino).await? {
+            libc::ENOENT => Ok(SUCCESS),
+            code => Ok(code),
+        }
+    }
+
+    async fn create(
+        &self,
+        req: &FileRequest,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+        flags: i32,
+    ) -> TritonFileResult<(Option<(FileAttr, u64)>, c_int)> {
+        if self.place(parent, name) == inode_shard(parent) {
+            return self
+                .shard(parent)?
+                .create(req, parent, name, mode, umask, flags)
+                .await;
+        }
+        self.make_linked(req, parent, name, mode, flags).await
+    }
+
+    async fn getattr(
+        &self,
+        req: &FileRequest,
+        ino: u64,
+    ) -> TritonFileResult<(Option<FileAttr>, c_int)> {
+        self.shard(ino)?.getattr(req, ino).await
+    }
+
+    async fn getattr_versioned(
+        &self,
+        req: &FileRequest,
+        ino: u64,
+    ) -> TritonFileResult<(Option<FileAttr>, c_int, u64)> {
+        self.shard(ino)?.getattr_versioned(req, ino).await
+    }
+
+    async fn open(
+        &self,
+        req: &FileRequest,
+        ino: u64,
+        flags: i32,
+    ) -> TritonFileResult<(Option<(u64, u32)>, c_int)> {
+        self.shard(ino)?.open(req, ino, flags).await
+    }
+
+    async fn release(
+        &self,
+        req: &FileRequest,
+        ino: u64,
+        fh: u64,
+        flags: i32,
+        lock_owner: Option<u64>,
+        flush: bool,
+    ) -> TritonFileResult<c_int> {
+        self.shard(ino)?
+            .release(req, ino, fh, flags, lock_owner, flush)
+            .await
+    }
+
+    async fn setxattr(
+        &self,
+        req: &FileRequest,
+        ino: u64,
+        name: &OsStr,
+        value: &[u8],
+        flags: i32,
+        position: u32,
+    ) -> TritonFileResult<c_int> {
+        self.shard(ino)?
+            .setxattr(req, ino, name, value, flags, position)
+            .await
+    }
+
+    async fn getxattr(
+        &self,
+        req: &FileRequest,
+        ino: u64,
+        name: &OsStr,
+        size: u32,
+    ) -> TritonFileResult<(Option<(String, u32)>, c_int)> {
+        self.shard(ino)?.getxattr(req, ino, name, size).await
+    }
+
+    async fn listxattr(
+        &self,
+        req: &FileRequest,
+        ino: u64,
+        size: u32,
+    ) -> TritonFileResult<(Option<(String, u32)>, c_int)> {
+        self.shard(ino)?.listxattr(req, ino, size).await
+    }
+
+    async fn access(&self, req: &FileRequest, ino: u64, mask: i32) -> TritonFileResult<c_int> {
+        self.shard(ino)?.access(req, ino, mask).await
+    }
+
+    async fn rename(
+        &self,
+        req: &FileRequest,
+        parent: u64,
+        name: &OsStr,
+        newparent: u64,
+        newname: &OsStr,
+        flags: u32,
+    ) -> TritonFileResult<c_int> {
+        let exchange = flags & libc::RENAME_EXCHANGE != 0;
+        let noreplace = flags & libc::RENAME_NOREPLACE != 0;
+        if (exchange && noreplace) || flags & !(libc::RENAME_EXCHANGE | libc::RENAME_NOREPLACE) != 0
+        {
+            return Ok(libc::EINVAL);
+        }
+        let (ino, kind) = match self.entry(req, parent, name).await? {
+            Ok(Some(entry)) => entry,
+            Ok(None) => return Ok(libc::ENOENT),
+            Err(code) => return Ok(code),
+        };
+        let existing = match self.entry(req, newparent, newname).await? {
+            Ok(existing) => existing,
+            Err(code) => return Ok(code),
+        };
+        let shard = inode_shard(parent);
+        if [newparent, ino]
+            .into_iter()
+            .chain(existing.map(|(ino, _)| ino))
+            .all(|ino| inode_shard(ino) == shard)
+        {
+            return self
+                .shard(parent)?
+                .rename(req, parent, name, newparent, newname, flags)
+                .await;
+        }
+        match existing {
+            Some(_) if noreplace => return Ok(libc::EEXIST),
+            None if exchange => return Ok(libc::ENOENT),
+            Some((existing, _)) if existing == ino => return Ok(SUCCESS),
+            _ => (),
+        }
+
+        let attr = match self.attr(req, ino).await? {
+            Ok(attr) => attr,
+            Err(code) => return Ok(code),
+        };
+        let existing_attr = match existing {
+            Some((existing, _)) => match self.attr(req, existing).await? {
+                Ok(attr) => Some(attr),
+                Err(code) => return Ok(code),
+            },
+            None => None,
+        };
+        // Moving a directory to a new parent changes its ".." entry.
+        let moves = |attr: &FileAttr| {
+            attr.kind == FileType::Directory
+                && parent != newparent
+                && !check_access(attr.uid, attr.gid, attr.perm, req.uid, req.gid, libc::W_OK)
+        };
+        if moves(&attr) || (exchange && existing_attr.as_ref().is_some_and(moves)) {
+            return Ok(libc::EACCES);
+        }
+        if let (false, Some(existing_attr)) = (exchange, existing_attr.as_ref()) {
+            // Only an empty directory, holding just "." and "..", can be
+            // replaced.
+            if existing_attr.kind == FileType::Directory {
+                let (entry, _) = self
+                    .shard(existing_attr.ino)?
+                    .readdir(req, existing_attr.ino, 0, 2)
+                    .await?;
+                if entry.is_some() {
+                    return Ok(libc::ENOTEMPTY);
+                }
+            }
+        }
+
+        // The new name is linked before the old one is unlinked, so that
+        // undoing the rename never has to find the inode a new entry.
+        let mut changes = vec![
+            EntryChange {
+                dir: newparent,
+                name: newname.as_bytes().to_vec(),
+                from: existing,
+                to: Some((ino, kind)),
+                owner: existing_attr.as_ref().map_or(req.uid, |attr| attr.uid),
+            },
+            EntryChange {
+                dir: parent,
+                name: name.as_bytes().to_vec(),
+                from: Some((ino, kind)),
+                to: if exchange { existing } else { None },
+                owner: attr.uid,
+            },
+        ];
+        let dotdot = |attr: &FileAttr, from: u64, to: u64| EntryChange {
+            dir: attr.ino,
+            name: b"..".to_vec(),
+            from: Some((from, FileType::Directory)),
+            to: Some((to, FileType::Directory)),
+            owner: attr.uid,
+        };
+        if parent != newparent {
+            if kind == FileType::Directory {
+                changes.push(dotdot(&attr, parent, newparent));
+            }
+            match &existing_attr {
+                Some(existing_attr) if exchange && existing_attr.kind == FileType::Directory => {
+                    changes.push(dotdot(existing_attr, newparent, parent));
+                }
+                _ => (),
+            }
+        }
+        let replaced = match existing {
+            Some((existing, _)) if !exchange => Some(existing),
+            _ => None,
+        };
+        self.rename_logged(req, changes, replaced).await
+    }
+
+    async fn setattr(
+        &self,
+        req: &FileRequest,
+        ino: u64,
+        mode: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        size: Option<u64>,
+        atime: Option<TimeOrNow>,
+        mtime: Option<TimeOrNow>,
+        ctime: Option<SystemTime>,
+        fh: Option<u64>,
+        crtime: Option<SystemTime>,
+        chgtime: Option<SystemTime>,
+        bkuptime: Option<SystemTime>,
+        flags: Option<u32>,
+    ) -> TritonFileResult<(Option<FileAttr>, c_int)> {
+        self.shard(ino)?
+            .setattr(
+                req, ino, mode, uid, gid, size, atime, mtime, ctime, fh, crtime, chgtime, bkuptime,
+                flags,
+            )
+            .await
+    }
+
+    async fn readdir(
+        &self,
+        req: &FileRequest,
+        inode: u64,
+        fh: u64,
+        offset: i64,
+    ) -> TritonFileResult<(Option<(u64, i64, FileType, DataList)>, c_int)> {
+        self.shard(inode)?.readdir(req, inode, fh, offset).await
+    }
+
+    async fn releasedir(
+        &self,
+        req: &FileRequest,
+        inode: u64,
+        fh: u64,
+        flags: i32,
+    ) -> TritonFileResult<c_int> {
+        self.shard(inode)?.releasedir(req, inode, fh, flags).await
+    }
+
+    async fn opendir(
+        &self,
+        req: &FileRequest,
+        inode: u64,
+        flags: i32,
+    ) -> TritonFileResult<(Option<(u64, u32)>, c_int)> {
+        self.shard(inode)?.opendir(req, inode, flags).await
+    }
+
+    async fn mkdir(
+        &self,
+        req: &FileRequest,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+    ) -> TritonFileResult<(Option<FileAttr>, c_int)> {
+        if self.place(parent, name) == inode_shard(parent) {
+            return self
+                .shard(parent)?
+                .mkdir(req, parent, name, mode, umask)
+                .await;
+        }
+        let mode = mode | libc::S_IFDIR;
+        let (made, code) = self.make_linked(req, parent, name, mode, 0).await?;
+        Ok((made.map(|(attr, _)| attr), code))
+    }
+}
+
+fn millis_since_epoch(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::OsStr;
+    use std::sync::Arc;
+    use std::time::{Duration, SystemTime};
+
+    use fuser::{FileType, FUSE_ROOT_ID};
+    use tempfile::TempDir;
+    use tribbler::config::shard_name;
+    use tribbler::error::{TritonFileResult, SUCCESS};
+    use tribbler::storage::{
+        inode_shard, FileRequest, KeyValue, RemoteFileSystem, ServerFileSystem, Storage,
+    };
+
+    use crate::client_fs::ops::{EntryChange, RenameIntent};
+    use super::{millis_since_epoch, ShardedStore, KEY_RENAME_INTENTS};
+
+    // A backend of its own, kept in `dir`.
+    fn backend(dir: &TempDir, num: usize) -> RemoteFileSystem {
+        RemoteFileSystem::in_dir(dir.path().to_string_lossy().to_string(), num)
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_cross_shard_operations() -> TritonFileResult<()> {
+        let dir = tempfile::tempdir()?;
+        let backend = backend(&dir, 42);
+        let mut shards: Vec<Arc<dyn Storage>> = vec![];
+        for shard in 0..4 {
+            shards.push(backend.namespace(&shard_name("sharded", shard)).await?.into());
+        }
+        let store = ShardedStore::new(shards);
+        let req = FileRequest {
+            uid: 0,
+            gid: 0,
+            pid: 0,
+        };
+
+        // enough files that some land on every shard
+        let mut inodes = vec![];
+        for i in 0..16 {
+            let name = format!("file-{}", i);
+            let (created, code) = store
+                .create(&req, FUSE_ROOT_ID, OsStr::new(&name), 0o100644, 0, libc::O_RDWR)
+                .await?;
+            assert_eq!(SUCCESS, code);
+            let ino = created.unwrap().0.ino;
+            let (attr, code) = store.lookup(&req, FUSE_ROOT_ID, OsStr::new(&name)).await?;
+            assert_eq!(SUCCESS, code);
+            assert_eq!(ino, attr.unwrap().ino);
+            inodes.push(ino);
+        }
+        for shard in 0..4 {
+            assert!(inodes.iter().any(|ino| inode_shard(*ino) == shard));
+        }
+
+        let (dir, code) = store
+            .mkdir(&req, FUSE_ROOT_ID, OsStr::new("dir"), 0o755, 0)
+            .await?;
+        assert_eq!(SUCCESS, code);
+        let dir = dir.unwrap().ino;
+        for (i, ino) in inodes.iter().enumerate() {
+            let (name, new_name) = (format!("file-{}", i), format!("moved-{}", i));
+            let code = store
+                .rename(&req, FUSE_ROOT_ID, OsStr::new(&name), dir, OsStr::new(&new_name), 0)
+                .await?;
+            assert_eq!(SUCCESS, code);
+            let (_, code) = store.lookup(&req, FUSE_ROOT_ID, OsStr::new(&name)).await?;
+            assert_eq!(libc::ENOENT, code);
+            let (attr, code) = store.lookup(&req, dir, OsStr::new(&new_name)).await?;
+            assert_eq!(SUCCESS, code);
+            assert_eq!(*ino, attr.unwrap().ino);
+
+            assert_eq!(SUCCESS, store.unlink(&req, dir, OsStr::new(&new_name)).await?);
+            let (_, code) = store.lookup(&req, dir, OsStr::new(&new_name)).await?;
+            assert_eq!(libc::ENOENT, code);
+        }
+        Ok(())
+    }
+
+    // A store over four shards of `namespace` of `backend`.
+    async fn sharded(backend: &RemoteFileSystem, namespace: &str) -> TritonFileResult<ShardedStore> {
+        let mut shards: Vec<Arc<dyn Storage>> = vec![];
+        for shard in 0..4 {
+            shards.push(backend.namespace(&shard_name(namespace, shard)).await?.into());
+        }
+        Ok(ShardedStore::new(shards))
+    }
+
+    // Two directories of the root on different shards.
+    async fn dirs_apart(store: &ShardedStore, req: &FileRequest) -> TritonFileResult<Vec<u64>> {
+        let mut dirs: Vec<u64> = vec![];
+        for i in 0.. {
+            let name = format!("dir-{}", i);
+            let (dir, code) = store
+                .mkdir(req, FUSE_ROOT_ID, OsStr::new(&name), 0o755, 0)
+                .await?;
+            assert_eq!(SUCCESS, code);
+            let dir = dir.unwrap().ino;
+            if dirs.iter().all(|other| inode_shard(*other) != inode_shard(dir)) {
+                dirs.push(dir);
+            }
+            if dirs.len() == 2 {
+                break;
+            }
+        }
+        Ok(dirs)
+    }
+
+    async fn make_file(
+        store: &ShardedStore,
+        req: &FileRequest,
+        dir: u64,
+        name: &str,
+    ) -> TritonFileResult<u64> {
+        let (created, code) = store
+            .create(req, dir, OsStr::new(name), 0o100644, 0, libc::O_RDWR)
+            .await?;
+        assert_eq!(SUCCESS, code);
+        Ok(created.unwrap().0.ino)
+    }
+
+    // Logs `intent` as a front would before making its changes.
+    async fn log_intent(store: &ShardedStore, intent: &RenameIntent) -> TritonFileResult<()> {
+        store.shards[0]
+            .list_append(&KeyValue {
+                key: KEY_RENAME_INTENTS.to_string(),
+                value: serde_json::to_string(intent)?,
+            })
+            .await?;
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_cross_shard_rename_exchange() -> TritonFileResult<()> {
+        let dir = tempfile::tempdir()?;
+        let backend = backend(&dir, 43);
+        let store = sharded(&backend, "exchanged").await?;
+        let req = FileRequest {
+            uid: 0,
+            gid: 0,
+            pid: 0,
+        };
+        let dirs = dirs_apart(&store, &req).await?;
+        let (a, b) = (OsStr::new("a"), OsStr::new("b"));
+        let a_ino = make_file(&store, &req, dirs[0], "a").await?;
+        let b_ino = make_file(&store, &req, dirs[1], "b").await?;
+
+        let code = store
+            .rename(&req, dirs[0], a, dirs[1], b, libc::RENAME_NOREPLACE)
+            .await?;
+        assert_eq!(libc::EEXIST, code);
+        let code = store
+            .rename(&req, dirs[0], a, dirs[1], OsStr::new("c"), libc::RENAME_EXCHANGE)
+            .await?;
+        assert_eq!(libc::ENOENT, code);
+        let code = store
+            .rename(&req, dirs[0], a, dirs[1], b, libc::RENAME_EXCHANGE)
+            .await?;
+        assert_eq!(SUCCESS, code);
+        let (attr, _) = store.lookup(&req, dirs[0], a).await?;
+        assert_eq!(b_ino, attr.unwrap().ino);
+        let (attr, _) = store.lookup(&req, dirs[1], b).await?;
+        assert_eq!(a_ino, attr.unwrap().ino);
+        // Both inodes are still linked once.
+        for ino in [a_ino, b_ino] {
+            let (attr, _) = store.getattr(&req, ino).await?;
+            assert_eq!(1, attr.unwrap().nlink);
+        }
+        assert!(store.shards[0].list_get(KEY_RENAME_INTENTS).await?.0.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_rename_recovery() -> TritonFileResult<()> {
+        let dir = tempfile::tempdir()?;
+        let backend = backend(&dir, 45);
+        let store = sharded(&backend, "recovered").await?;
+        let req = FileRequest {
+            uid: 0,
+            gid: 0,
+            pid: 0,
+        };
+        let dirs = dirs_apart(&store, &req).await?;
+
+        // A front moving a to c crashed after linking c, while its lease
+        // still runs.
+        let a_ino = make_file(&store, &req, dirs[0], "a").await?;
+        let moved = Some((a_ino, FileType::RegularFile));
+        let intent = RenameIntent {
+            id: 1,
+            changes: vec![
+                EntryChange {
+                    dir: dirs[1],
+                    name: b"c".to_vec(),
+                    from: None,
+                    to: moved,
+                    owner: 0,
+                },
+                EntryChange {
+                    dir: dirs[0],
+                    name: b"a".to_vec(),
+                    from: moved,
+                    to: None,
+                    owner: 0,
+                },
+            ],
+            replaced: None,
+            expires_at: millis_since_epoch(SystemTime::now() + Duration::from_secs(2)),
+        };
+        log_intent(&store, &intent).await?;
+        assert_eq!(SUCCESS, store.apply(&req, &intent.changes[0]).await?);
+
+        // A front starting meanwhile leaves the rename to its owner.
+        assert_eq!(SUCCESS, store.init(&req).await?);
+        let (attr, _) = store.lookup(&req, dirs[0], OsStr::new("a")).await?;
+        assert_eq!(a_ino, attr.unwrap().ino);
+        assert_eq!(1, store.shards[0].list_get(KEY_RENAME_INTENTS).await?.0.len());
+
+        // Once the lease expired, the next rename across shards finishes it.
+        tokio::time::sleep(Duration::from_millis(2100)).await;
+        make_file(&store, &req, dirs[0], "x").await?;
+        let code = store
+            .rename(&req, dirs[0], OsStr::new("x"), dirs[1], OsStr::new("y"), 0)
+            .await?;
+        assert_eq!(SUCCESS, code);
+        let (_, code) = store.lookup(&req, dirs[0], OsStr::new("a")).await?;
+        assert_eq!(libc::ENOENT, code);
+        let (attr, _) = store.lookup(&req, dirs[1], OsStr::new("c")).await?;
+        assert_eq!(a_ino, attr.unwrap().ino);
+        assert!(store.shards[0].list_get(KEY_RENAME_INTENTS).await?.0.is_empty());
+
+        // A front replacing old by new crashed after both changes but
+        // before removing its record. Recovery drops the link of old once.
+        let old_ino = make_file(&store, &req, dirs[1], "old").await?;
+        let new_ino = make_file(&store, &req, dirs[0], "new").await?;
+        let (old, new) = (
+            Some((old_ino, FileType::RegularFile)),
+            Some((new_ino, FileType::RegularFile)),
+        );
+        let intent = RenameIntent {
+            id: 2,
+            changes: vec![
+                EntryChange {
+                    dir: dirs[1],
+                    name: b"old".to_vec(),
+                    from: old,
+                    to: new,
+                    owner: 0,
+                },
+                EntryChange {
+                    dir: dirs[0],
+                    name: b"new".to_vec(),
+                    from: new,
+                    to: None,
+                    owner: 0,
+                },
+            ],
+            replaced: Some(old_ino),
+            expires_at: 0,
+        };
+        log_intent(&store, &intent).await?;
+        for change in intent.changes.iter() {
+            assert_eq!(SUCCESS, store.apply(&req, change).await?);
+        }
+        assert_eq!(SUCCESS, store.init(&req).await?);
+        assert_eq!(SUCCESS, store.init(&req).await?);
+        let (attr, _) = store.lookup(&req, dirs[1], OsStr::new("old")).await?;
+        assert_eq!(new_ino, attr.unwrap().ino);
+        let (attr, _) = store.getattr(&req, new_ino).await?;
+        assert_eq!(1, attr.unwrap().nlink);
+        match store.attr(&req, old_ino).await? {
+            Ok(attr) => assert_eq!(0, attr.nlink),
+            Err(code) => assert_eq!(libc::ENOENT, code),
+        }
+        assert!(store.shards[0].list_get(KEY_RENAME_INTENTS).await?.0.is_empty());
+        Ok(())
+    }
+}
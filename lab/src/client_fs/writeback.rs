@@ -0,0 +1,243 @@
+//! Data a front-end has answered writes for but not sent on yet.
+//!
+//! The kernel writes a few KiB at a time. With write-back on, the front
+//! keeps the data of each open file as runs of bytes, merging every write
+//! into the runs it touches, and sends whole runs on, in writes of up to
+//! `chunk_bytes`, once the file is flushed or too much is held back.
+use std::collections::{BTreeMap, HashMap};
+
+use libc::c_int;
+use tribbler::config::WriteBackConfig;
+use tribbler::storage::FileRequest;
+
+/// Who writes through a file handle, and how, as the backends are told.
+#[derive(Debug, Clone)]
+pub struct Writer {
+    pub request: FileRequest,
+    pub write_flags: u32,
+    pub flags: i32,
+    pub lock_owner: Option<u64>,
+}
+
+/// What was written through one handle of a file and not sent yet.
+#[derive(Debug)]
+pub struct Dirty {
+    pub writer: Writer,
+    // Runs of data by the offset they start at. Runs never touch, writes
+    // that would join two of them merge them.
+    runs: BTreeMap<u64, Vec<u8>>,
+    bytes: usize,
+}
+
+impl Dirty {
+    fn new(writer: Writer) -> Dirty {
+        Dirty {
+            writer,
+            runs: BTreeMap::new(),
+            bytes: 0,
+        }
+    }
+
+    // Lays `data` over the runs at `offset`, merging every run it touches
+    // into one.
+    fn write(&mut self, offset: u64, data: &[u8]) {
+        let end = offset + data.len() as u64;
+        let mut start = offset;
+        let mut touched = vec![];
+        if let Some((&at, run)) = self.runs.range(..offset).next_back() {
+            if at + run.len() as u64 >= offset {
+                start = at;
+                touched.push(at);
+            }
+        }
+        touched.extend(self.runs.range(offset..=end).map(|(&at, _)| at));
+        let mut merged = vec![];
+        for at in touched {
+            let run = self.runs.remove(&at).unwrap();
+            self.bytes -= run.len();
+            let from = (at - start) as usize;
+            if merged.len() < from + run.len() {
+                merged.resize(from + run.len(), 0);
+            }
+            merged[from..from + run.len()].copy_from_slice(&run);
+        }
+        let from = (offset - start) as usize;
+        if merged.len() < from + data.len() {
+            merged.resize(from + data.len(), 0);
+        }
+        merged[from..from + data.len()].copy_from_slice(data);
+        self.bytes += merged.len();
+        self.runs.insert(start, merged);
+    }
+
+    /// The data to send, in offset order, in pieces of at most `chunk`
+    /// bytes.
+    pub fn chunks(&self, chunk: usize) -> impl Iterator<Item = (u64, &[u8])> {
+        self.runs.iter().flat_map(move |(&at, run)| {
+            run.chunks(chunk.max(1))
+                .enumerate()
+                .map(move |(i, piece)| (at + (i * chunk.max(1)) as u64, piece))
+        })
+    }
+
+    // Where the data ends.
+    fn end(&self) -> u64 {
+        match self.runs.iter().next_back() {
+            Some((&at, run)) => at + run.len() as u64,
+            None => 0,
+        }
+    }
+}
+
+pub struct WriteBack {
+    config: WriteBackConfig,
+    // By inode and file handle.
+    files: HashMap<(u64, u64), Dirty>,
+    bytes: usize,
+    // Errors of sends made on a handle's behalf, reported the next time it
+    // is flushed.
+    failed: HashMap<(u64, u64), c_int>,
+}
+
+impl WriteBack {
+    pub fn new(config: WriteBackConfig) -> WriteBack {
+        WriteBack {
+            config,
+            files: HashMap::new(),
+            bytes: 0,
+            failed: HashMap::new(),
+        }
+    }
+
+    pub fn config(&self) -> &WriteBackConfig {
+        &self.config
+    }
+
+    /// Holds back `data`, written at `offset` through handle `fh` of `ino`.
+    pub fn write(&mut self, ino: u64, fh: u64, writer: Writer, offset: u64, data: &[u8]) {
+        let dirty = self
+            .files
+            .entry((ino, fh))
+            .or_insert_with(|| Dirty::new(writer.clone()));
+        dirty.writer = writer;
+        self.bytes -= dirty.bytes;
+        dirty.write(offset, data);
+        self.bytes += dirty.bytes;
+    }
+
+    /// Whether handle `fh` of `ino` holds back as much as it may.
+    pub fn file_full(&self, ino: u64, fh: u64) -> bool {
+        match self.files.get(&(ino, fh)) {
+            Some(dirty) => dirty.bytes >= self.config.file_dirty_bytes,
+            None => false,
+        }
+    }
+
+    /// Whether all files together hold back as much as they may.
+    pub fn full(&self) -> bool {
+        self.bytes >= self.config.dirty_bytes
+    }
+
+    /// Takes what handle `fh` of `ino` holds back, to send it.
+    pub fn take(&mut self, ino: u64, fh: u64) -> Option<Dirty> {
+        let dirty = self.files.remove(&(ino, fh))?;
+        self.bytes -= dirty.bytes;
+        Some(dirty)
+    }
+
+    /// Takes what every handle of `ino` holds back, by handle.
+    pub fn take_inode(&mut self, ino: u64) -> Vec<(u64, Dirty)> {
+        let handles: Vec<u64> = self
+            .files
+            .keys()
+            .filter(|(of, _)| *of == ino)
+            .map(|(_, fh)| *fh)
+            .collect();
+        handles
+            .into_iter()
+            .filter_map(|fh| Some((fh, self.take(ino, fh)?)))
+            .collect()
+    }
+
+    /// The handle holding back the most, by inode and handle.
+    pub fn largest(&self) -> Option<(u64, u64)> {
+        self.files
+            .iter()
+            .max_by_key(|(_, dirty)| dirty.bytes)
+            .map(|(key, _)| *key)
+    }
+
+    /// How far `ino` extends with what is held back for it, if anything is.
+    pub fn end(&self, ino: u64) -> Option<u64> {
+        self.files
+            .iter()
+            .filter(|((of, _), _)| *of == ino)
+            .map(|(_, dirty)| dirty.end())
+            .max()
+    }
+
+    /// Records that sending what handle `fh` of `ino` held back failed.
+    pub fn fail(&mut self, ino: u64, fh: u64, errcode: c_int) {
+        self.failed.insert((ino, fh), errcode);
+    }
+
+    /// The error an earlier send for handle `fh` of `ino` ran into, if any.
+    pub fn take_error(&mut self, ino: u64, fh: u64) -> Option<c_int> {
+        self.failed.remove(&(ino, fh))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tribbler::config::WriteBackConfig;
+    use tribbler::storage::FileRequest;
+
+    use super::{WriteBack, Writer};
+
+    fn writer() -> Writer {
+        Writer {
+            request: FileRequest {
+                uid: 0,
+                gid: 0,
+                pid: 0,
+            },
+            write_flags: 0,
+            flags: 0,
+            lock_owner: None,
+        }
+    }
+
+    #[test]
+    fn test_writes_coalesce() {
+        let mut write_back = WriteBack::new(WriteBackConfig {
+            enabled: true,
+            chunk_bytes: 4,
+            file_dirty_bytes: 12,
+            dirty_bytes: 100,
+        });
+        write_back.write(1, 7, writer(), 0, b"aaaa");
+        write_back.write(1, 7, writer(), 4, b"bbbb");
+        write_back.write(1, 7, writer(), 12, b"dd");
+        write_back.write(1, 7, writer(), 6, b"cc");
+        assert!(!write_back.file_full(1, 7));
+        assert_eq!(Some(14), write_back.end(1));
+        assert_eq!(None, write_back.end(2));
+
+        // joins the two runs
+        write_back.write(1, 7, writer(), 8, b"eeee");
+        assert!(write_back.file_full(1, 7));
+        let dirty = write_back.take(1, 7).unwrap();
+        let chunks: Vec<(u64, &[u8])> = dirty.chunks(4).collect();
+        assert_eq!(
+            vec![
+                (0, &b"aaaa"[..]),
+                (4, &b"bbcc"[..]),
+                (8, &b"eeee"[..]),
+                (12, &b"dd"[..]),
+            ],
+            chunks
+        );
+        assert!(write_back.largest().is_none());
+        assert!(!write_back.full());
+    }
+}
@@ -1,8 +1,19 @@
 use bincode::options;
 use clap::{crate_version, Arg, Command};
-use front::client_fs::lab::{new_bin_client, serve_back};
-use front::client_fs::{client::new_client, front::Front};
+use front::client_fs::binstore::{self, BinStore, KEY_REPAIR_LOG};
+use front::client_fs::keeper::{
+    change_config, replication_jobs, replication_status, ConfigChange, JobState, KeeperState,
+    Lease, ReplicationJob, ReplicationTask, ANTI_ENTROPY_INTERVAL, LEASE_DURATION,
+};
+use front::client_fs::lab::{new_bin_client, serve_back, serve_keeper};
+use front::client_fs::{
+    client::{new_client, ClientPool},
+    front::Front,
+};
 use front::client_fs::front::USERNAME;
+use front::client_fs::{cache::AttrCache, invalidate::Invalidator, membership::Membership};
+use front::client_fs::offline::OfflineStore;
+use front::client_fs::ops::RepairRecord;
 use fuser::{
     Filesystem, KernelConfig, MountOption, ReplyAttr, ReplyCreate, ReplyData, ReplyDirectory,
     ReplyEmpty, ReplyEntry, ReplyOpen, ReplyStatfs, ReplyWrite, ReplyXattr, Request, TimeOrNow,
@@ -11,23 +22,39 @@ use fuser::{
 use log::{error, LevelFilter};
 #[allow(unused_imports)]
 use rand::{prelude::SliceRandom, Rng};
+use std::ffi::OsStr;
 use std::fs::{File, OpenOptions};
 use std::io::{self, BufRead, BufReader, ErrorKind, Read, Seek, SeekFrom, Write};
 use std::{
-    sync::{mpsc, Arc},
+    sync::{mpsc, Arc, Mutex},
     time::{Duration, SystemTime},
     vec,
 };
 use tokio::{runtime::Handle, time};
-use tribbler::config::KeeperConfig;
+use tribbler::config::{
+    CacheConfig, KeeperConfig, Namespace, NamespaceKind, OfflineConfig, ReadPolicy,
+    DEFAULT_NAMESPACE,
+};
+use tribbler::disfuser::{disfuser_client::DisfuserClient, Create, FRequest};
+use tribbler::keeper::{membership_client::MembershipClient, Bin, Empty};
 use tribbler::{
     self,
     config::BackConfig,
-    error::{TritonFileError, TritonFileResult},
-    storage::{KeyValue, Pattern, RemoteFileSystem},
+    error::{is_epoch_mismatch, TritonFileError, TritonFileResult, SUCCESS},
+    storage::{
+        hash_name_to_idx, BinStorage, FileRequest, KeyValue, MembershipView, NodeCopy, Pattern,
+        RemoteFileSystem, ServerFileSystem, Storage,
+    },
 };
 
 const KEY_KEEPER: &str = "KEEPER";
+
+// A server a test started: its address, its task and what shuts it down.
+type Served = (
+    String,
+    tokio::task::JoinHandle<TritonFileResult<()>>,
+    tokio::sync::mpsc::Sender<()>,
+);
 const KEY_TIMESTAMP: &str = "TIMESTAMP";
 fn kv(key: &str, value: &str) -> KeyValue {
     KeyValue {
@@ -198,24 +225,39 @@ fn generate_random_string(len: usize) -> String {
     return password;
 }
 
+// Storage for the backend at `addr`, in an empty directory named after its
+// port, so that tests running side by side do not share files.
+fn back_storage(addr: &str) -> TritonFileResult<RemoteFileSystem> {
+    let port: usize = addr.rsplit(':').next().unwrap_or_default().parse()?;
+    let _ = std::fs::remove_dir_all(format!("tmp/{}", port));
+    Ok(RemoteFileSystem::new(port))
+}
+
+// Storage for the backend at `addr` that keeps the files it had before,
+// for a backend that comes back.
+fn kept_storage(addr: &str) -> TritonFileResult<RemoteFileSystem> {
+    let port: usize = addr.rsplit(':').next().unwrap_or_default().parse()?;
+    Ok(RemoteFileSystem::new(port))
+}
+
 // All the available backends and keepers and the number of lives
 // the number of keepers is at least 3
-async fn set_up_backs(
+async fn set_up_backs(backs: Vec<String>) -> TritonFileResult<Vec<Served>> {
+    start_backs(backs, back_storage).await
+}
+
+// Starts a backend at each of `backs` over the storage `storage` gives it.
+async fn start_backs(
     backs: Vec<String>,
-) -> TritonFileResult<
-    Vec<(
-        String,
-        tokio::task::JoinHandle<Result<(), Box<dyn std::error::Error + Send + Sync>>>,
-        tokio::sync::mpsc::Sender<()>,
-    )>,
-> {
+    storage: fn(&str) -> TritonFileResult<RemoteFileSystem>,
+) -> TritonFileResult<Vec<Served>> {
     let mut back_ends = vec![];
     for i in 0..backs.len() {
         let (sx, rx) = mpsc::channel();
         let (shut_sx, shut_rx) = tokio::sync::mpsc::channel(1);
         let cfg = BackConfig {
             addr: backs[i].clone(),
-            storage: Box::new(RemoteFileSystem::new(i.try_into().unwrap())),
+            storage: Box::new(storage(&backs[i])?),
             ready: Some(sx.clone()),
             shutdown: Some(shut_rx),
         };
@@ -282,6 +324,15 @@ async fn set_up_backs(
 //     return Ok(front);
 // }
 
+// Shuts the backends down and waits until they stopped serving.
+async fn stop_backs(back_ends: Vec<Served>) -> TritonFileResult<()> {
+    for (_, handle, shut_sx) in back_ends {
+        let _ = shut_sx.send(()).await;
+        handle.await??;
+    }
+    Ok(())
+}
+
 async fn shut_down_all(
     backs: Vec<(
         String,
@@ -324,20 +375,24 @@ async fn test_server_setup() -> TritonFileResult<()> {
         // options.push(MountOption::AllowRoot);
     }
     // let handle = Handle::current();
-    let runtime = tokio::runtime::Builder::new_current_thread()
-    .enable_all()
-    .build()
-    .unwrap();
-    let front =  Front::new(
-        bin_client,
-        runtime,
-        USERNAME.to_string(),
-    );
-    let result = fuser::mount2(
-        front, 
-        "/Users/stella/Desktop/tmp",
-        &options,
-    );
+    // The front's runtime can't be dropped from within this one.
+    let result = tokio::task::spawn_blocking(move || {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+        let front =  Front::new(
+            bin_client,
+            runtime,
+            USERNAME.to_string(),
+        );
+        fuser::mount2(
+            front, 
+            "/Users/stella/Desktop/tmp",
+            &options,
+        )
+    })
+    .await?;
     dbg!(&result);
     if let Err(e) = result {
         // Return a special error code for permission denied, which usually indicates that
@@ -360,6 +415,1316 @@ fn fuse_allow_other_enabled() -> io::Result<bool> {
     Ok(false)
 }
 
+async fn fence_all(view: &MembershipView, backs: &[String]) -> TritonFileResult<()> {
+    for addr in backs {
+        let fenced = new_client(addr).await?.fence(view).await?;
+        assert_eq!(view.epoch, fenced.epoch);
+    }
+    Ok(())
+}
+
+// Backend 0 is partitioned away: the keeper moves the others to a new epoch
+// without it. Neither an old client nor a new one may then write to both
+// sides of the partition.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_fence_partitioned_backend() -> TritonFileResult<()> {
+    let addrs: Vec<String> = (32420..32423).map(|p| format!("127.0.0.1:{}", p)).collect();
+    let backs = set_up_backs(addrs.clone()).await?;
+
+    let view = MembershipView {
+        epoch: 1,
+        live: addrs.clone(),
+        ..Default::default()
+    };
+    fence_all(&view, &addrs).await?;
+
+    let old = ClientPool::new();
+    old.observe_epoch(1);
+    for addr in addrs.iter() {
+        assert!(old.client(addr)?.set(&kv("k", "v1")).await?);
+    }
+
+    let survivors = addrs[1..].to_vec();
+    let view = MembershipView {
+        epoch: 2,
+        live: survivors.clone(),
+        ..Default::default()
+    };
+    fence_all(&view, &survivors).await?;
+
+    // The old client can't write to the survivors any more.
+    for addr in survivors.iter() {
+        let err = old.client(addr)?.set(&kv("k", "v2")).await.unwrap_err();
+        assert!(is_epoch_mismatch(err.as_ref()));
+    }
+    // A client that learned the new epoch can, but not to the partitioned
+    // backend, which never saw epoch 2.
+    let new = ClientPool::new();
+    new.observe_epoch(2);
+    for addr in survivors.iter() {
+        assert!(new.client(addr)?.set(&kv("k", "v3")).await?);
+    }
+    let err = new.client(&addrs[0])?.set(&kv("k", "v3")).await.unwrap_err();
+    assert!(is_epoch_mismatch(err.as_ref()));
+    // Fencing never moves a backend back to an older epoch.
+    let stale = MembershipView {
+        epoch: 1,
+        live: addrs.clone(),
+        ..Default::default()
+    };
+    let view = new_client(&addrs[1]).await?.fence(&stale).await?;
+    let expected = MembershipView {
+        epoch: 2,
+        live: survivors,
+        ..Default::default()
+    };
+    assert_eq!(expected, view);
+
+    shut_down_all(backs, vec![]).await;
+    Ok(())
+}
+
+// Clients learn the view and the replicas of a bin from the keeper instead
+// of probing the backends.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_keeper_membership_service() -> TritonFileResult<()> {
+    let backs: Vec<String> = (32430..32433).map(|p| format!("127.0.0.1:{}", p)).collect();
+    let keeper = "127.0.0.1:32440".to_string();
+    let back_ends = set_up_backs(backs.clone()).await?;
+
+    let (sx, rx) = mpsc::channel();
+    let (shut_sx, shut_rx) = tokio::sync::mpsc::channel(1);
+    let handle = tokio::spawn(serve_keeper(KeeperConfig {
+        backs: backs.clone(),
+        addrs: vec![keeper.clone()],
+        this: 0,
+        id: 1,
+        namespaces: vec![],
+        ready: Some(sx),
+        shutdown: Some(shut_rx),
+    }));
+    assert!(rx.recv_timeout(Duration::from_secs(5))?);
+
+    let mut client = MembershipClient::connect(format!("http://{}", keeper)).await?;
+    let mut views = client.watch(Empty {}).await?.into_inner();
+    let view = time::timeout(Duration::from_secs(20), views.message())
+        .await??
+        .ok_or("watch closed")?;
+    assert!(view.epoch >= 1);
+    assert_eq!(backs, view.live);
+
+    let assignment = client
+        .assignment(Bin {
+            name: "alice".to_string(),
+        })
+        .await?
+        .into_inner();
+    let ring = binstore::ring("alice", &backs);
+    assert_eq!(view.epoch, assignment.epoch);
+    assert_eq!(Some(ring[0].clone()), assignment.primary);
+    assert_eq!(Some(ring[1].clone()), assignment.backup);
+
+    let _ = shut_sx.send(()).await;
+    shut_down_all(back_ends, vec![(keeper, handle, shut_sx)]).await;
+    Ok(())
+}
+
+// Starts keeper `this` of `addrs` over `backs` and waits until it serves.
+async fn spawn_keeper(
+    backs: &[String],
+    addrs: &[String],
+    this: usize,
+    id: u128,
+    namespaces: &[Namespace],
+) -> TritonFileResult<(
+    tokio::task::JoinHandle<TritonFileResult<()>>,
+    tokio::sync::mpsc::Sender<()>,
+)> {
+    let (sx, rx) = mpsc::channel();
+    let (shut_sx, shut_rx) = tokio::sync::mpsc::channel(1);
+    let handle = tokio::spawn(serve_keeper(KeeperConfig {
+        backs: backs.to_vec(),
+        addrs: addrs.to_vec(),
+        this,
+        id,
+        namespaces: namespaces.to_vec(),
+        ready: Some(sx),
+        shutdown: Some(shut_rx),
+    }));
+    assert!(rx.recv_timeout(Duration::from_secs(5))?);
+    Ok((handle, shut_sx))
+}
+
+// Starts a single keeper over `backs` and waits until it leads and has
+// published a view.
+async fn start_leader(
+    backs: &[String],
+    keeper: &str,
+) -> TritonFileResult<(
+    tokio::task::JoinHandle<TritonFileResult<()>>,
+    tokio::sync::mpsc::Sender<()>,
+)> {
+    let (handle, shut_sx) = spawn_keeper(backs, &[keeper.to_string()], 0, 1, &[]).await?;
+    let mut client = MembershipClient::connect(format!("http://{}", keeper)).await?;
+    let mut views = client.watch(Empty {}).await?.into_inner();
+    time::timeout(Duration::from_secs(20), views.message())
+        .await??
+        .ok_or("watch closed")?;
+    Ok((handle, shut_sx))
+}
+
+// The cluster grows from 3 to 6 backends while a client keeps using it: the
+// bins that move are copied to their new replicas, and the client follows
+// the new config without a restart.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_grow_cluster() -> TritonFileResult<()> {
+    let all: Vec<String> = (32450..32456).map(|p| format!("127.0.0.1:{}", p)).collect();
+    let backs = all[..3].to_vec();
+    let keeper = "127.0.0.1:32460".to_string();
+    let back_ends = set_up_backs(all.clone()).await?;
+    let (handle, shut_sx) = start_leader(&backs, &keeper).await?;
+
+    let keepers = vec![keeper.clone()];
+    let store = BinStore::with_keepers(backs.clone(), keepers.clone(), ReadPolicy::default());
+    let names: Vec<String> = (0..20).map(|i| format!("user{}", i)).collect();
+    for name in names.iter() {
+        assert!(store.bin(name).await?.set(&kv("k", name)).await?);
+    }
+
+    for addr in all[3..].iter() {
+        let config = change_config(&keepers, ConfigChange::AddBackend(addr.clone())).await?;
+        assert!(config.backs.contains(addr));
+    }
+    let config = change_config(&keepers, ConfigChange::Show).await?;
+    assert_eq!(3, config.version);
+    assert_eq!(all, config.backs);
+    // Backends of the config file hold the keeper state.
+    assert!(change_config(&keepers, ConfigChange::RemoveBackend(all[0].clone()))
+        .await
+        .is_err());
+
+    let membership = store.membership();
+    time::timeout(Duration::from_secs(10), async {
+        while membership.config_version() < 3 {
+            time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await?;
+    assert_eq!(all, membership.backs());
+
+    for name in names.iter() {
+        // Every bin is on its replicas of the new config.
+        let (primary, backup) = binstore::assign(name, &all, &all);
+        for addr in [primary, backup].into_iter().flatten() {
+            let key = binstore::compose_key(name, binstore::KeyKind::KeyString, "k");
+            assert!(!new_client(&addr).await?.list_get(&key).await?.0.is_empty());
+        }
+        assert_eq!(Some(name.clone()), store.bin(name).await?.get("k").await?);
+    }
+
+    let _ = shut_sx.send(()).await;
+    shut_down_all(back_ends, vec![(keeper, handle, shut_sx)]).await;
+    Ok(())
+}
+
+// A draining backend stops serving bins as soon as the drain starts, and is
+// reported safe to remove once its bins were copied to the other backends.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_drain_backend() -> TritonFileResult<()> {
+    let all: Vec<String> = (32470..32474).map(|p| format!("127.0.0.1:{}", p)).collect();
+    let keeper = "127.0.0.1:32480".to_string();
+    let back_ends = set_up_backs(all.clone()).await?;
+    let (handle, shut_sx) = start_leader(&all[..3], &keeper).await?;
+    let keepers = vec![keeper.clone()];
+    let extra = all[3].clone();
+    change_config(&keepers, ConfigChange::AddBackend(extra.clone())).await?;
+
+    let store = BinStore::with_keepers(all.clone(), keepers.clone(), ReadPolicy::default());
+    let names: Vec<String> = (0..20).map(|i| format!("user{}", i)).collect();
+    for name in names.iter() {
+        assert!(store.bin(name).await?.set(&kv("k", name)).await?);
+    }
+
+    let config = change_config(&keepers, ConfigChange::DrainBackend(extra.clone())).await?;
+    assert!(config.draining.contains(&extra));
+    assert!(config.drained.contains(&extra));
+    let membership = store.membership();
+    time::timeout(Duration::from_secs(10), async {
+        while membership.config_version() < config.version {
+            time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await?;
+    assert!(!membership.is_alive(&extra));
+
+    let remaining = all[..3].to_vec();
+    for name in names.iter() {
+        let (primary, backup) = binstore::assign(name, &all, &remaining);
+        for addr in [primary, backup].into_iter().flatten() {
+            let key = binstore::compose_key(name, binstore::KeyKind::KeyString, "k");
+            assert!(!new_client(&addr).await?.list_get(&key).await?.0.is_empty());
+        }
+        assert_eq!(Some(name.clone()), store.bin(name).await?.get("k").await?);
+    }
+
+    let config = change_config(&keepers, ConfigChange::RemoveBackend(extra.clone())).await?;
+    assert_eq!(remaining, config.backs);
+    assert!(config.drained.is_empty());
+
+    let _ = shut_sx.send(()).await;
+    shut_down_all(back_ends, vec![(keeper, handle, shut_sx)]).await;
+    Ok(())
+}
+
+// With a single backend left, bins written in the meantime are tracked as
+// under-replicated, and copied to the other backend once it is back.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_degraded_repair() -> TritonFileResult<()> {
+    let backs: Vec<String> = (32490..32492).map(|p| format!("127.0.0.1:{}", p)).collect();
+    let keeper = "127.0.0.1:32495".to_string();
+    let keepers = vec![keeper.clone()];
+    let mut back_ends = set_up_backs(backs.clone()).await?;
+    let (handle, shut_sx) = start_leader(&backs, &keeper).await?;
+    let store = BinStore::with_keepers(backs.clone(), keepers.clone(), ReadPolicy::default());
+
+    // Wait for the backend to stop, so that it also closes the connections
+    // it already had.
+    let (_, down, down_sx) = back_ends.remove(1);
+    let _ = down_sx.send(()).await;
+    let _ = time::timeout(Duration::from_secs(10), down).await?;
+    time::timeout(Duration::from_secs(30), async {
+        while replication_status(&keepers).await.map_or(true, |s| s.live != 1) {
+            time::sleep(Duration::from_millis(200)).await;
+        }
+    })
+    .await?;
+    assert!(store.bin("alice").await?.set(&kv("k", "v")).await?);
+    time::timeout(Duration::from_secs(30), async {
+        while !replication_status(&keepers)
+            .await
+            .is_ok_and(|s| s.under_replicated.contains(&"alice".to_string()))
+        {
+            time::sleep(Duration::from_millis(200)).await;
+        }
+    })
+    .await?;
+
+    // The backend comes back empty.
+    let (sx, rx) = mpsc::channel();
+    let (up_sx, up_rx) = tokio::sync::mpsc::channel(1);
+    let up = spawn_back(BackConfig {
+        addr: backs[1].clone(),
+        storage: Box::new(back_storage(&backs[1])?),
+        ready: Some(sx),
+        shutdown: Some(up_rx),
+    });
+    assert!(rx.recv_timeout(Duration::from_secs(10))?);
+    back_ends.push((backs[1].clone(), up, up_sx));
+    time::timeout(Duration::from_secs(30), async {
+        while !replication_status(&keepers)
+            .await
+            .is_ok_and(|s| s.live == 2 && s.under_replicated.is_empty())
+        {
+            time::sleep(Duration::from_millis(200)).await;
+        }
+    })
+    .await?;
+    let key = binstore::compose_key("alice", binstore::KeyKind::KeyString, "k");
+    for addr in backs.iter() {
+        assert!(!new_client(addr).await?.list_get(&key).await?.0.is_empty());
+    }
+
+    let _ = shut_sx.send(()).await;
+    shut_down_all(back_ends, vec![(keeper, handle, shut_sx)]).await;
+    Ok(())
+}
+
+// The repair records kept in the repair log of the backend at `addr`.
+async fn repair_log(addr: &str) -> TritonFileResult<Vec<RepairRecord>> {
+    let mut records = vec![];
+    for raw in new_client(addr).await?.list_get(KEY_REPAIR_LOG).await?.0 {
+        records.push(serde_json::from_str(&raw)?);
+    }
+    Ok(records)
+}
+
+// The backup of a bin stops answering while the view still names it live:
+// mutations succeed on the primary alone, the first one the backup missed
+// is recorded in the primary's repair log, and the keeper picks the record
+// up once the backup is back.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_backup_failure_recorded() -> TritonFileResult<()> {
+    let backs: Vec<String> = (32620..32622).map(|p| format!("127.0.0.1:{}", p)).collect();
+    let keeper = "127.0.0.1:32625".to_string();
+    let mut back_ends = set_up_backs(backs.clone()).await?;
+    // A published view keeps the backup live for the store whatever its
+    // probes find.
+    let view = MembershipView {
+        epoch: 1,
+        live: backs.clone(),
+        ..Default::default()
+    };
+    fence_all(&view, &backs).await?;
+    let req = FileRequest {
+        uid: 0,
+        gid: 0,
+        pid: 0,
+    };
+    let store = BinStore::new(backs.clone());
+    let bin = store.bin(DEFAULT_NAMESPACE).await?;
+    bin.init(&req).await?;
+    let (created, code) = bin
+        .create(&req, FUSE_ROOT_ID, OsStr::new("f"), 0o100644, 0, libc::O_RDWR)
+        .await?;
+    assert_eq!(SUCCESS, code);
+    let ino = created.ok_or("create failed")?.0.ino;
+
+    let (primary, backup) = binstore::assign(DEFAULT_NAMESPACE, &backs, &backs);
+    let (primary, backup) = (primary.unwrap(), backup.unwrap());
+    let down = back_ends.iter().position(|(addr, _, _)| addr == &backup).unwrap();
+    stop_backs(vec![back_ends.remove(down)]).await?;
+    let data = b"only on the primary".to_vec();
+    write_file(bin.as_ref(), &req, ino, &data).await?;
+    assert_eq!(data, read_file(new_client(&primary).await?.as_ref(), &req, ino).await?);
+    let records = repair_log(&primary).await?;
+    assert!(records.iter().any(|r| r.backend == backup && r.inode == ino));
+
+    back_ends.extend(start_backs(vec![backup.clone()], kept_storage).await?);
+    assert!(read_file(new_client(&backup).await?.as_ref(), &req, ino).await?.is_empty());
+    let (handle, shut_sx) = start_leader(&backs, &keeper).await?;
+    time::timeout(Duration::from_secs(30), async {
+        while !repair_log(&primary).await.is_ok_and(|records| records.is_empty()) {
+            time::sleep(Duration::from_millis(200)).await;
+        }
+    })
+    .await?;
+
+    let _ = shut_sx.send(()).await;
+    shut_down_all(back_ends, vec![(keeper, handle, shut_sx)]).await;
+    Ok(())
+}
+
+// Files and directories created at the same time through one store get the
+// same inode numbers on both replicas, so that either replica can serve
+// them.
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_concurrent_creates_match() -> TritonFileResult<()> {
+    let backs: Vec<String> = (32630..32632).map(|p| format!("127.0.0.1:{}", p)).collect();
+    let back_ends = set_up_backs(backs.clone()).await?;
+    let req = FileRequest {
+        uid: 0,
+        gid: 0,
+        pid: 0,
+    };
+    let store = BinStore::new(backs.clone());
+    let bin: Arc<Box<dyn Storage>> = Arc::new(store.bin(DEFAULT_NAMESPACE).await?);
+    bin.init(&req).await?;
+
+    let names: Vec<String> = (0..24).map(|i| format!("f{}", i)).collect();
+    let mut creates = vec![];
+    for (i, name) in names.iter().enumerate() {
+        let (bin, name, req) = (bin.clone(), name.clone(), req.clone());
+        creates.push(tokio::spawn(async move {
+            let name = OsStr::new(&name);
+            let code = match i % 3 {
+                0 => bin.mkdir(&req, FUSE_ROOT_ID, name, 0o40755, 0).await?.1,
+                _ => {
+                    bin.create(&req, FUSE_ROOT_ID, name, 0o100644, 0, libc::O_RDWR)
+                        .await?
+                        .1
+                }
+            };
+            assert_eq!(SUCCESS, code);
+            TritonFileResult::Ok(())
+        }));
+    }
+    for create in creates {
+        create.await??;
+    }
+
+    let (primary, backup) = binstore::assign(DEFAULT_NAMESPACE, &backs, &backs);
+    let primary = new_client(&primary.unwrap()).await?;
+    let backup = new_client(&backup.unwrap()).await?;
+    for name in names.iter() {
+        let name = OsStr::new(name);
+        let (on_primary, code) = primary.lookup(&req, FUSE_ROOT_ID, name).await?;
+        assert_eq!(SUCCESS, code);
+        let (on_backup, code) = backup.lookup(&req, FUSE_ROOT_ID, name).await?;
+        assert_eq!(SUCCESS, code);
+        let (on_primary, on_backup) = (on_primary.unwrap(), on_backup.unwrap());
+        assert_eq!(on_primary.ino, on_backup.ino, "{:?}", name);
+        assert_eq!(on_primary.kind, on_backup.kind);
+    }
+
+    shut_down_all(back_ends, vec![]).await;
+    Ok(())
+}
+
+// A read of several blocks through the store comes back whole within the
+// retry policy's attempt timeout.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_large_read() -> TritonFileResult<()> {
+    let backs: Vec<String> = (32635..32637).map(|p| format!("127.0.0.1:{}", p)).collect();
+    let back_ends = set_up_backs(backs.clone()).await?;
+    let req = FileRequest {
+        uid: 0,
+        gid: 0,
+        pid: 0,
+    };
+    let bin = BinStore::new(backs.clone()).bin(DEFAULT_NAMESPACE).await?;
+    bin.init(&req).await?;
+    let (created, code) = bin
+        .create(&req, FUSE_ROOT_ID, OsStr::new("large"), 0o100644, 0, libc::O_RDWR)
+        .await?;
+    assert_eq!(SUCCESS, code);
+    let (attr, fh) = created.ok_or("create failed")?;
+    let len = 256 << 10;
+    let data: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+    let (written, code) = bin
+        .write(&req, attr.ino, fh, 0, &data, 0, 0, None)
+        .await?;
+    assert_eq!(SUCCESS, code);
+    assert_eq!(Some(len as u32), written);
+
+    let (read, code) = bin
+        .read(&req, attr.ino, fh, 0, len as u32, 0, None)
+        .await?;
+    assert_eq!(SUCCESS, code);
+    let read: Vec<u8> = serde_json::from_str(&read.ok_or("read failed")?)?;
+    assert_eq!(data, read);
+
+    shut_down_all(back_ends, vec![]).await;
+    Ok(())
+}
+
+// Reads the progress the leader keeper saved in the keeper bin.
+async fn keeper_state(
+    backs: &[String],
+) -> TritonFileResult<(Option<Lease>, Option<KeeperState>)> {
+    let keep_bin = BinStore::new(backs.to_vec()).keeper_bin(KEY_KEEPER).await?;
+    let lease = match keep_bin.get("LEADER_LEASE").await? {
+        Some(lease) => Some(serde_json::from_str(&lease)?),
+        None => None,
+    };
+    let state = match keep_bin.get("KEEPER_STATE").await? {
+        Some(state) => Some(serde_json::from_str(&state)?),
+        None => None,
+    };
+    Ok((lease, state))
+}
+
+// A keeper that restarts picks up the live list it saved, so no backend
+// looks like it joined again and the membership view stays the same.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_keeper_restart() -> TritonFileResult<()> {
+    let backs: Vec<String> = (32510..32513).map(|p| format!("127.0.0.1:{}", p)).collect();
+    let keeper = "127.0.0.1:32515".to_string();
+    let back_ends = set_up_backs(backs.clone()).await?;
+    let (handle, shut_sx) = start_leader(&backs, &keeper).await?;
+    time::timeout(Duration::from_secs(20), async {
+        while !keeper_state(&backs).await.is_ok_and(|(_, state)| {
+            state.is_some_and(|s| s.live_list == vec![true; 3] && s.target.is_none())
+        }) {
+            time::sleep(Duration::from_millis(200)).await;
+        }
+    })
+    .await?;
+    // The backends coming up are joins, whose copies ran as jobs.
+    let jobs = replication_jobs(std::slice::from_ref(&keeper)).await?;
+    assert!(!jobs.is_empty());
+    assert!(jobs.iter().all(|job| job.state == "done" && job.attempts >= 1));
+    let mut client = MembershipClient::connect(format!("http://{}", keeper)).await?;
+    let epoch = client.view(Empty {}).await?.into_inner().epoch;
+    let _ = shut_sx.send(()).await;
+    handle.await??;
+
+    // The keeper comes back as a new incarnation, and takes over once the
+    // lease of the old one expired.
+    let (sx, rx) = mpsc::channel();
+    let (shut_sx, shut_rx) = tokio::sync::mpsc::channel(1);
+    let handle = tokio::spawn(serve_keeper(KeeperConfig {
+        backs: backs.clone(),
+        addrs: vec![keeper.clone()],
+        this: 0,
+        id: 2,
+        namespaces: vec![],
+        ready: Some(sx),
+        shutdown: Some(shut_rx),
+    }));
+    assert!(rx.recv_timeout(Duration::from_secs(5))?);
+    time::timeout(Duration::from_secs(30), async {
+        while !keeper_state(&backs).await.is_ok_and(|(lease, state)| {
+            match (lease, state) {
+                (Some(lease), Some(state)) => lease.id == 2 && state.epoch == lease.epoch,
+                _ => false,
+            }
+        }) {
+            time::sleep(Duration::from_millis(200)).await;
+        }
+    })
+    .await?;
+    let (_, state) = keeper_state(&backs).await?;
+    assert_eq!(vec![true; 3], state.unwrap().live_list);
+    let mut client = MembershipClient::connect(format!("http://{}", keeper)).await?;
+    assert_eq!(epoch, client.view(Empty {}).await?.into_inner().epoch);
+
+    let _ = shut_sx.send(()).await;
+    shut_down_all(back_ends, vec![(keeper, handle, shut_sx)]).await;
+    Ok(())
+}
+
+// Waits until the lease in the keeper bin satisfies `cond`, and returns it.
+async fn wait_for_lease(
+    backs: &[String],
+    cond: impl Fn(&Lease) -> bool,
+) -> TritonFileResult<Lease> {
+    let lease = time::timeout(Duration::from_secs(40), async {
+        loop {
+            if let Ok((Some(lease), _)) = keeper_state(backs).await {
+                if cond(&lease) {
+                    return lease;
+                }
+            }
+            time::sleep(Duration::from_millis(200)).await;
+        }
+    })
+    .await?;
+    Ok(lease)
+}
+
+// When the leader dies, the follower takes over the next epoch once the
+// lease expired, and acts as leader.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_follower_takeover() -> TritonFileResult<()> {
+    let backs: Vec<String> = (32570..32573).map(|p| format!("127.0.0.1:{}", p)).collect();
+    let keepers = vec!["127.0.0.1:32575".to_string(), "127.0.0.1:32576".to_string()];
+    let back_ends = set_up_backs(backs.clone()).await?;
+    let (handle0, shut_sx0) = spawn_keeper(&backs, &keepers, 0, 1, &[]).await?;
+    let lease = wait_for_lease(&backs, |lease| lease.leader == 0).await?;
+    let (handle1, shut_sx1) = spawn_keeper(&backs, &keepers, 1, 2, &[]).await?;
+    // The follower refuses admin requests.
+    let change = ConfigChange::AddBackend(backs[0].clone());
+    assert!(change_config(&keepers[1..], change.clone()).await.is_err());
+
+    let _ = shut_sx0.send(()).await;
+    handle0.await??;
+    let taken = wait_for_lease(&backs, |lease| lease.leader == 1).await?;
+    assert_eq!(2, taken.id);
+    assert_eq!(lease.epoch + 1, taken.epoch);
+    time::timeout(Duration::from_secs(10), async {
+        while change_config(&keepers[1..], change.clone()).await.is_err() {
+            time::sleep(Duration::from_millis(200)).await;
+        }
+    })
+    .await?;
+
+    shut_down_all(back_ends, vec![(keepers[1].clone(), handle1, shut_sx1)]).await;
+    Ok(())
+}
+
+// A leader that finds a later epoch claimed by another keeper steps down
+// without touching its lease, and runs for the epoch after it once that
+// claim expired.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_deposed_leader_steps_down() -> TritonFileResult<()> {
+    let backs: Vec<String> = (32580..32583).map(|p| format!("127.0.0.1:{}", p)).collect();
+    let keepers = vec!["127.0.0.1:32585".to_string(), "127.0.0.1:32586".to_string()];
+    let back_ends = set_up_backs(backs.clone()).await?;
+    let (handle, shut_sx) = spawn_keeper(&backs, &keepers, 0, 1, &[]).await?;
+    let lease = wait_for_lease(&backs, |lease| lease.leader == 0).await?;
+    let change = ConfigChange::AddBackend(backs[0].clone());
+    time::timeout(Duration::from_secs(10), async {
+        while change_config(&keepers[..1], change.clone()).await.is_err() {
+            time::sleep(Duration::from_millis(200)).await;
+        }
+    })
+    .await?;
+
+    // Keeper 1 took over the next epoch while keeper 0 stalled, but has not
+    // written its lease yet.
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)?
+        .as_millis() as u64;
+    let claim = Lease {
+        leader: 1,
+        id: 9,
+        epoch: lease.epoch + 1,
+        expires_at: now + LEASE_DURATION.as_millis() as u64,
+    };
+    let keep_bin = BinStore::new(backs.clone()).keeper_bin(KEY_KEEPER).await?;
+    keep_bin
+        .set(&kv(
+            &format!("LEADER_CLAIM_{}", claim.epoch),
+            &serde_json::to_string(&claim)?,
+        ))
+        .await?;
+    time::timeout(Duration::from_secs(10), async {
+        while change_config(&keepers[..1], change.clone()).await.is_ok() {
+            time::sleep(Duration::from_millis(200)).await;
+        }
+    })
+    .await?;
+    let (stale, _) = keeper_state(&backs).await?;
+    assert_eq!(Some(lease.epoch), stale.map(|l| l.epoch));
+
+    // Keeper 1 never shows up, so keeper 0 leads again, in a fresh epoch.
+    let back = wait_for_lease(&backs, |l| l.leader == 0 && l.epoch > lease.epoch).await?;
+    assert_eq!(lease.epoch + 2, back.epoch);
+
+    shut_down_all(back_ends, vec![(keepers[0].clone(), handle, shut_sx)]).await;
+    Ok(())
+}
+
+// Tenants of the same backends don't see each other's files, and the
+// leader keeps the replicas of every namespace of the config in step, not
+// just those of the default one.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_tenant_isolation() -> TritonFileResult<()> {
+    let backs: Vec<String> = (32590..32592).map(|p| format!("127.0.0.1:{}", p)).collect();
+    let keeper = "127.0.0.1:32595".to_string();
+    let back_ends = set_up_backs(backs.clone()).await?;
+    let tenants = ["tenant-a", "tenant-b"].map(|name| Namespace {
+        name: name.to_string(),
+        kind: NamespaceKind::Private,
+        shards: 1,
+    });
+    let (handle, shut_sx) =
+        spawn_keeper(&backs, std::slice::from_ref(&keeper), 0, 1, &tenants).await?;
+    let req = FileRequest {
+        uid: 0,
+        gid: 0,
+        pid: 0,
+    };
+    let store = BinStore::new(backs.clone());
+    let (a, b) = (store.bin("tenant-a").await?, store.bin("tenant-b").await?);
+    a.init(&req).await?;
+    b.init(&req).await?;
+    let name = OsStr::new("only-in-a");
+    let (_, code) = a
+        .create(&req, FUSE_ROOT_ID, name, 0o100644, 0, libc::O_RDWR)
+        .await?;
+    assert_eq!(SUCCESS, code);
+    assert_eq!(libc::ENOENT, b.lookup(&req, FUSE_ROOT_ID, name).await?.1);
+    let default = store.bin(DEFAULT_NAMESPACE).await?;
+    assert_eq!(libc::ENOENT, default.lookup(&req, FUSE_ROOT_ID, name).await?.1);
+
+    // A create that never reached the backup of tenant-a is copied there by
+    // the next anti-entropy pass.
+    let (primary, backup) = binstore::assign("tenant-a", &backs, &backs);
+    let primary = new_client(&primary.unwrap()).await?.namespace("tenant-a").await?;
+    let backup = new_client(&backup.unwrap()).await?.namespace("tenant-a").await?;
+    let lost = OsStr::new("lost-on-backup");
+    let (created, code) = primary
+        .create(&req, FUSE_ROOT_ID, lost, 0o100644, 0, libc::O_RDWR)
+        .await?;
+    assert_eq!(SUCCESS, code);
+    let ino = created.ok_or("create failed")?.0.ino;
+    time::timeout(ANTI_ENTROPY_INTERVAL * 2, async {
+        while backup
+            .lookup(&req, FUSE_ROOT_ID, lost)
+            .await
+            .map_or(true, |(attr, _)| attr.map(|attr| attr.ino) != Some(ino))
+        {
+            time::sleep(Duration::from_millis(500)).await;
+        }
+    })
+    .await?;
+    for addr in backs.iter() {
+        let other = new_client(addr).await?.namespace("tenant-b").await?;
+        for name in [name, lost] {
+            assert_eq!(libc::ENOENT, other.lookup(&req, FUSE_ROOT_ID, name).await?.1);
+        }
+    }
+
+    let _ = shut_sx.send(()).await;
+    shut_down_all(back_ends, vec![(keeper, handle, shut_sx)]).await;
+    Ok(())
+}
+
+// In a shared namespace each request acts as its caller: a file belongs to
+// whoever made it, and others reach it through its group and other bits.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_shared_namespace_permissions() -> TritonFileResult<()> {
+    let backs: Vec<String> = (32600..32602).map(|p| format!("127.0.0.1:{}", p)).collect();
+    let back_ends = set_up_backs(backs.clone()).await?;
+    let bin = BinStore::new(backs.clone()).bin("team").await?;
+    let as_user = |uid: u32, gid: u32| FileRequest { uid, gid, pid: 0 };
+    let (root, alice, bob, eve) = (
+        as_user(0, 0),
+        as_user(1000, 100),
+        as_user(1001, 100),
+        as_user(1002, 200),
+    );
+    bin.init(&root).await?;
+    let (dir, code) = bin
+        .mkdir(&root, FUSE_ROOT_ID, OsStr::new("project"), 0o777, 0)
+        .await?;
+    assert_eq!(SUCCESS, code);
+    let dir = dir.ok_or("mkdir failed")?.ino;
+    let name = OsStr::new("notes");
+    let (created, code) = bin
+        .create(&alice, dir, name, 0o100640, 0, libc::O_RDWR)
+        .await?;
+    assert_eq!(SUCCESS, code);
+    let attr = created.ok_or("create failed")?.0;
+    assert_eq!((1000, 100), (attr.uid, attr.gid));
+
+    // bob shares alice's group, which may read but not write.
+    assert_eq!(SUCCESS, bin.open(&bob, attr.ino, libc::O_RDONLY).await?.1);
+    assert_eq!(libc::EACCES, bin.open(&bob, attr.ino, libc::O_WRONLY).await?.1);
+    // eve is in neither, and may not even read.
+    assert_eq!(libc::EACCES, bin.open(&eve, attr.ino, libc::O_RDONLY).await?.1);
+    assert_eq!(SUCCESS, bin.open(&alice, attr.ino, libc::O_RDWR).await?.1);
+    // Only root and the owner may change the mode.
+    let (_, code) = bin
+        .setattr(
+            &eve,
+            attr.ino,
+            Some(0o100666),
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+        )
+        .await?;
+    assert_eq!(libc::EPERM, code);
+
+    shut_down_all(back_ends, vec![]).await;
+    Ok(())
+}
+
+// Reads a whole small file through `store`.
+async fn read_file(store: &dyn Storage, req: &FileRequest, ino: u64) -> TritonFileResult<Vec<u8>> {
+    let (opened, code) = store.open(req, ino, libc::O_RDONLY).await?;
+    assert_eq!(SUCCESS, code);
+    let fh = opened.ok_or("open failed")?.0;
+    let (data, code) = store.read(req, ino, fh, 0, 1 << 10, 0, None).await?;
+    assert_eq!(SUCCESS, code);
+    store.release(req, ino, fh, 0, None, false).await?;
+    Ok(serde_json::from_str(&data.ok_or("read failed")?)?)
+}
+
+// Writes `data` over the start of a file through `store`.
+async fn write_file(
+    store: &dyn Storage,
+    req: &FileRequest,
+    ino: u64,
+    data: &[u8],
+) -> TritonFileResult<()> {
+    let (opened, code) = store.open(req, ino, libc::O_WRONLY).await?;
+    assert_eq!(SUCCESS, code);
+    let fh = opened.ok_or("open failed")?.0;
+    let (_, code) = store.write(req, ino, fh, 0, data, 0, 0, None).await?;
+    assert_eq!(SUCCESS, code);
+    store.release(req, ino, fh, 0, None, false).await?;
+    Ok(())
+}
+
+// A front that can go offline over the bin of `namespace` on `backs`,
+// with a file `name` it created, wrote `data` to and read back, so that
+// a copy of it is kept. Returns the store and the file's inode.
+async fn offline_front(
+    backs: &[String],
+    namespace: &str,
+    dir: &std::path::Path,
+    retry_ms: u64,
+    name: &str,
+    data: &[u8],
+) -> TritonFileResult<(OfflineStore, u64)> {
+    let bin = BinStore::new(backs.to_vec()).bin(namespace).await?;
+    let config = OfflineConfig {
+        enabled: true,
+        retry_ms,
+        ..OfflineConfig::default()
+    };
+    let store = OfflineStore::new(bin.into(), dir, config)?;
+    let req = FileRequest {
+        uid: 0,
+        gid: 0,
+        pid: 0,
+    };
+    store.init(&req).await?;
+    let (made, code) = store
+        .create(&req, FUSE_ROOT_ID, OsStr::new(name), 0o100644, 0, libc::O_RDWR)
+        .await?;
+    assert_eq!(SUCCESS, code);
+    let (attr, fh) = made.ok_or("create failed")?;
+    store.write(&req, attr.ino, fh, 0, data, 0, 0, None).await?;
+    store.release(&req, attr.ino, fh, 0, None, false).await?;
+    assert_eq!(data.to_vec(), read_file(&store, &req, attr.ino).await?);
+    let (fh, _) = store
+        .opendir(&req, FUSE_ROOT_ID, 0)
+        .await?
+        .0
+        .ok_or("opendir failed")?;
+    let mut offset = 0;
+    while let (Some(_), SUCCESS) = store.readdir(&req, FUSE_ROOT_ID, fh, offset).await? {
+        offset += 1;
+    }
+    Ok((store, attr.ino))
+}
+
+// While every backend is down, a front going on offline answers reads of
+// what it kept and logs its writes; once the backends are back it sends
+// them on its own.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_offline_write_replayed() -> TritonFileResult<()> {
+    let backs: Vec<String> = (32610..32612).map(|p| format!("127.0.0.1:{}", p)).collect();
+    let back_ends = set_up_backs(backs.clone()).await?;
+    let dir = tempfile::tempdir()?;
+    let (store, ino) = offline_front(&backs, "roaming", dir.path(), 200, "notes", b"online").await?;
+    let req = FileRequest {
+        uid: 0,
+        gid: 0,
+        pid: 0,
+    };
+
+    stop_backs(back_ends).await?;
+    let down = BinStore::new(backs.clone()).bin("roaming").await?;
+    assert!(down.getattr(&req, ino).await.is_err());
+    assert_eq!(b"online".to_vec(), read_file(&store, &req, ino).await?);
+    let (attr, code) = store.lookup(&req, FUSE_ROOT_ID, OsStr::new("notes")).await?;
+    assert_eq!(SUCCESS, code);
+    assert_eq!(ino, attr.ok_or("lookup failed")?.ino);
+    write_file(&store, &req, ino, b"offline").await?;
+    assert_eq!(b"offline".to_vec(), read_file(&store, &req, ino).await?);
+
+    let back_ends = start_backs(backs.clone(), kept_storage).await?;
+    let bin = BinStore::new(backs.clone()).bin("roaming").await?;
+    time::timeout(Duration::from_secs(20), async {
+        while !read_file(bin.as_ref(), &req, ino)
+            .await
+            .is_ok_and(|data| data == b"offline")
+        {
+            time::sleep(Duration::from_millis(200)).await;
+        }
+    })
+    .await?;
+
+    shut_down_all(back_ends, vec![]).await;
+    Ok(())
+}
+
+// A file changed on the backends while a front changed it offline keeps
+// the backends' version, and the front's is saved next to it.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_offline_conflict_copy() -> TritonFileResult<()> {
+    let backs: Vec<String> = (32615..32617).map(|p| format!("127.0.0.1:{}", p)).collect();
+    let back_ends = set_up_backs(backs.clone()).await?;
+    let dir = tempfile::tempdir()?;
+    // sent by hand below
+    let (store, ino) =
+        offline_front(&backs, "roaming", dir.path(), 60_000, "shared", b"base").await?;
+    let req = FileRequest {
+        uid: 0,
+        gid: 0,
+        pid: 0,
+    };
+
+    stop_backs(back_ends).await?;
+    write_file(&store, &req, ino, b"mine").await?;
+    let back_ends = start_backs(backs.clone(), kept_storage).await?;
+    let bin = BinStore::new(backs.clone()).bin("roaming").await?;
+    write_file(bin.as_ref(), &req, ino, b"theirs!").await?;
+
+    assert!(store.reconcile().await);
+    assert_eq!(b"theirs!".to_vec(), read_file(bin.as_ref(), &req, ino).await?);
+    let (fh, _) = bin
+        .opendir(&req, FUSE_ROOT_ID, 0)
+        .await?
+        .0
+        .ok_or("opendir failed")?;
+    let mut copies = vec![];
+    let mut offset = 0;
+    while let (Some((found, _, _, name)), SUCCESS) =
+        bin.readdir(&req, FUSE_ROOT_ID, fh, offset).await?
+    {
+        if name.0.starts_with(b"shared.conflict-") {
+            copies.push(found);
+        }
+        offset += 1;
+    }
+    assert_eq!(1, copies.len());
+    assert_eq!(b"mine".to_vec(), read_file(bin.as_ref(), &req, copies[0]).await?);
+
+    shut_down_all(back_ends, vec![]).await;
+    Ok(())
+}
+
+// Creates `count` files in the root of a backend's own file tree. They are
+// root's, so they fall in the bucket of gid 0, which is returned.
+async fn make_files(addr: &str, count: usize, len: usize) -> TritonFileResult<usize> {
+    let client = new_client(addr).await?;
+    let req = FileRequest {
+        uid: 0,
+        gid: 0,
+        pid: 0,
+    };
+    for i in 0..count {
+        let name = format!("file-{}", i);
+        let (created, _) = client
+            .create(&req, FUSE_ROOT_ID, OsStr::new(&name), 0o100644, 0, libc::O_RDWR)
+            .await?;
+        created.ok_or("create failed")?;
+    }
+    Ok(hash_name_to_idx("0", len))
+}
+
+// Copies of the files of bucket `bucket` (out of `len`) on a backend, in
+// inode order.
+async fn nodes_on(addr: &str, bucket: usize, len: usize) -> TritonFileResult<Vec<NodeCopy>> {
+    let (inodes, contents) = new_client(addr)
+        .await?
+        .get_all_nodes(bucket, len)
+        .await?
+        .ok_or("no file tree")?;
+    let mut nodes: Vec<NodeCopy> = inodes
+        .0
+        .into_iter()
+        .zip(contents.0)
+        .map(|(attrs, content)| NodeCopy {
+            attrs,
+            content: content.0,
+        })
+        .collect();
+    nodes.sort_by_key(|node| node.attrs.inode);
+    Ok(nodes)
+}
+
+// The inode numbers of a backend's own file tree.
+async fn inodes_on(addr: &str) -> TritonFileResult<Vec<u64>> {
+    Ok(nodes_on(addr, 0, 1)
+        .await?
+        .iter()
+        .map(|node| node.attrs.inode)
+        .collect())
+}
+
+// Leaves `job` behind as the only job of a replication from a live list
+// with just the first backend to one with all of them, as a keeper that
+// stopped half way would.
+async fn leave_job(backs: &[String], job: ReplicationJob) -> TritonFileResult<()> {
+    let mut live_list = vec![false; backs.len()];
+    live_list[0] = true;
+    let state = KeeperState {
+        epoch: 1,
+        config_version: 0,
+        live_list,
+        target: Some(vec![true; backs.len()]),
+        jobs: vec![job],
+        next_job: 1,
+    };
+    let keep_bin = BinStore::new(backs.to_vec()).keeper_bin(KEY_KEEPER).await?;
+    assert!(keep_bin.set(&kv("KEEPER_STATE", &serde_json::to_string(&state)?)).await?);
+    Ok(())
+}
+
+// Waits until the leader finished the replication left behind, and returns
+// its job.
+async fn left_job_done(backs: &[String]) -> TritonFileResult<ReplicationJob> {
+    let state = time::timeout(Duration::from_secs(30), async {
+        loop {
+            if let Ok((_, Some(state))) = keeper_state(backs).await {
+                if state.target.is_none() {
+                    return state;
+                }
+            }
+            time::sleep(Duration::from_millis(200)).await;
+        }
+    })
+    .await?;
+    let job = state.jobs.into_iter().find(|job| job.id == 0);
+    Ok(job.ok_or("job dropped")?)
+}
+
+// A file copy tried again after an earlier try copied the files, but failed
+// before that was recorded, leaves a single copy of every file.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_retry_file_job() -> TritonFileResult<()> {
+    let backs: Vec<String> = (32540..32542).map(|p| format!("127.0.0.1:{}", p)).collect();
+    let keeper = "127.0.0.1:32545".to_string();
+    let back_ends = set_up_backs(backs.clone()).await?;
+    let bucket = make_files(&backs[0], 8, backs.len()).await?;
+    let files = nodes_on(&backs[0], bucket, backs.len()).await?;
+    new_client(&backs[1])
+        .await?
+        .restore_nodes(files.clone(), &[])
+        .await?;
+    leave_job(
+        &backs,
+        ReplicationJob {
+            id: 0,
+            task: ReplicationTask {
+                from: 0,
+                to: 1,
+                for_addr: bucket,
+                files: true,
+            },
+            state: JobState::Failed,
+            attempts: 3,
+            checkpoint: None,
+            copied: 0,
+            error: Some("timed out".to_string()),
+        },
+    )
+    .await?;
+    let source = inodes_on(&backs[0]).await?;
+
+    let (handle, shut_sx) = start_leader(&backs, &keeper).await?;
+    let job = left_job_done(&backs).await?;
+    assert_eq!(JobState::Done, job.state);
+    assert_eq!(files.len() as u64, job.copied);
+    assert_eq!(source, inodes_on(&backs[0]).await?);
+    assert_eq!(source, inodes_on(&backs[1]).await?);
+
+    let _ = shut_sx.send(()).await;
+    shut_down_all(back_ends, vec![(keeper, handle, shut_sx)]).await;
+    Ok(())
+}
+
+// A keeper taking over from one that stopped in the middle of a file copy
+// starts after the last batch of inodes the copy checkpointed.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_resume_file_job() -> TritonFileResult<()> {
+    let backs: Vec<String> = (32550..32552).map(|p| format!("127.0.0.1:{}", p)).collect();
+    let keeper = "127.0.0.1:32555".to_string();
+    let back_ends = set_up_backs(backs.clone()).await?;
+    let bucket = make_files(&backs[0], 70, backs.len()).await?;
+    let files = nodes_on(&backs[0], bucket, backs.len()).await?;
+    // The stopped keeper copied the first batch, all but one inode of which
+    // made it, and checkpointed it.
+    let (batch, rest) = files.split_at(64);
+    let lost = batch[10].attrs.inode;
+    let copied: Vec<NodeCopy> = batch
+        .iter()
+        .filter(|node| node.attrs.inode != lost)
+        .cloned()
+        .collect();
+    new_client(&backs[1]).await?.restore_nodes(copied, &[]).await?;
+    leave_job(
+        &backs,
+        ReplicationJob {
+            id: 0,
+            task: ReplicationTask {
+                from: 0,
+                to: 1,
+                for_addr: bucket,
+                files: true,
+            },
+            state: JobState::Running,
+            attempts: 1,
+            checkpoint: Some(batch[63].attrs.inode.to_string()),
+            copied: 64,
+            error: None,
+        },
+    )
+    .await?;
+
+    let (handle, shut_sx) = start_leader(&backs, &keeper).await?;
+    let job = left_job_done(&backs).await?;
+    assert_eq!(JobState::Done, job.state);
+    assert_eq!(files.len() as u64, job.copied);
+    assert_eq!(Some(rest[rest.len() - 1].attrs.inode.to_string()), job.checkpoint);
+    // Only the inodes after the checkpoint were copied again.
+    let mut expected = inodes_on(&backs[0]).await?;
+    expected.retain(|inode| *inode != lost);
+    assert_eq!(expected, inodes_on(&backs[1]).await?);
+
+    let _ = shut_sx.send(()).await;
+    shut_down_all(back_ends, vec![(keeper, handle, shut_sx)]).await;
+    Ok(())
+}
+
+// A write that only reached the primary is noticed by the next getattr
+// under the read-repair policy, which answers with the primary's newer
+// version and copies it to the backup.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_read_repair() -> TritonFileResult<()> {
+    let backs: Vec<String> = (32500..32502).map(|p| format!("127.0.0.1:{}", p)).collect();
+    let back_ends = set_up_backs(backs.clone()).await?;
+    let store = BinStore::with_read_policy(backs.clone(), ReadPolicy::ReadRepair);
+    let bin = store.bin(USERNAME).await?;
+    let req = FileRequest {
+        uid: 0,
+        gid: 0,
+        pid: 0,
+    };
+    let name = format!("read-repair-{}", std::process::id());
+    let (created, _) = bin
+        .create(&req, FUSE_ROOT_ID, OsStr::new(&name), 0o100644, 0, libc::O_RDWR)
+        .await?;
+    let (attr, fh) = created.ok_or("create failed")?;
+    let ino = attr.ino;
+
+    // The write is lost on its way to the backup.
+    let (primary, backup) = binstore::assign(USERNAME, &backs, &backs);
+    let (primary, backup) = (primary.unwrap(), backup.unwrap());
+    new_client(&primary)
+        .await?
+        .write(&req, ino, fh, 0, b"hello", 0, 0, None)
+        .await?;
+
+    let (attr, _) = bin.getattr(&req, ino).await?;
+    assert_eq!(5, attr.ok_or("getattr failed")?.size);
+    let backup = new_client(&backup).await?;
+    time::timeout(Duration::from_secs(10), async {
+        while backup
+            .getattr(&req, ino)
+            .await
+            .map_or(true, |(a, _)| a.map_or(0, |a| a.size) != 5)
+        {
+            time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await?;
+
+    // A write left half-finished reached only the backup, so the backup
+    // holds the newer copy: reads answer with it, and the primary heals.
+    backup
+        .write(&req, ino, fh, 5, b" world", 0, 0, None)
+        .await?;
+    let (data, _) = bin.read(&req, ino, fh, 0, 64, 0, None).await?;
+    let data: Vec<u8> = serde_json::from_str(&data.ok_or("read failed")?)?;
+    assert_eq!(b"hello world".to_vec(), data);
+    let primary = new_client(&primary).await?;
+    time::timeout(Duration::from_secs(10), async {
+        while primary
+            .getattr(&req, ino)
+            .await
+            .map_or(true, |(a, _)| a.map_or(0, |a| a.size) != 11)
+        {
+            time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await?;
+    // The healed copy keeps its number.
+    let (attr, _) = primary
+        .lookup(&req, FUSE_ROOT_ID, OsStr::new(&name))
+        .await?;
+    assert_eq!(ino, attr.ok_or("lookup failed")?.ino);
+
+    shut_down_all(back_ends, vec![]).await;
+    Ok(())
+}
+
+// Under the version-checked read policy, a backup that went back to an
+// older copy of a file the front wrote is refused, and the read falls back
+// to the primary.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_version_checked_read() -> TritonFileResult<()> {
+    let backs: Vec<String> = (32560..32562).map(|p| format!("127.0.0.1:{}", p)).collect();
+    let back_ends = set_up_backs(backs.clone()).await?;
+    let store =
+        BinStore::with_read_policy(backs.clone(), ReadPolicy::AnyWithVersionCheck { max_lag: 1 });
+    let bin = store.bin(USERNAME).await?;
+    let req = FileRequest {
+        uid: 0,
+        gid: 0,
+        pid: 0,
+    };
+    let name = format!("version-check-{}", std::process::id());
+    let (created, _) = bin
+        .create(&req, FUSE_ROOT_ID, OsStr::new(&name), 0o100644, 0, libc::O_RDWR)
+        .await?;
+    let (attr, fh) = created.ok_or("create failed")?;
+    let ino = attr.ino;
+    let (_, backup) = binstore::assign(USERNAME, &backs, &backs);
+    let backup = new_client(&backup.unwrap()).await?;
+    let old = backup.get_nodes(&[ino]).await?;
+
+    // The writes reach both replicas, then the backup loses them.
+    for (offset, data) in [(0, "he"), (2, "ll"), (4, "o")] {
+        bin.write(&req, ino, fh, offset, data.as_bytes(), 0, 0, None)
+            .await?;
+    }
+    backup.restore_nodes(old, &[]).await?;
+
+    // The first read measures the primary, after which the backup, without
+    // samples yet, counts as the nearest replica.
+    for _ in 0..3 {
+        let (data, _) = bin.read(&req, ino, fh, 0, 64, 0, None).await?;
+        let data: Vec<u8> = serde_json::from_str(&data.ok_or("read failed")?)?;
+        assert_eq!(b"hello".to_vec(), data);
+    }
+
+    shut_down_all(back_ends, vec![]).await;
+    Ok(())
+}
+
+// A change made through one front reaches another front that cached the
+// changed directory, over the invalidation stream of the backend.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_invalidation_reaches_other_front() -> TritonFileResult<()> {
+    let backs: Vec<String> = (32520..32521).map(|p| format!("127.0.0.1:{}", p)).collect();
+    let back_ends = set_up_backs(backs.clone()).await?;
+    let req = FileRequest {
+        uid: 0,
+        gid: 0,
+        pid: 0,
+    };
+
+    // The first front watches the backend and caches the root, reading it
+    // as itself, which takes a lease on it.
+    let cache = Arc::new(Mutex::new(AttrCache::new(CacheConfig::default())));
+    let watcher = tokio::spawn(
+        Invalidator::new(cache.clone(), DEFAULT_NAMESPACE.to_string())
+            .follow(Arc::new(Membership::new(backs.clone()))),
+    );
+    let reader = new_client(&backs[0]).await?;
+    let (root, _) = reader.getattr(&req, FUSE_ROOT_ID).await?;
+    let mut untouched = root.ok_or("getattr failed")?;
+    untouched.ino = u64::MAX;
+    cache.lock().unwrap().insert_attr(&untouched);
+    // The second front names no client, so the first is told of its changes.
+    let mut writer = DisfuserClient::connect(format!("http://{}", backs[0])).await?;
+
+    let mut created = 0;
+    time::timeout(Duration::from_secs(10), async {
+        loop {
+            let (root, _) = reader.getattr(&req, FUSE_ROOT_ID).await?;
+            cache
+                .lock()
+                .unwrap()
+                .insert_attr(&root.ok_or("getattr failed")?);
+            writer
+                .create(Create {
+                    frequest: FRequest {
+                        uid: 0,
+                        gid: 0,
+                        pid: 0,
+                    },
+                    parent: FUSE_ROOT_ID,
+                    name: format!("invalidated-{}-{}", std::process::id(), created),
+                    mode: 0o100644,
+                    umask: 0,
+                    flags: libc::O_RDWR,
+                })
+                .await?;
+            created += 1;
+            // Until the watch stream is open, the read takes no lease.
+            time::sleep(Duration::from_millis(100)).await;
+            if cache.lock().unwrap().attr(FUSE_ROOT_ID).is_none() {
+                return Ok::<(), Box<dyn std::error::Error + Send + Sync>>(());
+            }
+        }
+    })
+    .await??;
+    // Dropped alone, not along with the whole cache as a broken stream
+    // would have it.
+    assert!(cache.lock().unwrap().attr(u64::MAX).is_some());
+
+    watcher.abort();
+    shut_down_all(back_ends, vec![]).await;
+    Ok(())
+}
+
 // #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
 // async fn test_server_shutdown_channel() -> TritonFileResult<()> {
 //     let ((back_addr, used_back_addr, unused_back_addr), (kp_addr, used_kp_addr, unused_kp_addr)) =
@@ -4,6 +4,6 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .build_client(true)
         .format(true)
         .out_dir("src")
-        .compile(&["proto/disfuser.proto"], &["proto"])?;
+        .compile(&["proto/disfuser.proto", "proto/keeper.proto"], &["proto"])?;
     Ok(())
 }
@@ -5,13 +5,14 @@
 use std::fs;
 use std::io::{stdout, Write};
 use std::sync::mpsc::Sender;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
 use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc::Receiver;
 
-use crate::error::TritonFileResult;
-use crate::storage::Storage;
+use crate::error::{TritonFileError, TritonFileResult};
+use crate::lease::LEASE_DURATION;
+use crate::storage::{Storage, MAX_SHARDS};
 
 pub const DEFAULT_CONFIG_LOCATION: &str = "bins.json";
 
@@ -52,10 +53,13 @@ pub struct KeeperConfig {
     pub backs: Vec<String>,
     /// The addresses of keepers
     pub addrs: Vec<String>,
-    /// The index of this back-end
+    /// The index of this keeper in `addrs`
     pub this: usize,
     /// Non zero incarnation identifier
     pub id: u128,
+    /// The namespaces of the config, besides [DEFAULT_NAMESPACE], whose
+    /// file replicas the leader compares
+    pub namespaces: Vec<Namespace>,
     /// Send a value when the keeper is ready. The distributed key-value
     /// service should be ready to serve when *any* of the keepers is
     /// ready.
@@ -72,11 +76,289 @@ impl KeeperConfig {
     }
 }
 
+/// Which replicas a front-end reads from.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ReadPolicy {
+    /// Always read from the primary replica.
+    #[default]
+    PrimaryOnly,
+    /// Read from whichever replica has answered fastest recently.
+    Nearest,
+    /// Read from the primary, and also from the backup if the primary has
+    /// not answered within the given percentile of its recent latencies.
+    /// The first answer wins.
+    Hedged { percentile: f64 },
+    /// Read from the nearest replica, but reject a backup answer whose inode
+    /// version is more than `max_lag` behind the newest version this
+    /// front-end has seen, and fall back to the primary.
+    AnyWithVersionCheck { max_lag: u64 },
+    /// Read from both replicas and answer with the one holding the newer
+    /// version of the inode. The stale replica is repaired in the
+    /// background.
+    ReadRepair,
+}
+
+/// The namespace a front-end mounts when none is given. Its files stay in
+/// the backends' own file systems, where they were before namespaces.
+pub const DEFAULT_NAMESPACE: &str = "Tan-cha";
+
+/// Who a namespace's files belong to.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum NamespaceKind {
+    /// A single tenant's: every request acts as the user who mounted it,
+    /// and no other local user can reach the mount.
+    #[default]
+    Private,
+    /// Used by many users at once: requests act as their callers, whose uid
+    /// and gid are checked against the mode of every file they touch.
+    Shared,
+}
+
+/// A tree of files of its own. Each namespace is kept in a bin named after
+/// it, so different namespaces are served by different backends. A
+/// namespace split into several shards spreads its files over the bins of
+/// all of them.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Namespace {
+    pub name: String,
+    #[serde(default)]
+    pub kind: NamespaceKind,
+    /// how many bins the files are spread over; it can grow, but not shrink,
+    /// while the namespace has files
+    #[serde(default = "default_shards")]
+    pub shards: usize,
+}
+
+fn default_shards() -> usize {
+    1
+}
+
+/// Separates the name of a namespace from the number of one of its shards
+/// in the name of the shard's bin.
+pub const SHARD_SEPARATOR: char = '@';
+
+/// The bin shard `shard` of namespace `name` is kept in. Shard 0 is the
+/// namespace's own bin, so the files a namespace had before it was split
+/// stay where they are.
+pub fn shard_name(name: &str, shard: usize) -> String {
+    if shard == 0 {
+        return name.to_string();
+    }
+    format!("{}{}{}", name, SHARD_SEPARATOR, shard)
+}
+
+/// Splits the name of a shard's bin into the name of the namespace and the
+/// number of the shard.
+pub fn parse_shard_name(name: &str) -> Option<(&str, usize)> {
+    match name.rsplit_once(SHARD_SEPARATOR) {
+        None => Some((name, 0)),
+        Some((namespace, shard)) => match shard.parse::<usize>() {
+            Ok(shard) if shard > 0 && shard < MAX_SHARDS => Some((namespace, shard)),
+            _ => None,
+        },
+    }
+}
+
+/// Whether `name` can name a namespace: backends keep a namespace in a
+/// directory of that name.
+pub fn valid_namespace(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name != "."
+        && name != ".."
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+/// How long a front-end, and the kernel above it, may use the attributes
+/// and directory entries it has looked up before asking the backends
+/// again. Changes made through the same front-end are seen at once, and
+/// backends push those made through others while the front-end holds a
+/// lease; only when a push is lost may a change take this long to show.
+/// Nothing is kept past a lease, [LEASE_DURATION].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct CacheConfig {
+    /// how long attributes of an inode are kept, in milliseconds
+    pub attr_ttl_ms: u64,
+    /// how long a name found in a directory is kept, in milliseconds
+    pub entry_ttl_ms: u64,
+    /// how long a name found missing from a directory is kept, in
+    /// milliseconds
+    pub negative_ttl_ms: u64,
+    /// how many attributes and entries are kept at most
+    pub capacity: usize,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            attr_ttl_ms: 1000,
+            entry_ttl_ms: 1000,
+            negative_ttl_ms: 500,
+            capacity: 65536,
+        }
+    }
+}
+
+impl CacheConfig {
+    pub fn attr_ttl(&self) -> Duration {
+        Duration::from_millis(self.attr_ttl_ms).min(LEASE_DURATION)
+    }
+
+    pub fn entry_ttl(&self) -> Duration {
+        Duration::from_millis(self.entry_ttl_ms).min(LEASE_DURATION)
+    }
+
+    pub fn negative_ttl(&self) -> Duration {
+        Duration::from_millis(self.negative_ttl_ms).min(LEASE_DURATION)
+    }
+}
+
+/// Whether a front-end answers writes at once and sends the data on later,
+/// merging writes that touch, and how much it may hold back. Data held
+/// back is sent when the file is flushed, fsynced or closed, when it is
+/// read, or when one of the limits is reached.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct WriteBackConfig {
+    /// whether writes are held back at all
+    pub enabled: bool,
+    /// the largest write sent on to the backends, in bytes
+    pub chunk_bytes: usize,
+    /// how much may be held back for one open file, in bytes
+    pub file_dirty_bytes: usize,
+    /// how much may be held back for all files together, in bytes
+    pub dirty_bytes: usize,
+}
+
+impl Default for WriteBackConfig {
+    fn default() -> Self {
+        WriteBackConfig {
+            enabled: false,
+            chunk_bytes: 1 << 20,
+            file_dirty_bytes: 8 << 20,
+            dirty_bytes: 64 << 20,
+        }
+    }
+}
+
+/// How a front-end keeps the file data it has read, in blocks, and reads
+/// ahead of a file read from start to end. A block is used only while the
+/// file's mtime and size are still those it was read at.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ReadCacheConfig {
+    /// whether file data is kept at all
+    pub enabled: bool,
+    /// how much a block holds, in bytes
+    pub block_bytes: usize,
+    /// how many blocks are fetched ahead of a file read in sequence
+    pub read_ahead_blocks: usize,
+    /// how much data is kept at most, in bytes
+    pub capacity_bytes: usize,
+}
+
+impl Default for ReadCacheConfig {
+    fn default() -> Self {
+        ReadCacheConfig {
+            enabled: true,
+            block_bytes: 128 << 10,
+            read_ahead_blocks: 8,
+            capacity_bytes: 64 << 20,
+        }
+    }
+}
+
+/// How many requests of the kernel a front-end works on at once, and on
+/// how many threads.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ConcurrencyConfig {
+    /// how many threads run requests
+    pub worker_threads: usize,
+    /// how many requests may run at once; the kernel waits for one to
+    /// finish before handing over more
+    pub max_requests: usize,
+}
+
+impl Default for ConcurrencyConfig {
+    fn default() -> Self {
+        ConcurrencyConfig {
+            worker_threads: 4,
+            max_requests: 64,
+        }
+    }
+}
+
+/// Whether a front-end goes on working while no backend can be reached,
+/// from local copies of the files and directories it used last, and where
+/// it keeps them. Changes made meanwhile are sent once the backends are
+/// back. A file that changed on the backends too keeps their version, and
+/// the front-end's is saved next to it as a conflicting copy.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct OfflineConfig {
+    pub enabled: bool,
+    /// where copies are kept, in a directory per namespace
+    pub cache_dir: String,
+    /// the largest file a copy of the data is kept of, in bytes
+    pub file_bytes: u64,
+    /// how much file data is kept at most, in bytes; data changed offline
+    /// is kept until it is sent
+    pub capacity_bytes: u64,
+    /// how often a front-end offline checks whether the backends are back,
+    /// in milliseconds
+    pub retry_ms: u64,
+}
+
+impl Default for OfflineConfig {
+    fn default() -> Self {
+        OfflineConfig {
+            enabled: false,
+            cache_dir: "offline".to_string(),
+            file_bytes: 4 << 20,
+            capacity_bytes: 256 << 20,
+            retry_ms: 5000,
+        }
+    }
+}
+
+impl OfflineConfig {
+    pub fn retry_interval(&self) -> Duration {
+        Duration::from_millis(self.retry_ms)
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 /// A config file defining the backend and keeper network addresses
 pub struct Config {
     pub backs: Vec<String>,
     pub keepers: Vec<String>,
+    /// how front-ends pick the replica to read from
+    #[serde(default)]
+    pub read_policy: ReadPolicy,
+    /// the namespaces front-ends may mount, besides [DEFAULT_NAMESPACE]
+    #[serde(default)]
+    pub namespaces: Vec<Namespace>,
+    /// how long front-ends cache attributes and directory entries
+    #[serde(default)]
+    pub cache: CacheConfig,
+    /// whether front-ends hold writes back, and how much
+    #[serde(default)]
+    pub write_back: WriteBackConfig,
+    /// how front-ends keep file data and read ahead
+    #[serde(default)]
+    pub read_cache: ReadCacheConfig,
+    /// how many requests front-ends work on at once
+    #[serde(default)]
+    pub concurrency: ConcurrencyConfig,
+    /// whether front-ends go on working offline
+    #[serde(default)]
+    pub offline: OfflineConfig,
 }
 
 impl Config {
@@ -116,6 +398,37 @@ impl Config {
         Ok(())
     }
 
+    /// Looks up the namespace called `name`. [DEFAULT_NAMESPACE] is a
+    /// private namespace unless the config says otherwise.
+    pub fn namespace(&self, name: &str) -> TritonFileResult<Namespace> {
+        if let Some(namespace) = self.namespaces.iter().find(|ns| ns.name == name) {
+            if !valid_namespace(name) {
+                return Err(Box::new(TritonFileError::Unknown(format!(
+                    "bad namespace name {:?}",
+                    name
+                ))));
+            }
+            if namespace.shards == 0 || namespace.shards > MAX_SHARDS {
+                return Err(Box::new(TritonFileError::Unknown(format!(
+                    "namespace {} can't have {} shards",
+                    name, namespace.shards
+                ))));
+            }
+            return Ok(namespace.clone());
+        }
+        if name == DEFAULT_NAMESPACE {
+            return Ok(Namespace {
+                name: name.to_string(),
+                kind: NamespaceKind::Private,
+                shards: 1,
+            });
+        }
+        Err(Box::new(TritonFileError::Unknown(format!(
+            "namespace {} is not in the config",
+            name
+        ))))
+    }
+
     /// gets the total number of backends in the config.
     pub fn back_count(&self) -> usize {
         self.backs.len()
@@ -166,6 +479,7 @@ impl Config {
             id: SystemTime::now()
                 .duration_since(std::time::UNIX_EPOCH)?
                 .as_nanos(),
+            namespaces: self.namespaces.clone(),
             ready,
             shutdown,
         })
@@ -244,6 +244,8 @@ pub struct GetattrReply {
     pub file_attr: ::prost::alloc::string::String,
     #[prost(int32, required, tag = "2")]
     pub errcode: i32,
+    #[prost(uint64, optional, tag = "3")]
+    pub version: ::core::option::Option<u64>,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct OpenReply {
@@ -454,6 +456,114 @@ pub struct WriteAllNodesReply {
     #[prost(int32, required, tag = "1")]
     pub errcode: i32,
 }
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct MembershipView {
+    #[prost(uint64, required, tag = "1")]
+    pub epoch: u64,
+    #[prost(string, repeated, tag = "2")]
+    pub live: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    #[prost(string, repeated, tag = "3")]
+    pub backs: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    #[prost(uint64, optional, tag = "4")]
+    pub config_version: ::core::option::Option<u64>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DigestRange {
+    #[prost(uint64, required, tag = "1")]
+    pub lo: u64,
+    #[prost(uint64, required, tag = "2")]
+    pub hi: u64,
+    #[prost(bool, required, tag = "3")]
+    pub leaves: bool,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct InodeDigest {
+    #[prost(uint64, required, tag = "1")]
+    pub inode: u64,
+    #[prost(uint64, required, tag = "2")]
+    pub hash: u64,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Digest {
+    #[prost(uint64, required, tag = "1")]
+    pub hash: u64,
+    #[prost(uint64, required, tag = "2")]
+    pub count: u64,
+    #[prost(uint64, repeated, packed = "false", tag = "3")]
+    pub children: ::prost::alloc::vec::Vec<u64>,
+    #[prost(message, repeated, tag = "4")]
+    pub inodes: ::prost::alloc::vec::Vec<InodeDigest>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Inodes {
+    #[prost(uint64, repeated, packed = "false", tag = "1")]
+    pub inodes: ::prost::alloc::vec::Vec<u64>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct NodeCopy {
+    #[prost(string, required, tag = "1")]
+    pub attrs: ::prost::alloc::string::String,
+    #[prost(bytes = "vec", required, tag = "2")]
+    pub content: ::prost::alloc::vec::Vec<u8>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct NodeCopies {
+    #[prost(message, repeated, tag = "1")]
+    pub nodes: ::prost::alloc::vec::Vec<NodeCopy>,
+    #[prost(uint64, repeated, packed = "false", tag = "2")]
+    pub remove: ::prost::alloc::vec::Vec<u64>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Entry {
+    #[prost(uint64, optional, tag = "1")]
+    pub ino: ::core::option::Option<u64>,
+    #[prost(string, optional, tag = "2")]
+    pub file_type: ::core::option::Option<::prost::alloc::string::String>,
+    #[prost(int32, required, tag = "3")]
+    pub errcode: i32,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SetEntry {
+    #[prost(message, required, tag = "1")]
+    pub frequest: FRequest,
+    #[prost(uint64, required, tag = "2")]
+    pub parent: u64,
+    #[prost(string, required, tag = "3")]
+    pub name: ::prost::alloc::string::String,
+    #[prost(uint64, optional, tag = "4")]
+    pub ino: ::core::option::Option<u64>,
+    #[prost(string, optional, tag = "5")]
+    pub file_type: ::core::option::Option<::prost::alloc::string::String>,
+    #[prost(uint64, optional, tag = "6")]
+    pub expect: ::core::option::Option<u64>,
+    #[prost(uint32, required, tag = "7")]
+    pub owner: u32,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct MakeNode {
+    #[prost(message, required, tag = "1")]
+    pub frequest: FRequest,
+    #[prost(string, required, tag = "2")]
+    pub parent_attr: ::prost::alloc::string::String,
+    #[prost(uint32, required, tag = "3")]
+    pub mode: u32,
+    #[prost(int32, required, tag = "4")]
+    pub flags: i32,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Watch {
+    #[prost(string, required, tag = "1")]
+    pub client: ::prost::alloc::string::String,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Invalidation {
+    #[prost(string, optional, tag = "1")]
+    pub namespace: ::core::option::Option<::prost::alloc::string::String>,
+    #[prost(uint64, required, tag = "2")]
+    pub ino: u64,
+    #[prost(string, optional, tag = "3")]
+    pub name: ::core::option::Option<::prost::alloc::string::String>,
+}
 #[doc = r" Generated client implementations."]
 pub mod disfuser_client {
     #![allow(unused_variables, dead_code, missing_docs, clippy::let_unit_value)]
@@ -930,6 +1040,149 @@ pub mod disfuser_client {
             let path = http::uri::PathAndQuery::from_static("/disfuser.disfuser/clock");
             self.inner.unary(request.into_request(), path, codec).await
         }
+        pub async fn fence(
+            &mut self,
+            request: impl tonic::IntoRequest<super::MembershipView>,
+        ) -> Result<tonic::Response<super::MembershipView>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/disfuser.disfuser/fence");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn digest(
+            &mut self,
+            request: impl tonic::IntoRequest<super::DigestRange>,
+        ) -> Result<tonic::Response<super::Digest>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/disfuser.disfuser/digest");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn get_nodes(
+            &mut self,
+            request: impl tonic::IntoRequest<super::Inodes>,
+        ) -> Result<tonic::Response<super::NodeCopies>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/disfuser.disfuser/getNodes");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn restore_nodes(
+            &mut self,
+            request: impl tonic::IntoRequest<super::NodeCopies>,
+        ) -> Result<tonic::Response<super::Bool>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/disfuser.disfuser/restoreNodes");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn namespaces(
+            &mut self,
+            request: impl tonic::IntoRequest<super::Pattern>,
+        ) -> Result<tonic::Response<super::StringList>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/disfuser.disfuser/namespaces");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn get_entry(
+            &mut self,
+            request: impl tonic::IntoRequest<super::LookUp>,
+        ) -> Result<tonic::Response<super::Entry>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/disfuser.disfuser/getEntry");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn set_entry(
+            &mut self,
+            request: impl tonic::IntoRequest<super::SetEntry>,
+        ) -> Result<tonic::Response<super::UnlinkReply>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/disfuser.disfuser/setEntry");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn make_node(
+            &mut self,
+            request: impl tonic::IntoRequest<super::MakeNode>,
+        ) -> Result<tonic::Response<super::CreateReply>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/disfuser.disfuser/makeNode");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn drop_link(
+            &mut self,
+            request: impl tonic::IntoRequest<super::Getattr>,
+        ) -> Result<tonic::Response<super::UnlinkReply>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/disfuser.disfuser/dropLink");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn watch(
+            &mut self,
+            request: impl tonic::IntoRequest<super::Watch>,
+        ) -> Result<tonic::Response<tonic::codec::Streaming<super::Invalidation>>, tonic::Status>
+        {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/disfuser.disfuser/watch");
+            self.inner
+                .server_streaming(request.into_request(), path, codec)
+                .await
+        }
     }
 }
 #[doc = r" Generated server implementations."]
@@ -1067,6 +1320,50 @@ pub mod disfuser_server {
             &self,
             request: tonic::Request<super::Clock>,
         ) -> Result<tonic::Response<super::Clock>, tonic::Status>;
+        async fn fence(
+            &self,
+            request: tonic::Request<super::MembershipView>,
+        ) -> Result<tonic::Response<super::MembershipView>, tonic::Status>;
+        async fn digest(
+            &self,
+            request: tonic::Request<super::DigestRange>,
+        ) -> Result<tonic::Response<super::Digest>, tonic::Status>;
+        async fn get_nodes(
+            &self,
+            request: tonic::Request<super::Inodes>,
+        ) -> Result<tonic::Response<super::NodeCopies>, tonic::Status>;
+        async fn restore_nodes(
+            &self,
+            request: tonic::Request<super::NodeCopies>,
+        ) -> Result<tonic::Response<super::Bool>, tonic::Status>;
+        async fn namespaces(
+            &self,
+            request: tonic::Request<super::Pattern>,
+        ) -> Result<tonic::Response<super::StringList>, tonic::Status>;
+        async fn get_entry(
+            &self,
+            request: tonic::Request<super::LookUp>,
+        ) -> Result<tonic::Response<super::Entry>, tonic::Status>;
+        async fn set_entry(
+            &self,
+            request: tonic::Request<super::SetEntry>,
+        ) -> Result<tonic::Response<super::UnlinkReply>, tonic::Status>;
+        async fn make_node(
+            &self,
+            request: tonic::Request<super::MakeNode>,
+        ) -> Result<tonic::Response<super::CreateReply>, tonic::Status>;
+        async fn drop_link(
+            &self,
+            request: tonic::Request<super::Getattr>,
+        ) -> Result<tonic::Response<super::UnlinkReply>, tonic::Status>;
+        #[doc = "Server streaming response type for the watch method."]
+        type watchStream: futures_core::Stream<Item = Result<super::Invalidation, tonic::Status>>
+            + Send
+            + 'static;
+        async fn watch(
+            &self,
+            request: tonic::Request<super::Watch>,
+        ) -> Result<tonic::Response<Self::watchStream>, tonic::Status>;
     }
     #[derive(Debug)]
     pub struct DisfuserServer<T: Disfuser> {
@@ -1973,6 +2270,309 @@ pub mod disfuser_server {
                     };
                     Box::pin(fut)
                 }
+                "/disfuser.disfuser/fence" => {
+                    #[allow(non_camel_case_types)]
+                    struct fenceSvc<T: Disfuser>(pub Arc<T>);
+                    impl<T: Disfuser> tonic::server::UnaryService<super::MembershipView> for fenceSvc<T> {
+                        type Response = super::MembershipView;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::MembershipView>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).fence(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = fenceSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/disfuser.disfuser/digest" => {
+                    #[allow(non_camel_case_types)]
+                    struct digestSvc<T: Disfuser>(pub Arc<T>);
+                    impl<T: Disfuser> tonic::server::UnaryService<super::DigestRange> for digestSvc<T> {
+                        type Response = super::Digest;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::DigestRange>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).digest(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = digestSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/disfuser.disfuser/getNodes" => {
+                    #[allow(non_camel_case_types)]
+                    struct getNodesSvc<T: Disfuser>(pub Arc<T>);
+                    impl<T: Disfuser> tonic::server::UnaryService<super::Inodes> for getNodesSvc<T> {
+                        type Response = super::NodeCopies;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(&mut self, request: tonic::Request<super::Inodes>) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).get_nodes(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = getNodesSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/disfuser.disfuser/restoreNodes" => {
+                    #[allow(non_camel_case_types)]
+                    struct restoreNodesSvc<T: Disfuser>(pub Arc<T>);
+                    impl<T: Disfuser> tonic::server::UnaryService<super::NodeCopies> for restoreNodesSvc<T> {
+                        type Response = super::Bool;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::NodeCopies>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).restore_nodes(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = restoreNodesSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/disfuser.disfuser/namespaces" => {
+                    #[allow(non_camel_case_types)]
+                    struct namespacesSvc<T: Disfuser>(pub Arc<T>);
+                    impl<T: Disfuser> tonic::server::UnaryService<super::Pattern> for namespacesSvc<T> {
+                        type Response = super::StringList;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::Pattern>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).namespaces(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = namespacesSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/disfuser.disfuser/getEntry" => {
+                    #[allow(non_camel_case_types)]
+                    struct getEntrySvc<T: Disfuser>(pub Arc<T>);
+                    impl<T: Disfuser> tonic::server::UnaryService<super::LookUp> for getEntrySvc<T> {
+                        type Response = super::Entry;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(&mut self, request: tonic::Request<super::LookUp>) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).get_entry(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = getEntrySvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/disfuser.disfuser/setEntry" => {
+                    #[allow(non_camel_case_types)]
+                    struct setEntrySvc<T: Disfuser>(pub Arc<T>);
+                    impl<T: Disfuser> tonic::server::UnaryService<super::SetEntry> for setEntrySvc<T> {
+                        type Response = super::UnlinkReply;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::SetEntry>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).set_entry(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = setEntrySvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/disfuser.disfuser/makeNode" => {
+                    #[allow(non_camel_case_types)]
+                    struct makeNodeSvc<T: Disfuser>(pub Arc<T>);
+                    impl<T: Disfuser> tonic::server::UnaryService<super::MakeNode> for makeNodeSvc<T> {
+                        type Response = super::CreateReply;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::MakeNode>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).make_node(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = makeNodeSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/disfuser.disfuser/dropLink" => {
+                    #[allow(non_camel_case_types)]
+                    struct dropLinkSvc<T: Disfuser>(pub Arc<T>);
+                    impl<T: Disfuser> tonic::server::UnaryService<super::Getattr> for dropLinkSvc<T> {
+                        type Response = super::UnlinkReply;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::Getattr>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).drop_link(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = dropLinkSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/disfuser.disfuser/watch" => {
+                    #[allow(non_camel_case_types)]
+                    struct watchSvc<T: Disfuser>(pub Arc<T>);
+                    impl<T: Disfuser> tonic::server::ServerStreamingService<super::Watch> for watchSvc<T> {
+                        type Response = super::Invalidation;
+                        type ResponseStream = T::watchStream;
+                        type Future =
+                            BoxFuture<tonic::Response<Self::ResponseStream>, tonic::Status>;
+                        fn call(&mut self, request: tonic::Request<super::Watch>) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).watch(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = watchSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.server_streaming(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
                 _ => Box::pin(async move {
                     Ok(http::Response::builder()
                         .status(200)
@@ -1,39 +1,57 @@
+use crate::config::valid_namespace;
 use crate::disfuser::disfuser_server::{self, Disfuser};
 use crate::disfuser::{
     self, Access, AccessReply, Create, CreateReply, GetAllNodes, GetAllNodesReply, Getattr,
-    GetattrReply, Getxattr, GetxattrReply, Init, InitReply, Listxattr, ListxattrReply, LookUp,
-    MkDir, MkDirReply, Open, OpenDir, OpenDirReply, OpenReply, Read, ReadDir, ReadDirReply,
-    Release, ReleaseDir, ReleaseDirReply, ReleaseReply, Rename, RenameReply, Reply, Setattr,
-    SetattrReply, Setxattr, SetxattrReply, Unlink, UnlinkReply, Write, WriteAllNodes,
-    WriteAllNodesReply, WriteReply,
+    GetattrReply, Getxattr, GetxattrReply, Init, InitReply, Invalidation, Listxattr,
+    ListxattrReply, LookUp, MkDir, MkDirReply, Open, OpenDir, OpenDirReply, OpenReply, Read,
+    ReadDir, ReadDirReply, Release, ReleaseDir, ReleaseDirReply, ReleaseReply, Rename, RenameReply,
+    Reply, Setattr, SetattrReply, Setxattr, SetxattrReply, Unlink, UnlinkReply, Watch, Write,
+    WriteAllNodes, WriteAllNodesReply, WriteReply,
 };
 use crate::error::SUCCESS;
+use crate::lease::{Leases, HEARTBEAT_INTERVAL, WATCH_BUFFER};
 use crate::simple::InodeAttributes;
 use crate::storage::{ContentList, DataList, FileRequest, InodeList, Storage};
 use async_trait::async_trait;
-use fuser::{BackgroundSession, FileAttr, MountOption, Request, TimeOrNow};
+use fuser::{FileAttr, FileType, TimeOrNow};
 use log::info;
 use std::cmp::min;
-use std::ffi::OsString;
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
 use std::pin::Pin;
+use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, Mutex};
 use tokio::time::Duration;
 use tokio_stream::wrappers::ReceiverStream;
 use tokio_stream::Stream;
 use tokio_stream::StreamExt;
+use tonic::metadata::MetadataMap;
 use tonic::{Response, Status};
 type readStream = Pin<Box<dyn Stream<Item = Result<Reply, Status>> + Send>>;
 type getxattrStream = Pin<Box<dyn Stream<Item = Result<GetxattrReply, Status>> + Send>>;
 type listxattrStream = Pin<Box<dyn Stream<Item = Result<ListxattrReply, Status>> + Send>>;
+type InvalidationStream = Pin<Box<dyn Stream<Item = Result<Invalidation, Status>> + Send>>;
 // type readStream = Pin<Box<dyn Stream<Item = Result<Read, Status>> + Send>>;
 // type lookupStream = Pin<Box<dyn Stream<Item = Result<LookUp, Status>> + Send>>;
 pub const slice_size: usize = 128;
+/// Request metadata carrying the membership epoch the caller acts under.
+pub const EPOCH_METADATA: &str = "x-tritonfile-epoch";
+/// Request metadata naming the namespace whose files a request is about.
+pub const NAMESPACE_METADATA: &str = "x-tritonfile-namespace";
+/// Request metadata naming the client a request comes from, whose leases
+/// it takes and which is not told about its own changes.
+pub const CLIENT_METADATA: &str = "x-tritonfile-client";
 use crate::disfuser::{Clock, Key, KeyValue, StringList, Value};
+use crate::error::EPOCH_MISMATCH;
 use crate::storage;
+use crate::storage::MembershipView;
 #[allow(dead_code)]
 pub struct DisfuserServer {
-    pub filesystem: Box<dyn Storage>,
+    pub filesystem: Arc<dyn Storage>,
+    // The storages of the namespaces requested so far.
+    namespaces: Mutex<HashMap<String, Arc<dyn Storage>>>,
+    leases: Arc<Leases>,
     // pub clock: RwLock<i64>,
 }
 
@@ -117,12 +135,97 @@ fn system_time_from_time(secs: i64, nsecs: u32) -> SystemTime {
         UNIX_EPOCH - Duration::new((-secs) as u64, nsecs)
     }
 }
+/// The client a request comes from, if it says, and the namespace whose
+/// files it is about, "" for the backend's own: whose leases it takes or
+/// breaks, and on what.
+fn lease_holder(metadata: &MetadataMap) -> (Option<String>, String) {
+    let client = metadata
+        .get(CLIENT_METADATA)
+        .and_then(|value| value.to_str().ok())
+        .map(|client| client.to_string());
+    let namespace = metadata
+        .get(NAMESPACE_METADATA)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("")
+        .to_string();
+    (client, namespace)
+}
+
+/// The inode entry `name` of `parent` points at, if there is one.
+async fn entry_ino(
+    filesystem: &Arc<dyn Storage>,
+    request: &FileRequest,
+    parent: u64,
+    name: &OsStr,
+) -> Option<u64> {
+    match filesystem.get_entry(request, parent, name).await {
+        Ok((Some((ino, _)), SUCCESS)) => Some(ino),
+        _ => None,
+    }
+}
+
 #[allow(dead_code)]
 impl DisfuserServer {
     pub fn new(storage: Box<dyn storage::Storage>) -> DisfuserServer {
         DisfuserServer {
-            filesystem: storage,
+            filesystem: storage.into(),
+            namespaces: Mutex::new(HashMap::new()),
+            leases: Arc::new(Leases::new()),
+        }
+    }
+
+    /// The storage holding the files of the namespace a request names, or
+    /// this backend's own file system if it names none.
+    async fn files(&self, metadata: &MetadataMap) -> Result<Arc<dyn Storage>, Status> {
+        let name = match metadata.get(NAMESPACE_METADATA) {
+            Some(value) => value
+                .to_str()
+                .ok()
+                .filter(|name| valid_namespace(name))
+                .ok_or_else(|| Status::invalid_argument("malformed namespace"))?,
+            None => return Ok(self.filesystem.clone()),
+        };
+        let mut namespaces = self.namespaces.lock().await;
+        if let Some(storage) = namespaces.get(name) {
+            return Ok(storage.clone());
+        }
+        let storage: Arc<dyn Storage> = self
+            .filesystem
+            .namespace(name)
+            .await
+            .map_err(|e| Status::unknown(e.to_string()))?
+            .into();
+        namespaces.insert(name.to_string(), storage.clone());
+        Ok(storage)
+    }
+
+    /// Rejects a mutation made under another membership epoch than ours, so
+    /// that a client with a stale view cannot write to a backend that was
+    /// failed over (or one that was partitioned away and missed the new
+    /// epoch). Requests without an epoch, e.g. from the keeper, are let
+    /// through.
+    async fn check_epoch(&self, metadata: &MetadataMap) -> Result<(), Status> {
+        let epoch = match metadata.get(EPOCH_METADATA) {
+            Some(value) => value
+                .to_str()
+                .ok()
+                .and_then(|v| v.parse::<u64>().ok())
+                .ok_or_else(|| Status::invalid_argument("malformed epoch"))?,
+            None => return Ok(()),
+        };
+        let current = self
+            .filesystem
+            .fence(&MembershipView::default())
+            .await
+            .map_err(|e| Status::unknown(e.to_string()))?
+            .epoch;
+        if epoch != current {
+            return Err(Status::failed_precondition(format!(
+                "{}: request has {}, backend has {}",
+                EPOCH_MISMATCH, epoch, current
+            )));
         }
+        Ok(())
     }
 }
 
@@ -132,11 +235,13 @@ impl Disfuser for DisfuserServer {
     type readStream = readStream;
     type getxattrStream = getxattrStream;
     type listxattrStream = listxattrStream;
+    type watchStream = InvalidationStream;
 
     async fn init(
         &self,
         request: tonic::Request<Init>,
     ) -> Result<tonic::Response<InitReply>, tonic::Status> {
+        let filesystem = self.files(request.metadata()).await?;
         let request_inner = request.into_inner();
         let file_request = FileRequest {
             uid: request_inner.frequest.clone().uid,
@@ -144,7 +249,7 @@ impl Disfuser for DisfuserServer {
             pid: request_inner.frequest.clone().pid,
         };
 
-        let result = self.filesystem.init(&file_request).await;
+        let result = filesystem.init(&file_request).await;
 
         // change fileAttr to string
         match result {
@@ -161,6 +266,8 @@ impl Disfuser for DisfuserServer {
         // put unwrapped value into ServerFileSystem read function
         // get read result, stringify it
         // and then stream it and send back to client
+        let filesystem = self.files(request.metadata()).await?;
+        let (client, namespace) = lease_holder(request.metadata());
         let r_inner = request.into_inner();
 
         let request = FileRequest {
@@ -169,8 +276,7 @@ impl Disfuser for DisfuserServer {
             pid: r_inner.frequest.clone().pid,
         };
 
-        let result = self
-            .filesystem
+        let result = filesystem
             .read(
                 &request,
                 r_inner.ino,
@@ -191,14 +297,16 @@ impl Disfuser for DisfuserServer {
                     }];
                 } else {
                     // divide the message into appropriate size, and make a vector
+                    self.leases
+                        .grant(client.as_deref(), &namespace, r_inner.ino);
                     let content = value.0.unwrap();
                     reply = reply_response_iter(content, SUCCESS);
                 }
             }
-            Err(_) => {}
+            Err(_) => return Err(Status::invalid_argument("read failed")),
         };
 
-        let mut stream = Box::pin(tokio_stream::iter(reply).throttle(Duration::from_millis(200)));
+        let mut stream = Box::pin(tokio_stream::iter(reply));
         let (tx, rx) = mpsc::channel(128);
 
         tokio::spawn(async move {
@@ -298,6 +406,9 @@ impl Disfuser for DisfuserServer {
         &self,
         request: tonic::Request<Write>,
     ) -> Result<tonic::Response<WriteReply>, tonic::Status> {
+        self.check_epoch(request.metadata()).await?;
+        let filesystem = self.files(request.metadata()).await?;
+        let (client, namespace) = lease_holder(request.metadata());
         let request_inner = request.into_inner();
         let file_request = FileRequest {
             uid: request_inner.frequest.clone().uid,
@@ -307,8 +418,7 @@ impl Disfuser for DisfuserServer {
         info!("disfuser_write write before");
         info!("{}", request_inner.data);
         let data: Vec<u8> = serde_json::from_str(&request_inner.data).unwrap();
-        let result = self
-            .filesystem
+        let result = filesystem
             .write(
                 &file_request,
                 request_inner.ino,
@@ -327,6 +437,8 @@ impl Disfuser for DisfuserServer {
                 if errcode != SUCCESS {
                     Ok(Response::new(WriteReply { size: 0, errcode }))
                 } else {
+                    self.leases
+                        .invalidate(client.as_deref(), &namespace, request_inner.ino, None);
                     Ok(Response::new(WriteReply {
                         size: value.unwrap(),
                         errcode,
@@ -341,6 +453,8 @@ impl Disfuser for DisfuserServer {
         &self,
         request: tonic::Request<LookUp>,
     ) -> Result<tonic::Response<Reply>, tonic::Status> {
+        let filesystem = self.files(request.metadata()).await?;
+        let (client, namespace) = lease_holder(request.metadata());
         let request_inner = request.into_inner();
         let file_request = FileRequest {
             uid: request_inner.frequest.clone().uid,
@@ -349,13 +463,19 @@ impl Disfuser for DisfuserServer {
         };
         let mut osstring = OsString::new();
         osstring.push(request_inner.name);
-        let result = self
-            .filesystem
+        let result = filesystem
             .lookup(&file_request, request_inner.parent, &osstring.as_os_str())
             .await;
 
         match result {
             Ok((file_attr, errcode)) => {
+                if errcode == SUCCESS || errcode == libc::ENOENT {
+                    self.leases
+                        .grant(client.as_deref(), &namespace, request_inner.parent);
+                }
+                if let Some(attr) = file_attr.filter(|_| errcode == SUCCESS) {
+                    self.leases.grant(client.as_deref(), &namespace, attr.ino);
+                }
                 if errcode != SUCCESS {
                     Ok(Response::new(Reply {
                         message: "".to_string(),
@@ -376,6 +496,9 @@ impl Disfuser for DisfuserServer {
         &self,
         request: tonic::Request<Create>,
     ) -> Result<tonic::Response<CreateReply>, tonic::Status> {
+        self.check_epoch(request.metadata()).await?;
+        let filesystem = self.files(request.metadata()).await?;
+        let (client, namespace) = lease_holder(request.metadata());
         let request_inner = request.into_inner();
         let file_request = FileRequest {
             uid: request_inner.frequest.clone().uid,
@@ -384,8 +507,7 @@ impl Disfuser for DisfuserServer {
         };
         let mut osstring = OsString::new();
         osstring.push(request_inner.name);
-        let result = self
-            .filesystem
+        let result = filesystem
             .create(
                 &file_request,
                 request_inner.parent,
@@ -404,6 +526,12 @@ impl Disfuser for DisfuserServer {
                         errcode: errcode,
                     }));
                 } else {
+                    self.leases.invalidate(
+                        client.as_deref(),
+                        &namespace,
+                        request_inner.parent,
+                        Some(&osstring.to_string_lossy()),
+                    );
                     return Ok(Response::new(CreateReply {
                         file_attr: serde_json::to_string(&res_op.clone().unwrap().0).unwrap(),
                         fh: res_op.clone().unwrap().1,
@@ -419,6 +547,9 @@ impl Disfuser for DisfuserServer {
         &self,
         request: tonic::Request<Unlink>,
     ) -> Result<tonic::Response<UnlinkReply>, tonic::Status> {
+        self.check_epoch(request.metadata()).await?;
+        let filesystem = self.files(request.metadata()).await?;
+        let (client, namespace) = lease_holder(request.metadata());
         let request_inner = request.into_inner();
         let file_request = FileRequest {
             uid: request_inner.frequest.clone().uid,
@@ -427,13 +558,25 @@ impl Disfuser for DisfuserServer {
         };
         let mut osstring = OsString::new();
         osstring.push(request_inner.name);
-        let result = self
-            .filesystem
+        let child = entry_ino(&filesystem, &file_request, request_inner.parent, &osstring).await;
+        let result = filesystem
             .unlink(&file_request, request_inner.parent, &osstring.as_os_str())
             .await;
 
         match result {
-            Ok(value) => Ok(Response::new(UnlinkReply { errcode: value })),
+            Ok(value) => {
+                if value == SUCCESS {
+                    let name = osstring.to_string_lossy();
+                    let parent = request_inner.parent;
+                    let client = client.as_deref();
+                    self.leases
+                        .invalidate(client, &namespace, parent, Some(&name));
+                    if let Some(child) = child {
+                        self.leases.invalidate(client, &namespace, child, None);
+                    }
+                }
+                Ok(Response::new(UnlinkReply { errcode: value }))
+            }
             Err(_) => Err(Status::invalid_argument("unlink failed")),
         }
     }
@@ -442,29 +585,34 @@ impl Disfuser for DisfuserServer {
         &self,
         request: tonic::Request<Getattr>,
     ) -> Result<tonic::Response<GetattrReply>, tonic::Status> {
+        let filesystem = self.files(request.metadata()).await?;
+        let (client, namespace) = lease_holder(request.metadata());
         let request_inner = request.into_inner();
         let file_request = FileRequest {
             uid: request_inner.frequest.clone().uid,
             gid: request_inner.frequest.clone().gid,
             pid: request_inner.frequest.clone().pid,
         };
-        let result = self
-            .filesystem
-            .getattr(&file_request, request_inner.ino)
+        let result = filesystem
+            .getattr_versioned(&file_request, request_inner.ino)
             .await;
 
         // change fileAttr to string
         match result {
-            Ok((file_attr, errcode)) => {
+            Ok((file_attr, errcode, version)) => {
                 if errcode != SUCCESS {
                     return Ok(Response::new(GetattrReply {
                         file_attr: "".to_string(),
                         errcode: errcode,
+                        version: None,
                     }));
                 } else {
+                    self.leases
+                        .grant(client.as_deref(), &namespace, request_inner.ino);
                     return Ok(Response::new(GetattrReply {
                         file_attr: serde_json::to_string(&file_attr.unwrap()).unwrap(),
                         errcode: errcode,
+                        version: Some(version),
                     }));
                 }
             }
@@ -476,14 +624,15 @@ impl Disfuser for DisfuserServer {
         &self,
         request: tonic::Request<Open>,
     ) -> Result<tonic::Response<OpenReply>, tonic::Status> {
+        let filesystem = self.files(request.metadata()).await?;
+        let (client, namespace) = lease_holder(request.metadata());
         let request_inner = request.into_inner();
         let file_request = FileRequest {
             uid: request_inner.frequest.clone().uid,
             gid: request_inner.frequest.clone().gid,
             pid: request_inner.frequest.clone().pid,
         };
-        let result = self
-            .filesystem
+        let result = filesystem
             .open(&file_request, request_inner.ino, request_inner.flags)
             .await;
 
@@ -496,6 +645,14 @@ impl Disfuser for DisfuserServer {
                         errcode: value.1,
                     }));
                 } else {
+                    if request_inner.flags & libc::O_TRUNC != 0 {
+                        self.leases.invalidate(
+                            client.as_deref(),
+                            &namespace,
+                            request_inner.ino,
+                            None,
+                        );
+                    }
                     return Ok(Response::new(OpenReply {
                         fh: value.0.clone().unwrap().0,
                         openflag: value.0.clone().unwrap().1,
@@ -510,14 +667,14 @@ impl Disfuser for DisfuserServer {
         &self,
         request: tonic::Request<Release>,
     ) -> Result<tonic::Response<ReleaseReply>, tonic::Status> {
+        let filesystem = self.files(request.metadata()).await?;
         let request_inner = request.into_inner();
         let file_request = FileRequest {
             uid: request_inner.frequest.clone().uid,
             gid: request_inner.frequest.clone().gid,
             pid: request_inner.frequest.clone().pid,
         };
-        let result = self
-            .filesystem
+        let result = filesystem
             .release(
                 &file_request,
                 request_inner.ino,
@@ -537,6 +694,9 @@ impl Disfuser for DisfuserServer {
         &self,
         request: tonic::Request<tonic::Streaming<Setxattr>>,
     ) -> Result<tonic::Response<SetxattrReply>, tonic::Status> {
+        self.check_epoch(request.metadata()).await?;
+        let filesystem = self.files(request.metadata()).await?;
+        let (client, namespace) = lease_holder(request.metadata());
         let mut in_stream = request.into_inner();
 
         let mut file_request: FileRequest = FileRequest {
@@ -575,8 +735,7 @@ impl Disfuser for DisfuserServer {
         let mut osstring = OsString::new();
         osstring.push(name);
 
-        let result = self
-            .filesystem
+        let result = filesystem
             .setxattr(
                 &file_request,
                 inode,
@@ -588,7 +747,13 @@ impl Disfuser for DisfuserServer {
             .await;
 
         match result {
-            Ok(value) => Ok(Response::new(SetxattrReply { errcode: value })),
+            Ok(value) => {
+                if value == SUCCESS {
+                    self.leases
+                        .invalidate(client.as_deref(), &namespace, inode, None);
+                }
+                Ok(Response::new(SetxattrReply { errcode: value }))
+            }
             Err(_) => Err(Status::invalid_argument("setxattr failed")),
         }
     }
@@ -597,6 +762,7 @@ impl Disfuser for DisfuserServer {
         &self,
         request: tonic::Request<Getxattr>,
     ) -> Result<tonic::Response<Self::getxattrStream>, tonic::Status> {
+        let filesystem = self.files(request.metadata()).await?;
         let r_inner = request.into_inner();
 
         let request = FileRequest {
@@ -608,8 +774,7 @@ impl Disfuser for DisfuserServer {
         let mut osstring = OsString::new();
         osstring.push(r_inner.name);
 
-        let result = self
-            .filesystem
+        let result = filesystem
             .getxattr(&request, r_inner.ino, &osstring.as_os_str(), r_inner.size)
             .await;
         info!("rpc server getxattr, filesystem result: {:?}", result);
@@ -629,10 +794,10 @@ impl Disfuser for DisfuserServer {
                     reply = getxattr_response_iter(content, size, SUCCESS);
                 }
             }
-            Err(_) => {}
+            Err(_) => return Err(Status::invalid_argument("getxattr failed")),
         };
 
-        let mut stream = Box::pin(tokio_stream::iter(reply).throttle(Duration::from_millis(200)));
+        let mut stream = Box::pin(tokio_stream::iter(reply));
         let (tx, rx) = mpsc::channel(128);
 
         tokio::spawn(async move {
@@ -660,6 +825,7 @@ impl Disfuser for DisfuserServer {
         &self,
         request: tonic::Request<Listxattr>,
     ) -> Result<tonic::Response<Self::listxattrStream>, tonic::Status> {
+        let filesystem = self.files(request.metadata()).await?;
         let r_inner = request.into_inner();
 
         let request = FileRequest {
@@ -668,8 +834,7 @@ impl Disfuser for DisfuserServer {
             pid: r_inner.frequest.clone().pid,
         };
 
-        let result = self
-            .filesystem
+        let result = filesystem
             .listxattr(&request, r_inner.ino, r_inner.size)
             .await;
         let mut reply = Vec::new();
@@ -688,10 +853,10 @@ impl Disfuser for DisfuserServer {
                     reply = listxattr_response_iter(content, size, SUCCESS);
                 }
             }
-            Err(_) => {}
+            Err(_) => return Err(Status::invalid_argument("listxattr failed")),
         };
 
-        let mut stream = Box::pin(tokio_stream::iter(reply).throttle(Duration::from_millis(200)));
+        let mut stream = Box::pin(tokio_stream::iter(reply));
         let (tx, rx) = mpsc::channel(128);
 
         tokio::spawn(async move {
@@ -718,14 +883,14 @@ impl Disfuser for DisfuserServer {
         &self,
         request: tonic::Request<Access>,
     ) -> Result<tonic::Response<AccessReply>, tonic::Status> {
+        let filesystem = self.files(request.metadata()).await?;
         let request_inner = request.into_inner();
         let file_request = FileRequest {
             uid: request_inner.frequest.clone().uid,
             gid: request_inner.frequest.clone().gid,
             pid: request_inner.frequest.clone().pid,
         };
-        let result = self
-            .filesystem
+        let result = filesystem
             .access(&file_request, request_inner.ino, request_inner.mask)
             .await;
 
@@ -740,6 +905,9 @@ impl Disfuser for DisfuserServer {
         &self,
         request: tonic::Request<Rename>,
     ) -> Result<tonic::Response<RenameReply>, tonic::Status> {
+        self.check_epoch(request.metadata()).await?;
+        let filesystem = self.files(request.metadata()).await?;
+        let (client, namespace) = lease_holder(request.metadata());
         let request_inner = request.into_inner();
         let file_request = FileRequest {
             uid: request_inner.frequest.clone().uid,
@@ -753,8 +921,10 @@ impl Disfuser for DisfuserServer {
         let mut new_name = OsString::new();
         new_name.push(request_inner.newname);
 
-        let result = self
-            .filesystem
+        let (parent, newparent) = (request_inner.parent, request_inner.newparent);
+        let moved = entry_ino(&filesystem, &file_request, parent, &old_name).await;
+        let replaced = entry_ino(&filesystem, &file_request, newparent, &new_name).await;
+        let result = filesystem
             .rename(
                 &file_request,
                 request_inner.parent,
@@ -767,7 +937,20 @@ impl Disfuser for DisfuserServer {
 
         // change fileAttr to string
         match result {
-            Ok(value) => Ok(Response::new(RenameReply { errcode: value })),
+            Ok(value) => {
+                if value == SUCCESS {
+                    let client = client.as_deref();
+                    let (name, newname) = (old_name.to_string_lossy(), new_name.to_string_lossy());
+                    self.leases
+                        .invalidate(client, &namespace, parent, Some(&name));
+                    self.leases
+                        .invalidate(client, &namespace, newparent, Some(&newname));
+                    for ino in moved.into_iter().chain(replaced) {
+                        self.leases.invalidate(client, &namespace, ino, None);
+                    }
+                }
+                Ok(Response::new(RenameReply { errcode: value }))
+            }
             Err(_) => Err(Status::invalid_argument("rename failed")),
         }
     }
@@ -776,6 +959,9 @@ impl Disfuser for DisfuserServer {
         &self,
         request: tonic::Request<Setattr>,
     ) -> Result<tonic::Response<SetattrReply>, tonic::Status> {
+        self.check_epoch(request.metadata()).await?;
+        let filesystem = self.files(request.metadata()).await?;
+        let (client, namespace) = lease_holder(request.metadata());
         let request_inner = request.into_inner();
         let file_request = FileRequest {
             uid: request_inner.frequest.clone().uid,
@@ -801,8 +987,7 @@ impl Disfuser for DisfuserServer {
 
         let empty_time = Some(system_time_from_time(0, 0));
 
-        let result = self
-            .filesystem
+        let result = filesystem
             .setattr(
                 &file_request,
                 request_inner.ino,
@@ -830,6 +1015,8 @@ impl Disfuser for DisfuserServer {
                         errcode: value.1,
                     }));
                 } else {
+                    self.leases
+                        .invalidate(client.as_deref(), &namespace, request_inner.ino, None);
                     return Ok(Response::new(SetattrReply {
                         file_attr: serde_json::to_string(&value.0.clone().unwrap()).unwrap(),
                         errcode: value.1,
@@ -844,14 +1031,15 @@ impl Disfuser for DisfuserServer {
         &self,
         request: tonic::Request<ReadDir>,
     ) -> Result<tonic::Response<ReadDirReply>, tonic::Status> {
+        let filesystem = self.files(request.metadata()).await?;
+        let (client, namespace) = lease_holder(request.metadata());
         let request_inner = request.into_inner();
         let file_request = FileRequest {
             uid: request_inner.frequest.clone().uid,
             gid: request_inner.frequest.clone().gid,
             pid: request_inner.frequest.clone().pid,
         };
-        let result = self
-            .filesystem
+        let result = filesystem
             .readdir(
                 &file_request,
                 request_inner.ino,
@@ -872,6 +1060,8 @@ impl Disfuser for DisfuserServer {
                         errcode,
                     }))
                 } else {
+                    self.leases
+                        .grant(client.as_deref(), &namespace, request_inner.ino);
                     match value {
                         Some(result) => Ok(Response::new(ReadDirReply {
                             ino: Some(result.0),
@@ -898,9 +1088,9 @@ impl Disfuser for DisfuserServer {
         &self,
         request: tonic::Request<GetAllNodes>,
     ) -> Result<tonic::Response<GetAllNodesReply>, tonic::Status> {
+        let filesystem = self.files(request.metadata()).await?;
         let request_inner = request.into_inner();
-        let result = self
-            .filesystem
+        let result = filesystem
             .get_all_nodes(request_inner.for_addr as usize, request_inner.len as usize)
             .await;
 
@@ -946,6 +1136,8 @@ impl Disfuser for DisfuserServer {
         &self,
         request: tonic::Request<WriteAllNodes>,
     ) -> Result<tonic::Response<WriteAllNodesReply>, tonic::Status> {
+        self.check_epoch(request.metadata()).await?;
+        let filesystem = self.files(request.metadata()).await?;
         let request_inner = request.into_inner();
         let mut inode_vec: Vec<InodeAttributes> = Vec::new();
         for file_attr in request_inner.file_attr {
@@ -961,10 +1153,7 @@ impl Disfuser for DisfuserServer {
         }
         let content_list = ContentList(data_vec);
 
-        let result = self
-            .filesystem
-            .write_all_nodes(inode_list, content_list)
-            .await;
+        let result = filesystem.write_all_nodes(inode_list, content_list).await;
 
         match result {
             Ok(_value) => Ok(Response::new(WriteAllNodesReply { errcode: SUCCESS })),
@@ -976,14 +1165,14 @@ impl Disfuser for DisfuserServer {
         &self,
         request: tonic::Request<OpenDir>,
     ) -> Result<tonic::Response<OpenDirReply>, tonic::Status> {
+        let filesystem = self.files(request.metadata()).await?;
         let request_inner = request.into_inner();
         let file_request = FileRequest {
             uid: request_inner.frequest.clone().uid,
             gid: request_inner.frequest.clone().gid,
             pid: request_inner.frequest.clone().pid,
         };
-        let result = self
-            .filesystem
+        let result = filesystem
             .opendir(&file_request, request_inner.ino, request_inner.flags)
             .await;
 
@@ -1013,6 +1202,9 @@ impl Disfuser for DisfuserServer {
         &self,
         request: tonic::Request<MkDir>,
     ) -> Result<tonic::Response<MkDirReply>, tonic::Status> {
+        self.check_epoch(request.metadata()).await?;
+        let filesystem = self.files(request.metadata()).await?;
+        let (client, namespace) = lease_holder(request.metadata());
         let request_inner = request.into_inner();
         let file_request = FileRequest {
             uid: request_inner.frequest.clone().uid,
@@ -1023,8 +1215,7 @@ impl Disfuser for DisfuserServer {
         let mut name = OsString::new();
         name.push(request_inner.name);
 
-        let result = self
-            .filesystem
+        let result = filesystem
             .mkdir(
                 &file_request,
                 request_inner.parent,
@@ -1043,6 +1234,12 @@ impl Disfuser for DisfuserServer {
                         errcode,
                     }))
                 } else {
+                    self.leases.invalidate(
+                        client.as_deref(),
+                        &namespace,
+                        request_inner.parent,
+                        Some(&name.to_string_lossy()),
+                    );
                     let v = value.unwrap();
                     Ok(Response::new(MkDirReply {
                         fileattr: serde_json::to_string(&v).unwrap(),
@@ -1058,14 +1255,14 @@ impl Disfuser for DisfuserServer {
         &self,
         request: tonic::Request<ReleaseDir>,
     ) -> Result<tonic::Response<ReleaseDirReply>, tonic::Status> {
+        let filesystem = self.files(request.metadata()).await?;
         let request_inner = request.into_inner();
         let file_request = FileRequest {
             uid: request_inner.frequest.clone().uid,
             gid: request_inner.frequest.clone().gid,
             pid: request_inner.frequest.clone().pid,
         };
-        let result = self
-            .filesystem
+        let result = filesystem
             .releasedir(
                 &file_request,
                 request_inner.inode,
@@ -1103,6 +1300,7 @@ impl Disfuser for DisfuserServer {
         &self,
         request: tonic::Request<disfuser::KeyValue>,
     ) -> Result<tonic::Response<disfuser::Bool>, tonic::Status> {
+        self.check_epoch(request.metadata()).await?;
         let req = request.into_inner();
         let key = req.key;
         let value = req.value;
@@ -1176,6 +1374,7 @@ impl Disfuser for DisfuserServer {
         &self,
         request: tonic::Request<disfuser::KeyValue>,
     ) -> Result<tonic::Response<disfuser::Bool>, tonic::Status> {
+        self.check_epoch(request.metadata()).await?;
         let req = request.into_inner();
         let key = req.key;
         let value = req.value;
@@ -1201,6 +1400,7 @@ impl Disfuser for DisfuserServer {
         &self,
         request: tonic::Request<disfuser::KeyValue>,
     ) -> Result<tonic::Response<disfuser::ListRemoveResponse>, tonic::Status> {
+        self.check_epoch(request.metadata()).await?;
         let req = request.into_inner();
         let key = req.key;
         let value = req.value;
@@ -1234,4 +1434,293 @@ impl Disfuser for DisfuserServer {
             Err(e) => Err(Status::unknown(e.to_string())),
         }
     }
+
+    async fn fence(
+        &self,
+        request: tonic::Request<disfuser::MembershipView>,
+    ) -> Result<tonic::Response<disfuser::MembershipView>, tonic::Status> {
+        let req = request.into_inner();
+        let ret = self
+            .filesystem
+            .fence(&MembershipView {
+                epoch: req.epoch,
+                live: req.live,
+                backs: req.backs,
+                config_version: req.config_version.unwrap_or(0),
+            })
+            .await;
+        match ret {
+            Ok(view) => Ok(Response::new(disfuser::MembershipView {
+                epoch: view.epoch,
+                live: view.live,
+                backs: view.backs,
+                config_version: Some(view.config_version),
+            })),
+            Err(e) => Err(Status::unknown(e.to_string())),
+        }
+    }
+
+    async fn digest(
+        &self,
+        request: tonic::Request<disfuser::DigestRange>,
+    ) -> Result<tonic::Response<disfuser::Digest>, tonic::Status> {
+        let filesystem = self.files(request.metadata()).await?;
+        let req = request.into_inner();
+        if req.lo > req.hi {
+            return Err(Status::invalid_argument("empty inode range"));
+        }
+        match filesystem.digest(req.lo, req.hi, req.leaves).await {
+            Ok(digest) => Ok(Response::new(disfuser::Digest {
+                hash: digest.hash,
+                count: digest.count,
+                children: digest.children,
+                inodes: digest
+                    .inodes
+                    .into_iter()
+                    .map(|(inode, hash)| disfuser::InodeDigest { inode, hash })
+                    .collect(),
+            })),
+            Err(e) => Err(Status::unknown(e.to_string())),
+        }
+    }
+
+    async fn get_nodes(
+        &self,
+        request: tonic::Request<disfuser::Inodes>,
+    ) -> Result<tonic::Response<disfuser::NodeCopies>, tonic::Status> {
+        let filesystem = self.files(request.metadata()).await?;
+        let req = request.into_inner();
+        match filesystem.get_nodes(&req.inodes).await {
+            Ok(nodes) => Ok(Response::new(disfuser::NodeCopies {
+                nodes: nodes
+                    .into_iter()
+                    .map(|node| disfuser::NodeCopy {
+                        attrs: serde_json::to_string(&node.attrs).unwrap(),
+                        content: node.content,
+                    })
+                    .collect(),
+                remove: vec![],
+            })),
+            Err(e) => Err(Status::unknown(e.to_string())),
+        }
+    }
+
+    async fn restore_nodes(
+        &self,
+        request: tonic::Request<disfuser::NodeCopies>,
+    ) -> Result<tonic::Response<disfuser::Bool>, tonic::Status> {
+        self.check_epoch(request.metadata()).await?;
+        let filesystem = self.files(request.metadata()).await?;
+        let (_, namespace) = lease_holder(request.metadata());
+        let req = request.into_inner();
+        let mut nodes = vec![];
+        for node in req.nodes {
+            let attrs = serde_json::from_str::<InodeAttributes>(&node.attrs)
+                .map_err(|e| Status::invalid_argument(e.to_string()))?;
+            nodes.push(storage::NodeCopy {
+                attrs,
+                content: node.content,
+            });
+        }
+        let changed: Vec<u64> = nodes
+            .iter()
+            .map(|node| node.attrs.inode)
+            .chain(req.remove.iter().copied())
+            .collect();
+        match filesystem.restore_nodes(nodes, &req.remove).await {
+            Ok(()) => {
+                for ino in changed {
+                    self.leases.invalidate(None, &namespace, ino, None);
+                }
+                Ok(Response::new(disfuser::Bool { value: true }))
+            }
+            Err(e) => Err(Status::unknown(e.to_string())),
+        }
+    }
+
+    async fn get_entry(
+        &self,
+        request: tonic::Request<LookUp>,
+    ) -> Result<tonic::Response<disfuser::Entry>, tonic::Status> {
+        let filesystem = self.files(request.metadata()).await?;
+        let (client, namespace) = lease_holder(request.metadata());
+        let req = request.into_inner();
+        let file_request = FileRequest {
+            uid: req.frequest.uid,
+            gid: req.frequest.gid,
+            pid: req.frequest.pid,
+        };
+        let name = OsString::from(req.name);
+        match filesystem.get_entry(&file_request, req.parent, &name).await {
+            Ok((entry, errcode)) => {
+                if errcode == SUCCESS || errcode == libc::ENOENT {
+                    self.leases.grant(client.as_deref(), &namespace, req.parent);
+                }
+                Ok(Response::new(disfuser::Entry {
+                    ino: entry.map(|(ino, _)| ino),
+                    file_type: entry.map(|(_, kind)| serde_json::to_string(&kind).unwrap()),
+                    errcode,
+                }))
+            }
+            Err(e) => Err(Status::unknown(e.to_string())),
+        }
+    }
+
+    async fn set_entry(
+        &self,
+        request: tonic::Request<disfuser::SetEntry>,
+    ) -> Result<tonic::Response<UnlinkReply>, tonic::Status> {
+        self.check_epoch(request.metadata()).await?;
+        let filesystem = self.files(request.metadata()).await?;
+        let (client, namespace) = lease_holder(request.metadata());
+        let req = request.into_inner();
+        let file_request = FileRequest {
+            uid: req.frequest.uid,
+            gid: req.frequest.gid,
+            pid: req.frequest.pid,
+        };
+        let entry = match (req.ino, req.file_type) {
+            (Some(ino), Some(kind)) => Some((
+                ino,
+                serde_json::from_str::<FileType>(&kind)
+                    .map_err(|e| Status::invalid_argument(e.to_string()))?,
+            )),
+            (None, None) => None,
+            _ => return Err(Status::invalid_argument("entry without a kind")),
+        };
+        let name = OsString::from(req.name);
+        match filesystem
+            .set_entry(
+                &file_request,
+                req.parent,
+                &name,
+                entry,
+                req.expect,
+                req.owner,
+            )
+            .await
+        {
+            Ok(errcode) => {
+                if errcode == SUCCESS {
+                    self.leases.invalidate(
+                        client.as_deref(),
+                        &namespace,
+                        req.parent,
+                        Some(&name.to_string_lossy()),
+                    );
+                }
+                Ok(Response::new(UnlinkReply { errcode }))
+            }
+            Err(e) => Err(Status::unknown(e.to_string())),
+        }
+    }
+
+    async fn make_node(
+        &self,
+        request: tonic::Request<disfuser::MakeNode>,
+    ) -> Result<tonic::Response<CreateReply>, tonic::Status> {
+        self.check_epoch(request.metadata()).await?;
+        let filesystem = self.files(request.metadata()).await?;
+        let req = request.into_inner();
+        let file_request = FileRequest {
+            uid: req.frequest.uid,
+            gid: req.frequest.gid,
+            pid: req.frequest.pid,
+        };
+        let parent = serde_json::from_str::<FileAttr>(&req.parent_attr)
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        match filesystem
+            .make_node(&file_request, &parent, req.mode, req.flags)
+            .await
+        {
+            Ok((Some((attr, fh)), errcode)) => Ok(Response::new(CreateReply {
+                file_attr: serde_json::to_string(&attr).unwrap(),
+                fh,
+                errcode,
+            })),
+            Ok((None, errcode)) => Ok(Response::new(CreateReply {
+                file_attr: "".to_string(),
+                fh: 0,
+                errcode,
+            })),
+            Err(e) => Err(Status::unknown(e.to_string())),
+        }
+    }
+
+    async fn drop_link(
+        &self,
+        request: tonic::Request<Getattr>,
+    ) -> Result<tonic::Response<UnlinkReply>, tonic::Status> {
+        self.check_epoch(request.metadata()).await?;
+        let filesystem = self.files(request.metadata()).await?;
+        let (client, namespace) = lease_holder(request.metadata());
+        let req = request.into_inner();
+        let file_request = FileRequest {
+            uid: req.frequest.uid,
+            gid: req.frequest.gid,
+            pid: req.frequest.pid,
+        };
+        match filesystem.drop_link(&file_request, req.ino).await {
+            Ok(errcode) => {
+                if errcode == SUCCESS {
+                    self.leases
+                        .invalidate(client.as_deref(), &namespace, req.ino, None);
+                }
+                Ok(Response::new(UnlinkReply { errcode }))
+            }
+            Err(e) => Err(Status::unknown(e.to_string())),
+        }
+    }
+
+    async fn namespaces(
+        &self,
+        request: tonic::Request<disfuser::Pattern>,
+    ) -> Result<tonic::Response<disfuser::StringList>, tonic::Status> {
+        let req = request.into_inner();
+        let ret = self
+            .filesystem
+            .namespaces(&storage::Pattern {
+                prefix: req.prefix,
+                suffix: req.suffix,
+            })
+            .await;
+        match ret {
+            Ok(list) => Ok(Response::new(disfuser::StringList { list: list.0 })),
+            Err(e) => Err(Status::unknown(e.to_string())),
+        }
+    }
+
+    async fn watch(
+        &self,
+        request: tonic::Request<Watch>,
+    ) -> Result<tonic::Response<Self::watchStream>, tonic::Status> {
+        let client = request.into_inner().client;
+        let (stream, mut invalidations) = self.leases.watch(&client);
+        let leases = self.leases.clone();
+        let (tx, rx) = mpsc::channel(WATCH_BUFFER);
+        tokio::spawn(async move {
+            loop {
+                let next = tokio::time::timeout(HEARTBEAT_INTERVAL, invalidations.recv()).await;
+                let invalidation = match next {
+                    Ok(Some(invalidation)) => invalidation,
+                    // Dropped for falling behind, or replaced by a newer
+                    // stream of the same client.
+                    Ok(None) => break,
+                    Err(_) => Invalidation {
+                        namespace: None,
+                        ino: 0,
+                        name: None,
+                    },
+                };
+                if tx.send(Ok(invalidation)).await.is_err() {
+                    break;
+                }
+            }
+            leases.close(&client, stream);
+            info!("watch of {} closed", client);
+        });
+        Ok(Response::new(
+            Box::pin(ReceiverStream::new(rx)) as Self::watchStream
+        ))
+    }
 }
@@ -13,6 +13,15 @@ pub enum TritonFileError {
     /// catch-all error for other issues
     Unknown(String),
     UserInterfaceError(c_int),
+    /// an operation did not finish before its deadline
+    DeadlineExceeded(String),
+    /// an operation kept failing with retryable errors until its retry
+    /// budget was used up
+    RetriesExhausted(String),
+    /// no live backend could serve the operation
+    NoLiveStore,
+    /// a keeper's leader lease of the given epoch expired or was taken over
+    NotLeader(u64),
 }
 
 pub const SUCCESS: c_int = -1;
@@ -26,6 +35,10 @@ impl Display for TritonFileError {
             TritonFileError::RpcError(x) => format!("rpc error: {}", x),
             TritonFileError::FilesTooMany => "too many files".to_string(),
             TritonFileError::Unknown(x) => format!("unknown error: {}", x),
+            TritonFileError::DeadlineExceeded(x) => format!("deadline exceeded: {}", x),
+            TritonFileError::RetriesExhausted(x) => format!("retries exhausted: {}", x),
+            TritonFileError::NoLiveStore => "can't find any live store".to_string(),
+            TritonFileError::NotLeader(x) => format!("no longer leader of epoch {}", x),
             x => format!("{:?}", x),
         };
         write!(f, "{}", x)
@@ -54,3 +67,70 @@ impl From<Box<dyn Error>> for TritonFileError {
         TritonFileError::Unknown(x.to_string())
     }
 }
+
+/// Prefix of the `failed_precondition` message a backend answers with when a
+/// mutation carries a membership epoch other than its own.
+pub const EPOCH_MISMATCH: &str = "epoch mismatch";
+
+/// Whether `err` is a backend rejecting a mutation made under another
+/// membership epoch than its own.
+pub fn is_epoch_mismatch(err: &(dyn Error + Send + Sync + 'static)) -> bool {
+    match err.downcast_ref::<tonic::Status>() {
+        Some(status) => {
+            status.code() == tonic::Code::FailedPrecondition
+                && status.message().starts_with(EPOCH_MISMATCH)
+        }
+        None => false,
+    }
+}
+
+/// Whether retrying an operation that failed with `err` may succeed. Transport
+/// failures, the transient gRPC codes and epoch mismatches (which go away once
+/// the caller learned the current membership) are retryable; anything else (bad
+/// arguments, serialization errors, a backend failing or panicking, ...) would
+/// fail the same way again. An `Unknown` status is only retryable if the
+/// client's transport raised it, in which case it wraps the transport error:
+/// backends answer with `Unknown` for failures of their own.
+pub fn is_retryable(err: &(dyn Error + Send + Sync + 'static)) -> bool {
+    if is_epoch_mismatch(err) {
+        return true;
+    }
+    if let Some(status) = err.downcast_ref::<tonic::Status>() {
+        return match status.code() {
+            tonic::Code::Unavailable
+            | tonic::Code::DeadlineExceeded
+            | tonic::Code::Cancelled
+            | tonic::Code::Aborted
+            | tonic::Code::ResourceExhausted => true,
+            tonic::Code::Unknown => status.source().is_some(),
+            _ => false,
+        };
+    }
+    if err.downcast_ref::<tonic::transport::Error>().is_some() {
+        return true;
+    }
+    matches!(
+        err.downcast_ref::<TritonFileError>(),
+        Some(TritonFileError::RpcError(_))
+            | Some(TritonFileError::NoLiveStore)
+            | Some(TritonFileError::DeadlineExceeded(_))
+    )
+}
+
+/// The errno a FUSE reply should carry for an operation that failed with
+/// `err`.
+pub fn errno(err: &(dyn Error + Send + Sync + 'static)) -> c_int {
+    if let Some(status) = err.downcast_ref::<tonic::Status>() {
+        return match status.code() {
+            tonic::Code::DeadlineExceeded => libc::ETIMEDOUT,
+            tonic::Code::Unavailable => libc::ENETDOWN,
+            _ => libc::EIO,
+        };
+    }
+    match err.downcast_ref::<TritonFileError>() {
+        Some(TritonFileError::DeadlineExceeded(_)) => libc::ETIMEDOUT,
+        Some(TritonFileError::NoLiveStore) => libc::ENETDOWN,
+        Some(TritonFileError::UserInterfaceError(code)) => *code,
+        _ => libc::EIO,
+    }
+}
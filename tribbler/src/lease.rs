@@ -0,0 +1,204 @@
+//! Read leases a backend hands out to the clients caching its inodes.
+//!
+//! A client that keeps a `watch` stream open gets a lease on every inode it
+//! reads through the backend, and on every directory it looks names up in.
+//! While the lease lasts, a change another client makes to that inode, or
+//! to an entry of that directory, is pushed down the stream, so the client
+//! can drop what it cached. Clients without a stream get no leases and are
+//! left to their TTLs.
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tokio::sync::mpsc;
+
+use crate::disfuser::Invalidation;
+
+/// How long a lease lasts after the read that granted it. Clients must not
+/// cache anything for longer.
+pub const LEASE_DURATION: Duration = Duration::from_secs(60);
+/// A watch stream is sent a heartbeat, an [Invalidation] of inode 0, at
+/// least this often, so that a client can tell a quiet backend from a dead
+/// one.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+/// How many invalidations may wait for a client before it is considered
+/// too slow and its stream is closed.
+pub const WATCH_BUFFER: usize = 1024;
+
+// Holders of the leases on each inode of each namespace, and when their
+// leases run out.
+type Held = HashMap<(String, u64), HashMap<String, Instant>>;
+
+#[derive(Default)]
+pub struct Leases {
+    // The backend's own files are namespace "".
+    held: Mutex<Held>,
+    // When the leases that ran out were last dropped.
+    swept: Mutex<Option<Instant>>,
+    // Streams of the clients watching this backend, by the number they
+    // were opened as.
+    watchers: Mutex<HashMap<String, (u64, mpsc::Sender<Invalidation>)>>,
+    opened: AtomicU64,
+}
+
+impl Leases {
+    pub fn new() -> Leases {
+        Leases::default()
+    }
+
+    /// Opens the stream the invalidations for `client` are sent down,
+    /// replacing the one it had. Returns the number of the stream, to close
+    /// it with, and the stream. The stream ends if the client falls behind.
+    pub fn watch(&self, client: &str) -> (u64, mpsc::Receiver<Invalidation>) {
+        let (sender, receiver) = mpsc::channel(WATCH_BUFFER);
+        let stream = self.opened.fetch_add(1, Ordering::SeqCst);
+        self.watchers
+            .lock()
+            .unwrap()
+            .insert(client.to_string(), (stream, sender));
+        (stream, receiver)
+    }
+
+    /// Grants `client` a lease on inode `ino` of `namespace`, if it watches
+    /// this backend.
+    pub fn grant(&self, client: Option<&str>, namespace: &str, ino: u64) {
+        let client = match client {
+            Some(client) if self.watchers.lock().unwrap().contains_key(client) => client,
+            _ => return,
+        };
+        let now = Instant::now();
+        let mut held = self.held.lock().unwrap();
+        self.sweep(&mut held, now);
+        held.entry((namespace.to_string(), ino))
+            .or_default()
+            .insert(client.to_string(), now + LEASE_DURATION);
+    }
+
+    // Drops the leases that ran out by `now`, at most once per
+    // [LEASE_DURATION], so that inodes nobody reads any more do not stay
+    // in `held` until they change.
+    fn sweep(&self, held: &mut Held, now: Instant) {
+        let mut swept = self.swept.lock().unwrap();
+        if swept.is_some_and(|at| now.saturating_duration_since(at) < LEASE_DURATION) {
+            return;
+        }
+        *swept = Some(now);
+        for holders in held.values_mut() {
+            holders.retain(|_, expires| *expires > now);
+        }
+        held.retain(|_, holders| !holders.is_empty());
+    }
+
+    /// Tells the holders of a lease on inode `ino` of `namespace`, other
+    /// than `writer`, that the inode changed, or that its entry `name` did
+    /// if there is one.
+    pub fn invalidate(&self, writer: Option<&str>, namespace: &str, ino: u64, name: Option<&str>) {
+        let holders: Vec<String> = {
+            let mut held = self.held.lock().unwrap();
+            let key = (namespace.to_string(), ino);
+            let now = Instant::now();
+            let holders = match held.get_mut(&key) {
+                Some(holders) => holders,
+                None => return,
+            };
+            holders.retain(|_, expires| *expires > now);
+            let notified = holders
+                .keys()
+                .filter(|client| Some(client.as_str()) != writer)
+                .cloned()
+                .collect();
+            if holders.is_empty() {
+                held.remove(&key);
+            }
+            notified
+        };
+        let invalidation = Invalidation {
+            namespace: Some(namespace.to_string()).filter(|namespace| !namespace.is_empty()),
+            ino,
+            name: name.map(|name| name.to_string()),
+        };
+        let mut watchers = self.watchers.lock().unwrap();
+        for client in holders {
+            let sent = match watchers.get(&client) {
+                Some((_, sender)) => sender.try_send(invalidation.clone()).is_ok(),
+                None => continue,
+            };
+            // A client that falls behind, or went away, loses its stream,
+            // and with it every lease: it has to start over with an empty
+            // cache.
+            if !sent {
+                watchers.remove(&client);
+                self.forget(&client);
+            }
+        }
+    }
+
+    /// Drops stream `stream` of `client` and the leases it held, unless the
+    /// client has opened a newer stream since.
+    pub fn close(&self, client: &str, stream: u64) {
+        let mut watchers = self.watchers.lock().unwrap();
+        match watchers.get(client) {
+            Some((opened, _)) if *opened == stream => {
+                watchers.remove(client);
+            }
+            _ => return,
+        }
+        self.forget(client);
+    }
+
+    fn forget(&self, client: &str) {
+        let mut held = self.held.lock().unwrap();
+        for holders in held.values_mut() {
+            holders.remove(client);
+        }
+        held.retain(|_, holders| !holders.is_empty());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Instant;
+
+    use super::{Leases, LEASE_DURATION};
+
+    #[test]
+    fn test_only_other_watching_holders_are_told() {
+        let leases = Leases::new();
+        let (_, mut a) = leases.watch("a");
+        let (_, mut b) = leases.watch("b");
+        leases.grant(Some("a"), "", 5);
+        leases.grant(Some("b"), "", 5);
+        leases.grant(Some("c"), "", 5);
+        leases.grant(Some("a"), "team", 6);
+
+        leases.invalidate(Some("a"), "", 5, Some("x"));
+        let invalidation = b.try_recv().unwrap();
+        assert_eq!(None, invalidation.namespace);
+        assert_eq!(5, invalidation.ino);
+        assert_eq!(Some("x".to_string()), invalidation.name);
+        assert!(a.try_recv().is_err());
+
+        leases.invalidate(None, "team", 6, None);
+        let invalidation = a.try_recv().unwrap();
+        assert_eq!(Some("team".to_string()), invalidation.namespace);
+        assert!(b.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_expired_leases_are_swept() {
+        let leases = Leases::new();
+        let _watch = leases.watch("a");
+        for ino in 1..=100 {
+            leases.grant(Some("a"), "", ino);
+        }
+        assert_eq!(100, leases.held.lock().unwrap().len());
+
+        // within a lease of the last sweep, nothing is swept
+        let mut held = leases.held.lock().unwrap();
+        leases.sweep(&mut held, Instant::now() + LEASE_DURATION / 2);
+        assert_eq!(100, held.len());
+        leases.sweep(&mut held, Instant::now() + LEASE_DURATION * 2);
+        assert!(held.is_empty());
+    }
+}
@@ -15,9 +15,15 @@
 pub mod addr;
 pub mod colon;
 pub mod config;
+/// protobuf-generated stubs of the backend file service, whose rpcs are
+/// named in lower camel case
+#[allow(non_camel_case_types)]
 pub mod disfuser;
 pub mod disfuser_server;
 pub mod error;
+/// protobuf-generated stubs of the keeper membership service
+pub mod keeper;
+pub mod lease;
 /// protobuf-generated RPC stubs and message structs
 pub mod rpc;
 pub mod simple;
@@ -67,6 +67,16 @@ impl From<FileKind> for fuser::FileType {
     }
 }
 
+impl From<fuser::FileType> for FileKind {
+    fn from(kind: fuser::FileType) -> Self {
+        match kind {
+            fuser::FileType::Directory => FileKind::Directory,
+            fuser::FileType::Symlink => FileKind::Symlink,
+            _ => FileKind::File,
+        }
+    }
+}
+
 #[derive(Debug)]
 enum XattrNamespace {
     Security,
@@ -217,6 +227,10 @@ pub struct InodeAttributes {
     pub uid: u32,
     pub gid: u32,
     pub xattrs: BTreeMap<Vec<u8>, Vec<u8>>,
+    // Bumped on every write of the inode, so replicas can tell which of them
+    // is behind
+    #[serde(default)]
+    pub version: u64,
 }
 
 impl From<InodeAttributes> for fuser::FileAttr {
@@ -307,11 +321,27 @@ impl SimpleFS {
         current_inode + 1
     }
 
+    // Makes inodes be allocated after `base` from now on, unless some have
+    // been allocated already
+    pub fn start_inodes_at(&self, base: Inode) {
+        let path = Path::new(&self.data_dir).join("superblock");
+        if path.exists() {
+            return;
+        }
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        bincode::serialize_into(file, &base).unwrap();
+    }
+
     pub fn allocate_next_file_handle(&self, read: bool, write: bool) -> u64 {
         info!("allocate_next_inode, next fh {:#?}", self.next_file_handle);
         let mut fh = self.next_file_handle.fetch_add(1, Ordering::SeqCst);
         // Assert that we haven't run out of file handles
-        assert!(fh < FILE_HANDLE_WRITE_BIT && fh < FILE_HANDLE_READ_BIT);
+        assert_eq!(0, fh & (FILE_HANDLE_WRITE_BIT | FILE_HANDLE_READ_BIT));
         if read {
             fh |= FILE_HANDLE_READ_BIT;
         }
@@ -372,6 +402,25 @@ impl SimpleFS {
     }
 
     pub fn write_inode(&self, inode: &InodeAttributes) {
+        let path = Path::new(&self.data_dir)
+            .join("inodes")
+            .join(inode.inode.to_string());
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        let versioned = InodeAttributes {
+            version: inode.version + 1,
+            ..inode.clone()
+        };
+        bincode::serialize_into(file, &versioned).unwrap();
+    }
+
+    // Writes an inode copied from another replica as is, keeping its number
+    // and version, and makes sure the number is never allocated again
+    pub fn restore_inode(&self, inode: &InodeAttributes) {
         let path = Path::new(&self.data_dir)
             .join("inodes")
             .join(inode.inode.to_string());
@@ -382,6 +431,22 @@ impl SimpleFS {
             .open(&path)
             .unwrap();
         bincode::serialize_into(file, inode).unwrap();
+
+        let path = Path::new(&self.data_dir).join("superblock");
+        let current_inode: Inode = if let Ok(file) = File::open(&path) {
+            bincode::deserialize_from(file).unwrap()
+        } else {
+            fuser::FUSE_ROOT_ID
+        };
+        if inode.inode > current_inode {
+            let file = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&path)
+                .unwrap();
+            bincode::serialize_into(file, &inode.inode).unwrap();
+        }
     }
 
     // Check whether a file should be removed from storage. Should be called after decrementing
@@ -508,6 +573,7 @@ impl Filesystem for SimpleFS {
                 uid: 0,
                 gid: 0,
                 xattrs: Default::default(),
+                version: 0,
             };
             self.write_inode(&root);
             let mut entries = BTreeMap::new();
@@ -813,6 +879,7 @@ impl Filesystem for SimpleFS {
             uid: req.uid(),
             gid: creation_gid(&parent_attrs, req.gid()),
             xattrs: Default::default(),
+            version: 0,
         };
         self.write_inode(&attrs);
         File::create(self.content_path(inode)).unwrap();
@@ -891,6 +958,7 @@ impl Filesystem for SimpleFS {
             uid: req.uid(),
             gid: creation_gid(&parent_attrs, req.gid()),
             xattrs: Default::default(),
+            version: 0,
         };
         self.write_inode(&attrs);
 
@@ -1070,6 +1138,7 @@ impl Filesystem for SimpleFS {
             uid: req.uid(),
             gid: creation_gid(&parent_attrs, req.gid()),
             xattrs: Default::default(),
+            version: 0,
         };
 
         if let Err(error_code) = self.insert_link(req, parent, name, inode, FileKind::Symlink) {
@@ -1766,6 +1835,7 @@ impl Filesystem for SimpleFS {
             uid: req.uid(),
             gid: creation_gid(&parent_attrs, req.gid()),
             xattrs: Default::default(),
+            version: 0,
         };
         self.write_inode(&attrs);
         File::create(self.content_path(inode)).unwrap();
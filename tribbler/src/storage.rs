@@ -13,9 +13,9 @@ use fuser::TimeOrNow;
 use fuser::TimeOrNow::Now;
 use fuser::FUSE_ROOT_ID;
 use libc::c_int;
-use libc::NOTE_USECONDS;
 use log::error;
 use log::info;
+use serde::{Deserialize, Serialize};
 use std::cmp::min;
 use std::collections::hash_map::DefaultHasher;
 use std::collections::BTreeMap;
@@ -30,11 +30,15 @@ use std::io::Write;
 use std::os::unix::prelude::FileExt;
 use std::os::unix::prelude::OsStrExt;
 use std::path::Path;
+use std::sync::Arc;
+use std::sync::{Mutex, MutexGuard};
 use std::time::SystemTime;
 use std::{collections::HashMap, ffi::OsStr, fs, io::ErrorKind, sync::RwLock};
 use tokio::io::BufStream;
+use tokio::sync::Semaphore;
 use tokio_stream::{Stream, StreamExt};
 
+use crate::config::{parse_shard_name, valid_namespace};
 use crate::disfuser::Data;
 use crate::error;
 use crate::error::TritonFileError;
@@ -82,6 +86,57 @@ impl KeyValue {
     }
 }
 
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+/// A membership view published by the keeper: the backends it considers
+/// alive, stamped with an epoch that grows every time the view changes.
+pub struct MembershipView {
+    pub epoch: u64,
+    pub live: Vec<String>,
+    /// All backends of the cluster config the view belongs to, empty if it
+    /// was never changed from the one every process started with.
+    #[serde(default)]
+    pub backs: Vec<String>,
+    #[serde(default)]
+    pub config_version: u64,
+}
+
+/// Number of subranges a [Digest] splits its inode range into.
+pub const DIGEST_FANOUT: u64 = 16;
+/// Ranges holding at most this many inodes are digested inode by inode.
+pub const DIGEST_LEAF_SIZE: usize = 64;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+/// A node of the hash tree over the inodes of a backend whose numbers fall
+/// in a range. Ranges with more than [DIGEST_LEAF_SIZE] inodes carry the
+/// hashes of their subranges (see [split_range]), smaller ones the
+/// `(inode, hash)` of every inode in them.
+pub struct Digest {
+    pub hash: u64,
+    pub count: u64,
+    pub children: Vec<u64>,
+    pub inodes: Vec<(u64, u64)>,
+}
+
+#[derive(Debug, Clone)]
+/// An inode together with its contents: file data, or the serialized
+/// entries of a directory.
+pub struct NodeCopy {
+    pub attrs: InodeAttributes,
+    pub content: Vec<u8>,
+}
+
+/// Inodes of shard `n` of a namespace are numbered from
+/// `n << SHARD_INODE_BITS` on, so the shard keeping an inode can be told
+/// from its number.
+pub const SHARD_INODE_BITS: u32 = 48;
+/// Most shards a namespace can be split into.
+pub const MAX_SHARDS: usize = 1 << (64 - SHARD_INODE_BITS);
+
+/// The shard of its namespace inode `ino` is kept on.
+pub fn inode_shard(ino: u64) -> usize {
+    (ino >> SHARD_INODE_BITS) as usize
+}
+
 #[derive(Debug, Clone, Default)]
 /// A type which represents a pattern that can be used to match on a String.
 pub struct Pattern {
@@ -117,6 +172,88 @@ pub fn hash_name_to_idx(name: &str, len: usize) -> usize {
     hasher.finish() as usize % len
 }
 
+/// Splits `[lo, hi)` into at most [DIGEST_FANOUT] consecutive subranges.
+pub fn split_range(lo: u64, hi: u64) -> Vec<(u64, u64)> {
+    let width = (hi - lo) / DIGEST_FANOUT + 1;
+    let mut ranges = vec![];
+    let mut start = lo;
+    while start < hi {
+        let end = min(start.saturating_add(width), hi);
+        ranges.push((start, end));
+        start = end;
+    }
+    ranges
+}
+
+/// Hash of an inode as replicas must agree on it: its attributes except
+/// times, version and open handles, and its contents block by block.
+pub fn node_hash(attrs: &InodeAttributes, content: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    attrs.inode.hash(&mut hasher);
+    (attrs.kind as u8).hash(&mut hasher);
+    attrs.size.hash(&mut hasher);
+    attrs.mode.hash(&mut hasher);
+    attrs.hardlinks.hash(&mut hasher);
+    attrs.uid.hash(&mut hasher);
+    attrs.gid.hash(&mut hasher);
+    attrs.xattrs.hash(&mut hasher);
+    for block in content.chunks(BLOCK_SIZE as usize) {
+        block.hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+/// The inodes a copy of a directory has entries for; none if the copy is
+/// not a directory.
+pub fn listed_inodes(node: &NodeCopy) -> Vec<u64> {
+    if node.attrs.kind != FileKind::Directory {
+        return vec![];
+    }
+    bincode::deserialize::<BTreeMap<Vec<u8>, (u64, FileKind)>>(&node.content)
+        .map(|entries| entries.values().map(|(inode, _)| *inode).collect())
+        .unwrap_or_default()
+}
+
+// Hash of the range [lo, hi) given the sorted (inode, hash) of its inodes.
+fn range_hash(leaves: &[(u64, u64)], lo: u64, hi: u64) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    if leaves.len() <= DIGEST_LEAF_SIZE {
+        leaves.hash(&mut hasher);
+    } else {
+        for (start, end) in split_range(lo, hi) {
+            range_hash(in_range(leaves, start, end), start, end).hash(&mut hasher);
+        }
+    }
+    hasher.finish()
+}
+
+fn in_range(leaves: &[(u64, u64)], lo: u64, hi: u64) -> &[(u64, u64)] {
+    let start = leaves.partition_point(|(inode, _)| *inode < lo);
+    let end = leaves.partition_point(|(inode, _)| *inode < hi);
+    &leaves[start..end]
+}
+
+/// Builds the [Digest] of `[lo, hi)` from the sorted `(inode, hash)` of
+/// the inodes of a backend. With `list_inodes` set the inodes are listed
+/// even if the range is large.
+pub fn digest_of(hashes: &[(u64, u64)], lo: u64, hi: u64, list_inodes: bool) -> Digest {
+    let nodes = in_range(hashes, lo, hi);
+    let mut digest = Digest {
+        hash: range_hash(nodes, lo, hi),
+        count: nodes.len() as u64,
+        ..Default::default()
+    };
+    if list_inodes || nodes.len() <= DIGEST_LEAF_SIZE {
+        digest.inodes = nodes.to_vec();
+    } else {
+        digest.children = split_range(lo, hi)
+            .into_iter()
+            .map(|(start, end)| range_hash(in_range(nodes, start, end), start, end))
+            .collect();
+    }
+    digest
+}
+
 #[async_trait]
 /// Key-value pair interfaces
 /// Default value for all keys is empty string
@@ -213,6 +350,18 @@ pub trait ServerFileSystem {
         ino: u64,
     ) -> TritonFileResult<(Option<FileAttr>, c_int)>;
 
+    /// Same as [ServerFileSystem::getattr], but also returns the version of
+    /// the inode, which grows with every change to it. Implementations that
+    /// do not track versions report 0.
+    async fn getattr_versioned(
+        &self,
+        req: &FileRequest,
+        ino: u64,
+    ) -> TritonFileResult<(Option<FileAttr>, c_int, u64)> {
+        let (attr, errcode) = self.getattr(req, ino).await?;
+        Ok((attr, errcode, 0))
+    }
+
     async fn open(
         &self,
         _req: &FileRequest,
@@ -331,6 +480,108 @@ pub trait Storage: ServerFileSystem + KeyString + KeyList + Send + Sync {
     /// be unique, no smaller than `at_least`, and strictly larger than the
     /// value returned last time, unless it was [u64::MAX]
     async fn clock(&self, at_least: u64) -> TritonFileResult<u64>;
+
+    /// Installs `view` if its epoch is newer than the one the storage knows,
+    /// and returns the view in effect afterwards. Fencing with the default
+    /// (epoch 0) view only queries it.
+    async fn fence(&self, _view: &MembershipView) -> TritonFileResult<MembershipView> {
+        Err(Box::new(TritonFileError::Unknown(
+            "fencing is not supported".to_string(),
+        )))
+    }
+
+    /// Returns the [Digest] of the inodes numbered in `[lo, hi)`, listing
+    /// them one by one if `leaves` is set.
+    async fn digest(&self, _lo: u64, _hi: u64, _leaves: bool) -> TritonFileResult<Digest> {
+        Err(Box::new(TritonFileError::Unknown(
+            "digests are not supported".to_string(),
+        )))
+    }
+
+    /// Returns copies of the given inodes, skipping the ones that do not
+    /// exist.
+    async fn get_nodes(&self, _inodes: &[u64]) -> TritonFileResult<Vec<NodeCopy>> {
+        Err(Box::new(TritonFileError::Unknown(
+            "copying nodes is not supported".to_string(),
+        )))
+    }
+
+    /// Overwrites inodes with copies taken from another replica, keeping
+    /// their numbers, and deletes the inodes in `remove`.
+    async fn restore_nodes(&self, _nodes: Vec<NodeCopy>, _remove: &[u64]) -> TritonFileResult<()> {
+        Err(Box::new(TritonFileError::Unknown(
+            "restoring nodes is not supported".to_string(),
+        )))
+    }
+
+    /// Returns the inode and kind entry `name` of directory `parent` points
+    /// to. The inode may be kept on another shard of the namespace.
+    async fn get_entry(
+        &self,
+        _req: &FileRequest,
+        _parent: u64,
+        _name: &OsStr,
+    ) -> TritonFileResult<(Option<(u64, FileType)>, c_int)> {
+        Err(Box::new(TritonFileError::Unknown(
+            "entries are not supported".to_string(),
+        )))
+    }
+
+    /// Points entry `name` of directory `parent` at `entry`, or removes it
+    /// if `entry` is [None], provided it points at `expect` now (or is
+    /// absent if `expect` is [None]). `owner` is the uid of the inode the
+    /// entry points at now, checked in sticky directories.
+    async fn set_entry(
+        &self,
+        _req: &FileRequest,
+        _parent: u64,
+        _name: &OsStr,
+        _entry: Option<(u64, FileType)>,
+        _expect: Option<u64>,
+        _owner: u32,
+    ) -> TritonFileResult<c_int> {
+        Err(Box::new(TritonFileError::Unknown(
+            "entries are not supported".to_string(),
+        )))
+    }
+
+    /// Creates a file or directory no directory links to yet, as
+    /// [ServerFileSystem::create] or [ServerFileSystem::mkdir] would in
+    /// directory `parent`, which may be kept on another shard.
+    async fn make_node(
+        &self,
+        _req: &FileRequest,
+        _parent: &FileAttr,
+        _mode: u32,
+        _flags: i32,
+    ) -> TritonFileResult<(Option<(FileAttr, u64)>, c_int)> {
+        Err(Box::new(TritonFileError::Unknown(
+            "entries are not supported".to_string(),
+        )))
+    }
+
+    /// Drops a link to inode `ino` after an entry pointing at it was
+    /// removed, and deletes the inode once no link is left.
+    async fn drop_link(&self, _req: &FileRequest, _ino: u64) -> TritonFileResult<c_int> {
+        Err(Box::new(TritonFileError::Unknown(
+            "entries are not supported".to_string(),
+        )))
+    }
+
+    /// Returns the storage of namespace `name`, whose files are kept apart
+    /// from the files of this storage and of every other namespace.
+    /// Key-value data is not namespaced.
+    async fn namespace(&self, _name: &str) -> TritonFileResult<Box<dyn Storage>> {
+        Err(Box::new(TritonFileError::Unknown(
+            "namespaces are not supported".to_string(),
+        )))
+    }
+
+    /// Lists the namespaces that have files on this storage and whose names
+    /// match the given pattern.
+    async fn namespaces(&self, _p: &Pattern) -> TritonFileResult<List> {
+        Ok(List(vec![]))
+    }
 }
 
 /// This is a toy implementation of a backend storage service.
@@ -342,19 +593,51 @@ pub struct RemoteFileSystem {
     kvs: RwLock<HashMap<String, String>>,
     kv_list: RwLock<HashMap<String, List>>,
     clock: RwLock<u64>,
-    fs: SimpleFS,
+    view: RwLock<MembershipView>,
+    fs: Arc<SimpleFS>,
     label: usize,
+    data_permits: Arc<Semaphore>,
+    meta_permits: Arc<Semaphore>,
+    namespace_permits: Arc<Semaphore>,
+    inode_locks: Arc<Vec<Mutex<()>>>,
+}
+
+/// Directory of a backend's data holding the file systems of namespaces.
+const NAMESPACE_DIR: &str = "namespaces";
+
+/// Maximum number of content reads/writes allowed on the blocking pool at once
+pub const MAX_DATA_DISK_OPS: usize = 16;
+/// Maximum number of inode/metadata operations allowed on the blocking pool at once
+pub const MAX_META_DISK_OPS: usize = 32;
+/// Number of locks the inodes of a backend are striped over
+pub const INODE_LOCK_STRIPES: usize = 64;
+
+/// Which permit pool a blocking disk operation is charged against. Data
+/// operations (file contents) cannot starve metadata operations, and
+/// namespace mutations (create, mkdir, unlink, rename, bulk restore) are
+/// serialized since they read-modify-write directory entries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiskClass {
+    Data,
+    Meta,
+    Namespace,
 }
 
 impl RemoteFileSystem {
     /// Creates a new instance of [MemStorage]
     pub fn new(num: usize) -> RemoteFileSystem {
-        if !fs::metadata(format!("tmp/{}", num)).is_ok() {
+        RemoteFileSystem::in_dir(format!("tmp/{}", num), num)
+    }
+
+    /// Opens the file system kept under `dir`, creating it if needed. `num`
+    /// only labels its log lines.
+    pub fn in_dir(dir: String, num: usize) -> RemoteFileSystem {
+        if fs::metadata(&dir).is_err() {
             info!("try to create metadata file: main_dir",);
-            fs::create_dir_all(format!("tmp/{}", num)).unwrap();
+            fs::create_dir_all(&dir).unwrap();
         }
 
-        let fs = SimpleFS::new(format!("tmp/{}", num), false, true);
+        let fs = SimpleFS::new(dir, false, true);
 
         if !fs::metadata(Path::new(&fs.data_dir).join("inodes")).is_ok() {
             info!("try to create metadata file: inode_dir");
@@ -381,6 +664,7 @@ impl RemoteFileSystem {
                 uid: 0,
                 gid: 0,
                 xattrs: Default::default(),
+                version: 0,
             };
             fs.write_inode(&root);
             let mut entries = BTreeMap::new();
@@ -392,10 +676,82 @@ impl RemoteFileSystem {
             kvs: RwLock::new(HashMap::new()),
             kv_list: RwLock::new(HashMap::new()),
             clock: RwLock::new(0),
-            fs,
+            view: RwLock::new(MembershipView::default()),
+            fs: Arc::new(fs),
             label: num,
+            data_permits: Arc::new(Semaphore::new(MAX_DATA_DISK_OPS)),
+            meta_permits: Arc::new(Semaphore::new(MAX_META_DISK_OPS)),
+            namespace_permits: Arc::new(Semaphore::new(1)),
+            inode_locks: Arc::new((0..INODE_LOCK_STRIPES).map(|_| Mutex::new(())).collect()),
         }
     }
+
+    fn permits(&self, class: DiskClass) -> Arc<Semaphore> {
+        match class {
+            DiskClass::Data => self.data_permits.clone(),
+            DiskClass::Meta => self.meta_permits.clone(),
+            DiskClass::Namespace => self.namespace_permits.clone(),
+        }
+    }
+
+    /// Runs a synchronous [SimpleFS] operation on tokio's blocking pool so
+    /// that disk I/O never stalls the async workers serving RPCs. The caller
+    /// waits for a permit of the given class first, which bounds how many
+    /// blocking threads a burst of requests can tie up.
+    ///
+    /// Namespace operations hold the locks of every inode while they run,
+    /// since they update whichever inodes they come across.
+    async fn run_blocking<T, F>(&self, class: DiskClass, f: F) -> TritonFileResult<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&SimpleFS) -> TritonFileResult<T> + Send + 'static,
+    {
+        self.run_locked(class, None, f).await
+    }
+
+    /// Like [RemoteFileSystem::run_blocking], for an operation that updates
+    /// the attributes of `inode`. It holds the lock of `inode` while it
+    /// runs, so that concurrent read-modify-writes of the attributes, such
+    /// as writes growing the file, don't lose each other's updates.
+    async fn run_blocking_on<T, F>(&self, class: DiskClass, inode: u64, f: F) -> TritonFileResult<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&SimpleFS) -> TritonFileResult<T> + Send + 'static,
+    {
+        self.run_locked(class, Some(inode), f).await
+    }
+
+    async fn run_locked<T, F>(
+        &self,
+        class: DiskClass,
+        inode: Option<u64>,
+        f: F,
+    ) -> TritonFileResult<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&SimpleFS) -> TritonFileResult<T> + Send + 'static,
+    {
+        let _permit = self.permits(class).acquire_owned().await?;
+        let fs = self.fs.clone();
+        let locks = self.inode_locks.clone();
+        tokio::task::spawn_blocking(move || {
+            // Namespace operations are serialized by their permit, so only
+            // they ever hold more than one lock, always in the same order.
+            let _held: Vec<MutexGuard<()>> = match (class, inode) {
+                (DiskClass::Namespace, _) => locks.iter().map(lock_stripe).collect(),
+                (_, Some(inode)) => vec![lock_stripe(&locks[inode as usize % locks.len()])],
+                (_, None) => vec![],
+            };
+            f(&fs)
+        })
+        .await?
+    }
+}
+
+// A panic in an operation holding the lock leaves nothing half-updated in
+// memory, so a poisoned lock is taken all the same.
+fn lock_stripe(stripe: &Mutex<()>) -> MutexGuard<'_, ()> {
+    stripe.lock().unwrap_or_else(|e| e.into_inner())
 }
 
 #[async_trait]
@@ -498,27 +854,28 @@ impl ServerFileSystem for RemoteFileSystem {
         for_addr: usize,
         len: usize,
     ) -> TritonFileResult<Option<(InodeList, ContentList)>> {
-        let fs = &self.fs;
-
-        let mut node_list = vec![];
-        let mut contents = vec![];
-
-        for entry in fs::read_dir(format!("tmp/{}/inodes", &self.label))? {
-            let entry = entry?;
-            let path = entry.path();
-            let metadata = fs::metadata(&path)?;
-
-            if metadata.is_file() {
-                let inode = path.file_name().unwrap().to_str().unwrap().parse::<u64>()?;
-                let node_attr = fs.get_inode(inode).ok().unwrap();
-                if hash_name_to_idx(&node_attr.gid.to_string(), len) == for_addr {
-                    node_list.push(fs.get_inode(inode).ok().unwrap());
-                    let content_path = fs.content_path(inode);
-                    contents.push(DataList(fs::read(content_path)?));
+        self.run_blocking(DiskClass::Data, move |fs| {
+            let mut node_list = vec![];
+            let mut contents = vec![];
+
+            for entry in fs::read_dir(Path::new(&fs.data_dir).join("inodes"))? {
+                let entry = entry?;
+                let path = entry.path();
+                let metadata = fs::metadata(&path)?;
+
+                if metadata.is_file() {
+                    let inode = path.file_name().unwrap().to_str().unwrap().parse::<u64>()?;
+                    let node_attr = fs.get_inode(inode).ok().unwrap();
+                    if hash_name_to_idx(&node_attr.gid.to_string(), len) == for_addr {
+                        node_list.push(fs.get_inode(inode).ok().unwrap());
+                        let content_path = fs.content_path(inode);
+                        contents.push(DataList(fs::read(content_path)?));
+                    }
                 }
             }
-        }
-        Ok(Some((InodeList(node_list), ContentList(contents))))
+            Ok(Some((InodeList(node_list), ContentList(contents))))
+        })
+        .await
     }
 
     async fn write_all_nodes(
@@ -526,33 +883,34 @@ impl ServerFileSystem for RemoteFileSystem {
         inode_list: InodeList,
         content_list: ContentList,
     ) -> TritonFileResult<()> {
-        let fs = &self.fs;
-
-        let inode_list = inode_list.0;
-        let content_list = content_list.0;
-        for i in 0..inode_list.len() {
-            let node_attr = inode_list.get(i).unwrap();
-            let mut old_bTree = node_attr.xattrs.clone();
-            let mut new_bTree = BTreeMap::new();
-            new_bTree.append(&mut old_bTree);
-            let inode = fs.allocate_next_inode();
-            let new_node_attr = InodeAttributes {
-                inode,
-                xattrs: new_bTree,
-                ..*node_attr
-            };
-            fs.write_inode(&new_node_attr);
-            let contents = content_list.get(i).unwrap();
-            let content_path = fs.content_path(inode);
-            let mut file = OpenOptions::new()
-                .write(true)
-                .create(true)
-                .truncate(true)
-                .open(&content_path)
-                .unwrap();
-            file.write(&contents.0[..])?;
-        }
-        Ok(())
+        self.run_blocking(DiskClass::Namespace, move |fs| {
+            let inode_list = inode_list.0;
+            let content_list = content_list.0;
+            for i in 0..inode_list.len() {
+                let node_attr = inode_list.get(i).unwrap();
+                let mut old_xattrs = node_attr.xattrs.clone();
+                let mut new_xattrs = BTreeMap::new();
+                new_xattrs.append(&mut old_xattrs);
+                let inode = fs.allocate_next_inode();
+                let new_node_attr = InodeAttributes {
+                    inode,
+                    xattrs: new_xattrs,
+                    ..*node_attr
+                };
+                fs.write_inode(&new_node_attr);
+                let contents = content_list.get(i).unwrap();
+                let content_path = fs.content_path(inode);
+                let mut file = OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .truncate(true)
+                    .open(&content_path)
+                    .unwrap();
+                file.write_all(&contents.0[..])?;
+            }
+            Ok(())
+        })
+        .await
     }
 
     async fn init(&self, _req: &FileRequest) -> TritonFileResult<c_int> {
@@ -594,32 +952,34 @@ impl ServerFileSystem for RemoteFileSystem {
         _flags: i32,
         _lock_owner: Option<u64>,
     ) -> TritonFileResult<(Option<String>, c_int)> {
-        let fs = &self.fs;
         info!(
             "No.{:?}, gid:{:?}, uid:{:?} read() called on {:?} offset={:?} size={:?}",
             self.label, _req.gid, _req.uid, inode, offset, size
         );
         assert!(offset >= 0);
-        if !fs.check_file_handle_read(fh) {
+        if !self.fs.check_file_handle_read(fh) {
             return Ok((None, libc::EACCES));
         }
 
-        let path = fs.content_path(inode);
-        if let Ok(file) = File::open(&path) {
-            let file_size = file.metadata().unwrap().len();
-            // Could underflow if file length is less than local_start
-            let read_size = min(size, file_size.saturating_sub(offset as u64) as u32);
-
-            let mut buffer = vec![0; read_size as usize];
-            file.read_exact_at(&mut buffer, offset as u64).unwrap();
-            info!("back end read buffer {:?}", buffer);
-            return Ok((
-                Some(serde_json::to_string(&buffer).unwrap()),
-                error::SUCCESS,
-            ));
-        } else {
-            return Ok((None, libc::ENOENT));
-        }
+        self.run_blocking(DiskClass::Data, move |fs| {
+            let path = fs.content_path(inode);
+            if let Ok(file) = File::open(&path) {
+                let file_size = file.metadata().unwrap().len();
+                // Could underflow if file length is less than local_start
+                let read_size = min(size, file_size.saturating_sub(offset as u64) as u32);
+
+                let mut buffer = vec![0; read_size as usize];
+                file.read_exact_at(&mut buffer, offset as u64).unwrap();
+                info!("back end read buffer {:?}", buffer);
+                Ok((
+                    Some(serde_json::to_string(&buffer).unwrap()),
+                    error::SUCCESS,
+                ))
+            } else {
+                Ok((None, libc::ENOENT))
+            }
+        })
+        .await
     }
 
     async fn write(
@@ -633,7 +993,6 @@ impl ServerFileSystem for RemoteFileSystem {
         #[allow(unused_variables)] flags: i32,
         _lock_owner: Option<u64>,
     ) -> TritonFileResult<(Option<u32>, c_int)> {
-        let fs = &self.fs;
         info!(
             "No.{:?} gid:{:?} uid:{:?} write() called with {:?} size={:?}",
             self.label,
@@ -643,35 +1002,39 @@ impl ServerFileSystem for RemoteFileSystem {
             data.len()
         );
         assert!(offset >= 0);
-        if !fs.check_file_handle_write(fh) {
+        if !self.fs.check_file_handle_write(fh) {
             return Ok((None, libc::EACCES));
         }
 
-        let path = fs.content_path(inode);
-        if let Ok(mut file) = OpenOptions::new().write(true).open(&path) {
-            file.seek(SeekFrom::Start(offset as u64)).unwrap();
-            file.write_all(data).unwrap();
-
-            let mut attrs = fs.get_inode(inode).unwrap();
-            attrs.last_metadata_changed = time_now();
-            attrs.last_modified = time_now();
-            if data.len() + offset as usize > attrs.size as usize {
-                attrs.size = (data.len() + offset as usize) as u64;
-            }
-            // #[cfg(feature = "abi-7-31")]
-            // if flags & FUSE_WRITE_KILL_PRIV as i32 != 0 {
-            //     clear_suid_sgid(&mut attrs);
-            // }
-            // XXX: In theory we should only need to do this when WRITE_KILL_PRIV is set for 7.31+
-            // However, xfstests fail in that case
-            clear_suid_sgid(&mut attrs);
-            fs.write_inode(&attrs);
-            info!("write sucess in back end");
-            return Ok((Some(data.len() as u32), error::SUCCESS));
-        } else {
-            info!("write fails in back end");
-            return Ok((None, libc::EBADF));
-        }
+        let data = data.to_vec();
+        self.run_blocking_on(DiskClass::Data, inode, move |fs| {
+            let path = fs.content_path(inode);
+            if let Ok(mut file) = OpenOptions::new().write(true).open(&path) {
+                file.seek(SeekFrom::Start(offset as u64)).unwrap();
+                file.write_all(&data).unwrap();
+
+                let mut attrs = fs.get_inode(inode).unwrap();
+                attrs.last_metadata_changed = time_now();
+                attrs.last_modified = time_now();
+                if data.len() + offset as usize > attrs.size as usize {
+                    attrs.size = (data.len() + offset as usize) as u64;
+                }
+                // #[cfg(feature = "abi-7-31")]
+                // if flags & FUSE_WRITE_KILL_PRIV as i32 != 0 {
+                //     clear_suid_sgid(&mut attrs);
+                // }
+                // XXX: In theory we should only need to do this when WRITE_KILL_PRIV is set for 7.31+
+                // However, xfstests fail in that case
+                clear_suid_sgid(&mut attrs);
+                fs.write_inode(&attrs);
+                info!("write sucess in back end");
+                Ok((Some(data.len() as u32), error::SUCCESS))
+            } else {
+                info!("write fails in back end");
+                Ok((None, libc::EBADF))
+            }
+        })
+        .await
     }
 
     async fn lookup(
@@ -685,26 +1048,31 @@ impl ServerFileSystem for RemoteFileSystem {
             self.label, req.gid, req.uid, parent
         );
 
-        let fs = &self.fs;
         if name.len() > simple::MAX_NAME_LENGTH as usize {
             return Ok((None, libc::ENAMETOOLONG));
         }
-        let parent_attrs = fs.get_inode(parent).unwrap();
-        if !check_access(
-            parent_attrs.uid,
-            parent_attrs.gid,
-            parent_attrs.mode,
-            req.uid,
-            req.gid,
-            libc::X_OK,
-        ) {
-            return Ok((None, libc::EACCES));
-        }
 
-        match fs.lookup_name(parent, name) {
-            Ok(attrs) => Ok((Some(attrs.into()), error::SUCCESS)),
-            Err(error_code) => Ok((None, error_code)),
-        }
+        let req = req.clone();
+        let name = name.to_os_string();
+        self.run_blocking(DiskClass::Meta, move |fs| {
+            let parent_attrs = fs.get_inode(parent).unwrap();
+            if !check_access(
+                parent_attrs.uid,
+                parent_attrs.gid,
+                parent_attrs.mode,
+                req.uid,
+                req.gid,
+                libc::X_OK,
+            ) {
+                return Ok((None, libc::EACCES));
+            }
+
+            match fs.lookup_name(parent, &name) {
+                Ok(attrs) => Ok((Some(attrs.into()), error::SUCCESS)),
+                Err(error_code) => Ok((None, error_code)),
+            }
+        })
+        .await
     }
 
     async fn unlink(
@@ -718,57 +1086,61 @@ impl ServerFileSystem for RemoteFileSystem {
             self.label, req.gid, req.uid, parent
         );
 
-        let fs = &self.fs;
+        let req = req.clone();
+        let name = name.to_os_string();
+        self.run_blocking(DiskClass::Namespace, move |fs| {
+            let name = name.as_os_str();
+            let mut attrs = match fs.lookup_name(parent, name) {
+                Ok(attrs) => attrs,
+                Err(error_code) => {
+                    return Ok(error_code);
+                }
+            };
 
-        let mut attrs = match fs.lookup_name(parent, name) {
-            Ok(attrs) => attrs,
-            Err(error_code) => {
-                return Ok(error_code);
-            }
-        };
+            let mut parent_attrs = match fs.get_inode(parent) {
+                Ok(attrs) => attrs,
+                Err(error_code) => {
+                    return Ok(error_code);
+                }
+            };
 
-        let mut parent_attrs = match fs.get_inode(parent) {
-            Ok(attrs) => attrs,
-            Err(error_code) => {
-                return Ok(error_code);
+            if !check_access(
+                parent_attrs.uid,
+                parent_attrs.gid,
+                parent_attrs.mode,
+                req.uid,
+                req.gid,
+                libc::W_OK,
+            ) {
+                return Ok(libc::EACCES);
             }
-        };
-
-        if !check_access(
-            parent_attrs.uid,
-            parent_attrs.gid,
-            parent_attrs.mode,
-            req.uid,
-            req.gid,
-            libc::W_OK,
-        ) {
-            return Ok(libc::EACCES);
-        }
 
-        let uid = req.uid;
-        // "Sticky bit" handling
-        if parent_attrs.mode & libc::S_ISVTX as u16 != 0
-            && uid != 0
-            && uid != parent_attrs.uid
-            && uid != attrs.uid
-        {
-            return Ok(libc::EACCES);
-        }
+            let uid = req.uid;
+            // "Sticky bit" handling
+            if parent_attrs.mode & libc::S_ISVTX as u16 != 0
+                && uid != 0
+                && uid != parent_attrs.uid
+                && uid != attrs.uid
+            {
+                return Ok(libc::EACCES);
+            }
 
-        parent_attrs.last_metadata_changed = time_now();
-        parent_attrs.last_modified = time_now();
-        fs.write_inode(&parent_attrs);
+            parent_attrs.last_metadata_changed = time_now();
+            parent_attrs.last_modified = time_now();
+            fs.write_inode(&parent_attrs);
 
-        attrs.hardlinks -= 1;
-        attrs.last_metadata_changed = time_now();
-        fs.write_inode(&attrs);
-        fs.gc_inode(&attrs);
+            attrs.hardlinks -= 1;
+            attrs.last_metadata_changed = time_now();
+            fs.write_inode(&attrs);
+            fs.gc_inode(&attrs);
 
-        let mut entries = fs.get_directory_content(parent).unwrap();
-        entries.remove(name.as_bytes());
-        fs.write_directory_content(parent, entries);
+            let mut entries = fs.get_directory_content(parent).unwrap();
+            entries.remove(name.as_bytes());
+            fs.write_directory_content(parent, entries);
 
-        Ok(error::SUCCESS)
+            Ok(error::SUCCESS)
+        })
+        .await
     }
 
     async fn create(
@@ -785,81 +1157,89 @@ impl ServerFileSystem for RemoteFileSystem {
             self.label, req.gid, req.uid, parent, name
         );
 
-        let fs = &self.fs;
-        if fs.lookup_name(parent, name).is_ok() {
-            return Ok((None, libc::EEXIST));
-        }
-
-        let (read, write) = match flags & libc::O_ACCMODE {
-            libc::O_RDONLY => (true, false),
-            libc::O_WRONLY => (false, true),
-            libc::O_RDWR => (true, true),
-            // Exactly one access mode flag must be specified
-            _ => {
-                return Ok((None, libc::EINVAL));
+        let req = req.clone();
+        let name = name.to_os_string();
+        self.run_blocking(DiskClass::Namespace, move |fs| {
+            let name = name.as_os_str();
+            if fs.lookup_name(parent, name).is_ok() {
+                return Ok((None, libc::EEXIST));
             }
-        };
 
-        let mut parent_attrs = match fs.get_inode(parent) {
-            Ok(attrs) => attrs,
-            Err(error_code) => {
-                return Ok((None, error_code));
-            }
-        };
+            let (read, write) = match flags & libc::O_ACCMODE {
+                libc::O_RDONLY => (true, false),
+                libc::O_WRONLY => (false, true),
+                libc::O_RDWR => (true, true),
+                // Exactly one access mode flag must be specified
+                _ => {
+                    return Ok((None, libc::EINVAL));
+                }
+            };
 
-        if !check_access(
-            parent_attrs.uid,
-            parent_attrs.gid,
-            parent_attrs.mode,
-            req.uid,
-            req.gid,
-            libc::W_OK,
-        ) {
-            return Ok((None, libc::EACCES));
-        }
-        parent_attrs.last_modified = time_now();
-        parent_attrs.last_metadata_changed = time_now();
-        fs.write_inode(&parent_attrs);
+            let mut parent_attrs = match fs.get_inode(parent) {
+                Ok(attrs) => attrs,
+                Err(error_code) => {
+                    return Ok((None, error_code));
+                }
+            };
 
-        if req.uid != 0 {
-            mode &= !(libc::S_ISUID | libc::S_ISGID) as u32;
-        }
+            if !check_access(
+                parent_attrs.uid,
+                parent_attrs.gid,
+                parent_attrs.mode,
+                req.uid,
+                req.gid,
+                libc::W_OK,
+            ) {
+                return Ok((None, libc::EACCES));
+            }
+            parent_attrs.last_modified = time_now();
+            parent_attrs.last_metadata_changed = time_now();
+            fs.write_inode(&parent_attrs);
 
-        let inode = fs.allocate_next_inode();
-        let attrs = InodeAttributes {
-            inode,
-            open_file_handles: 1,
-            size: 0,
-            last_accessed: time_now(),
-            last_modified: time_now(),
-            last_metadata_changed: time_now(),
-            kind: simple::as_file_kind(mode),
-            mode: fs.creation_mode(mode),
-            hardlinks: 1,
-            uid: req.uid,
-            gid: simple::creation_gid(&parent_attrs, req.gid),
-            xattrs: Default::default(),
-        };
-        fs.write_inode(&attrs);
-        File::create(fs.content_path(inode)).unwrap();
+            // mode_t is u16 on macOS.
+            #[allow(clippy::unnecessary_cast)]
+            if req.uid != 0 {
+                mode &= !(libc::S_ISUID | libc::S_ISGID) as u32;
+            }
 
-        if simple::as_file_kind(mode) == FileKind::Directory {
-            let mut entries = BTreeMap::new();
-            entries.insert(b".".to_vec(), (inode, FileKind::Directory));
-            entries.insert(b"..".to_vec(), (parent, FileKind::Directory));
-            fs.write_directory_content(inode, entries);
-        }
+            let inode = fs.allocate_next_inode();
+            let attrs = InodeAttributes {
+                inode,
+                open_file_handles: 1,
+                size: 0,
+                last_accessed: time_now(),
+                last_modified: time_now(),
+                last_metadata_changed: time_now(),
+                kind: simple::as_file_kind(mode),
+                mode: fs.creation_mode(mode),
+                hardlinks: 1,
+                uid: req.uid,
+                gid: simple::creation_gid(&parent_attrs, req.gid),
+                xattrs: Default::default(),
+                version: 0,
+            };
+            fs.write_inode(&attrs);
+            File::create(fs.content_path(inode)).unwrap();
 
-        let mut entries = fs.get_directory_content(parent).unwrap();
-        entries.insert(name.as_bytes().to_vec(), (inode, attrs.kind));
-        fs.write_directory_content(parent, entries);
+            if simple::as_file_kind(mode) == FileKind::Directory {
+                let mut entries = BTreeMap::new();
+                entries.insert(b".".to_vec(), (inode, FileKind::Directory));
+                entries.insert(b"..".to_vec(), (parent, FileKind::Directory));
+                fs.write_directory_content(inode, entries);
+            }
 
-        // TODO: implement flags
+            let mut entries = fs.get_directory_content(parent).unwrap();
+            entries.insert(name.as_bytes().to_vec(), (inode, attrs.kind));
+            fs.write_directory_content(parent, entries);
 
-        Ok((
-            Some((attrs.into(), fs.allocate_next_file_handle(read, write))),
-            error::SUCCESS,
-        ))
+            // TODO: implement flags
+
+            Ok((
+                Some((attrs.into(), fs.allocate_next_file_handle(read, write))),
+                error::SUCCESS,
+            ))
+        })
+        .await
     }
 
     async fn getattr(
@@ -872,11 +1252,26 @@ impl ServerFileSystem for RemoteFileSystem {
             self.label, _req.gid, _req.uid, ino
         );
 
-        let fs = &self.fs;
-        match fs.get_inode(ino) {
+        self.run_blocking(DiskClass::Meta, move |fs| match fs.get_inode(ino) {
             Ok(attrs) => Ok((Some(attrs.into()), SUCCESS)),
             Err(error_code) => Ok((None, error_code)),
-        }
+        })
+        .await
+    }
+
+    async fn getattr_versioned(
+        &self,
+        _req: &FileRequest,
+        ino: u64,
+    ) -> TritonFileResult<(Option<FileAttr>, c_int, u64)> {
+        self.run_blocking(DiskClass::Meta, move |fs| match fs.get_inode(ino) {
+            Ok(attrs) => {
+                let version = attrs.version;
+                Ok((Some(attrs.into()), SUCCESS, version))
+            }
+            Err(error_code) => Ok((None, error_code, 0)),
+        })
+        .await
     }
 
     async fn open(
@@ -890,8 +1285,6 @@ impl ServerFileSystem for RemoteFileSystem {
             self.label, req.gid, req.uid, inode
         );
 
-        let fs = &self.fs;
-
         let (access_mask, read, write) = match flags & libc::O_ACCMODE {
             libc::O_RDONLY => {
                 // Behavior is undefined, but most filesystems return EACCES
@@ -913,22 +1306,26 @@ impl ServerFileSystem for RemoteFileSystem {
             }
         };
 
-        match fs.get_inode(inode) {
-            Ok(mut attr) => {
-                if check_access(attr.uid, attr.gid, attr.mode, req.uid, req.gid, access_mask) {
-                    attr.open_file_handles += 1;
-                    fs.write_inode(&attr);
-                    let open_flags = if fs.direct_io { FOPEN_DIRECT_IO } else { 0 };
-                    return Ok((
-                        Some((fs.allocate_next_file_handle(read, write), open_flags)),
-                        SUCCESS,
-                    ));
-                } else {
-                    return Ok((None, libc::EACCES));
+        let req = req.clone();
+        self.run_blocking_on(DiskClass::Meta, inode, move |fs| {
+            match fs.get_inode(inode) {
+                Ok(mut attr) => {
+                    if check_access(attr.uid, attr.gid, attr.mode, req.uid, req.gid, access_mask) {
+                        attr.open_file_handles += 1;
+                        fs.write_inode(&attr);
+                        let open_flags = if fs.direct_io { FOPEN_DIRECT_IO } else { 0 };
+                        Ok((
+                            Some((fs.allocate_next_file_handle(read, write), open_flags)),
+                            SUCCESS,
+                        ))
+                    } else {
+                        Ok((None, libc::EACCES))
+                    }
                 }
+                Err(error_code) => Ok((None, error_code)),
             }
-            Err(error_code) => return Ok((None, error_code)),
-        }
+        })
+        .await
     }
 
     async fn release(
@@ -945,11 +1342,13 @@ impl ServerFileSystem for RemoteFileSystem {
             self.label, _req.gid, _req.uid, inode
         );
 
-        let fs = &self.fs;
-        if let Ok(mut attrs) = fs.get_inode(inode) {
-            attrs.open_file_handles -= 1;
-        }
-        return Ok(SUCCESS);
+        self.run_blocking(DiskClass::Meta, move |fs| {
+            if let Ok(mut attrs) = fs.get_inode(inode) {
+                attrs.open_file_handles -= 1;
+            }
+            Ok(SUCCESS)
+        })
+        .await
     }
 
     async fn setxattr(
@@ -966,19 +1365,25 @@ impl ServerFileSystem for RemoteFileSystem {
             self.label, request.gid, request.uid, inode
         );
 
-        let fs = &self.fs;
-        if let Ok(mut attrs) = fs.get_inode(inode) {
-            if let Err(error) = xattr_access_check(key.as_bytes(), libc::W_OK, &attrs, request) {
-                return Ok(error);
-            }
+        let request = request.clone();
+        let key = key.to_os_string();
+        let value = value.to_vec();
+        self.run_blocking_on(DiskClass::Meta, inode, move |fs| {
+            if let Ok(mut attrs) = fs.get_inode(inode) {
+                if let Err(error) = xattr_access_check(key.as_bytes(), libc::W_OK, &attrs, &request)
+                {
+                    return Ok(error);
+                }
 
-            attrs.xattrs.insert(key.as_bytes().to_vec(), value.to_vec());
-            attrs.last_metadata_changed = time_now();
-            fs.write_inode(&attrs);
-            return Ok(SUCCESS);
-        } else {
-            return Ok(libc::EBADF);
-        }
+                attrs.xattrs.insert(key.as_bytes().to_vec(), value);
+                attrs.last_metadata_changed = time_now();
+                fs.write_inode(&attrs);
+                Ok(SUCCESS)
+            } else {
+                Ok(libc::EBADF)
+            }
+        })
+        .await
     }
 
     //reply Vec<u8> as string
@@ -994,38 +1399,42 @@ impl ServerFileSystem for RemoteFileSystem {
             self.label, request.gid, request.uid, inode, key
         );
 
-        let fs = &self.fs;
-
-        if let Ok(attrs) = fs.get_inode(inode) {
-            if let Err(error) = xattr_access_check(key.as_bytes(), libc::R_OK, &attrs, request) {
-                info!("filesystem access_check fail");
-                return Ok((None, error));
-            }
+        let request = request.clone();
+        let key = key.to_os_string();
+        self.run_blocking(DiskClass::Meta, move |fs| {
+            if let Ok(attrs) = fs.get_inode(inode) {
+                if let Err(error) = xattr_access_check(key.as_bytes(), libc::R_OK, &attrs, &request)
+                {
+                    info!("filesystem access_check fail");
+                    return Ok((None, error));
+                }
 
-            // check size to get usize
-            if let Some(data) = attrs.xattrs.get(key.as_bytes()) {
-                if size == 0 {
-                    return Ok((
-                        Some((serde_json::to_string("").unwrap(), data.len() as u32)),
-                        SUCCESS,
-                    ));
-                } else if data.len() <= size as usize {
-                    return Ok((
-                        Some((serde_json::to_string(data).unwrap(), data.len() as u32)),
-                        SUCCESS,
-                    ));
+                // check size to get usize
+                if let Some(data) = attrs.xattrs.get(key.as_bytes()) {
+                    if size == 0 {
+                        Ok((
+                            Some((serde_json::to_string("").unwrap(), data.len() as u32)),
+                            SUCCESS,
+                        ))
+                    } else if data.len() <= size as usize {
+                        Ok((
+                            Some((serde_json::to_string(data).unwrap(), data.len() as u32)),
+                            SUCCESS,
+                        ))
+                    } else {
+                        Ok((None, libc::ERANGE))
+                    }
                 } else {
-                    return Ok((None, libc::ERANGE));
+                    #[cfg(target_os = "linux")]
+                    return Ok((None, libc::ENODATA));
+                    #[cfg(not(target_os = "linux"))]
+                    return Ok((None, libc::ENODATA));
                 }
             } else {
-                #[cfg(target_os = "linux")]
-                return Ok((None, libc::ENODATA));
-                #[cfg(not(target_os = "linux"))]
-                return Ok((None, libc::ENODATA));
+                Ok((None, libc::EBADF))
             }
-        } else {
-            return Ok((None, libc::EBADF));
-        }
+        })
+        .await
     }
 
     async fn listxattr(
@@ -1039,30 +1448,32 @@ impl ServerFileSystem for RemoteFileSystem {
             self.label, _req.gid, _req.uid, inode
         );
 
-        let fs = &self.fs;
-        if let Ok(attrs) = fs.get_inode(inode) {
-            let mut bytes = vec![];
-            // Convert to concatenated null-terminated strings
-            for key in attrs.xattrs.keys() {
-                bytes.extend(key);
-                bytes.push(0);
-            }
-            if size == 0 {
-                return Ok((
-                    Some((serde_json::to_string("").unwrap(), bytes.len() as u32)),
-                    SUCCESS,
-                ));
-            } else if bytes.len() <= size as usize {
-                return Ok((
-                    Some((serde_json::to_string(&bytes).unwrap(), bytes.len() as u32)),
-                    SUCCESS,
-                ));
+        self.run_blocking(DiskClass::Meta, move |fs| {
+            if let Ok(attrs) = fs.get_inode(inode) {
+                let mut bytes = vec![];
+                // Convert to concatenated null-terminated strings
+                for key in attrs.xattrs.keys() {
+                    bytes.extend(key);
+                    bytes.push(0);
+                }
+                if size == 0 {
+                    Ok((
+                        Some((serde_json::to_string("").unwrap(), bytes.len() as u32)),
+                        SUCCESS,
+                    ))
+                } else if bytes.len() <= size as usize {
+                    Ok((
+                        Some((serde_json::to_string(&bytes).unwrap(), bytes.len() as u32)),
+                        SUCCESS,
+                    ))
+                } else {
+                    Ok((None, libc::ERANGE))
+                }
             } else {
-                return Ok((None, libc::ERANGE));
+                Ok((None, libc::EBADF))
             }
-        } else {
-            return Ok((None, libc::EBADF));
-        }
+        })
+        .await
     }
 
     async fn access(&self, req: &FileRequest, inode: u64, mask: i32) -> TritonFileResult<c_int> {
@@ -1071,8 +1482,8 @@ impl ServerFileSystem for RemoteFileSystem {
             self.label, req.gid, req.uid, inode, mask
         );
 
-        let fs = &self.fs;
-        match fs.get_inode(inode) {
+        let req = req.clone();
+        self.run_blocking(DiskClass::Meta, move |fs| match fs.get_inode(inode) {
             Ok(attr) => {
                 info!(
                     "the file permssion is {}, {}, {}",
@@ -1088,7 +1499,8 @@ impl ServerFileSystem for RemoteFileSystem {
                 info!("access error {:?}", error_code);
                 return Ok(error_code);
             }
-        }
+        })
+        .await
     }
 
     async fn rename(
@@ -1105,190 +1517,208 @@ impl ServerFileSystem for RemoteFileSystem {
             self.label, req.gid, req.uid, parent
         );
 
-        let fs = &self.fs;
-
-        let mut inode_attrs = match fs.lookup_name(parent, name) {
-            Ok(attrs) => attrs,
-            Err(error_code) => {
-                return Ok(error_code);
-            }
-        };
-
-        let mut parent_attrs = match fs.get_inode(parent) {
-            Ok(attrs) => attrs,
-            Err(error_code) => {
-                return Ok(error_code);
-            }
-        };
-
-        if !check_access(
-            parent_attrs.uid,
-            parent_attrs.gid,
-            parent_attrs.mode,
-            req.uid,
-            req.gid,
-            libc::W_OK,
-        ) {
-            return Ok(libc::EACCES);
+        #[cfg(target_os = "linux")]
+        if flags & libc::RENAME_EXCHANGE != 0 && flags & libc::RENAME_NOREPLACE != 0 {
+            return Ok(libc::EINVAL);
         }
 
-        // "Sticky bit" handling
-        if parent_attrs.mode & libc::S_ISVTX as u16 != 0
-            && req.uid != 0
-            && req.uid != parent_attrs.uid
-            && req.uid != inode_attrs.uid
-        {
-            return Ok(libc::EACCES);
-        }
+        let req = req.clone();
+        let name = name.to_os_string();
+        let new_name = new_name.to_os_string();
+        self.run_blocking(DiskClass::Namespace, move |fs| {
+            let name = name.as_os_str();
+            let new_name = new_name.as_os_str();
+            let mut inode_attrs = match fs.lookup_name(parent, name) {
+                Ok(attrs) => attrs,
+                Err(error_code) => {
+                    return Ok(error_code);
+                }
+            };
 
-        let mut new_parent_attrs = match fs.get_inode(new_parent) {
-            Ok(attrs) => attrs,
-            Err(error_code) => {
-                return Ok(error_code);
-            }
-        };
+            let mut parent_attrs = match fs.get_inode(parent) {
+                Ok(attrs) => attrs,
+                Err(error_code) => {
+                    return Ok(error_code);
+                }
+            };
 
-        if !check_access(
-            new_parent_attrs.uid,
-            new_parent_attrs.gid,
-            new_parent_attrs.mode,
-            req.uid,
-            req.gid,
-            libc::W_OK,
-        ) {
-            return Ok(libc::EACCES);
-        }
+            if !check_access(
+                parent_attrs.uid,
+                parent_attrs.gid,
+                parent_attrs.mode,
+                req.uid,
+                req.gid,
+                libc::W_OK,
+            ) {
+                return Ok(libc::EACCES);
+            }
 
-        // "Sticky bit" handling in new_parent
-        if new_parent_attrs.mode & libc::S_ISVTX as u16 != 0 {
-            if let Ok(existing_attrs) = fs.lookup_name(new_parent, new_name) {
-                if req.uid != 0 && req.uid != new_parent_attrs.uid && req.uid != existing_attrs.uid
-                {
-                    return Ok(libc::EACCES);
-                }
+            // "Sticky bit" handling
+            if parent_attrs.mode & libc::S_ISVTX as u16 != 0
+                && req.uid != 0
+                && req.uid != parent_attrs.uid
+                && req.uid != inode_attrs.uid
+            {
+                return Ok(libc::EACCES);
             }
-        }
 
-        #[cfg(target_os = "linux")]
-        if flags & libc::RENAME_EXCHANGE as u32 != 0 {
-            let mut new_inode_attrs = match self.lookup_name(new_parent, new_name) {
+            let mut new_parent_attrs = match fs.get_inode(new_parent) {
                 Ok(attrs) => attrs,
                 Err(error_code) => {
                     return Ok(error_code);
                 }
             };
 
-            let mut entries = self.get_directory_content(new_parent).unwrap();
+            if !check_access(
+                new_parent_attrs.uid,
+                new_parent_attrs.gid,
+                new_parent_attrs.mode,
+                req.uid,
+                req.gid,
+                libc::W_OK,
+            ) {
+                return Ok(libc::EACCES);
+            }
+
+            // "Sticky bit" handling in new_parent
+            if new_parent_attrs.mode & libc::S_ISVTX as u16 != 0 {
+                if let Ok(existing_attrs) = fs.lookup_name(new_parent, new_name) {
+                    if req.uid != 0
+                        && req.uid != new_parent_attrs.uid
+                        && req.uid != existing_attrs.uid
+                    {
+                        return Ok(libc::EACCES);
+                    }
+                }
+            }
+
+            #[cfg(target_os = "linux")]
+            if flags & libc::RENAME_NOREPLACE != 0 && fs.lookup_name(new_parent, new_name).is_ok() {
+                return Ok(libc::EEXIST);
+            }
+
+            #[cfg(target_os = "linux")]
+            if flags & libc::RENAME_EXCHANGE != 0 {
+                let mut new_inode_attrs = match fs.lookup_name(new_parent, new_name) {
+                    Ok(attrs) => attrs,
+                    Err(error_code) => {
+                        return Ok(error_code);
+                    }
+                };
+
+                let mut entries = fs.get_directory_content(new_parent).unwrap();
+                entries.insert(
+                    new_name.as_bytes().to_vec(),
+                    (inode_attrs.inode, inode_attrs.kind),
+                );
+                fs.write_directory_content(new_parent, entries);
+
+                let mut entries = fs.get_directory_content(parent).unwrap();
+                entries.insert(
+                    name.as_bytes().to_vec(),
+                    (new_inode_attrs.inode, new_inode_attrs.kind),
+                );
+                fs.write_directory_content(parent, entries);
+
+                parent_attrs.last_metadata_changed = time_now();
+                parent_attrs.last_modified = time_now();
+                fs.write_inode(&parent_attrs);
+                new_parent_attrs.last_metadata_changed = time_now();
+                new_parent_attrs.last_modified = time_now();
+                fs.write_inode(&new_parent_attrs);
+                inode_attrs.last_metadata_changed = time_now();
+                fs.write_inode(&inode_attrs);
+                new_inode_attrs.last_metadata_changed = time_now();
+                fs.write_inode(&new_inode_attrs);
+
+                if inode_attrs.kind == FileKind::Directory {
+                    let mut entries = fs.get_directory_content(inode_attrs.inode).unwrap();
+                    entries.insert(b"..".to_vec(), (new_parent, FileKind::Directory));
+                    fs.write_directory_content(inode_attrs.inode, entries);
+                }
+                if new_inode_attrs.kind == FileKind::Directory {
+                    let mut entries = fs.get_directory_content(new_inode_attrs.inode).unwrap();
+                    entries.insert(b"..".to_vec(), (parent, FileKind::Directory));
+                    fs.write_directory_content(new_inode_attrs.inode, entries);
+                }
+
+                return Ok(SUCCESS);
+            }
+
+            // Only overwrite an existing directory if it's empty
+            if let Ok(new_name_attrs) = fs.lookup_name(new_parent, new_name) {
+                if new_name_attrs.kind == FileKind::Directory
+                    && fs
+                        .get_directory_content(new_name_attrs.inode)
+                        .unwrap()
+                        .len()
+                        > 2
+                {
+                    return Ok(libc::ENOTEMPTY);
+                }
+            }
+
+            // Only move an existing directory to a new parent, if we have write access to it,
+            // because that will change the ".." link in it
+            if inode_attrs.kind == FileKind::Directory
+                && parent != new_parent
+                && !check_access(
+                    inode_attrs.uid,
+                    inode_attrs.gid,
+                    inode_attrs.mode,
+                    req.uid,
+                    req.gid,
+                    libc::W_OK,
+                )
+            {
+                return Ok(libc::EACCES);
+            }
+
+            // If target already exists decrement its hardlink count
+            if let Ok(mut existing_inode_attrs) = fs.lookup_name(new_parent, new_name) {
+                let mut entries = fs.get_directory_content(new_parent).unwrap();
+                entries.remove(new_name.as_bytes());
+                fs.write_directory_content(new_parent, entries);
+
+                if existing_inode_attrs.kind == FileKind::Directory {
+                    existing_inode_attrs.hardlinks = 0;
+                } else {
+                    existing_inode_attrs.hardlinks -= 1;
+                }
+                existing_inode_attrs.last_metadata_changed = time_now();
+                fs.write_inode(&existing_inode_attrs);
+                fs.gc_inode(&existing_inode_attrs);
+            }
+
+            let mut entries = fs.get_directory_content(parent).unwrap();
+            entries.remove(name.as_bytes());
+            fs.write_directory_content(parent, entries);
+
+            let mut entries = fs.get_directory_content(new_parent).unwrap();
             entries.insert(
                 new_name.as_bytes().to_vec(),
                 (inode_attrs.inode, inode_attrs.kind),
             );
-            self.write_directory_content(new_parent, entries);
-
-            let mut entries = self.get_directory_content(parent).unwrap();
-            entries.insert(
-                name.as_bytes().to_vec(),
-                (new_inode_attrs.inode, new_inode_attrs.kind),
-            );
-            self.write_directory_content(parent, entries);
+            fs.write_directory_content(new_parent, entries);
 
             parent_attrs.last_metadata_changed = time_now();
             parent_attrs.last_modified = time_now();
-            self.write_inode(&parent_attrs);
+            fs.write_inode(&parent_attrs);
             new_parent_attrs.last_metadata_changed = time_now();
             new_parent_attrs.last_modified = time_now();
-            self.write_inode(&new_parent_attrs);
+            fs.write_inode(&new_parent_attrs);
             inode_attrs.last_metadata_changed = time_now();
-            self.write_inode(&inode_attrs);
-            new_inode_attrs.last_metadata_changed = time_now();
-            self.write_inode(&new_inode_attrs);
+            fs.write_inode(&inode_attrs);
 
             if inode_attrs.kind == FileKind::Directory {
-                let mut entries = self.get_directory_content(inode_attrs.inode).unwrap();
+                let mut entries = fs.get_directory_content(inode_attrs.inode).unwrap();
                 entries.insert(b"..".to_vec(), (new_parent, FileKind::Directory));
-                self.write_directory_content(inode_attrs.inode, entries);
-            }
-            if new_inode_attrs.kind == FileKind::Directory {
-                let mut entries = self.get_directory_content(new_inode_attrs.inode).unwrap();
-                entries.insert(b"..".to_vec(), (parent, FileKind::Directory));
-                self.write_directory_content(new_inode_attrs.inode, entries);
+                fs.write_directory_content(inode_attrs.inode, entries);
             }
 
             return Ok(SUCCESS);
-        }
-
-        // Only overwrite an existing directory if it's empty
-        if let Ok(new_name_attrs) = fs.lookup_name(new_parent, new_name) {
-            if new_name_attrs.kind == FileKind::Directory
-                && fs
-                    .get_directory_content(new_name_attrs.inode)
-                    .unwrap()
-                    .len()
-                    > 2
-            {
-                return Ok(libc::ENOTEMPTY);
-            }
-        }
-
-        // Only move an existing directory to a new parent, if we have write access to it,
-        // because that will change the ".." link in it
-        if inode_attrs.kind == FileKind::Directory
-            && parent != new_parent
-            && !check_access(
-                inode_attrs.uid,
-                inode_attrs.gid,
-                inode_attrs.mode,
-                req.uid,
-                req.gid,
-                libc::W_OK,
-            )
-        {
-            return Ok(libc::EACCES);
-        }
-
-        // If target already exists decrement its hardlink count
-        if let Ok(mut existing_inode_attrs) = fs.lookup_name(new_parent, new_name) {
-            let mut entries = fs.get_directory_content(new_parent).unwrap();
-            entries.remove(new_name.as_bytes());
-            fs.write_directory_content(new_parent, entries);
-
-            if existing_inode_attrs.kind == FileKind::Directory {
-                existing_inode_attrs.hardlinks = 0;
-            } else {
-                existing_inode_attrs.hardlinks -= 1;
-            }
-            existing_inode_attrs.last_metadata_changed = time_now();
-            fs.write_inode(&existing_inode_attrs);
-            fs.gc_inode(&existing_inode_attrs);
-        }
-
-        let mut entries = fs.get_directory_content(parent).unwrap();
-        entries.remove(name.as_bytes());
-        fs.write_directory_content(parent, entries);
-
-        let mut entries = fs.get_directory_content(new_parent).unwrap();
-        entries.insert(
-            new_name.as_bytes().to_vec(),
-            (inode_attrs.inode, inode_attrs.kind),
-        );
-        fs.write_directory_content(new_parent, entries);
-
-        parent_attrs.last_metadata_changed = time_now();
-        parent_attrs.last_modified = time_now();
-        fs.write_inode(&parent_attrs);
-        new_parent_attrs.last_metadata_changed = time_now();
-        new_parent_attrs.last_modified = time_now();
-        fs.write_inode(&new_parent_attrs);
-        inode_attrs.last_metadata_changed = time_now();
-        fs.write_inode(&inode_attrs);
-
-        if inode_attrs.kind == FileKind::Directory {
-            let mut entries = fs.get_directory_content(inode_attrs.inode).unwrap();
-            entries.insert(b"..".to_vec(), (new_parent, FileKind::Directory));
-            fs.write_directory_content(inode_attrs.inode, entries);
-        }
-
-        return Ok(SUCCESS);
+        })
+        .await
     }
 
     async fn setattr(
@@ -1313,152 +1743,157 @@ impl ServerFileSystem for RemoteFileSystem {
             self.label, req.gid, req.uid, inode
         );
 
-        let fs = &self.fs;
-
-        let mut attrs = match fs.get_inode(inode) {
-            Ok(attrs) => attrs,
-            Err(error_code) => {
-                return Ok((None, error_code));
-            }
-        };
-
-        if let Some(mode) = mode {
-            info!("chmod() called with {:?}, {:o}", inode, mode);
-            if req.uid != 0 && req.uid != attrs.uid {
-                return Ok((None, libc::EPERM));
-            }
-            if req.uid != 0 && req.gid != attrs.gid && !get_groups(req.pid).contains(&attrs.gid) {
-                // If SGID is set and the file belongs to a group that the caller is not part of
-                // then the SGID bit is suppose to be cleared during chmod
-                attrs.mode = (mode & !libc::S_ISGID as u32) as u16;
-            } else {
-                attrs.mode = mode as u16;
-            }
-            attrs.last_metadata_changed = time_now();
-            fs.write_inode(&attrs);
-            return Ok((Some(attrs.into()), SUCCESS));
-        }
+        let req = req.clone();
+        self.run_blocking_on(DiskClass::Meta, inode, move |fs| {
+            let mut attrs = match fs.get_inode(inode) {
+                Ok(attrs) => attrs,
+                Err(error_code) => {
+                    return Ok((None, error_code));
+                }
+            };
 
-        if uid.is_some() || gid.is_some() {
-            info!("chown() called with {:?} {:?} {:?}", inode, uid, gid);
-            if let Some(gid) = gid {
-                // Non-root users can only change gid to a group they're in
-                if req.uid != 0 && !get_groups(req.pid).contains(&gid) {
+            if let Some(mode) = mode {
+                info!("chmod() called with {:?}, {:o}", inode, mode);
+                if req.uid != 0 && req.uid != attrs.uid {
                     return Ok((None, libc::EPERM));
                 }
-            }
-            if let Some(uid) = uid {
-                if req.uid != 0
-                    // but no-op changes by the owner are not an error
-                    && !(uid == attrs.uid && req.uid == attrs.uid)
+                // mode_t is u16 on macOS.
+                #[allow(clippy::unnecessary_cast)]
+                if req.uid != 0 && req.gid != attrs.gid && !get_groups(req.pid).contains(&attrs.gid)
                 {
-                    return Ok((None, libc::EPERM));
+                    // If SGID is set and the file belongs to a group that the caller is not part of
+                    // then the SGID bit is suppose to be cleared during chmod
+                    attrs.mode = (mode & !libc::S_ISGID as u32) as u16;
+                } else {
+                    attrs.mode = mode as u16;
                 }
-            }
-            // Only owner may change the group
-            if gid.is_some() && req.uid != 0 && req.uid != attrs.uid {
-                return Ok((None, libc::EPERM));
+                attrs.last_metadata_changed = time_now();
+                fs.write_inode(&attrs);
+                return Ok((Some(attrs.into()), SUCCESS));
             }
 
-            if attrs.mode & (libc::S_IXUSR | libc::S_IXGRP | libc::S_IXOTH) as u16 != 0 {
-                // SUID & SGID are suppose to be cleared when chown'ing an executable file
-                clear_suid_sgid(&mut attrs);
-            }
+            if uid.is_some() || gid.is_some() {
+                info!("chown() called with {:?} {:?} {:?}", inode, uid, gid);
+                if let Some(gid) = gid {
+                    // Non-root users can only change gid to a group they're in
+                    if req.uid != 0 && !get_groups(req.pid).contains(&gid) {
+                        return Ok((None, libc::EPERM));
+                    }
+                }
+                if let Some(uid) = uid {
+                    if req.uid != 0
+                        // but no-op changes by the owner are not an error
+                        && !(uid == attrs.uid && req.uid == attrs.uid)
+                    {
+                        return Ok((None, libc::EPERM));
+                    }
+                }
+                // Only owner may change the group
+                if gid.is_some() && req.uid != 0 && req.uid != attrs.uid {
+                    return Ok((None, libc::EPERM));
+                }
 
-            if let Some(uid) = uid {
-                attrs.uid = uid;
-                // Clear SETUID on owner change
-                attrs.mode &= !libc::S_ISUID as u16;
-            }
-            if let Some(gid) = gid {
-                attrs.gid = gid;
-                // Clear SETGID unless user is root
-                if req.uid != 0 {
-                    attrs.mode &= !libc::S_ISGID as u16;
+                if attrs.mode & (libc::S_IXUSR | libc::S_IXGRP | libc::S_IXOTH) as u16 != 0 {
+                    // SUID & SGID are suppose to be cleared when chown'ing an executable file
+                    clear_suid_sgid(&mut attrs);
+                }
+
+                if let Some(uid) = uid {
+                    attrs.uid = uid;
+                    // Clear SETUID on owner change
+                    attrs.mode &= !libc::S_ISUID as u16;
                 }
+                if let Some(gid) = gid {
+                    attrs.gid = gid;
+                    // Clear SETGID unless user is root
+                    if req.uid != 0 {
+                        attrs.mode &= !libc::S_ISGID as u16;
+                    }
+                }
+                attrs.last_metadata_changed = time_now();
+                fs.write_inode(&attrs);
+                return Ok((Some(attrs.into()), SUCCESS));
             }
-            attrs.last_metadata_changed = time_now();
-            fs.write_inode(&attrs);
-            return Ok((Some(attrs.into()), SUCCESS));
-        }
 
-        if let Some(size) = size {
-            info!("truncate() called with {:?} {:?}", inode, size);
-            if let Some(handle) = fh {
-                // If the file handle is available, check access locally.
-                // This is important as it preserves the semantic that a file handle opened
-                // with W_OK will never fail to truncate, even if the file has been subsequently
-                // chmod'ed
-                if fs.check_file_handle_write(handle) {
-                    if let Err(error_code) = fs.truncate(inode, size, 0, 0) {
-                        return Ok((None, error_code));
+            if let Some(size) = size {
+                info!("truncate() called with {:?} {:?}", inode, size);
+                if let Some(handle) = fh {
+                    // If the file handle is available, check access locally.
+                    // This is important as it preserves the semantic that a file handle opened
+                    // with W_OK will never fail to truncate, even if the file has been subsequently
+                    // chmod'ed
+                    if fs.check_file_handle_write(handle) {
+                        if let Err(error_code) = fs.truncate(inode, size, 0, 0) {
+                            return Ok((None, error_code));
+                        }
+                    } else {
+                        return Ok((None, libc::EACCES));
                     }
-                } else {
-                    return Ok((None, libc::EACCES));
+                } else if let Err(error_code) = fs.truncate(inode, size, req.uid, req.gid) {
+                    return Ok((None, error_code));
                 }
-            } else if let Err(error_code) = fs.truncate(inode, size, req.uid, req.gid) {
-                return Ok((None, error_code));
             }
-        }
 
-        let now = time_now();
-        if let Some(atime) = atime {
-            info!("utimens() called with {:?}, atime={:?}", inode, atime);
+            let now = time_now();
+            if let Some(atime) = atime {
+                info!("utimens() called with {:?}, atime={:?}", inode, atime);
 
-            if attrs.uid != req.uid && req.uid != 0 && atime != Now {
-                return Ok((None, libc::EPERM));
-            }
+                if attrs.uid != req.uid && req.uid != 0 && atime != Now {
+                    return Ok((None, libc::EPERM));
+                }
 
-            if attrs.uid != req.uid
-                && !check_access(
-                    attrs.uid,
-                    attrs.gid,
-                    attrs.mode,
-                    req.uid,
-                    req.gid,
-                    libc::W_OK,
-                )
-            {
-                return Ok((None, libc::EACCES));
+                if attrs.uid != req.uid
+                    && !check_access(
+                        attrs.uid,
+                        attrs.gid,
+                        attrs.mode,
+                        req.uid,
+                        req.gid,
+                        libc::W_OK,
+                    )
+                {
+                    return Ok((None, libc::EACCES));
+                }
+
+                attrs.last_accessed = match atime {
+                    TimeOrNow::SpecificTime(time) => time_from_system_time(&time),
+                    Now => now,
+                };
+                attrs.last_metadata_changed = now;
+                fs.write_inode(&attrs);
             }
+            if let Some(mtime) = mtime {
+                info!("utimens() called with {:?}, mtime={:?}", inode, mtime);
 
-            attrs.last_accessed = match atime {
-                TimeOrNow::SpecificTime(time) => time_from_system_time(&time),
-                Now => now,
-            };
-            attrs.last_metadata_changed = now;
-            fs.write_inode(&attrs);
-        }
-        if let Some(mtime) = mtime {
-            info!("utimens() called with {:?}, mtime={:?}", inode, mtime);
+                if attrs.uid != req.uid && req.uid != 0 && mtime != Now {
+                    return Ok((None, libc::EPERM));
+                }
 
-            if attrs.uid != req.uid && req.uid != 0 && mtime != Now {
-                return Ok((None, libc::EPERM));
-            }
+                if attrs.uid != req.uid
+                    && !check_access(
+                        attrs.uid,
+                        attrs.gid,
+                        attrs.mode,
+                        req.uid,
+                        req.gid,
+                        libc::W_OK,
+                    )
+                {
+                    return Ok((None, libc::EACCES));
+                }
 
-            if attrs.uid != req.uid
-                && !check_access(
-                    attrs.uid,
-                    attrs.gid,
-                    attrs.mode,
-                    req.uid,
-                    req.gid,
-                    libc::W_OK,
-                )
-            {
-                return Ok((None, libc::EACCES));
+                attrs.last_modified = match mtime {
+                    TimeOrNow::SpecificTime(time) => time_from_system_time(&time),
+                    Now => now,
+                };
+                attrs.last_metadata_changed = now;
+                fs.write_inode(&attrs);
             }
 
-            attrs.last_modified = match mtime {
-                TimeOrNow::SpecificTime(time) => time_from_system_time(&time),
-                Now => now,
-            };
-            attrs.last_metadata_changed = now;
-            fs.write_inode(&attrs);
-        }
-
-        let attrs = fs.get_inode(inode).unwrap();
-        return Ok((Some(attrs.into()), SUCCESS));
+            let attrs = fs.get_inode(inode).unwrap();
+            Ok((Some(attrs.into()), SUCCESS))
+        })
+        .await
     }
 
     async fn readdir(
@@ -1473,31 +1908,32 @@ impl ServerFileSystem for RemoteFileSystem {
             self.label, _req.gid, _req.uid, inode
         );
 
-        let fs = &self.fs;
         assert!(offset >= 0);
-        let entries = match fs.get_directory_content(inode) {
-            Ok(entries) => entries,
-            Err(error_code) => {
-                return Ok((None, error_code));
-            }
-        };
+        self.run_blocking(DiskClass::Meta, move |fs| {
+            let entries = match fs.get_directory_content(inode) {
+                Ok(entries) => entries,
+                Err(error_code) => {
+                    return Ok((None, error_code));
+                }
+            };
 
-        for (index, entry) in entries.iter().skip(offset as usize).enumerate() {
-            let (name, (inode, file_type)) = entry;
-            info!("The name in readdir is {:?}", name);
-            return Ok((
-                Some((
-                    *inode,
-                    offset + index as i64 + 1,
-                    (*file_type).into(),
-                    DataList(name.to_vec()),
-                )),
-                SUCCESS,
-            ));
-        }
+            if let Some((name, (inode, file_type))) = entries.iter().nth(offset as usize) {
+                info!("The name in readdir is {:?}", name);
+                return Ok((
+                    Some((
+                        *inode,
+                        offset + 1,
+                        (*file_type).into(),
+                        DataList(name.to_vec()),
+                    )),
+                    SUCCESS,
+                ));
+            }
 
-        // might need to change to suceess
-        return Ok((None, SUCCESS));
+            // might need to change to suceess
+            Ok((None, SUCCESS))
+        })
+        .await
     }
 
     async fn releasedir(
@@ -1512,11 +1948,13 @@ impl ServerFileSystem for RemoteFileSystem {
             self.label, _req.gid, _req.uid, inode, _fh
         );
 
-        let fs = &self.fs;
-        if let Ok(mut attrs) = fs.get_inode(inode) {
-            attrs.open_file_handles -= 1;
-        }
-        Ok(SUCCESS)
+        self.run_blocking(DiskClass::Meta, move |fs| {
+            if let Ok(mut attrs) = fs.get_inode(inode) {
+                attrs.open_file_handles -= 1;
+            }
+            Ok(SUCCESS)
+        })
+        .await
     }
 
     async fn opendir(
@@ -1530,7 +1968,6 @@ impl ServerFileSystem for RemoteFileSystem {
             self.label, req.gid, req.uid, inode
         );
 
-        let fs = &self.fs;
         let (access_mask, read, write) = match flags & libc::O_ACCMODE {
             libc::O_RDONLY => {
                 // Behavior is undefined, but most filesystems return EACCES
@@ -1547,22 +1984,26 @@ impl ServerFileSystem for RemoteFileSystem {
             }
         };
 
-        match fs.get_inode(inode) {
-            Ok(mut attr) => {
-                if check_access(attr.uid, attr.gid, attr.mode, req.uid, req.gid, access_mask) {
-                    attr.open_file_handles += 1;
-                    fs.write_inode(&attr);
-                    let open_flags = if fs.direct_io { FOPEN_DIRECT_IO } else { 0 };
-                    return Ok((
-                        Some((fs.allocate_next_file_handle(read, write), open_flags)),
-                        SUCCESS,
-                    ));
-                } else {
-                    return Ok((None, libc::EACCES));
+        let req = req.clone();
+        self.run_blocking_on(DiskClass::Meta, inode, move |fs| {
+            match fs.get_inode(inode) {
+                Ok(mut attr) => {
+                    if check_access(attr.uid, attr.gid, attr.mode, req.uid, req.gid, access_mask) {
+                        attr.open_file_handles += 1;
+                        fs.write_inode(&attr);
+                        let open_flags = if fs.direct_io { FOPEN_DIRECT_IO } else { 0 };
+                        Ok((
+                            Some((fs.allocate_next_file_handle(read, write), open_flags)),
+                            SUCCESS,
+                        ))
+                    } else {
+                        Ok((None, libc::EACCES))
+                    }
                 }
+                Err(error_code) => Ok((None, error_code)),
             }
-            Err(error_code) => Ok((None, error_code)),
-        }
+        })
+        .await
     }
 
     async fn mkdir(
@@ -1578,66 +2019,76 @@ impl ServerFileSystem for RemoteFileSystem {
             self.label, req.gid, req.uid, parent, name, mode
         );
 
-        let fs = &self.fs;
-        if fs.lookup_name(parent, name).is_ok() {
-            return Ok((None, libc::EEXIST));
-        }
-
-        let mut parent_attrs = match fs.get_inode(parent) {
-            Ok(attrs) => attrs,
-            Err(error_code) => {
-                return Ok((None, error_code));
+        let req = req.clone();
+        let name = name.to_os_string();
+        self.run_blocking(DiskClass::Namespace, move |fs| {
+            let name = name.as_os_str();
+            if fs.lookup_name(parent, name).is_ok() {
+                return Ok((None, libc::EEXIST));
             }
-        };
 
-        if !check_access(
-            parent_attrs.uid,
-            parent_attrs.gid,
-            parent_attrs.mode,
-            req.uid,
-            req.gid,
-            libc::W_OK,
-        ) {
-            return Ok((None, libc::EACCES));
-        }
-        parent_attrs.last_modified = time_now();
-        parent_attrs.last_metadata_changed = time_now();
-        fs.write_inode(&parent_attrs);
+            let mut parent_attrs = match fs.get_inode(parent) {
+                Ok(attrs) => attrs,
+                Err(error_code) => {
+                    return Ok((None, error_code));
+                }
+            };
 
-        if req.uid != 0 {
-            mode &= !(libc::S_ISUID | libc::S_ISGID) as u32;
-        }
-        if parent_attrs.mode & libc::S_ISGID as u16 != 0 {
-            mode |= libc::S_ISGID as u32;
-        }
+            if !check_access(
+                parent_attrs.uid,
+                parent_attrs.gid,
+                parent_attrs.mode,
+                req.uid,
+                req.gid,
+                libc::W_OK,
+            ) {
+                return Ok((None, libc::EACCES));
+            }
+            parent_attrs.last_modified = time_now();
+            parent_attrs.last_metadata_changed = time_now();
+            fs.write_inode(&parent_attrs);
 
-        let inode = fs.allocate_next_inode();
-        let attrs = InodeAttributes {
-            inode,
-            open_file_handles: 0,
-            size: BLOCK_SIZE,
-            last_accessed: time_now(),
-            last_modified: time_now(),
-            last_metadata_changed: time_now(),
-            kind: FileKind::Directory,
-            mode: fs.creation_mode(mode),
-            hardlinks: 2, // Directories start with link count of 2, since they have a self link
-            uid: req.uid,
-            gid: creation_gid(&parent_attrs, req.gid),
-            xattrs: Default::default(),
-        };
-        fs.write_inode(&attrs);
+            // mode_t is u16 on macOS.
+            #[allow(clippy::unnecessary_cast)]
+            if req.uid != 0 {
+                mode &= !(libc::S_ISUID | libc::S_ISGID) as u32;
+            }
+            // mode_t is u16 on macOS.
+            #[allow(clippy::unnecessary_cast)]
+            if parent_attrs.mode & libc::S_ISGID as u16 != 0 {
+                mode |= libc::S_ISGID as u32;
+            }
 
-        let mut entries = BTreeMap::new();
-        entries.insert(b".".to_vec(), (inode, FileKind::Directory));
-        entries.insert(b"..".to_vec(), (parent, FileKind::Directory));
-        fs.write_directory_content(inode, entries);
+            let inode = fs.allocate_next_inode();
+            let attrs = InodeAttributes {
+                inode,
+                open_file_handles: 0,
+                size: BLOCK_SIZE,
+                last_accessed: time_now(),
+                last_modified: time_now(),
+                last_metadata_changed: time_now(),
+                kind: FileKind::Directory,
+                mode: fs.creation_mode(mode),
+                hardlinks: 2, // Directories start with link count of 2, since they have a self link
+                uid: req.uid,
+                gid: creation_gid(&parent_attrs, req.gid),
+                xattrs: Default::default(),
+                version: 0,
+            };
+            fs.write_inode(&attrs);
 
-        let mut entries = fs.get_directory_content(parent).unwrap();
-        entries.insert(name.as_bytes().to_vec(), (inode, FileKind::Directory));
-        fs.write_directory_content(parent, entries);
+            let mut entries = BTreeMap::new();
+            entries.insert(b".".to_vec(), (inode, FileKind::Directory));
+            entries.insert(b"..".to_vec(), (parent, FileKind::Directory));
+            fs.write_directory_content(inode, entries);
 
-        return Ok((Some(attrs.into()), SUCCESS));
+            let mut entries = fs.get_directory_content(parent).unwrap();
+            entries.insert(name.as_bytes().to_vec(), (inode, FileKind::Directory));
+            fs.write_directory_content(parent, entries);
+
+            Ok((Some(attrs.into()), SUCCESS))
+        })
+        .await
     }
 }
 
@@ -1656,6 +2107,315 @@ impl Storage for RemoteFileSystem {
         }
         Ok(ret)
     }
+
+    async fn fence(&self, view: &MembershipView) -> TritonFileResult<MembershipView> {
+        let mut current = self.view.write().map_err(|e| e.to_string())?;
+        if view.epoch > current.epoch {
+            info!("fenced to epoch {}, live: {:?}", view.epoch, &view.live);
+            *current = view.clone();
+        }
+        Ok(current.clone())
+    }
+
+    async fn digest(&self, lo: u64, hi: u64, leaves: bool) -> TritonFileResult<Digest> {
+        self.run_blocking(DiskClass::Data, move |fs| {
+            let mut hashes = vec![];
+            for entry in fs::read_dir(Path::new(&fs.data_dir).join("inodes"))? {
+                let inode = match entry?.file_name().to_str().map(|n| n.parse::<u64>()) {
+                    Some(Ok(inode)) if lo <= inode && inode < hi => inode,
+                    _ => continue,
+                };
+                // the inode may have been removed since the directory was listed
+                if let Ok(attrs) = fs.get_inode(inode) {
+                    let content = fs::read(fs.content_path(inode)).unwrap_or_default();
+                    hashes.push((inode, node_hash(&attrs, &content)));
+                }
+            }
+            hashes.sort();
+            Ok(digest_of(&hashes, lo, hi, leaves))
+        })
+        .await
+    }
+
+    async fn get_nodes(&self, inodes: &[u64]) -> TritonFileResult<Vec<NodeCopy>> {
+        let inodes = inodes.to_vec();
+        self.run_blocking(DiskClass::Data, move |fs| {
+            let mut nodes = vec![];
+            for inode in inodes {
+                if let Ok(attrs) = fs.get_inode(inode) {
+                    let content = fs::read(fs.content_path(inode)).unwrap_or_default();
+                    nodes.push(NodeCopy { attrs, content });
+                }
+            }
+            Ok(nodes)
+        })
+        .await
+    }
+
+    async fn restore_nodes(&self, nodes: Vec<NodeCopy>, remove: &[u64]) -> TritonFileResult<()> {
+        let remove = remove.to_vec();
+        self.run_blocking(DiskClass::Namespace, move |fs| {
+            for node in nodes {
+                fs::write(fs.content_path(node.attrs.inode), &node.content)?;
+                fs.restore_inode(&node.attrs);
+            }
+            for inode in remove {
+                let inode_path = Path::new(&fs.data_dir)
+                    .join("inodes")
+                    .join(inode.to_string());
+                for path in [inode_path, fs.content_path(inode)] {
+                    match fs::remove_file(path) {
+                        Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+                        _ => (),
+                    }
+                }
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_entry(
+        &self,
+        req: &FileRequest,
+        parent: u64,
+        name: &OsStr,
+    ) -> TritonFileResult<(Option<(u64, FileType)>, c_int)> {
+        if name.len() > simple::MAX_NAME_LENGTH as usize {
+            return Ok((None, libc::ENAMETOOLONG));
+        }
+
+        let req = req.clone();
+        let name = name.to_os_string();
+        self.run_blocking(DiskClass::Meta, move |fs| {
+            let parent_attrs = match fs.get_inode(parent) {
+                Ok(attrs) => attrs,
+                Err(error_code) => return Ok((None, error_code)),
+            };
+            if !check_access(
+                parent_attrs.uid,
+                parent_attrs.gid,
+                parent_attrs.mode,
+                req.uid,
+                req.gid,
+                libc::X_OK,
+            ) {
+                return Ok((None, libc::EACCES));
+            }
+
+            let entries = match fs.get_directory_content(parent) {
+                Ok(entries) => entries,
+                Err(error_code) => return Ok((None, error_code)),
+            };
+            match entries.get(name.as_bytes()) {
+                Some((inode, kind)) => Ok((Some((*inode, (*kind).into())), SUCCESS)),
+                None => Ok((None, libc::ENOENT)),
+            }
+        })
+        .await
+    }
+
+    async fn set_entry(
+        &self,
+        req: &FileRequest,
+        parent: u64,
+        name: &OsStr,
+        entry: Option<(u64, FileType)>,
+        expect: Option<u64>,
+        owner: u32,
+    ) -> TritonFileResult<c_int> {
+        if name.len() > simple::MAX_NAME_LENGTH as usize {
+            return Ok(libc::ENAMETOOLONG);
+        }
+
+        let req = req.clone();
+        let name = name.to_os_string();
+        self.run_blocking(DiskClass::Namespace, move |fs| {
+            let mut parent_attrs = match fs.get_inode(parent) {
+                Ok(attrs) => attrs,
+                Err(error_code) => return Ok(error_code),
+            };
+            if !check_access(
+                parent_attrs.uid,
+                parent_attrs.gid,
+                parent_attrs.mode,
+                req.uid,
+                req.gid,
+                libc::W_OK,
+            ) {
+                return Ok(libc::EACCES);
+            }
+
+            let mut entries = match fs.get_directory_content(parent) {
+                Ok(entries) => entries,
+                Err(error_code) => return Ok(error_code),
+            };
+            let current = entries.get(name.as_bytes()).map(|(inode, _)| *inode);
+            if current != expect {
+                return Ok(if current.is_some() {
+                    libc::EEXIST
+                } else {
+                    libc::ENOENT
+                });
+            }
+
+            // "Sticky bit" handling
+            if current.is_some()
+                && parent_attrs.mode & libc::S_ISVTX as u16 != 0
+                && req.uid != 0
+                && req.uid != parent_attrs.uid
+                && req.uid != owner
+            {
+                return Ok(libc::EACCES);
+            }
+
+            match entry {
+                Some((inode, kind)) => {
+                    entries.insert(name.as_bytes().to_vec(), (inode, kind.into()));
+                }
+                None => {
+                    entries.remove(name.as_bytes());
+                }
+            }
+            fs.write_directory_content(parent, entries);
+
+            parent_attrs.last_metadata_changed = time_now();
+            parent_attrs.last_modified = time_now();
+            fs.write_inode(&parent_attrs);
+            Ok(SUCCESS)
+        })
+        .await
+    }
+
+    async fn make_node(
+        &self,
+        req: &FileRequest,
+        parent: &FileAttr,
+        mut mode: u32,
+        flags: i32,
+    ) -> TritonFileResult<(Option<(FileAttr, u64)>, c_int)> {
+        info!(
+            "No.{:?} gid:{:?} uid:{:?} make_node() called in {:?} {:o}",
+            self.label, req.gid, req.uid, parent.ino, mode
+        );
+
+        let kind = simple::as_file_kind(mode);
+        let (read, write) = match flags & libc::O_ACCMODE {
+            _ if kind == FileKind::Directory => (false, false),
+            libc::O_RDONLY => (true, false),
+            libc::O_WRONLY => (false, true),
+            libc::O_RDWR => (true, true),
+            // Exactly one access mode flag must be specified
+            _ => {
+                return Ok((None, libc::EINVAL));
+            }
+        };
+
+        let req = req.clone();
+        let parent = *parent;
+        self.run_blocking(DiskClass::Namespace, move |fs| {
+            // mode_t is u16 on macOS.
+            #[allow(clippy::unnecessary_cast)]
+            if req.uid != 0 {
+                mode &= !(libc::S_ISUID | libc::S_ISGID) as u32;
+            }
+            // mode_t is u16 on macOS.
+            #[allow(clippy::unnecessary_cast)]
+            let setgid = parent.perm & libc::S_ISGID as u16 != 0;
+            #[allow(clippy::unnecessary_cast)]
+            if kind == FileKind::Directory && setgid {
+                mode |= libc::S_ISGID as u32;
+            }
+
+            let inode = fs.allocate_next_inode();
+            let dir = kind == FileKind::Directory;
+            let attrs = InodeAttributes {
+                inode,
+                open_file_handles: if dir { 0 } else { 1 },
+                size: if dir { BLOCK_SIZE } else { 0 },
+                last_accessed: time_now(),
+                last_modified: time_now(),
+                last_metadata_changed: time_now(),
+                kind,
+                mode: fs.creation_mode(mode),
+                hardlinks: if dir { 2 } else { 1 },
+                uid: req.uid,
+                gid: if setgid { parent.gid } else { req.gid },
+                xattrs: Default::default(),
+                version: 0,
+            };
+            fs.write_inode(&attrs);
+
+            if dir {
+                let mut entries = BTreeMap::new();
+                entries.insert(b".".to_vec(), (inode, FileKind::Directory));
+                entries.insert(b"..".to_vec(), (parent.ino, FileKind::Directory));
+                fs.write_directory_content(inode, entries);
+                return Ok((Some((attrs.into(), 0)), SUCCESS));
+            }
+            File::create(fs.content_path(inode)).unwrap();
+            Ok((
+                Some((attrs.into(), fs.allocate_next_file_handle(read, write))),
+                SUCCESS,
+            ))
+        })
+        .await
+    }
+
+    async fn drop_link(&self, _req: &FileRequest, ino: u64) -> TritonFileResult<c_int> {
+        self.run_blocking(DiskClass::Namespace, move |fs| {
+            let mut attrs = match fs.get_inode(ino) {
+                Ok(attrs) => attrs,
+                Err(error_code) => return Ok(error_code),
+            };
+            if attrs.kind == FileKind::Directory {
+                attrs.hardlinks = 0;
+            } else {
+                attrs.hardlinks = attrs.hardlinks.saturating_sub(1);
+            }
+            attrs.last_metadata_changed = time_now();
+            fs.write_inode(&attrs);
+            fs.gc_inode(&attrs);
+            Ok(SUCCESS)
+        })
+        .await
+    }
+
+    async fn namespace(&self, name: &str) -> TritonFileResult<Box<dyn Storage>> {
+        let shard = match parse_shard_name(name) {
+            Some((namespace, shard)) if valid_namespace(namespace) => shard,
+            _ => {
+                return Err(Box::new(TritonFileError::Unknown(format!(
+                    "bad namespace name {:?}",
+                    name
+                ))))
+            }
+        };
+        let dir = Path::new(&self.fs.data_dir).join(NAMESPACE_DIR).join(name);
+        let storage = RemoteFileSystem::in_dir(dir.to_string_lossy().to_string(), self.label);
+        if shard > 0 {
+            storage
+                .fs
+                .start_inodes_at((shard as u64) << SHARD_INODE_BITS);
+        }
+        Ok(Box::new(storage))
+    }
+
+    async fn namespaces(&self, p: &Pattern) -> TritonFileResult<List> {
+        let dir = Path::new(&self.fs.data_dir).join(NAMESPACE_DIR);
+        let mut names = vec![];
+        if let Ok(entries) = fs::read_dir(dir) {
+            for entry in entries {
+                if let Some(name) = entry?.file_name().to_str() {
+                    if p.matches(name) {
+                        names.push(name.to_string());
+                    }
+                }
+            }
+        }
+        names.sort();
+        Ok(List(names))
+    }
 }
 
 #[async_trait]
@@ -1667,11 +2427,22 @@ pub trait BinStorage: Send + Sync {
 
 #[cfg(test)]
 mod test {
+    use std::ffi::OsStr;
     use std::fs;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use fuser::FUSE_ROOT_ID;
+    use tempfile::TempDir;
 
     use crate::error::TritonFileResult;
+    use crate::error::SUCCESS;
 
-    use super::{KeyList, KeyString, KeyValue, RemoteFileSystem};
+    use super::{
+        digest_of, split_range, FileRequest, KeyList, KeyString, KeyValue, Pattern,
+        RemoteFileSystem, ServerFileSystem, Storage, DIGEST_FANOUT, DIGEST_LEAF_SIZE,
+        MAX_DATA_DISK_OPS,
+    };
 
     async fn setup_test_storage() -> RemoteFileSystem {
         let storage = RemoteFileSystem::new(1);
@@ -1692,6 +2463,11 @@ mod test {
         storage
     }
 
+    // A backend of its own, kept in `dir`.
+    fn backend(dir: &TempDir, num: usize) -> RemoteFileSystem {
+        RemoteFileSystem::in_dir(dir.path().to_string_lossy().to_string(), num)
+    }
+
     #[tokio::test]
     async fn test_create_dir() -> TritonFileResult<()> {
         if !fs::metadata(format!("tmp/{}", 2)).is_ok() {
@@ -1701,6 +2477,188 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_digest_finds_changed_subtree() {
+        let ranges = split_range(0, u64::MAX);
+        assert_eq!(DIGEST_FANOUT as usize, ranges.len());
+        assert_eq!(u64::MAX, ranges.last().unwrap().1);
+
+        let ours: Vec<(u64, u64)> = (1..1000).map(|inode| (inode, inode * 7)).collect();
+        let mut theirs = ours.clone();
+        theirs[500].1 += 1;
+        let a = digest_of(&ours, 0, u64::MAX, false);
+        let b = digest_of(&theirs, 0, u64::MAX, false);
+        assert_ne!(a.hash, b.hash);
+        assert!(a.inodes.is_empty());
+        // all inodes fall in the first subrange
+        assert_eq!(a.children[1..], b.children[1..]);
+        assert_ne!(a.children[0], b.children[0]);
+
+        // a small range lists its inodes, and hashes the same either way
+        let (lo, hi) = (490, 520);
+        let small = digest_of(&ours, lo, hi, false);
+        assert!(small.inodes.len() <= DIGEST_LEAF_SIZE);
+        assert_eq!(30, small.count);
+        assert_eq!(small.hash, digest_of(&ours, lo, hi, true).hash);
+        assert_eq!(ours, digest_of(&ours, 0, u64::MAX, true).inodes);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_blocking_round_trip() -> TritonFileResult<()> {
+        let dir = tempfile::tempdir()?;
+        let storage = backend(&dir, 26);
+        let req = FileRequest {
+            uid: 0,
+            gid: 0,
+            pid: 0,
+        };
+        let name = "round-trip";
+        let (created, code) = storage
+            .create(
+                &req,
+                FUSE_ROOT_ID,
+                OsStr::new(name),
+                0o100644,
+                0,
+                libc::O_RDWR,
+            )
+            .await?;
+        assert_eq!(SUCCESS, code);
+        let (attr, fh) = created.unwrap();
+
+        let (written, code) = storage
+            .write(&req, attr.ino, fh, 0, b"hello", 0, 0, None)
+            .await?;
+        assert_eq!(SUCCESS, code);
+        assert_eq!(Some(5), written);
+
+        let (data, code) = storage.read(&req, attr.ino, fh, 0, 5, 0, None).await?;
+        assert_eq!(SUCCESS, code);
+        let data: Vec<u8> = serde_json::from_str(&data.unwrap())?;
+        assert_eq!(b"hello".to_vec(), data);
+
+        assert_eq!(
+            SUCCESS,
+            storage.unlink(&req, FUSE_ROOT_ID, OsStr::new(name)).await?
+        );
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_writes_keep_attributes() -> TritonFileResult<()> {
+        let dir = tempfile::tempdir()?;
+        let storage = Arc::new(backend(&dir, 26));
+        let req = FileRequest {
+            uid: 0,
+            gid: 0,
+            pid: 0,
+        };
+        let (created, code) = storage
+            .create(
+                &req,
+                FUSE_ROOT_ID,
+                OsStr::new("written"),
+                0o100644,
+                0,
+                libc::O_RDWR,
+            )
+            .await?;
+        assert_eq!(SUCCESS, code);
+        let (attr, fh) = created.unwrap();
+        let (_, _, before) = storage.getattr_versioned(&req, attr.ino).await?;
+
+        // Each write grows the file and bumps its version; none of them
+        // may be lost to another one running at the same time.
+        let writes = 4 * MAX_DATA_DISK_OPS;
+        let mut tasks = vec![];
+        for i in 0..writes {
+            let storage = storage.clone();
+            let req = req.clone();
+            tasks.push(tokio::spawn(async move {
+                let offset = (i * 16) as i64;
+                storage
+                    .write(&req, attr.ino, fh, offset, &[1; 16], 0, 0, None)
+                    .await
+            }));
+        }
+        for task in tasks {
+            assert_eq!((Some(16), SUCCESS), task.await??);
+        }
+        let (attr, code, after) = storage.getattr_versioned(&req, attr.ino).await?;
+        assert_eq!(SUCCESS, code);
+        assert_eq!((writes * 16) as u64, attr.unwrap().size);
+        assert_eq!(before + writes as u64, after);
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_meta_not_starved_by_data() -> TritonFileResult<()> {
+        let dir = tempfile::tempdir()?;
+        let storage = backend(&dir, 27);
+        let req = FileRequest {
+            uid: 0,
+            gid: 0,
+            pid: 0,
+        };
+        // Hold every data permit, as a burst of large reads would.
+        let _held = storage
+            .data_permits
+            .clone()
+            .acquire_many_owned(MAX_DATA_DISK_OPS as u32)
+            .await?;
+
+        let timeout = Duration::from_secs(1);
+        tokio::time::timeout(timeout, storage.clock(0)).await??;
+        let (attr, code) =
+            tokio::time::timeout(timeout, storage.getattr(&req, FUSE_ROOT_ID)).await??;
+        assert_eq!(SUCCESS, code);
+        assert_eq!(FUSE_ROOT_ID, attr.unwrap().ino);
+
+        let (opened, code) = storage.open(&req, FUSE_ROOT_ID, libc::O_RDONLY).await?;
+        assert_eq!(SUCCESS, code);
+        let (fh, _) = opened.unwrap();
+        assert!(
+            tokio::time::timeout(timeout, storage.read(&req, FUSE_ROOT_ID, fh, 0, 1, 0, None))
+                .await
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_namespaces_are_isolated() -> TritonFileResult<()> {
+        let dir = tempfile::tempdir()?;
+        let storage = backend(&dir, 28);
+        let req = FileRequest {
+            uid: 0,
+            gid: 0,
+            pid: 0,
+        };
+        assert!(storage.namespace("../a").await.is_err());
+        let a = storage.namespace("a").await?;
+        let b = storage.namespace("b").await?;
+        let name = OsStr::new("only-in-a");
+        let (_, code) = a
+            .create(&req, FUSE_ROOT_ID, name, 0o100644, 0, libc::O_RDWR)
+            .await?;
+        assert_eq!(SUCCESS, code);
+
+        assert_eq!(SUCCESS, a.lookup(&req, FUSE_ROOT_ID, name).await?.1);
+        assert_eq!(libc::ENOENT, b.lookup(&req, FUSE_ROOT_ID, name).await?.1);
+        assert_eq!(
+            libc::ENOENT,
+            storage.lookup(&req, FUSE_ROOT_ID, name).await?.1
+        );
+        let p = Pattern {
+            prefix: "a".to_string(),
+            suffix: "".to_string(),
+        };
+        assert_eq!(vec!["a".to_string()], storage.namespaces(&p).await?.0);
+
+        assert_eq!(SUCCESS, a.unlink(&req, FUSE_ROOT_ID, name).await?);
+        Ok(())
+    }
+
     // #[tokio::test]
     // async fn storage_get_empty() -> TritonFileResult<()> {
     //     let storage = setup_test_storage().await;